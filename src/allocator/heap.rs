@@ -0,0 +1,168 @@
+use core::{alloc::{GlobalAlloc, Layout}, fmt::Display};
+#[cfg(debug_assertions)]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// Heap accounting, to be wrapped around the kernel heap once it's used as the `#[global_allocator]`
+// Counters only exist in debug builds, release builds forward directly to the inner allocator
+// TODO: per-allocation tags for leak hunting (needs an allocation header)
+
+#[cfg(debug_assertions)]
+static BYTES_IN_USE: AtomicUsize = AtomicUsize::new(0);
+#[cfg(debug_assertions)]
+static PEAK_BYTES_IN_USE: AtomicUsize = AtomicUsize::new(0);
+#[cfg(debug_assertions)]
+static LIVE_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+#[cfg(debug_assertions)]
+static TOTAL_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    pub bytes_in_use: usize,
+    pub peak_bytes_in_use: usize,
+    pub live_allocations: usize,
+    pub total_allocations: usize,
+}
+
+impl Display for HeapStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} bytes in {} allocations (peak {} bytes, {} total allocations)",
+            self.bytes_in_use, self.live_allocations, self.peak_bytes_in_use, self.total_allocations
+        )
+    }
+}
+
+/// Returns `None` in release builds
+pub fn stats() -> Option<HeapStats> {
+    #[cfg(debug_assertions)]
+    {
+        Some(HeapStats {
+            bytes_in_use: BYTES_IN_USE.load(Ordering::Relaxed),
+            peak_bytes_in_use: PEAK_BYTES_IN_USE.load(Ordering::Relaxed),
+            live_allocations: LIVE_ALLOCATIONS.load(Ordering::Relaxed),
+            total_allocations: TOTAL_ALLOCATIONS.load(Ordering::Relaxed),
+        })
+    }
+    #[cfg(not(debug_assertions))]
+    {
+        None
+    }
+}
+
+/// Compares the current stats with `baseline` (taken before the checked code ran),
+/// returns the number of bytes still allocated since then \
+/// Always succeeds in release builds
+pub fn check_leaks(baseline: HeapStats) -> Result<(), usize> {
+    match stats() {
+        Some(current) if current.bytes_in_use > baseline.bytes_in_use => {
+            Err(current.bytes_in_use - baseline.bytes_in_use)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[inline]
+fn record_alloc(#[allow(unused_variables)] size: usize) {
+    #[cfg(debug_assertions)]
+    {
+        let in_use = BYTES_IN_USE.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK_BYTES_IN_USE.fetch_max(in_use, Ordering::Relaxed);
+        LIVE_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        TOTAL_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[inline]
+fn record_dealloc(#[allow(unused_variables)] size: usize) {
+    #[cfg(debug_assertions)]
+    {
+        BYTES_IN_USE.fetch_sub(size, Ordering::Relaxed);
+        LIVE_ALLOCATIONS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Forwards to `A`, counting allocations in debug builds
+pub struct AccountingAllocator<A: GlobalAlloc> {
+    inner: A,
+}
+
+impl<A: GlobalAlloc> AccountingAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for AccountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            record_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe {
+            self.inner.dealloc(ptr, layout);
+        }
+        record_dealloc(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            record_dealloc(layout.size());
+            record_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+// Counters only exist in debug builds
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use std::alloc::System;
+
+    use super::*;
+
+    #[test]
+    fn accounting_follows_alloc_dealloc_pairs() {
+        // The only user of the global counters
+        let allocator = AccountingAllocator::new(System);
+        let baseline = stats().unwrap();
+        let (small, large) = (Layout::from_size_align(24, 8).unwrap(), Layout::from_size_align(4096, 64).unwrap());
+
+        unsafe {
+            let first = allocator.alloc(small);
+            let second = allocator.alloc_zeroed(large);
+            let current = stats().unwrap();
+            assert_eq!(current.bytes_in_use, baseline.bytes_in_use + 24 + 4096);
+            assert_eq!(current.live_allocations, baseline.live_allocations + 2);
+            assert_eq!(current.total_allocations, baseline.total_allocations + 2);
+            assert_eq!(check_leaks(baseline), Err(24 + 4096));
+
+            allocator.dealloc(second, large);
+            // Grows in place or moves, either way counted as a dealloc / alloc pair
+            let first = allocator.realloc(first, small, 100);
+            let current = stats().unwrap();
+            assert_eq!(current.bytes_in_use, baseline.bytes_in_use + 100);
+            assert_eq!(current.live_allocations, baseline.live_allocations + 1);
+            assert_eq!(current.total_allocations, baseline.total_allocations + 3);
+            assert!(current.peak_bytes_in_use >= baseline.bytes_in_use + 24 + 4096);
+
+            allocator.dealloc(first, Layout::from_size_align(100, 8).unwrap());
+        }
+        let current = stats().unwrap();
+        assert_eq!((current.bytes_in_use, current.live_allocations), (baseline.bytes_in_use, baseline.live_allocations));
+        assert_eq!(check_leaks(baseline), Ok(()));
+    }
+}
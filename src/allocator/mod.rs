@@ -1 +1,4 @@
+pub mod heap;
 pub mod physical;
+pub mod slab;
+pub mod vm;
@@ -1,27 +1,59 @@
 // TODO: DMA support
 
-use core::{sync::atomic::{AtomicUsize, Ordering}, slice};
+use core::{fmt::Display, sync::atomic::{AtomicUsize, Ordering}, slice};
 
 use arrayvec::ArrayVec;
+use spin::{Mutex, Once};
+use static_assertions::const_assert_eq;
 
 use crate::{
     arch::{boot::{self, MemoryMapEntryKind}, intrinsics::atomic_bit_test_set, paging::{self, IdentityMapToken}, PhysicalAddress},
-    common::{macros::{assert_arg, debug_assert_arg, token_type}, sync::InitOnce}
+    common::{log::{error, warn}, macros::{assert_arg, debug_assert_arg, token_type}, sync::{Backoff, InitOnce}}
 };
 
 pub const FRAME_SIZE: usize = paging::PAGE_SIZE;
+/// Granularity of [MemoryRegion]s in large frame mode (a 2 MiB page)
+pub const LARGE_FRAME_SIZE: usize = 512 * FRAME_SIZE;
+/// Granularity of the [FrameAllocator] regions, [LARGE_FRAME_SIZE] with the `large-frames` feature \
+/// Frame counts passed to the allocator are still in `FRAME_SIZE` units, rounded up to whole region frames
+#[cfg(not(feature = "large-frames"))]
+pub const REGION_FRAME_SIZE: usize = FRAME_SIZE;
+#[cfg(feature = "large-frames")]
+pub const REGION_FRAME_SIZE: usize = LARGE_FRAME_SIZE;
 pub const MAX_MEMORY_REGION_COUNT: usize = 4096;
+/// Maximum number of regions added by [FrameAllocator::add_region], counted towards [MAX_MEMORY_REGION_COUNT]
+pub const MAX_ADDED_REGION_COUNT: usize = 64;
 
 static ALLOCATOR: InitOnce<FrameAllocator> = InitOnce::new(FrameAllocator::empty());
+static OOM_HANDLER: Mutex<OomHandler> = Mutex::new(default_oom_handler);
+
+/// Called when a critical allocation of `frame_count` frames fails
+pub type OomHandler = fn(allocator: &FrameAllocator, frame_count: usize) -> !;
 
 token_type!(FrameAllocatorToken);
 
+#[cfg(not(feature = "large-frames"))]
+type Region = MemoryRegion;
+#[cfg(feature = "large-frames")]
+type Region = LargeFrameRegion;
+
 pub fn global_allocator(#[allow(unused_variables)] token: FrameAllocatorToken) -> &'static FrameAllocator {
     debug_assert!(ALLOCATOR.is_completed());
     // SAFETY: allocator was initialized
     unsafe { ALLOCATOR.get_unchecked() }
 }
 
+/// Replaces the out of memory policy used by [FrameAllocator::allocate_critical]
+pub fn set_oom_handler(handler: OomHandler) {
+    *OOM_HANDLER.lock() = handler;
+}
+
+fn default_oom_handler(allocator: &FrameAllocator, frame_count: usize) -> ! {
+    error!("Allocator stats: {}", allocator.stats());
+    error!("{allocator}");
+    panic!("Out of physical memory ({frame_count} frames requested)");
+}
+
 /// This function may only be called once, all subsequent calls will panic or be ignored \
 /// All `MemoryMapEntryKind::Usable` entries in `memory_map` must be valid and unused
 pub unsafe fn initialize(memory_map: boot::MemoryMap, identity_map_token: IdentityMapToken) -> FrameAllocatorToken {
@@ -42,8 +74,13 @@ pub unsafe fn initialize(memory_map: boot::MemoryMap, identity_map_token: Identi
 
 #[derive(Debug)]
 pub struct FrameAllocator {
-    regions: ArrayVec<MemoryRegion, MAX_MEMORY_REGION_COUNT>,
-    last_allocation_region: AtomicUsize
+    /// Regions from the boot memory map, sorted by base and immutable after initialization
+    regions: ArrayVec<Region, MAX_MEMORY_REGION_COUNT>,
+    last_allocation_region: AtomicUsize,
+    /// Regions added at runtime, in insertion order
+    added_regions: [Once<Region>; MAX_ADDED_REGION_COUNT],
+    /// Claimed `added_regions` slots, a claimed slot may still be initializing
+    added_region_count: AtomicUsize,
 }
 
 impl FrameAllocator {
@@ -51,21 +88,96 @@ impl FrameAllocator {
         Self {
             regions: ArrayVec::new_const(),
             last_allocation_region: AtomicUsize::new(0),
+            added_regions: [const { Once::new() }; MAX_ADDED_REGION_COUNT],
+            added_region_count: AtomicUsize::new(0),
         }
     }
 
+    /// Folds [`base`; `base + len`) into the allocator at runtime as a new region,
+    /// e.g. `MemoryMapEntryKind::Reclaimable` memory once the ACPI tables are parsed and modules consumed \
+    /// The range is shrunk to [REGION_FRAME_SIZE] boundaries (see [MemoryRegion::bounds]) \
+    /// Returns `Err` if it's too small to hold a region,
+    /// or if [MAX_ADDED_REGION_COUNT] or [MAX_MEMORY_REGION_COUNT] regions are already in use \
+    /// Safety:
+    /// Memory in range [`base`; `base + len`) must be valid, covered by the identity map and truly free -
+    /// no longer referenced by the kernel, bootloader, firmware or devices (including the current stack and
+    /// bootloader provided structures), and not managed by the allocator already
+    pub unsafe fn add_region(&self, base: PhysicalAddress, len: usize, identity_map_token: IdentityMapToken) -> Result<(), ()> {
+        debug_assert_arg!(
+            base,
+            self.all_regions().all(|region| region.end() <= base || base + len <= region.base),
+            "Must not overlap managed memory."
+        );
+
+        let (base, len) = Region::bounds(base, len).ok_or(())?;
+        let boot_region_count = self.regions.len();
+        let slot = self.added_region_count.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+            (count < MAX_ADDED_REGION_COUNT && boot_region_count + count < MAX_MEMORY_REGION_COUNT).then_some(count + 1)
+        }).map_err(|_| ())?;
+
+        self.added_regions[slot].call_once(|| unsafe { Region::new(base, len, identity_map_token) });
+        Ok(())
+    }
+
+    /// Initialized regions added by [FrameAllocator::add_region]
+    fn added_regions(&self) -> impl Iterator<Item = &Region> + '_ {
+        let count = self.added_region_count.load(Ordering::SeqCst).min(MAX_ADDED_REGION_COUNT);
+        self.added_regions[..count].iter().filter_map(Once::get)
+    }
+
+    /// Boot regions followed by regions added at runtime
+    fn all_regions(&self) -> impl Iterator<Item = &Region> + '_ {
+        self.regions.iter().chain(self.added_regions())
+    }
+
     /// All `MemoryMapEntryKind::Usable` entries in `memory_map` must be valid and unused
     unsafe fn fill(&mut self, memory_map: boot::MemoryMap, identity_map_token: IdentityMapToken) {
-        for entry in memory_map.entries.iter().filter(|x| x.kind == MemoryMapEntryKind::Usable) {
-            let region = unsafe { MemoryRegion::new(entry.base, entry.len, identity_map_token) };
-            if self.regions.try_push(region).is_err() {
-                // TODO: warn!("Too many memory regions")
-                break;
+        // Fewer, larger regions - each region keeps `MIN_FRAMES_REQUIRED` frames in reserve
+        let usable = memory_map.coalesced().filter(|x| x.kind == MemoryMapEntryKind::Usable);
+        let (mut dropped_count, mut dropped_size) = (0_usize, 0_usize);
+        for entry in usable {
+            if entry.checked_end().is_none() {
+                warn!("Memory region at {} exceeds the physical address space", entry.base);
+                continue;
+            }
+            // Single frame regions can't hold their own bitmap
+            let Some((base, len)) = Region::bounds(entry.base, entry.len) else {
+                continue;
+            };
+
+            if !self.regions.is_full() {
+                let region = unsafe { Region::new(base, len, identity_map_token) };
+                self.regions.push(region);
+                continue;
+            }
+
+            // Too many regions - keep the largest ones
+            dropped_count += 1;
+            let (smallest_ix, smallest) = self.regions.iter()
+                .enumerate()
+                .min_by_key(|(_, region)| region.len())
+                .expect("Region list is full");
+            if smallest.len() >= len {
+                dropped_size += len;
+            } else {
+                dropped_size += smallest.len();
+                self.regions[smallest_ix] = unsafe { Region::new(base, len, identity_map_token) };
             }
         }
+
+        if dropped_count > 0 {
+            warn!(
+                "More than {MAX_MEMORY_REGION_COUNT} usable memory regions, {dropped_count} smallest regions ({} KiB) left unused",
+                dropped_size / 1024
+            );
+        }
+        // Lookups in `free` and `allocate_near` rely on the regions being sorted
+        self.regions.sort_unstable_by_key(|region| region.base);
     }
 
+    /// Boot regions are tried round robin, regions added by [FrameAllocator::add_region] only after all of them
     pub fn allocate(&self, frame_count: usize) -> Option<PhysicalAddress> {
+        let frame_count = region_frames(frame_count);
         let region_count = self.regions.len();
         // start_region_id % region_count = index of the first region checked
         let start_region_id = self.last_allocation_region.fetch_add(1, Ordering::SeqCst);
@@ -75,7 +187,49 @@ impl FrameAllocator {
                 return Some(address);
             }
         }
-        None
+        self.added_regions().find_map(|region| region.allocate(frame_count))
+    }
+
+    /// Like [FrameAllocator::allocate], but invokes the out of memory handler (see [set_oom_handler]) on failure
+    pub fn allocate_critical(&self, frame_count: usize) -> PhysicalAddress {
+        match self.allocate(frame_count) {
+            Some(address) => address,
+            None => {
+                // Copy the handler out so that it may call `set_oom_handler`
+                let handler = *OOM_HANDLER.lock();
+                handler(self, frame_count)
+            }
+        }
+    }
+
+    /// Prefers the region containing `hint` (starting at the chunk containing it) and its neighbors,
+    /// falls back to [FrameAllocator::allocate]
+    pub fn allocate_near(&self, hint: PhysicalAddress, frame_count: usize) -> Option<PhysicalAddress> {
+        let region_count = self.regions.len();
+        if region_count == 0 {
+            return self.allocate(frame_count);
+        }
+
+        // Region containing `hint` or the closest one below it
+        let hint_ix = self.regions.partition_point(|region| region.base <= hint).saturating_sub(1);
+        let hint_region = &self.regions[hint_ix];
+        let start_chunk = if hint_region.check_if_owned(hint) {
+            hint_region.chunk_index(hint)
+        } else {
+            0
+        };
+        if let Some(address) = hint_region.allocate_from(start_chunk, region_frames(frame_count)) {
+            return Some(address);
+        }
+
+        let neighbors = [hint_ix.checked_sub(1), Some(hint_ix + 1).filter(|&ix| ix < region_count)];
+        for ix in neighbors.into_iter().flatten() {
+            if let Some(address) = self.regions[ix].allocate(region_frames(frame_count)) {
+                return Some(address);
+            }
+        }
+
+        self.allocate(frame_count)
     }
 
     pub fn free(&self, address: PhysicalAddress, frame_count: usize) {
@@ -87,90 +241,265 @@ impl FrameAllocator {
             } else {
                 core::cmp::Ordering::Greater
             }
-        }).expect("Attempted to free an invalid address");
+        });
+        let region = match region_ix {
+            Ok(ix) => &self.regions[ix],
+            Err(_) => self.added_regions()
+                .find(|region| region.check_if_owned(address))
+                .expect("Attempted to free an invalid address"),
+        };
+
+        region.free(address, region_frames(frame_count));
+    }
+
+    /// Contention counters summed over all regions
+    pub fn stats(&self) -> AllocatorStats {
+        self.all_regions().fold(AllocatorStats::default(), |stats, region| stats + region.counters.snapshot())
+    }
+
+    /// Read-only snapshots of all managed memory regions, boot regions by base followed by added regions
+    pub fn regions(&self) -> impl Iterator<Item = RegionInfo> + '_ {
+        self.all_regions().map(|region| RegionInfo {
+            base: region.base,
+            frame_count: region.len() / FRAME_SIZE,
+            frames_used: region.frames_used.load(Ordering::Relaxed).saturating_sub(region.padding_frames())
+                * (REGION_FRAME_SIZE / FRAME_SIZE),
+        })
+    }
+
+    /// Frame usage of the region at `index` (in [FrameAllocator::regions] order), one entry per [REGION_FRAME_SIZE]
+    /// frame, `true` - frame used or reserved \
+    /// Each bitmap chunk is read once when the iterator reaches it, concurrent changes may be partially visible \
+    /// Panics if `index` is out of range
+    pub fn region_bitmap(&self, index: usize) -> impl Iterator<Item = bool> + '_ {
+        let region = self.all_regions().nth(index).expect("Region index out of range");
+        region.chunks.iter().flat_map(|chunk| {
+            let bits = chunk.0.load(Ordering::Relaxed);
+            (0..FrameBitmapChunk::BITS).map(move |bit| bits & (1 << bit) != 0)
+        }).take(region.len() / REGION_FRAME_SIZE)
+    }
+}
+
+impl Display for FrameAllocator {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "{:>18} {:>18} {:>10} {:>10}", "base", "end", "frames", "used")?;
+        for region in self.regions() {
+            writeln!(f, "{region}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Snapshot of a single [FrameAllocator] region in `FRAME_SIZE` frames, counters may be outdated
+#[derive(Clone, Copy, Debug)]
+pub struct RegionInfo {
+    pub base: PhysicalAddress,
+    pub frame_count: usize,
+    pub frames_used: usize,
+}
+
+impl RegionInfo {
+    /// Size in bytes
+    pub fn size(&self) -> usize {
+        self.frame_count * FRAME_SIZE
+    }
+
+    pub fn end(&self) -> PhysicalAddress {
+        self.base + self.size()
+    }
+}
+
+impl Display for RegionInfo {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:>#18x} {:>#18x} {:>10} {:>10}",
+            usize::from(self.base),
+            usize::from(self.end()),
+            self.frame_count,
+            self.frames_used
+        )
+    }
+}
+
+/// Snapshot of the allocator contention counters
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocatorStats {
+    /// Region allocation attempts (a single `FrameAllocator::allocate` call may try several regions)
+    pub allocation_attempts: usize,
+    /// Failed compare-exchanges in multi-frame allocations
+    pub cas_retries: usize,
+    /// Regions skipped because fewer than `MIN_FRAMES_REQUIRED` frames were available
+    pub contention_skips: usize,
+}
+
+impl core::ops::Add for AllocatorStats {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            allocation_attempts: self.allocation_attempts + rhs.allocation_attempts,
+            cas_retries: self.cas_retries + rhs.cas_retries,
+            contention_skips: self.contention_skips + rhs.contention_skips,
+        }
+    }
+}
+
+impl Display for AllocatorStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} attempts, {} CAS retries, {} contention skips",
+            self.allocation_attempts, self.cas_retries, self.contention_skips
+        )
+    }
+}
+
+/// Diagnostic counters, all accesses are `Relaxed`
+#[derive(Debug, Default)]
+struct AllocatorCounters {
+    allocation_attempts: AtomicUsize,
+    cas_retries: AtomicUsize,
+    contention_skips: AtomicUsize,
+}
 
-        self.regions[region_ix].free(address, frame_count);
+impl AllocatorCounters {
+    fn snapshot(&self) -> AllocatorStats {
+        AllocatorStats {
+            allocation_attempts: self.allocation_attempts.load(Ordering::Relaxed),
+            cas_retries: self.cas_retries.load(Ordering::Relaxed),
+            contention_skips: self.contention_skips.load(Ordering::Relaxed),
+        }
     }
 }
 
+/// Frame bitmap over a physical memory range, `FRAME` is the allocation granularity \
+/// Large frames (e.g. [LARGE_FRAME_SIZE]) cover more memory with a smaller bitmap,
+/// at the cost of coarser allocations
 #[derive(Debug)]
-pub struct MemoryRegion {
+pub struct MemoryRegion<const FRAME: usize = FRAME_SIZE> {
     base: PhysicalAddress,
+    /// Length in bytes, the bitmap may cover more frames (see `frame_count`)
+    size: usize,
     frames_used: AtomicUsize,
-    chunks: &'static [FrameBitmapChunk]
+    chunks: &'static [FrameBitmapChunk],
+    counters: AllocatorCounters,
 }
 
-impl MemoryRegion {
+pub type LargeFrameRegion = MemoryRegion<LARGE_FRAME_SIZE>;
+
+impl<const FRAME: usize> MemoryRegion<FRAME> {
     const MIN_FRAMES_REQUIRED: usize = 4;
+    const VALID_FRAME_SIZE: () = assert!(
+        FRAME.is_power_of_two() && FRAME % paging::PAGE_SIZE == 0,
+        "Frame size must be a power of two multiple of PAGE_SIZE"
+    );
+    /// Size of memory covered by a single bitmap chunk
+    const CHUNK_MEMORY_SIZE: usize = FrameBitmapChunk::BITS as usize * FRAME;
 
-    // TODO: refactor
-    /// `base` and `size` must be `FRAME_SIZE` aligned \
-    /// `size` must be greater than `FRAME_SIZE` \
-    /// Memory in range [`base`; `base + size`) must be valid and unused
+    /// `base` and `size` must be `FRAME` aligned \
+    /// `size` must be greater than `FRAME` \
+    /// Memory in range [`base`; `base + size`) must be valid and unused, the frame bitmap is stored at `base`
     pub unsafe fn new(base: PhysicalAddress, size: usize, identity_map_token: IdentityMapToken) -> Self {
-        assert_arg!(base, base % FRAME_SIZE == 0, "Must be FRAME_SIZE aligned.");
-        assert_arg!(size, size % FRAME_SIZE == 0, "Must be FRAME_SIZE aligned.");
-        assert_arg!(size, size > FRAME_SIZE, "Must be greater than FRAME_SIZE.");
+        let bitmap = bitmap_location(base, identity_map_token);
+        unsafe {
+            Self::with_bitmap(base, size, bitmap, true)
+        }
+    }
 
-        // bytes per chunk
-        const ALIGNMENT: usize = FRAME_SIZE * FrameBitmapChunk::BITS as usize;
+    /// Largest `FRAME` aligned part of [`base`; `base + size`), `None` if it can't hold a region
+    pub fn bounds(base: PhysicalAddress, size: usize) -> Option<(PhysicalAddress, usize)> {
+        let start = base.align_up(FRAME);
+        let end = (base + size).align_down(FRAME);
+        (end > start && end - start > FRAME).then(|| (start, end - start))
+    }
 
-        /// Returns size of a chunks array in bytes
-        /// size must be a multiple of ALIGNMENT
-        fn chunk_array_size(size: usize) -> usize {
-            (size / ALIGNMENT) * core::mem::size_of::<FrameBitmapChunk>()
-        }
+    /// Size in bytes of the frame bitmap of a `size` bytes long region
+    pub fn bitmap_size(size: usize) -> usize {
+        (size / FRAME).div_ceil(FrameBitmapChunk::BITS as usize) * core::mem::size_of::<FrameBitmapChunk>()
+    }
 
-        let region_end = (base + size).next_multiple_of(ALIGNMENT);
+    /// Creates a region with the frame bitmap stored at `bitmap` (e.g. a region not covered by the identity map) \
+    /// If `bitmap_in_region` is set, `bitmap` must point to `base` and the frames it occupies are marked as used \
+    /// Safety:
+    /// `bitmap` must be valid for writes of [MemoryRegion::bitmap_size] bytes, aligned and unused for `'static`,
+    /// other requirements are the same as in [MemoryRegion::new]
+    pub unsafe fn with_bitmap(base: PhysicalAddress, size: usize, bitmap: *mut FrameBitmapChunk, bitmap_in_region: bool) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::VALID_FRAME_SIZE;
+        assert_arg!(base, base % FRAME == 0, "Must be FRAME aligned.");
+        assert_arg!(size, size % FRAME == 0, "Must be FRAME aligned.");
+        assert_arg!(size, size > FRAME, "Must be greater than FRAME.");
+        assert_arg!(bitmap, bitmap.is_aligned());
 
-        let chunks_size = chunk_array_size(size);
-        // Frames required to store the chunk array
-        let chunks_size_frames = chunks_size.div_ceil(FRAME_SIZE);
-        assert!(chunks_size < size);
+        let frame_total = size / FRAME;
+        let chunk_count = frame_total.div_ceil(FrameBitmapChunk::BITS as usize);
+        // Frames used to store the bitmap
+        let bitmap_frames = if bitmap_in_region {
+            Self::bitmap_size(size).div_ceil(FRAME)
+        } else {
+            0
+        };
+        assert!(bitmap_frames < frame_total);
 
-        // Reserved frames - frames between ((base + end) | region_end)
-        let end_reserved_frames = (region_end - (base + size)) / FRAME_SIZE;
+        // Bits of the last chunk past the end of the region
+        let end_reserved_frames = chunk_count * FrameBitmapChunk::BITS as usize - frame_total;
         assert!(end_reserved_frames < FrameBitmapChunk::BITS as usize);
-        
-        let chunk_array_ptr = paging::to_virtual(base, identity_map_token).as_mut_ptr().cast::<FrameBitmapChunk>();
-        let mut start_reserved_frames_left = chunks_size_frames;
-        for i in 0..chunks_size {
-            unsafe {
-                // Reserved frames in the current chunk
-                let chunk = FrameBitmapChunk::new(start_reserved_frames_left);
-                start_reserved_frames_left = start_reserved_frames_left.saturating_sub(FrameBitmapChunk::BITS as usize);
 
-                core::ptr::write_volatile(chunk_array_ptr.add(i), chunk);
+        let mut start_reserved_frames_left = bitmap_frames;
+        for i in 0..chunk_count {
+            // Reserved frames in the current chunk
+            let reserved = start_reserved_frames_left.min(FrameBitmapChunk::BITS as usize);
+            start_reserved_frames_left -= reserved;
+            let chunk = FrameBitmapChunk::new(low_bits(reserved));
+            unsafe {
+                core::ptr::write_volatile(bitmap.add(i), chunk);
             }
         }
         unsafe {
-            let last_chunk = (*chunk_array_ptr.add(chunks_size - 1)).0.get_mut();
+            let last_chunk = (*bitmap.add(chunk_count - 1)).0.get_mut();
             // Set `end_reserved_frames` most significant bits to 1
-            let end_reserved_bits = !((1_usize << (usize::BITS as usize - end_reserved_frames)).wrapping_sub(1));
-            // `chunks_size_frames` and `end_reserved_frames` shouldn't overlap
+            let end_reserved_bits = !low_bits(FrameBitmapChunk::BITS as usize - end_reserved_frames);
+            // `bitmap_frames` and `end_reserved_frames` shouldn't overlap
             assert_eq!(*last_chunk & end_reserved_bits, 0);
             *last_chunk |= end_reserved_bits;
         }
 
-        assert!(chunk_array_ptr.is_aligned());
         Self {
             base,
-            frames_used: AtomicUsize::new(0),
-            chunks: unsafe { slice::from_raw_parts(chunk_array_ptr, chunks_size) }
+            size,
+            // Padding past the end counts as used, see `frame_count`
+            frames_used: AtomicUsize::new(bitmap_frames + end_reserved_frames),
+            chunks: unsafe { slice::from_raw_parts(bitmap, chunk_count) },
+            counters: AllocatorCounters::default(),
         }
     }
 
+    /// Frames covered by the bitmap, including the padding past the end of the region
     fn frame_count(&self) -> usize {
         self.chunks.len() * (FrameBitmapChunk::BITS as usize)
     }
 
+    /// Bits of the last chunk past the end of the region, always marked as used
+    fn padding_frames(&self) -> usize {
+        self.frame_count() - self.size / FRAME
+    }
+
+    // `frames_used` is advisory, the bitmap (SeqCst bts / CAS) is the only source of truth and no other
+    // data is published through the counter, so `Relaxed` is sufficient:
+    // - allocations increment after setting the bits and frees decrement after clearing them,
+    //   the counter itself never underflows (a frame can't be freed before its allocation returns)
+    // - between a free clearing its bits and decrementing the counter, other CPUs may allocate the
+    //   same frames, so the counter may briefly exceed `frame_count` and the subtraction saturates
+    // - a stale value may skip a region (`MIN_FRAMES_REQUIRED`) only while a free is in flight,
+    //   RMW operations on the counter are totally ordered, so the skip can't persist once the free completes
     fn frames_available(&self) -> usize {
-        self.frame_count() - self.frames_used.load(Ordering::Relaxed)
+        self.frame_count().saturating_sub(self.frames_used.load(Ordering::Relaxed))
     }
 
     /// Length in bytes
     fn len(&self) -> usize {
-         self.frame_count() * FRAME_SIZE
+        self.size
     }
 
     fn end(&self) -> PhysicalAddress {
@@ -178,30 +507,42 @@ impl MemoryRegion {
     }
 
     pub fn allocate(&self, frame_count: usize) -> Option<PhysicalAddress> {
+        self.allocate_from(0, frame_count)
+    }
+
+    /// Scans the chunks starting at `start_chunk`, wrapping around
+    pub fn allocate_from(&self, start_chunk: usize, frame_count: usize) -> Option<PhysicalAddress> {
         if frame_count > usize::BITS as usize {
             // Current implementation can't handle allocations crossing bitmap chunks
             return None;
         }
         let frame_count = frame_count as u8;
+        self.counters.allocation_attempts.fetch_add(1, Ordering::Relaxed);
         if self.frames_available() < Self::MIN_FRAMES_REQUIRED {
             // Not enough frames available - contention too high for this region
+            self.counters.contention_skips.fetch_add(1, Ordering::Relaxed);
             return None;
         }
 
+        let chunk_count = self.chunks.len();
+        let chunks = (0..chunk_count)
+            .map(|i| (start_chunk + i) % chunk_count)
+            .map(|chunk_ix| (chunk_ix, &self.chunks[chunk_ix]));
+
         if frame_count == 1 {
-            for (chunk_ix, chunk) in self.chunks.iter().enumerate() {
+            for (chunk_ix, chunk) in chunks {
                 if let Some(offset) = chunk.allocate_single() {
-                    let address = (chunk_ix * FrameBitmapChunk::MEMORY_SIZE) + (offset as usize * FRAME_SIZE);
-                    self.frames_used.fetch_add(1, Ordering::Relaxed); // TODO: is relaxed enough?
-                    return Some(PhysicalAddress::new(address));
+                    let address = self.base + (chunk_ix * Self::CHUNK_MEMORY_SIZE) + (offset as usize * FRAME);
+                    self.frames_used.fetch_add(1, Ordering::Relaxed);
+                    return Some(address);
                 }
             }
         } else {
-            for (chunk_ix, chunk) in self.chunks.iter().enumerate() {
-                if let Some(offset) = chunk.allocate_many(frame_count) {
-                    let address = (chunk_ix * FrameBitmapChunk::MEMORY_SIZE) + (offset as usize * FRAME_SIZE);
-                    self.frames_used.fetch_add(frame_count as usize, Ordering::Relaxed); // TODO: is relaxed enough?
-                    return Some(PhysicalAddress::new(address));
+            for (chunk_ix, chunk) in chunks {
+                if let Some(offset) = chunk.allocate_many(frame_count, &self.counters.cas_retries) {
+                    let address = self.base + (chunk_ix * Self::CHUNK_MEMORY_SIZE) + (offset as usize * FRAME);
+                    self.frames_used.fetch_add(frame_count as usize, Ordering::Relaxed);
+                    return Some(address);
                 }
             }
         }
@@ -213,52 +554,79 @@ impl MemoryRegion {
 
         debug_assert_arg!(frame_count, frame_count <= usize::BITS as usize);
 
-        let chunk_ix = Self::chunk_index(self.base, base);
-        let offset = (Into::<usize>::into(base) / FRAME_SIZE) % FrameBitmapChunk::BITS as usize;
-        self.chunks[chunk_ix].free(offset as u8, frame_count as u8);
-        self.frames_used.fetch_sub(frame_count, Ordering::Relaxed); // TODO: is relaxed enough?
+        let chunk_ix = self.chunk_index(base);
+        let offset = ((base - self.base) / FRAME) % FrameBitmapChunk::BITS as usize;
+        let freed = self.chunks[chunk_ix].free(offset as u8, frame_count as u8);
+        debug_assert!(freed.count_ones() as usize == frame_count, "Double free detected");
+        // Only frames that were actually allocated are subtracted
+        self.frames_used.fetch_sub(freed.count_ones() as usize, Ordering::Relaxed);
     }
 
     pub fn check_if_owned(&self, address: PhysicalAddress) -> bool {
         address >= self.base && address < self.end()
     }
 
-    fn chunk_index(region_base: PhysicalAddress, address: PhysicalAddress) -> usize {
-        (address - region_base) / ((FrameBitmapChunk::BITS as usize) * FRAME_SIZE)
+    /// Index of the chunk covering `address`, which must be owned by the region
+    fn chunk_index(&self, address: PhysicalAddress) -> usize {
+        (address - self.base) / Self::CHUNK_MEMORY_SIZE
     }
 }
 
+/// Virtual address of a frame bitmap stored at the start of a region
+#[cfg(not(test))]
+fn bitmap_location(base: PhysicalAddress, identity_map_token: IdentityMapToken) -> *mut FrameBitmapChunk {
+    paging::to_virtual(base, identity_map_token).as_mut_ptr().cast()
+}
+
+/// Test regions are backed by host memory, their physical addresses are host pointers
+#[cfg(test)]
+fn bitmap_location(base: PhysicalAddress, _identity_map_token: IdentityMapToken) -> *mut FrameBitmapChunk {
+    base.as_usize() as *mut FrameBitmapChunk
+}
+
+/// Region frames covering `frame_count` frames of `FRAME_SIZE`
+const fn region_frames(frame_count: usize) -> usize {
+    frame_count.div_ceil(REGION_FRAME_SIZE / FRAME_SIZE)
+}
+
+/// Mask with `count` lowest bits set, `count` may be equal to `usize::BITS`
+fn low_bits(count: usize) -> usize {
+    1_usize.checked_shl(count as u32).unwrap_or(0).wrapping_sub(1)
+}
+
 #[repr(transparent)]
 #[derive(Debug)]
-struct FrameBitmapChunk(AtomicUsize);
+pub struct FrameBitmapChunk(AtomicUsize);
 
 impl FrameBitmapChunk {
     pub const BITS: u32 = usize::BITS;
 
-    /// Size of memory covered by this chunk
-    pub const MEMORY_SIZE: usize = Self::BITS as usize * FRAME_SIZE;
-
     pub fn new(initial_value: usize) -> Self {
         FrameBitmapChunk(AtomicUsize::new(initial_value))
     }
 
+    /// Sets the lowest clear bit, the bitmap is scanned with `trailing_zeros` and only that bit is set atomically
     pub fn allocate_single(&self) -> Option<u8> {
-        if self.0.load(Ordering::SeqCst) != usize::MAX {
-            for bit in 0..(usize::BITS as usize) {
-                if unsafe { !atomic_bit_test_set(self.0.as_ptr(), bit) } {
-                    return Some(bit as u8);
-                }
+        let mut bits = self.0.load(Ordering::SeqCst);
+        while bits != usize::MAX {
+            let bit = first_clear_bit(bits);
+            if unsafe { !atomic_bit_test_set(self.0.as_ptr(), bit as usize) } {
+                return Some(bit as u8);
             }
+            // Taken by another CPU in the meantime, rescan
+            bits = self.0.load(Ordering::SeqCst);
         }
 
         None
     }
 
-    pub fn allocate_many(&self, count: u8) -> Option<u8> {
+    /// Failed compare-exchanges are counted in `retries`
+    pub fn allocate_many(&self, count: u8, retries: &AtomicUsize) -> Option<u8> {
         debug_assert_arg!(count, count < usize::BITS as u8);
 
         let mut previous = self.0.load(Ordering::SeqCst);
         let mask = (1_usize << count).wrapping_sub(1);
+        let mut backoff = Backoff::new();
 
         // All possible bit patterns (e.g. 0011, 0110, 1100...)
         for shift in 0..(usize::BITS as u8 - count) {
@@ -268,6 +636,8 @@ impl FrameBitmapChunk {
                     match self.0.compare_exchange(previous, previous | shifted_mask, Ordering::SeqCst, Ordering::SeqCst) {
                         Ok(_) => return Some(shift),
                         Err(value) => {
+                            retries.fetch_add(1, Ordering::Relaxed);
+                            backoff.spin();
                             previous = value;
                         }
                     }
@@ -280,12 +650,23 @@ impl FrameBitmapChunk {
         None
     }
 
-    pub fn free(&self, offset: u8, count: u8) {
+    /// Clears only the bits that are currently set, returns the previously set bits of the freed range \
+    /// The free was valid if all `count` bits were set, a double free doesn't modify any other bits
+    pub fn free(&self, offset: u8, count: u8) -> usize {
         assert!(count <= usize::BITS as u8);
-        let mask: usize = (1_usize << count).wrapping_sub(1) << offset;
+        let mask: usize = low_bits(count as usize) << offset;
 
-        let old = self.0.fetch_xor(mask, Ordering::SeqCst);
-        debug_assert!(old & mask == mask, "Double free detected");
+        let mut backoff = Backoff::new();
+        let mut old = self.0.load(Ordering::SeqCst);
+        loop {
+            match self.0.compare_exchange_weak(old, old & !mask, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return old & mask,
+                Err(value) => {
+                    backoff.spin();
+                    old = value;
+                }
+            }
+        }
     }
 }
 
@@ -294,3 +675,390 @@ impl Clone for FrameBitmapChunk {
         Self(self.0.load(Ordering::Acquire).into())
     }
 }
+
+/// Index of the lowest clear bit, `usize::BITS` if all bits are set
+const fn first_clear_bit(bits: usize) -> u32 {
+    (!bits).trailing_zeros()
+}
+
+const_assert_eq!(first_clear_bit(0), 0);
+const_assert_eq!(first_clear_bit(0b0111), 3);
+const_assert_eq!(first_clear_bit(0b1011), 2);
+const_assert_eq!(first_clear_bit(usize::MAX >> 1), usize::BITS - 1);
+const_assert_eq!(first_clear_bit(usize::MAX), usize::BITS);
+
+#[cfg(test)]
+impl FrameAllocator {
+    /// Builds an allocator over leaked, zeroed host memory, one region per entry of `region_sizes` (in bytes)
+    pub(crate) fn with_host_regions(region_sizes: &[usize]) -> std::boxed::Box<Self> {
+        let mut allocator = std::boxed::Box::new(Self::empty());
+        for &size in region_sizes {
+            let base = host_memory(size);
+            allocator.regions.push(unsafe { Region::new(base, size, IdentityMapToken::new()) });
+        }
+        allocator.regions.sort_unstable_by_key(|region| region.base);
+        allocator
+    }
+}
+
+/// Leaks `size` bytes of zeroed host memory, `LARGE_FRAME_SIZE` aligned so that it may back regions of either granularity
+#[cfg(test)]
+fn host_memory(size: usize) -> PhysicalAddress {
+    let layout = std::alloc::Layout::from_size_align(size, LARGE_FRAME_SIZE).unwrap();
+    // SAFETY: `layout` has a non-zero size, the memory is never freed
+    let memory = unsafe { std::alloc::alloc_zeroed(layout) };
+    assert!(!memory.is_null());
+    PhysicalAddress::new(memory as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, vec::Vec};
+
+    use crate::arch::boot::MemoryMapEntry;
+
+    use super::*;
+
+    #[test]
+    fn alloc_until_full_then_free_all() {
+        let sizes = [100 * FRAME_SIZE, 200 * FRAME_SIZE, 64 * FRAME_SIZE];
+        let allocator = FrameAllocator::with_host_regions(&sizes);
+        let initial_used: Vec<_> = allocator.regions().map(|region| region.frames_used).collect();
+
+        for _ in 0..2 {
+            let mut allocated = BTreeSet::new();
+            while let Some(address) = allocator.allocate(1) {
+                assert_eq!(address.as_usize() % FRAME_SIZE, 0);
+                let region = allocator.regions.iter().find(|region| region.check_if_owned(address)).unwrap();
+                // The first frame of every region holds its bitmap
+                assert_ne!(address, region.base);
+                assert!(allocated.insert(address), "Frame {address} allocated twice");
+            }
+
+            // Every region keeps `MIN_FRAMES_REQUIRED - 1` frames in reserve
+            let reserve = MemoryRegion::<FRAME_SIZE>::MIN_FRAMES_REQUIRED - 1;
+            let expected: usize = sizes.iter().map(|size| size / FRAME_SIZE - 1 - reserve).sum();
+            assert_eq!(allocated.len(), expected);
+
+            for &address in &allocated {
+                allocator.free(address, 1);
+            }
+            let used: Vec<_> = allocator.regions().map(|region| region.frames_used).collect();
+            assert_eq!(used, initial_used);
+        }
+    }
+
+    #[test]
+    fn counters_track_attempts_skips_and_retries() {
+        let allocator = FrameAllocator::with_host_regions(&[32 * FRAME_SIZE]);
+        let mut frames = Vec::new();
+        while let Some(address) = allocator.allocate(1) {
+            frames.push(address);
+        }
+        let stats = allocator.stats();
+        // Every successful allocation plus the one that found the region exhausted
+        assert_eq!(stats.allocation_attempts, frames.len() + 1);
+        assert_eq!(stats.contention_skips, 1);
+        assert_eq!(stats.cas_retries, 0);
+
+        // Forced contention: the reserve is below `MIN_FRAMES_REQUIRED` until enough frames are freed
+        assert!(allocator.allocate(1).is_none());
+        // Tries the hint region, then falls back to `allocate`
+        assert!(allocator.allocate_near(frames[0], 1).is_none());
+        assert_eq!(allocator.stats().contention_skips, 4);
+        for address in frames.drain(..) {
+            allocator.free(address, 1);
+        }
+
+        // Multi-frame allocations racing on the same bitmap chunk
+        let started = std::time::Instant::now();
+        while allocator.stats().cas_retries == 0 {
+            assert!(started.elapsed().as_secs() < 10, "No CAS retries observed");
+            std::thread::scope(|scope| {
+                for _ in 0..4 {
+                    scope.spawn(|| {
+                        for _ in 0..10_000 {
+                            if let Some(address) = allocator.allocate(2) {
+                                allocator.free(address, 2);
+                            }
+                        }
+                    });
+                }
+            });
+        }
+        let stats = allocator.stats();
+        assert!(stats.allocation_attempts > 40_000);
+        assert!(stats.cas_retries > 0);
+    }
+
+    #[test]
+    fn oom_handler_fires_only_for_critical_allocations() {
+        static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+        fn handler(allocator: &FrameAllocator, frame_count: usize) -> ! {
+            HANDLED.store(frame_count, Ordering::SeqCst);
+            // The drained allocator's stats are available at the failure point
+            std::panic::panic_any(allocator.stats().contention_skips)
+        }
+
+        // The only test replacing the handler
+        set_oom_handler(handler);
+        let allocator = FrameAllocator::with_host_regions(&[16 * FRAME_SIZE]);
+        let frame = allocator.allocate_critical(1);
+        assert_eq!(HANDLED.load(Ordering::SeqCst), 0);
+        while allocator.allocate(1).is_some() {}
+
+        // Non-critical callers still get `None`
+        assert!(allocator.allocate(2).is_none());
+        assert_eq!(HANDLED.load(Ordering::SeqCst), 0);
+
+        let payload = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| allocator.allocate_critical(3))).unwrap_err();
+        assert_eq!(HANDLED.load(Ordering::SeqCst), 3);
+        assert_eq!(payload.downcast_ref::<usize>(), Some(&3));
+
+        set_oom_handler(default_oom_handler);
+        allocator.free(frame, 1);
+    }
+
+    #[test]
+    fn added_region_is_used_after_boot_regions() {
+        let allocator = FrameAllocator::with_host_regions(&[32 * FRAME_SIZE]);
+        let mut boot_frames = Vec::new();
+        while let Some(address) = allocator.allocate(1) {
+            boot_frames.push(address);
+        }
+
+        let size = 16 * FRAME_SIZE;
+        let base = host_memory(size);
+        unsafe {
+            allocator.add_region(base, size, IdentityMapToken::new()).unwrap();
+        }
+        let added = allocator.regions().last().unwrap();
+        assert_eq!((added.base, added.frame_count), (base, 16));
+
+        let address = allocator.allocate(1).expect("Added region unused");
+        assert!(address > base && address < base + size, "{address} outside the added region");
+        let used = allocator.regions().last().unwrap().frames_used;
+        allocator.free(address, 1);
+        assert_eq!(allocator.regions().last().unwrap().frames_used, used - 1);
+        // The freed frame is handed out again, the boot region is still full
+        assert_eq!(allocator.allocate(1), Some(address));
+
+        for address in boot_frames {
+            allocator.free(address, 1);
+        }
+        allocator.free(address, 1);
+    }
+
+    #[test]
+    fn concurrent_alloc_free_stress() {
+        const THREADS: usize = 8;
+        const ROUNDS: usize = 2000;
+
+        let allocator = FrameAllocator::with_host_regions(&[128 * FRAME_SIZE, 64 * FRAME_SIZE]);
+        let initial_used: Vec<_> = allocator.regions().map(|region| region.frames_used).collect();
+        let owners = std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+        std::thread::scope(|scope| {
+            for thread in 0..THREADS {
+                let (allocator, owners) = (&allocator, &owners);
+                scope.spawn(move || {
+                    let mut held = Vec::new();
+                    for round in 0..ROUNDS {
+                        let frame_count = 1 + (thread + round) % 3;
+                        if let Some(address) = allocator.allocate(frame_count) {
+                            for i in 0..frame_count {
+                                let previous = owners.lock().unwrap().insert(address + i * FRAME_SIZE, thread);
+                                assert!(previous.is_none(), "Frame allocated twice");
+                            }
+                            held.push((address, frame_count));
+                        }
+                        // Keep a few allocations alive to contend on partially used chunks
+                        if held.len() > 4 || round % 7 == 0 {
+                            if let Some((address, frame_count)) = held.pop() {
+                                for i in 0..frame_count {
+                                    owners.lock().unwrap().remove(&(address + i * FRAME_SIZE));
+                                }
+                                allocator.free(address, frame_count);
+                            }
+                        }
+                    }
+                    for (address, frame_count) in held {
+                        for i in 0..frame_count {
+                            owners.lock().unwrap().remove(&(address + i * FRAME_SIZE));
+                        }
+                        allocator.free(address, frame_count);
+                    }
+                });
+            }
+        });
+
+        let used: Vec<_> = allocator.regions().map(|region| region.frames_used).collect();
+        assert_eq!(used, initial_used);
+        for (index, region) in allocator.regions.iter().enumerate() {
+            // Only the bitmap frame and the padding are left
+            let set_bits = allocator.region_bitmap(index).filter(|&used| used).count();
+            assert_eq!(set_bits, 1);
+            assert_eq!(region.frames_available(), region.frame_count() - region.padding_frames() - 1);
+        }
+    }
+
+    #[test]
+    fn fill_coalesces_adjacent_usable_entries() {
+        let memory = host_memory(32 * FRAME_SIZE);
+        let entry = |frame: usize, len: usize, kind| MemoryMapEntry::new(memory + frame * FRAME_SIZE, len * FRAME_SIZE, kind);
+        let entries = std::vec![
+            entry(0, 4, MemoryMapEntryKind::Usable),
+            entry(4, 8, MemoryMapEntryKind::Usable),
+            entry(12, 4, MemoryMapEntryKind::Usable),
+            entry(16, 2, MemoryMapEntryKind::Reserved),
+            // Adjacent, but separated by the reserved entry
+            entry(18, 6, MemoryMapEntryKind::Usable),
+            entry(24, 8, MemoryMapEntryKind::Reclaimable),
+        ];
+        let memory_map = boot::MemoryMap { entries: entries.leak() };
+        assert_eq!(memory_map.coalesced().count(), 4);
+
+        let mut allocator = std::boxed::Box::new(FrameAllocator::empty());
+        unsafe {
+            allocator.fill(memory_map, IdentityMapToken::new());
+        }
+        let regions: Vec<_> = allocator.regions().map(|region| (region.base, region.frame_count)).collect();
+        assert_eq!(regions, [(memory, 16), (memory + 18 * FRAME_SIZE, 6)]);
+
+        // A multi-frame allocation spanning the original entry boundaries fits in the merged region
+        let region = &allocator.regions[0];
+        let address = region.allocate(12).unwrap();
+        assert!(region.check_if_owned(address) && region.check_if_owned(address + 11 * FRAME_SIZE));
+    }
+
+    #[test]
+    fn region_bitmap_follows_allocations() {
+        let allocator = FrameAllocator::with_host_regions(&[100 * FRAME_SIZE]);
+        let base = allocator.regions[0].base;
+        let frame = |address: PhysicalAddress| (address - base) / FRAME_SIZE;
+        let used = |allocator: &FrameAllocator| -> Vec<usize> {
+            allocator.region_bitmap(0).enumerate().filter(|&(_, used)| used).map(|(frame, _)| frame).collect()
+        };
+        // Padding bits past the end of the region aren't reported
+        assert_eq!(allocator.region_bitmap(0).count(), 100);
+        assert_eq!(used(&allocator), [0]);
+
+        let single = allocator.allocate(1).unwrap();
+        let triple = allocator.allocate(3).unwrap();
+        assert_eq!((frame(single), frame(triple)), (1, 2));
+        assert_eq!(used(&allocator), [0, 1, 2, 3, 4]);
+
+        allocator.free(single, 1);
+        allocator.free(triple + FRAME_SIZE, 1);
+        assert_eq!(used(&allocator), [0, 2, 4]);
+        // Lowest free frame first
+        assert_eq!(frame(allocator.allocate(1).unwrap()), 1);
+        assert_eq!(used(&allocator), [0, 1, 2, 4]);
+
+        // Added regions follow the boot regions
+        let size = 8 * FRAME_SIZE;
+        unsafe {
+            allocator.add_region(host_memory(size), size, IdentityMapToken::new()).unwrap();
+        }
+        assert_eq!(allocator.region_bitmap(1).collect::<Vec<_>>(), [true, false, false, false, false, false, false, false]);
+    }
+
+    #[test]
+    fn region_setup_at_both_granularities() {
+        fn check<const FRAME: usize>() {
+            // Two chunks, the last one is partially padding
+            let size = 100 * FRAME;
+            let base = host_memory(size);
+            assert_eq!(MemoryRegion::<FRAME>::bitmap_size(size), 2 * core::mem::size_of::<FrameBitmapChunk>());
+            let region = unsafe { MemoryRegion::<FRAME>::new(base, size, IdentityMapToken::new()) };
+            assert_eq!((region.chunks.len(), region.padding_frames()), (2, 28));
+            // The bitmap fits in the first frame
+            assert_eq!(region.chunks[0].0.load(Ordering::SeqCst), 1);
+            assert_eq!(region.chunks[1].0.load(Ordering::SeqCst), !low_bits(36));
+            assert_eq!(region.frames_available(), 99);
+
+            assert_eq!(region.allocate(1), Some(base + FRAME));
+            assert_eq!(region.allocate(3), Some(base + 2 * FRAME));
+            assert_eq!(region.chunk_index(base + 64 * FRAME), 1);
+            assert_eq!(region.allocate_from(1, 1), Some(base + 64 * FRAME));
+            region.free(base + FRAME, 1);
+            region.free(base + 2 * FRAME, 3);
+            region.free(base + 64 * FRAME, 1);
+            assert_eq!(region.frames_available(), 99);
+        }
+        check::<FRAME_SIZE>();
+        check::<LARGE_FRAME_SIZE>();
+
+        let address = PhysicalAddress::new;
+        assert_eq!(MemoryRegion::<FRAME_SIZE>::bounds(address(0x20_1000), 0x40_0000), Some((address(0x20_1000), 0x40_0000)));
+        assert_eq!(MemoryRegion::<FRAME_SIZE>::bounds(address(0x1000), FRAME_SIZE), None);
+        // Shrunk to [0x40_0000; 0x60_0000), a single large frame
+        assert_eq!(LargeFrameRegion::bounds(address(0x20_1000), 0x40_0000), None);
+        assert_eq!(LargeFrameRegion::bounds(address(0x20_1000), 0x60_0000), Some((address(0x40_0000), 0x40_0000)));
+    }
+
+    #[test]
+    fn chunk_double_free_is_detected_without_flipping_other_bits() {
+        let chunk = FrameBitmapChunk::new(0b1111_0101);
+        let bits = |chunk: &FrameBitmapChunk| chunk.0.load(Ordering::SeqCst);
+
+        assert_eq!(chunk.free(4, 2), 0b11_0000);
+        assert_eq!(bits(&chunk), 0b1100_0101);
+        // Nothing left to clear, an XOR would have set the bits again
+        assert_eq!(chunk.free(4, 2), 0);
+        assert_eq!(bits(&chunk), 0b1100_0101);
+
+        // Partially free range, only the set bits are cleared
+        assert_eq!(chunk.free(1, 7), 0b1100_0100);
+        assert_eq!(bits(&chunk), 0b0000_0001);
+
+        let full = FrameBitmapChunk::new(usize::MAX);
+        assert_eq!(full.free(0, usize::BITS as u8), usize::MAX);
+        assert_eq!(full.free(0, usize::BITS as u8), 0);
+        assert_eq!(bits(&full), 0);
+    }
+
+    #[test]
+    fn fill_keeps_the_largest_regions() {
+        // Separated by gaps so that the entries aren't coalesced, the last entry is the largest
+        let count = MAX_MEMORY_REGION_COUNT + 1;
+        let memory = host_memory(count * 4 * FRAME_SIZE);
+        let entries: Vec<_> = (0..count).map(|i| {
+            let len = if i == count - 1 { 3 } else { 2 } * FRAME_SIZE;
+            MemoryMapEntry::new(memory + i * 4 * FRAME_SIZE, len, MemoryMapEntryKind::Usable)
+        }).collect();
+        let memory_map = boot::MemoryMap { entries: entries.leak() };
+
+        let mut allocator = std::boxed::Box::new(FrameAllocator::empty());
+        unsafe {
+            allocator.fill(memory_map, IdentityMapToken::new());
+        }
+        assert_eq!(allocator.regions.len(), MAX_MEMORY_REGION_COUNT);
+        assert!(allocator.regions.is_sorted_by_key(|region| region.base));
+        let last = memory_map.entries[count - 1];
+        assert!(allocator.regions.iter().any(|region| region.base == last.base && region.len() == last.len));
+    }
+
+    #[test]
+    fn multi_frame_allocations_are_contiguous() {
+        let allocator = FrameAllocator::with_host_regions(&[256 * FRAME_SIZE]);
+        let mut allocations = Vec::new();
+        while let Some(address) = allocator.allocate(7) {
+            allocations.push(address);
+        }
+        assert!(!allocations.is_empty());
+
+        let mut frames = BTreeSet::new();
+        for &address in &allocations {
+            for i in 0..7 {
+                assert!(frames.insert(address + i * FRAME_SIZE));
+            }
+        }
+        for address in allocations {
+            allocator.free(address, 7);
+        }
+        // Only the bitmap frame is left
+        assert_eq!(allocator.regions().next().unwrap().frames_used, 1);
+    }
+}
@@ -0,0 +1,162 @@
+use core::{marker::PhantomData, mem::{align_of, size_of}, ptr::NonNull};
+
+use spin::Mutex;
+
+use crate::arch::{paging::IdentityMapToken, PhysicalAddress};
+
+use super::physical::{FrameAllocator, FRAME_SIZE};
+
+/// Allocator of `T`-sized objects carved from whole frames, grows by one frame when exhausted \
+/// Frames are never returned to the frame allocator
+pub struct Slab<'a, T> {
+    free_list: Mutex<Option<NonNull<FreeSlot>>>,
+    frame_allocator: &'a FrameAllocator,
+    identity_map: IdentityMapToken,
+    _phantom: PhantomData<T>,
+}
+
+/// Free slots store the next free slot in place
+struct FreeSlot {
+    next: Option<NonNull<FreeSlot>>,
+}
+
+// SAFETY: the free list is only accessed under the mutex, allocated objects are exclusively owned
+unsafe impl<T: Send> Send for Slab<'_, T> {}
+unsafe impl<T: Send> Sync for Slab<'_, T> {}
+
+impl<'a, T> Slab<'a, T> {
+    const SLOT_ALIGN: usize = if align_of::<T>() > align_of::<FreeSlot>() { align_of::<T>() } else { align_of::<FreeSlot>() };
+    const SLOT_SIZE: usize = {
+        let size = if size_of::<T>() > size_of::<FreeSlot>() { size_of::<T>() } else { size_of::<FreeSlot>() };
+        size.next_multiple_of(Self::SLOT_ALIGN)
+    };
+    pub const OBJECTS_PER_FRAME: usize = {
+        assert!(Self::SLOT_SIZE <= FRAME_SIZE && Self::SLOT_ALIGN <= FRAME_SIZE, "Slab object larger than a frame");
+        FRAME_SIZE / Self::SLOT_SIZE
+    };
+
+    /// Frames are taken from `frame_allocator`, e.g. [global_allocator](super::physical::global_allocator)
+    pub const fn new(frame_allocator: &'a FrameAllocator, identity_map: IdentityMapToken) -> Self {
+        Self {
+            free_list: Mutex::new(None),
+            frame_allocator,
+            identity_map,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns `None` if the slab is empty and no frame could be allocated
+    pub fn alloc(&self, value: T) -> Option<&'a mut T> {
+        let mut free_list = self.free_list.lock();
+        if free_list.is_none() {
+            *free_list = Some(self.grow()?);
+        }
+
+        let slot = free_list.take()?;
+        unsafe {
+            // SAFETY: slots on the free list are valid, unused and aligned for both `FreeSlot` and `T`
+            *free_list = slot.as_ref().next;
+            let object = slot.cast::<T>().as_ptr();
+            object.write(value);
+            Some(&mut *object)
+        }
+    }
+
+    /// Drops the object and returns its slot to the free list \
+    /// Safety:
+    /// `object` must have been allocated by this slab
+    pub unsafe fn free(&self, object: &'a mut T) {
+        let object = object as *mut T;
+        let mut free_list = self.free_list.lock();
+        unsafe {
+            object.drop_in_place();
+            let slot = object.cast::<FreeSlot>();
+            slot.write(FreeSlot { next: *free_list });
+            *free_list = Some(NonNull::new_unchecked(slot));
+        }
+    }
+
+    /// Allocates a frame and links its slots, returns the head of the new list
+    fn grow(&self) -> Option<NonNull<FreeSlot>> {
+        let frame = self.frame_allocator.allocate(1)?;
+        let base = frame_location(frame, self.identity_map);
+
+        let mut next = None;
+        for index in (0..Self::OBJECTS_PER_FRAME).rev() {
+            unsafe {
+                // SAFETY: the frame is unused and identity mapped, the slot is within the frame
+                let slot = base.add(index * Self::SLOT_SIZE).cast::<FreeSlot>();
+                slot.write(FreeSlot { next });
+                next = Some(NonNull::new_unchecked(slot));
+            }
+        }
+        next
+    }
+}
+
+/// Virtual address of an identity mapped frame
+#[cfg(not(test))]
+fn frame_location(frame: PhysicalAddress, identity_map: IdentityMapToken) -> *mut u8 {
+    crate::arch::paging::to_virtual(frame, identity_map).as_mut_ptr().cast()
+}
+
+/// Test allocators are backed by host memory, their physical addresses are host pointers
+#[cfg(test)]
+fn frame_location(frame: PhysicalAddress, _identity_map: IdentityMapToken) -> *mut u8 {
+    frame.as_usize() as *mut u8
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, collections::BTreeSet, vec::Vec};
+
+    use super::*;
+
+    /// Counts its drops, 800 bytes - 5 objects per frame
+    struct Object<'a> {
+        drops: &'a Cell<usize>,
+        _payload: [u64; 99],
+    }
+
+    impl Drop for Object<'_> {
+        fn drop(&mut self) {
+            self.drops.set(self.drops.get() + 1);
+        }
+    }
+
+    fn frames_used(allocator: &FrameAllocator) -> usize {
+        allocator.regions().map(|region| region.frames_used).sum()
+    }
+
+    #[test]
+    fn free_list_alloc_free_cycle() {
+        let allocator = FrameAllocator::with_host_regions(&[32 * FRAME_SIZE]);
+        let slab = Slab::new(&allocator, unsafe { IdentityMapToken::new() });
+        assert_eq!(Slab::<Object>::OBJECTS_PER_FRAME, 5);
+        let initial_frames = frames_used(&allocator);
+        let drops = Cell::new(0);
+        let object = || Object { drops: &drops, _payload: [0; 99] };
+
+        let mut objects: Vec<_> = (0..5).map(|_| slab.alloc(object()).unwrap()).collect();
+        assert_eq!(frames_used(&allocator), initial_frames + 1);
+        let addresses: BTreeSet<_> = objects.iter().map(|object| *object as *const Object as usize).collect();
+        assert_eq!(addresses.len(), 5);
+        let frame = addresses.first().unwrap() & !(FRAME_SIZE - 1);
+        assert!(addresses.iter().all(|address| address & !(FRAME_SIZE - 1) == frame));
+
+        // Grows by one frame once exhausted
+        objects.push(slab.alloc(object()).unwrap());
+        assert_eq!(frames_used(&allocator), initial_frames + 2);
+
+        // Freed slots are reused last in, first out, without touching the frame allocator
+        let freed = [objects.remove(1), objects.remove(3)].map(|object| {
+            let address = object as *mut Object as usize;
+            unsafe { slab.free(object) };
+            address
+        });
+        assert_eq!(drops.get(), 2);
+        let reused = [slab.alloc(object()).unwrap(), slab.alloc(object()).unwrap()];
+        assert_eq!(reused.map(|object| object as *mut Object as usize), [freed[1], freed[0]]);
+        assert_eq!(frames_used(&allocator), initial_frames + 2);
+    }
+}
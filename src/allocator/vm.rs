@@ -0,0 +1,183 @@
+// Virtual address space allocator
+// Hands out page aligned ranges of a fixed window, free ranges are kept in a sorted list (first fit),
+// adjacent free ranges are merged. Only address space is managed, mapping is up to the caller
+
+use spin::Mutex;
+
+use crate::{arch::{paging::PAGE_SIZE, AddressRange, VirtualAddress}, common::macros::assert_arg};
+
+#[derive(Debug)]
+pub struct VmAllocator<const MAX_FREE_RANGES: usize> {
+    window: AddressRange<VirtualAddress>,
+    free: Mutex<FreeList<MAX_FREE_RANGES>>,
+}
+
+impl<const MAX_FREE_RANGES: usize> VmAllocator<MAX_FREE_RANGES> {
+    /// `window` must be page aligned and not used by any other mapping
+    pub const fn new(window: AddressRange<VirtualAddress>) -> Self {
+        assert!(MAX_FREE_RANGES > 0);
+        assert!(window.start.is_page_aligned() && window.end.is_page_aligned());
+
+        let mut ranges = [window; MAX_FREE_RANGES];
+        ranges[0] = window;
+        Self { window, free: Mutex::new(FreeList { ranges, len: 1 }) }
+    }
+
+    pub const fn window(&self) -> AddressRange<VirtualAddress> {
+        self.window
+    }
+
+    /// Allocates `size` bytes (rounded up to pages) aligned to `alignment` (at least a page) \
+    /// Returns `None` if no free range is large enough
+    pub fn allocate(&self, size: usize, alignment: usize) -> Option<AddressRange<VirtualAddress>> {
+        assert_arg!(alignment, alignment.is_power_of_two());
+        let size = size.checked_next_multiple_of(PAGE_SIZE)?;
+        let alignment = alignment.max(PAGE_SIZE);
+        if size == 0 {
+            return None;
+        }
+
+        let mut free = self.free.lock();
+        for index in 0..free.len {
+            let range = free.ranges[index];
+            let start = range.start.align_up(alignment);
+            let Some(end) = usize::from(start).checked_add(size).map(VirtualAddress::new) else {
+                continue;
+            };
+            if start < range.start || end > range.end {
+                continue;
+            }
+
+            // Splitting the range in the middle needs an extra entry
+            let (head, tail) = (start > range.start, end < range.end);
+            match (head, tail) {
+                (false, false) => free.remove(index),
+                (true, false) => free.ranges[index].end = start,
+                (false, true) => free.ranges[index].start = end,
+                (true, true) => {
+                    if !free.insert(index + 1, AddressRange::new(end, range.end)) {
+                        continue;
+                    }
+                    free.ranges[index].end = start;
+                },
+            }
+            return Some(AddressRange::new(start, end));
+        }
+        None
+    }
+
+    /// Returns a range obtained from [VmAllocator::allocate] \
+    /// Returns `Err` if the free list is full, the range is leaked in that case
+    pub fn free(&self, range: AddressRange<VirtualAddress>) -> Result<(), ()> {
+        assert_arg!(range, self.window.start <= range.start && range.end <= self.window.end && range.start.is_page_aligned());
+        let range = AddressRange::new(range.start, range.end.page_align_up());
+        if range.is_empty() {
+            return Ok(());
+        }
+
+        let mut free = self.free.lock();
+        let index = free.ranges[..free.len].partition_point(|x| x.start < range.start);
+        debug_assert!(index == 0 || free.ranges[index - 1].end <= range.start, "Double free of {range:?}");
+        debug_assert!(index == free.len || range.end <= free.ranges[index].start, "Double free of {range:?}");
+
+        let merge_previous = index > 0 && free.ranges[index - 1].end == range.start;
+        let merge_next = index < free.len && free.ranges[index].start == range.end;
+        match (merge_previous, merge_next) {
+            (true, true) => {
+                free.ranges[index - 1].end = free.ranges[index].end;
+                free.remove(index);
+            },
+            (true, false) => free.ranges[index - 1].end = range.end,
+            (false, true) => free.ranges[index].start = range.start,
+            (false, false) => {
+                if !free.insert(index, range) {
+                    return Err(());
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Total free address space in bytes
+    pub fn free_size(&self) -> usize {
+        let free = self.free.lock();
+        free.ranges[..free.len].iter().map(|x| x.size()).sum()
+    }
+}
+
+/// Free ranges sorted by address, non-overlapping and non-adjacent
+#[derive(Debug)]
+struct FreeList<const N: usize> {
+    ranges: [AddressRange<VirtualAddress>; N],
+    len: usize,
+}
+
+impl<const N: usize> FreeList<N> {
+    fn remove(&mut self, index: usize) {
+        self.ranges.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+    }
+
+    /// Returns `false` if full
+    fn insert(&mut self, index: usize, range: AddressRange<VirtualAddress>) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.ranges.copy_within(index..self.len, index + 1);
+        self.ranges[index] = range;
+        self.len += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const WINDOW: AddressRange<VirtualAddress> =
+        AddressRange { start: VirtualAddress::new(0x10_0000), end: VirtualAddress::new(0x10_0000 + 64 * PAGE_SIZE) };
+
+    #[test]
+    fn allocated_ranges_do_not_overlap() {
+        let vm = VmAllocator::<8>::new(WINDOW);
+        let requests = [(1, 1), (3 * PAGE_SIZE, PAGE_SIZE), (PAGE_SIZE, 8 * PAGE_SIZE), (5000, 1), (4 * PAGE_SIZE, 16 * PAGE_SIZE)];
+        let ranges: Vec<_> = requests.iter().map(|&(size, alignment)| {
+            let range = vm.allocate(size, alignment).expect("Window exhausted");
+            assert_eq!(range.size(), size.next_multiple_of(PAGE_SIZE));
+            assert_eq!(usize::from(range.start) % alignment.max(PAGE_SIZE), 0);
+            assert!(WINDOW.start <= range.start && range.end <= WINDOW.end);
+            range
+        }).collect();
+        for (i, a) in ranges.iter().enumerate() {
+            for b in &ranges[i + 1..] {
+                assert!(a.end <= b.start || b.end <= a.start, "{a:?} overlaps {b:?}");
+            }
+        }
+        let used: usize = ranges.iter().map(|x| x.size()).sum();
+        assert_eq!(vm.free_size(), WINDOW.size() - used);
+        assert!(vm.allocate(WINDOW.size(), 1).is_none());
+
+        // Out of order frees merge back into the whole window
+        for &index in &[2, 0, 4, 1, 3] {
+            vm.free(ranges[index]).unwrap();
+        }
+        assert_eq!(vm.free_size(), WINDOW.size());
+        assert_eq!(vm.free.lock().len, 1);
+        assert_eq!(vm.allocate(WINDOW.size(), 1), Some(WINDOW));
+    }
+
+    #[test]
+    fn freed_ranges_are_reused_first_fit() {
+        let vm = VmAllocator::<2>::new(WINDOW);
+        let pages: Vec<_> = (0..4).map(|_| vm.allocate(PAGE_SIZE, 1).unwrap()).collect();
+        vm.free(pages[0]).unwrap();
+        assert_eq!(vm.allocate(1, 1), Some(pages[0]));
+        vm.free(pages[0]).unwrap();
+
+        // Neither adjacent to the first free range nor to the rest of the window, the list is full
+        assert!(vm.free(pages[2]).is_err());
+        assert_eq!(vm.free_size(), WINDOW.size() - 3 * PAGE_SIZE);
+    }
+}
@@ -1,16 +1,22 @@
-use core::slice;
+use core::{slice, time::Duration};
 
-use spin::RwLock;
-use static_assertions::const_assert;
+use spin::{Mutex, RwLock};
+use static_assertions::{const_assert, const_assert_eq};
 
-use crate::{arch::devices::framebuffer::{RawFramebuffer, Rgb, Pixel, Framebuffer}, common::{macros::{assert_arg, include_data_bytes}, mem::Aligned}};
+use crate::{
+    arch::devices::framebuffer::{RawFramebuffer, Rgb, Pixel, Framebuffer},
+    common::{macros::{assert_arg, include_data_bytes}, mem::Aligned, time::stopwatch::busy_wait}
+};
 
 const BACKGROUND: Rgb = Rgb::WHITE;
 // const FOREGROUND: Rgb = Rgb::from_argb32(0xa31f34);
-const LOGO_WIDTH: usize = 256;
-const LOGO_HEIGHT: usize = 256;
+pub(super) const LOGO_WIDTH: usize = 256;
+pub(super) const LOGO_HEIGHT: usize = 256;
 const LOGO_BYTE_SIZE: usize = LOGO_WIDTH * LOGO_HEIGHT * 4;
 static LOGO_RAW_BYTES: RwLock<Aligned<4, [u8; LOGO_BYTE_SIZE]>> = RwLock::new(Aligned::<4, [u8; LOGO_BYTE_SIZE]>::new(*include_data_bytes!("logo.raw")));
+/// Back buffer of the logo fade, frames are composed here and copied to the framebuffer at once
+static FADE_BUFFER: Mutex<[u32; LOGO_WIDTH * LOGO_HEIGHT]> = Mutex::new([0; LOGO_WIDTH * LOGO_HEIGHT]);
+const FADE_FRAME_TIME: Duration = Duration::from_millis(16);
 
 pub struct LogoScreen<'fb> {
     framebuffer: Framebuffer<'fb>
@@ -29,26 +35,106 @@ impl<'fb> LogoScreen<'fb> {
         screen
     }
 
+    /// Same as [LogoScreen::new], fades the logo in from the background over `frames` frames
+    pub fn new_animated(framebuffer: Framebuffer<'fb>, frames: u32) -> Self {
+        assert_arg!(framebuffer, framebuffer.info.width >= LOGO_WIDTH);
+        assert_arg!(framebuffer, framebuffer.info.height >= LOGO_HEIGHT);
+        assert_arg!(frames, frames > 0);
+
+        let screen = Self {
+            framebuffer
+        };
+        screen.fill_background();
+        let logo_rect = screen.logo_rect();
+        let pixels = logo_pixels();
+        let mut buffer = FADE_BUFFER.lock();
+        for frame in 0..frames {
+            let level = fade_level(frame, frames);
+            for (target, &value) in buffer.iter_mut().zip(pixels) {
+                *target = mix(alpha_blend(value, BACKGROUND), BACKGROUND, level).into_argb32();
+            }
+            logo_rect.copy(&*buffer);
+            if frame + 1 < frames {
+                busy_wait(FADE_FRAME_TIME);
+            }
+        }
+        screen
+    }
+
     fn show(&self) {
+        self.fill_background();
+        self.logo_rect().blit_with_bg(logo_pixels(), BACKGROUND);
+    }
+
+    fn fill_background(&self) {
         let (width, height) = (self.framebuffer.info.width, self.framebuffer.info.height);
         let screen_rect = Rect::new(&self.framebuffer, (0, 0).into(), width, height);
         screen_rect.fill(BACKGROUND);
+    }
 
+    /// Centered on the screen
+    fn logo_rect(&self) -> Rect<'_> {
+        let (width, height) = (self.framebuffer.info.width, self.framebuffer.info.height);
         let center: Pixel = (width / 2, height / 2).into();
-        let origin: Pixel = center - (LOGO_WIDTH / 2, LOGO_HEIGHT / 2);
-        let logo_rect = Rect::new(&self.framebuffer, origin, LOGO_WIDTH, LOGO_HEIGHT);
-        let pixels = unsafe {
-            // &[u8] -> &[u32]
-            let bytes = LOGO_RAW_BYTES.read();
-            assert!((bytes.value.as_ptr().cast::<u32>() as usize % 4) == 0);
-            slice::from_raw_parts(bytes.value.as_ptr().cast::<u32>(), bytes.value.len() / 4)
-        };
-        logo_rect.blit_with_bg(pixels, BACKGROUND);
+        let origin: Pixel = center.saturating_sub((LOGO_WIDTH / 2, LOGO_HEIGHT / 2));
+        Rect::new(&self.framebuffer, origin, LOGO_WIDTH, LOGO_HEIGHT)
+    }
+}
+
+fn logo_pixels() -> &'static [u32] {
+    unsafe {
+        // &[u8] -> &[u32]
+        let bytes = LOGO_RAW_BYTES.read();
+        assert!((bytes.value.as_ptr().cast::<u32>() as usize % 4) == 0);
+        // The logo is never written, so it outlives the guard
+        slice::from_raw_parts(bytes.value.as_ptr().cast::<u32>(), bytes.value.len() / 4)
     }
 }
 
+/// Logo weight of fade frame `frame` out of `frames`, the last frame is fully opaque
+const fn fade_level(frame: u32, frames: u32) -> u8 {
+    ((frame as u64 + 1) * 255 / frames as u64) as u8
+}
+
+/// `foreground` over `background` with `level` / 255 opacity
+const fn mix(foreground: Rgb, background: Rgb, level: u8) -> Rgb {
+    let foreground = foreground.scale_brightness(level);
+    let background = background.scale_brightness(255 - level);
+    Rgb {
+        r: foreground.r.saturating_add(background.r),
+        g: foreground.g.saturating_add(background.g),
+        b: foreground.b.saturating_add(background.b),
+    }
+}
+
+/// Blends an ARGB32 logo pixel over an opaque background
+fn alpha_blend(color_value: u32, background: Rgb) -> Rgb {
+    let Rgb { r, g, b } = color_value.into();
+    let Rgb { r: bg_r, g: bg_g, b: bg_b } = background;
+    // Normalized foreground alpha [0..1]
+    let alpha = (color_value >> 24) as f64 / 255_f64;
+    // Alpha blending
+    // Total alpha is always 1 (background alpha is always 1)
+    // C = A*a' + B(1 - a')
+    let r = ((r as f64) * alpha + bg_r as f64 * (1_f64 - alpha)) as u8;
+    let g = ((g as f64) * alpha + bg_g as f64 * (1_f64 - alpha)) as u8;
+    let b = ((b as f64) * alpha + bg_b as f64 * (1_f64 - alpha)) as u8;
+    // TODO: swap r and b in logo.raw
+    let (r, b) = (b, r);
+    Rgb { r, g, b }
+}
+
+const_assert_eq!(fade_level(0, 4), 63);
+const_assert_eq!(fade_level(1, 4), 127);
+const_assert_eq!(fade_level(2, 4), 191);
+const_assert_eq!(fade_level(3, 4), 255);
+const_assert_eq!(fade_level(0, 1), 255);
+const_assert_eq!(mix(Rgb::BLACK, Rgb::WHITE, 0).into_argb32(), Rgb::WHITE.into_argb32());
+const_assert_eq!(mix(Rgb::BLACK, Rgb::WHITE, 255).into_argb32(), Rgb::BLACK.into_argb32());
+const_assert_eq!(mix(Rgb::BLACK, Rgb::WHITE, 127).r, 128);
+
 #[derive(Clone, Copy, Debug)]
-struct Rect<'fb> {
+pub(super) struct Rect<'fb> {
     fb: &'fb RawFramebuffer,
     origin: Pixel,
     width: usize,
@@ -76,33 +162,86 @@ impl<'fb> Rect<'fb> {
         }
     }
 
+    /// `data` must be tightly packed (`width` pixels per row)
     pub fn blit_with_bg(&self, data: &[u32], background: Rgb) {
-        const_assert!(RawFramebuffer::ARGB32_ONLY);
-        assert_arg!(data, data.len() >= self.width * self.height);
+        self.blit_with_bg_strided(data, self.width, background);
+    }
 
-        let Rgb { r: bg_r, g: bg_g, b: bg_b } = background;
-        let bg_r = bg_r as f64;
-        let bg_g = bg_g as f64;
-        let bg_b = bg_b as f64;
+    /// Blits `width` pixels out of every `src_stride` pixels of `data`, e.g. a window of a larger image \
+    /// The last row doesn't have to be padded to `src_stride`
+    pub fn blit_with_bg_strided(&self, data: &[u32], src_stride: usize, background: Rgb) {
+        const_assert!(RawFramebuffer::ARGB32_ONLY);
+        assert_arg!(src_stride, src_stride >= self.width);
+        let required = match self.height {
+            0 => 0,
+            height => src_stride.checked_mul(height - 1)
+                .and_then(|x| x.checked_add(self.width))
+                .expect("Source size overflow"),
+        };
+        assert_arg!(data, data.len() >= required, "Shorter than src_stride * height");
 
         for y in 0..self.height {
             for x in 0..self.width {
-                let color_value = data[x + y * self.width];
-                let Rgb { r, g, b } = color_value.into();
-                // Normalized foreground alpha [0..1]
-                let alpha = (color_value >> 24) as f64 / 255_f64;
-                // Alpha blending
-                // Total alpha is always 1 (background alpha is always 1)
-                // C = A*a' + B(1 - a')
-                let r = ((r as f64) * alpha + bg_r * (1_f64 - alpha)) as u8;
-                let g = ((g as f64) * alpha + bg_g * (1_f64 - alpha)) as u8;
-                let b = ((b as f64) * alpha + bg_b * (1_f64 - alpha)) as u8;
-                // TODO: swap r and b in logo.raw
-                let (r, b) = (b, r);
+                let color = alpha_blend(data[x + y * src_stride], background);
                 unsafe {
-                    self.fb.write_pixel_rgb_unchecked(Pixel { x: self.origin.x + x, y: self.origin.y + y }, Rgb { r, g, b });
+                    self.fb.write_pixel_rgb_unchecked(Pixel { x: self.origin.x + x, y: self.origin.y + y }, color);
                 }
             }
         }
     }
+
+    /// Copies tightly packed opaque ARGB32 pixels without blending
+    pub fn copy(&self, data: &[u32]) {
+        const_assert!(RawFramebuffer::ARGB32_ONLY);
+        assert_arg!(data, data.len() >= self.width * self.height, "Shorter than width * height");
+
+        for (y, row) in data.chunks_exact(self.width).take(self.height).enumerate() {
+            for (x, &value) in row.iter().enumerate() {
+                unsafe {
+                    self.fb.write_pixel_raw_unchecked(Pixel { x: self.origin.x + x, y: self.origin.y + y }, value);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{boxed::Box, vec, vec::Vec};
+
+    use super::*;
+
+    /// Opaque gray, `alpha_blend` swaps red and blue
+    const fn gray(level: u32) -> u32 {
+        0xFF00_0000 | level << 16 | level << 8 | level
+    }
+
+    #[test]
+    fn strided_blit_reads_a_window_of_a_wider_source() {
+        let raw = RawFramebuffer::new_in_memory(Box::leak(vec![0; 6 * 4].into_boxed_slice()), 6, 4);
+        let framebuffer = Framebuffer::new(&raw);
+        let rect = Rect::new(&framebuffer, Pixel { x: 1, y: 1 }, 3, 2);
+
+        // 5 pixels per source row, the last row ends right after the window
+        let source: Vec<_> = (0..8).map(|index| gray(0x10 * (index / 5) + index % 5 + 1)).collect();
+        rect.blit_with_bg_strided(&source, 5, Rgb::BLACK);
+
+        let mut pixels = vec![0; 6 * 4];
+        raw.capture(&mut pixels);
+        let rows: Vec<_> = pixels.chunks(6).map(|row| row.iter().map(|&value| value & 0xFF).collect::<Vec<_>>()).collect();
+        assert_eq!(rows, [
+            [0, 0, 0, 0, 0, 0],
+            [0, 0x01, 0x02, 0x03, 0, 0],
+            [0, 0x11, 0x12, 0x13, 0, 0],
+            [0, 0, 0, 0, 0, 0],
+        ]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn strided_blit_rejects_short_sources() {
+        let raw = RawFramebuffer::new_in_memory(Box::leak(vec![0; 6 * 4].into_boxed_slice()), 6, 4);
+        let framebuffer = Framebuffer::new(&raw);
+        Rect::new(&framebuffer, Pixel { x: 0, y: 0 }, 3, 2).blit_with_bg_strided(&[gray(1); 7], 5, Rgb::BLACK);
+    }
 }
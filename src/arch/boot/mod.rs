@@ -1,53 +1,185 @@
 use core::fmt::{Debug, Display, Write};
-use crate::{common::{macros::{debug_assert_arg, assert_arg}, time::UnixEpochTime}, arch::{PhysicalAddress, VirtualAddress}};
 
-use self::logo::LogoScreen;
+use arrayvec::ArrayVec;
+use itertools::Itertools;
+use spin::Once;
 
-use super::{devices::framebuffer::{Framebuffer, FramebufferInfo, FramebufferList, RawFramebuffer}, intrinsics::{cpuid, halt}};
+use crate::{common::{log::{self, debug, warn, Level}, macros::{check_arg, invalid_arg, ArgError}, time::{stopwatch::{self, Stopwatch}, UnixEpochTime}}, arch::{paging::IdentityMapToken, PhysicalAddress, VirtualAddress}, smbios::Smbios};
+
+use self::{logo::LogoScreen, progress::ProgressBar};
+
+use super::{devices::framebuffer::{Framebuffer, FramebufferInfo, FramebufferList, RawFramebuffer}, interrupts::idt::IdtVector, intrinsics::{cpuid, time_stamp_counter_serializing}, processor::{InterruptStacks, Processor}};
 
 mod logo;
+mod progress;
 
 #[cfg(all(target_arch = "x86_64", feature = "limine"))]
 mod x86_64_limine;
+#[cfg(all(target_arch = "x86_64", feature = "multiboot2", not(feature = "limine")))]
+mod multiboot2;
+
+static BOOT_TERMINAL_WRITER: Once<BootTerminalWriter> = Once::new();
+
+static BOOTSTRAP_PROCESSOR: Once<Processor> = Once::new();
+static BOOTSTRAP_STACKS: InterruptStacks = InterruptStacks::new();
+static MODULES: Once<&'static [Module]> = Once::new();
 
-pub static mut BOOT_TERMINAL_WRITER: Option<BootTerminalWriter> = Option::None;
+/// About a quarter of a second, see [LogoScreen::new_animated]
+const LOGO_FADE_FRAMES: u32 = 16;
+/// Frame allocator, paging, local APIC, see [ProgressBar]
+const BOOT_STAGES: usize = 3;
+/// Preemption ticks per second
+const PREEMPT_FREQUENCY: u64 = 100;
 
 pub fn main(data: BootData) -> ! {
     initialize_terminal(data.terminal_writer);
+    if data.terminal_writer.is_serial() {
+        boot_println!("Bootloader terminal unavailable, using serial output");
+    }
+    // Warnings from the protocol loader
+    log::print_early_records();
+    for skipped in data.skipped_framebuffers {
+        warn!("Framebuffer {} skipped: {}", skipped.index, skipped.reason);
+    }
 
-    print_cpu_brand();
-    // halt();
+    if let Some(level) = data.option("loglevel") {
+        match Level::from_name(level) {
+            Some(level) => log::set_max_level(level),
+            None => boot_println!("Invalid log level: {level}"),
+        }
+    }
+
+    BOOTSTRAP_PROCESSOR.call_once(|| Processor::new(&BOOTSTRAP_STACKS)).install();
+    #[cfg(debug_assertions)]
+    crate::arch::interrupts::self_test();
+    calibrate_time_stamp_counter();
+    MODULES.call_once(|| data.modules);
+
+    let identity_map_token = crate::arch::paging::initialize_identity_map(
+        data.identity_map_base,
+        data.identity_map_end
+    );
+
+    if !data.has_flag("quiet") {
+        print_boot_banner(&data, identity_map_token);
+    }
 
     // TODO: initialize arch::devices::framebuffer instead
-    let framebuffer = data.framebuffers.entries.first().and_then(|&fb| unsafe { RawFramebuffer::new(fb).ok() });
-    if let Some(framebuffer) = framebuffer {
-        LogoScreen::new(Framebuffer::new(&framebuffer));
+    let framebuffer = data.framebuffers.entries.first().and_then(|&fb| {
+        // SAFETY: boot data framebuffers are valid for the kernel's lifetime
+        unsafe { RawFramebuffer::from_boot_info(fb) }
+            .inspect_err(|error| warn!("Framebuffer unavailable: {error}"))
+            .ok()
+    });
+    if let Some(framebuffer) = &framebuffer {
+        crate::arch::devices::emergency::set_framebuffer(framebuffer.info);
+        crate::arch::devices::framebuffer::initialize(data.framebuffers);
     }
+    let framebuffer = framebuffer.as_ref().map(Framebuffer::new);
+    if let Some(framebuffer) = &framebuffer {
+        if data.has_flag("quiet") {
+            LogoScreen::new(Framebuffer::new(framebuffer.raw()));
+        } else {
+            LogoScreen::new_animated(Framebuffer::new(framebuffer.raw()), LOGO_FADE_FRAMES);
+        }
+    }
+    let progress = framebuffer.as_ref()
+        .filter(|_| !data.has_flag("quiet"))
+        .and_then(ProgressBar::new);
+    let stage_done = |stage| {
+        if let Some(progress) = &progress {
+            progress.set_progress(stage, BOOT_STAGES);
+        }
+    };
 
-    let identity_map_token = crate::arch::paging::initialize_identity_map(data.identity_map_base);
     // TODO: fix memory map loading
     // halt();
-    unsafe {
-        crate::allocator::physical::initialize(data.memory_map, identity_map_token);
+    let (frame_allocator_token, elapsed) = Stopwatch::measure(|| unsafe {
+        crate::allocator::physical::initialize(data.memory_map, identity_map_token)
+    });
+    debug!("Frame allocator initialized in {elapsed}");
+    stage_done(1);
+    if !data.has_flag("quiet") {
+        boot_print!("{}", crate::allocator::physical::global_allocator(frame_allocator_token));
     }
 
-    boot_println!("time: {}", data.boot_time.millis());
-    boot_println!("boot: {:?}", data.terminal_writer);
-
-    halt();
+    let _paging_token = crate::arch::paging::initialize(frame_allocator_token, identity_map_token);
+    stage_done(2);
+
+    let local_apic = crate::arch::interrupts::apic::initialize_local_apic(identity_map_token);
+    match local_apic.calibrate_timer() {
+        Some(timer_frequency) => {
+            // SAFETY: the bootstrap processor's IDT routes the timer vector to `TimerHandler`, which sends the EOI
+            let hertz = unsafe { local_apic.start_periodic_timer_at(IdtVector::TIMER.value(), timer_frequency, PREEMPT_FREQUENCY) };
+            debug!("Local APIC timer: {} kHz, {hertz} Hz preemption tick", timer_frequency / 1000);
+        },
+        None => warn!("PIT unavailable, local APIC timer not calibrated, preemption tick disabled"),
+    }
+    stage_done(3);
+    #[cfg(feature = "fault-injection")]
+    crate::arch::interrupts::fault_injection::run(_paging_token);
+    #[cfg(feature = "test-qemu")]
+    crate::arch::qemu::exit(crate::arch::qemu::ExitCode::Success);
+
+    #[cfg(not(feature = "test-qemu"))]
+    super::intrinsics::halt();
     
     // todo!()
     //unreachable!();
 }
 
+/// Measures the time stamp counter against the PIT, [Stopwatch] measurements are reported in cycles until then
+fn calibrate_time_stamp_counter() {
+    match crate::arch::devices::pit::calibrate(|| time_stamp_counter_serializing().0) {
+        Some(hertz) => {
+            stopwatch::set_tsc_frequency(hertz);
+            debug!("Time stamp counter frequency: {} MHz", hertz / 1_000_000);
+        },
+        None => warn!("PIT unavailable, time stamp counter not calibrated"),
+    }
+}
+
+/// Files loaded by the bootloader alongside the kernel, empty before `main` runs
+pub fn modules() -> &'static [Module] {
+    MODULES.get().copied().unwrap_or_default()
+}
+
+/// `None` before `main` runs
+pub fn boot_terminal_writer() -> Option<BootTerminalWriter> {
+    BOOT_TERMINAL_WRITER.get().copied()
+}
+
 fn initialize_terminal(writer: BootTerminalWriter) {
-    unsafe { BOOT_TERMINAL_WRITER = Some(writer) };
+    BOOT_TERMINAL_WRITER.call_once(|| writer);
 }
 
-fn print_cpu_brand() {
+fn print_boot_banner(data: &BootData, identity_map_token: IdentityMapToken) {
     let brand = cpuid::brand();
     let brand = core::str::from_utf8(&brand).unwrap_or("[invalid UTF-8]");
-    boot_println!("CPU brand string: {brand}");
+
+    boot_println!("Bootloader:   {}", data.bootloader_info);
+    boot_println!("CPU:          {brand}");
+    let smbios = data.smbios_entry_point.and_then(|x| unsafe { Smbios::new(x, identity_map_token).ok() });
+    if let Some(system) = smbios.and_then(|x| x.system_info()) {
+        boot_println!(
+            "System:       {} {}",
+            system.manufacturer.unwrap_or_default(),
+            system.product_name.unwrap_or_default()
+        );
+    }
+    if let Some(bios) = smbios.and_then(|x| x.bios_info()) {
+        boot_println!(
+            "BIOS:         {} {}",
+            bios.vendor.unwrap_or_default(),
+            bios.version.unwrap_or_default()
+        );
+    }
+    boot_println!("Usable RAM:   {} MiB", data.memory_map.usable_size() / (1024 * 1024));
+    match data.framebuffers.entries.first() {
+        Some(fb) => boot_println!("Framebuffer:  {}x{} ({} bpp)", fb.width, fb.height, fb.bpp),
+        None => boot_println!("Framebuffer:  none"),
+    }
+    boot_println!("Boot time:    {}", data.boot_time.date_time());
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -55,23 +187,180 @@ pub struct BootData {
     pub bootloader_info: BootloaderInfo,
     pub memory_map: MemoryMap,
     pub identity_map_base: PhysicalAddress,
+    /// First physical address not covered by the identity map, must include all usable memory
+    pub identity_map_end: PhysicalAddress,
     pub framebuffers: FramebufferList,
     pub terminal_writer: BootTerminalWriter,
     /// Unix epoch time on boot
     pub boot_time: UnixEpochTime,
     pub kernel_address: (PhysicalAddress, VirtualAddress),
+    /// Kernel command line, whitespace separated flags
+    pub command_line: Option<&'static str>,
+    /// SMBIOS entry point (32-bit or 64-bit)
+    pub smbios_entry_point: Option<VirtualAddress>,
+    pub modules: &'static [Module],
+    /// Framebuffers left out of `framebuffers`, logged by [main]
+    pub skipped_framebuffers: &'static [SkippedFramebuffer],
 }
 
+/// File loaded by the bootloader (e.g. an initramfs), the data is accessed in place
 #[derive(Clone, Copy, Debug)]
-#[repr(transparent)]
-pub struct BootTerminalWriter(fn(&str) -> core::fmt::Result);
+pub struct Module {
+    /// Path the module was loaded from
+    pub name: &'static str,
+    pub command_line: Option<&'static str>,
+    pub data: &'static [u8],
+}
+
+impl BootData {
+    /// Checks if `flag` is present on the kernel command line
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.command_line.is_some_and(|x| x.split_ascii_whitespace().any(|x| x == flag))
+    }
+
+    /// Returns the value of the first `key=value` option on the kernel command line
+    pub fn option(&self, key: &str) -> Option<&'static str> {
+        self.command_line?
+            .split_ascii_whitespace()
+            .find_map(|x| x.strip_prefix(key)?.strip_prefix('='))
+    }
+}
+
+/// Output device behind a [BootTerminalWriter]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BootTerminalKind {
+    /// Terminal provided by the bootloader protocol
+    Bootloader,
+    /// COM1
+    Serial,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BootTerminalWriter {
+    write: fn(&str) -> core::fmt::Result,
+    kind: BootTerminalKind,
+}
+
+impl BootTerminalWriter {
+    pub const fn bootloader(write: fn(&str) -> core::fmt::Result) -> Self {
+        Self { write, kind: BootTerminalKind::Bootloader }
+    }
+
+    pub const fn serial() -> Self {
+        Self { write: crate::arch::serial::write_str_com1, kind: BootTerminalKind::Serial }
+    }
+
+    /// Uses the bootloader terminal if available, COM1 otherwise
+    pub fn select(bootloader_terminal: Option<BootTerminalWriter>) -> Self {
+        bootloader_terminal.unwrap_or(Self::serial())
+    }
+
+    pub fn kind(&self) -> BootTerminalKind {
+        self.kind
+    }
+
+    pub fn is_serial(&self) -> bool {
+        self.kind == BootTerminalKind::Serial
+    }
+}
 
 impl Write for BootTerminalWriter {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        self.0(s)
+        (self.write)(s)
+    }
+}
+
+/// Fills `buffer` with `entries` sorted by base, shared by the protocol loaders \
+/// If there are more entries than capacity, usable entries are kept first, then the largest ones \
+/// Returns the total size of the usable entries that didn't fit
+fn fill_memory_map<const N: usize>(
+    buffer: &mut ArrayVec<MemoryMapEntry, N>,
+    entries: impl IntoIterator<Item = MemoryMapEntry>
+) -> usize {
+    let priority = |entry: &MemoryMapEntry| (entry.kind == MemoryMapEntryKind::Usable, entry.len);
+    let mut dropped_usable_size = 0;
+    for entry in entries {
+        let Err(error) = buffer.try_push(entry) else {
+            continue;
+        };
+        let entry = error.element();
+        let Some(lowest) = buffer.iter_mut().min_by_key(|x| priority(x)) else {
+            // Zero capacity
+            dropped_usable_size += if entry.kind == MemoryMapEntryKind::Usable { entry.len } else { 0 };
+            continue;
+        };
+        let dropped = if priority(lowest) < priority(&entry) {
+            core::mem::replace(lowest, entry)
+        } else {
+            entry
+        };
+        if dropped.kind == MemoryMapEntryKind::Usable {
+            dropped_usable_size += dropped.len;
+        }
+    }
+
+    buffer.sort_unstable_by_key(|entry| entry.base);
+    dropped_usable_size
+}
+
+/// Wall clock time for protocols without a boot time, falls back to the Unix epoch if the RTC is invalid
+fn rtc_boot_time() -> UnixEpochTime {
+    crate::arch::devices::rtc::now().unwrap_or_else(|| {
+        warn!("Invalid RTC date, boot time set to the Unix epoch");
+        UnixEpochTime::UNIX_EPOCH
+    })
+}
+
+/// Appends `modules` to `buffer` in order until it's full, shared by the protocol loaders \
+/// Returns the number of modules that didn't fit
+fn fill_modules<const N: usize>(buffer: &mut ArrayVec<Module, N>, modules: impl IntoIterator<Item = Module>) -> usize {
+    let mut modules = modules.into_iter();
+    buffer.extend(modules.by_ref().take(N - buffer.len()));
+    modules.count()
+}
+
+/// Framebuffer reported by the bootloader that isn't usable
+#[derive(Clone, Copy, Debug)]
+pub struct SkippedFramebuffer {
+    /// Position in the bootloader's framebuffer list
+    pub index: usize,
+    pub reason: FramebufferSkipReason,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum FramebufferSkipReason {
+    /// Bits per pixel don't fit [FramebufferInfo::bpp]
+    UnsupportedBpp(u16),
+    /// Not a direct color framebuffer, e.g. EGA text mode
+    UnsupportedType(u8),
+    /// The bootloader's description is shorter than expected
+    Truncated,
+    Invalid(ArgError),
+}
+
+impl Display for FramebufferSkipReason {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            FramebufferSkipReason::UnsupportedBpp(bpp) => write!(f, "unsupported bits per pixel: {bpp}"),
+            FramebufferSkipReason::UnsupportedType(typ) => write!(f, "unsupported type: {typ}"),
+            FramebufferSkipReason::Truncated => f.write_str("truncated framebuffer info"),
+            FramebufferSkipReason::Invalid(error) => write!(f, "{error}"),
+        }
     }
 }
 
+/// Rejects framebuffers that can't be drawn to, shared by the protocol loaders
+fn validate_framebuffer_info(info: &FramebufferInfo) -> Result<(), ArgError> {
+    check_arg!(info, info.width > 0 && info.height > 0, "Empty framebuffer")?;
+    check_arg!(info, info.bpp > 0, "Invalid bits per pixel")?;
+    check_arg!(
+        info,
+        info.stride >= info.width * (info.bpp as usize).div_ceil(8),
+        "Framebuffer stride smaller than a row"
+    )?;
+    Ok(())
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct BootloaderInfo {
     pub protocol: BootloaderProtocol,
@@ -93,6 +382,7 @@ impl Display for BootloaderInfo {
 #[non_exhaustive]
 pub enum BootloaderProtocol {
     Limine,
+    Multiboot2,
 }
 
 impl Display for BootloaderProtocol {
@@ -111,30 +401,92 @@ impl MemoryMap {
     /// `entries.len()` must be greater than 0
     /// All entries must be valid, all `MemoryMapEntryKind::Usable` entries must be usable
     pub unsafe fn new(entries: &'static [MemoryMapEntry]) -> Self {
-        #[cfg(debug_assertions)]
-        use itertools::Itertools;
+        let memory_map = MemoryMap { entries };
+        if let Err(error) = memory_map.validate() {
+            invalid_arg!(entries, error);
+        }
 
-        assert_arg!(
-            entries,
-            !entries.is_empty(),
-            "Memory map contains no elements"
-        );
-        debug_assert_arg!(
-            entries,
-            entries.is_sorted_by_key(|x| x.base),
-            "Memory map entries not sorted by base"
-        );
-        debug_assert_arg!(
-            entries,
-            entries
-                .iter()
-                .filter(|x| x.kind == MemoryMapEntryKind::Usable)
-                .tuple_windows()
-                .all(|(prev, next)| prev.base + prev.len <= next.base),
-            "Usable memory map entries overlapping"
-        );
+        memory_map
+    }
+
+    /// Checks that the map is non-empty, sorted by base, no entry exceeds the physical address space
+    /// and usable entries don't overlap \
+    /// Runs in all builds, linear in the entry count
+    pub fn validate(&self) -> Result<(), MemoryMapError> {
+        let entries = self.entries;
+        if entries.is_empty() {
+            return Err(MemoryMapError::Empty);
+        }
+
+        let mut previous_usable: Option<&MemoryMapEntry> = None;
+        for (index, entry) in entries.iter().enumerate() {
+            if entry.checked_end().is_none() {
+                return Err(MemoryMapError::AddressOverflow { index });
+            }
+
+            if index > 0 && entries[index - 1].base > entry.base {
+                return Err(MemoryMapError::Unsorted { index });
+            }
+
+            if entry.kind == MemoryMapEntryKind::Usable {
+                // checked_end of every previous entry succeeded
+                if previous_usable.is_some_and(|previous| previous.end() > entry.base) {
+                    return Err(MemoryMapError::Overlapping { index });
+                }
+                previous_usable = Some(entry);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Entries with adjacent entries of the same kind merged into one
+    pub fn coalesced(&self) -> impl Iterator<Item = MemoryMapEntry> + 'static {
+        self.entries.iter().copied().coalesce(|previous, entry| {
+            if previous.kind == entry.kind && previous.checked_end() == Some(entry.base) {
+                Ok(MemoryMapEntry::new(previous.base, previous.len + entry.len, previous.kind))
+            } else {
+                Err((previous, entry))
+            }
+        })
+    }
 
-        MemoryMap { entries }
+    /// End of the highest entry
+    pub fn end(&self) -> PhysicalAddress {
+        self.entries.iter().map(|x| x.end()).max().unwrap_or(PhysicalAddress::new(0))
+    }
+
+    /// Total size of `MemoryMapEntryKind::Usable` entries in bytes
+    pub fn usable_size(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|x| x.kind == MemoryMapEntryKind::Usable)
+            .map(|x| x.len)
+            .sum()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryMapError {
+    Empty,
+    /// Entry at `index` has a lower base than the previous entry
+    Unsorted { index: usize },
+    /// Usable entry at `index` overlaps the previous usable entry
+    Overlapping { index: usize },
+    /// Entry at `index` exceeds the physical address space
+    AddressOverflow { index: usize },
+}
+
+impl Display for MemoryMapError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            MemoryMapError::Empty => f.write_str("Memory map contains no elements"),
+            MemoryMapError::Unsorted { index } => write!(f, "Memory map entries not sorted by base (entry {index})"),
+            MemoryMapError::Overlapping { index } => write!(f, "Usable memory map entries overlapping (entry {index})"),
+            MemoryMapError::AddressOverflow { index } => {
+                write!(f, "Memory map entry exceeds the physical address space (entry {index})")
+            }
+        }
     }
 }
 
@@ -148,7 +500,7 @@ impl IntoIterator for MemoryMap {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct MemoryMapEntry {
     /// Base physical address
     pub base: PhysicalAddress,
@@ -164,6 +516,11 @@ impl MemoryMapEntry {
     pub fn end(self) -> PhysicalAddress {
         self.base + self.len
     }
+
+    /// Returns `None` if the entry ends past the top of the physical address space
+    pub fn checked_end(self) -> Option<PhysicalAddress> {
+        usize::from(self.base).checked_add(self.len).map(PhysicalAddress::new)
+    }
 }
 
 #[non_exhaustive]
@@ -172,12 +529,15 @@ pub enum MemoryMapEntryKind {
     Usable,
     Kernel,
     Reserved,
+    /// Holds bootloader or firmware data (e.g. ACPI tables), may be handed to
+    /// [FrameAllocator::add_region](crate::allocator::physical::FrameAllocator::add_region) once that data is no longer used
+    Reclaimable,
 }
 
 // TODO: refactor into generic logger with fb/serial/etc. support
 macro_rules! boot_print {
     ($($arg:tt)*) => (_ = core::fmt::Write::write_fmt(
-        unsafe { crate::arch::boot::BOOT_TERMINAL_WRITER }.as_mut().expect("Boot terminal unavailable"), format_args!($($arg)*)
+        &mut crate::arch::boot::boot_terminal_writer().expect("Boot terminal unavailable"), format_args!($($arg)*)
     ));
 }
 pub(crate) use boot_print;
@@ -187,3 +547,136 @@ macro_rules! boot_println {
     ($($arg:tt)*) => (crate::arch::boot::boot_print!("{}\n", format_args!($($arg)*)));
 }
 pub(crate) use boot_println;
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use crate::common::sync::InitOnce;
+
+    use super::*;
+
+    fn module(name: &'static str) -> Module {
+        Module { name, command_line: None, data: name.as_bytes() }
+    }
+
+    #[test]
+    fn entries_wrapping_the_address_space_are_rejected() {
+        let top = MemoryMapEntry::new(PhysicalAddress::new(usize::MAX - 0xFFF), 0x1000, MemoryMapEntryKind::Reserved);
+        assert!(top.checked_end().is_none());
+        let below_top = MemoryMapEntry::new(PhysicalAddress::new(usize::MAX - 0x1FFF), 0x1000, MemoryMapEntryKind::Reserved);
+        assert_eq!(below_top.checked_end(), Some(PhysicalAddress::new(usize::MAX - 0xFFF)));
+
+        let usable = MemoryMapEntry::new(PhysicalAddress::new(0x1000), 0x1000, MemoryMapEntryKind::Usable);
+        let memory_map = MemoryMap { entries: std::vec![usable, below_top].leak() };
+        assert_eq!(memory_map.validate(), Ok(()));
+        let memory_map = MemoryMap { entries: std::vec![usable, top].leak() };
+        assert_eq!(memory_map.validate(), Err(MemoryMapError::AddressOverflow { index: 1 }));
+    }
+
+    #[test]
+    fn usable_size_sums_only_usable_entries() {
+        let entry = |base: usize, len: usize, kind| MemoryMapEntry::new(PhysicalAddress::new(base), len, kind);
+        let memory_map = MemoryMap {
+            entries: std::vec![
+                entry(0, 0x9F000, MemoryMapEntryKind::Usable),
+                entry(0xA0000, 0x60000, MemoryMapEntryKind::Reserved),
+                entry(0x100000, 0x200000, MemoryMapEntryKind::Kernel),
+                entry(0x300000, 0x7D00000, MemoryMapEntryKind::Usable),
+                entry(0x8000000, 0x10000, MemoryMapEntryKind::Reclaimable),
+            ].leak(),
+        };
+        assert_eq!(memory_map.usable_size(), 0x9F000 + 0x7D00000);
+        assert_eq!(MemoryMap { entries: &[] }.usable_size(), 0);
+    }
+
+    #[test]
+    fn validate_reports_each_failure() {
+        let entry = |base: usize, len: usize, kind| MemoryMapEntry::new(PhysicalAddress::new(base), len, kind);
+        let validate = |entries: &[MemoryMapEntry]| MemoryMap { entries: entries.to_vec().leak() }.validate();
+        let usable = MemoryMapEntryKind::Usable;
+        let reserved = MemoryMapEntryKind::Reserved;
+
+        assert_eq!(validate(&[]), Err(MemoryMapError::Empty));
+        assert_eq!(
+            validate(&[entry(0x1000, 0x1000, usable), entry(0x3000, 0x1000, reserved), entry(0x2000, 0x1000, usable)]),
+            Err(MemoryMapError::Unsorted { index: 2 })
+        );
+        assert_eq!(
+            validate(&[entry(0x1000, 0x2000, usable), entry(0x2000, 0x1000, reserved), entry(0x2800, 0x1000, usable)]),
+            Err(MemoryMapError::Overlapping { index: 2 })
+        );
+        assert_eq!(
+            validate(&[entry(0x1000, 0x1000, usable), entry(usize::MAX, 2, reserved)]),
+            Err(MemoryMapError::AddressOverflow { index: 1 })
+        );
+        // Reserved entries may overlap anything, touching usable entries don't overlap
+        assert_eq!(
+            validate(&[entry(0x1000, 0x2000, usable), entry(0x1800, 0x4000, reserved), entry(0x3000, 0x1000, usable)]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn boot_buffers_are_filled_once() {
+        // Same pattern as the loaders' `MEMORY_MAP_BUFFER`
+        static BUFFER: InitOnce<ArrayVec<MemoryMapEntry, 4>> = InitOnce::new(ArrayVec::new_const());
+        let entry = |base: usize, kind| MemoryMapEntry::new(PhysicalAddress::new(base), 0x1000, kind);
+
+        let entries = BUFFER.initialize(|buffer| {
+            fill_memory_map(buffer, [entry(0x3000, MemoryMapEntryKind::Usable), entry(0x1000, MemoryMapEntryKind::Reserved)]);
+        });
+        let memory_map = MemoryMap { entries: entries.as_slice() };
+        assert_eq!(memory_map.validate(), Ok(()));
+        assert_eq!(memory_map.entries.iter().map(|x| usize::from(x.base)).collect::<Vec<_>>(), [0x1000, 0x3000]);
+
+        // Later fills are ignored, handed out slices stay valid
+        let again = BUFFER.initialize(|buffer| buffer.clear());
+        assert!(core::ptr::eq(again.as_slice(), memory_map.entries));
+        assert_eq!(memory_map.entries.len(), 2);
+
+        // Only set by `main`
+        assert!(boot_terminal_writer().is_none());
+    }
+
+    #[test]
+    fn fill_modules_keeps_the_first_modules() {
+        let mut buffer = ArrayVec::<Module, 2>::new();
+        let ignored = fill_modules(&mut buffer, ["initramfs", "init", "extra"].map(module));
+        assert_eq!(ignored, 1);
+        let names: Vec<_> = buffer.iter().map(|x| x.name).collect();
+        assert_eq!(names, ["initramfs", "init"]);
+        assert_eq!(buffer[1].data, b"init");
+
+        let mut buffer = ArrayVec::<Module, 4>::new();
+        assert_eq!(fill_modules(&mut buffer, ["a", "b"].map(module)), 0);
+        assert_eq!(buffer.len(), 2);
+    }
+
+    #[test]
+    fn fill_memory_map_keeps_usable_and_largest_entries() {
+        let entry = |base: usize, len: usize, kind| MemoryMapEntry::new(PhysicalAddress::new(base), len, kind);
+        let entries = [
+            entry(0x5000, 0x1000, MemoryMapEntryKind::Usable),
+            entry(0x1000, 0x1000, MemoryMapEntryKind::Reserved),
+            entry(0x3000, 0x2000, MemoryMapEntryKind::Usable),
+            entry(0x8000, 0x4000, MemoryMapEntryKind::Usable),
+        ];
+        let mut buffer = ArrayVec::<MemoryMapEntry, 2>::new();
+        let dropped = fill_memory_map(&mut buffer, entries);
+        assert_eq!(dropped, 0x1000);
+        let bases: Vec<_> = buffer.iter().map(|x| usize::from(x.base)).collect();
+        assert_eq!(bases, [0x3000, 0x8000]);
+
+        // Everything fits, sorted by base
+        let mut buffer = ArrayVec::<MemoryMapEntry, 4>::new();
+        assert_eq!(fill_memory_map(&mut buffer, entries), 0);
+        let bases: Vec<_> = buffer.iter().map(|x| usize::from(x.base)).collect();
+        assert_eq!(bases, [0x1000, 0x3000, 0x5000, 0x8000]);
+
+        // Only usable entries count as dropped memory
+        let mut buffer = ArrayVec::<MemoryMapEntry, 1>::new();
+        assert_eq!(fill_memory_map(&mut buffer, entries), 0x3000);
+        assert_eq!(usize::from(buffer[0].base), 0x8000);
+    }
+}
@@ -0,0 +1,438 @@
+// Multiboot2 boot path
+// Multiboot2 loaders enter the kernel in 32-bit protected mode, a trampoline has to enable long mode,
+// identity map at least the first 4 GiB at virtual address 0 and jump to `multiboot2_start`
+// TODO: the multiboot2 header and the 32-bit trampoline (linker script support)
+//
+// Boot information layout (all fields little endian, tags 8-byte aligned):
+// u32 total_size, u32 reserved, tags...
+// tag: u32 type, u32 size (including the header, excluding padding), type 0 ends the list
+
+use core::{ffi::CStr, ops::Range};
+
+use arrayvec::ArrayVec;
+
+use crate::{
+    allocator::physical::MAX_MEMORY_REGION_COUNT,
+    arch::{devices::framebuffer::{ColorMode, CustomColorMode}, PhysicalAddress, VirtualAddress},
+    common::{log::warn, sync::InitOnce}
+};
+
+use super::{
+    fill_memory_map, fill_modules, rtc_boot_time, validate_framebuffer_info, BootData, BootTerminalWriter, BootloaderInfo, BootloaderProtocol, FramebufferInfo,
+    FramebufferList, FramebufferSkipReason, MemoryMap, MemoryMapEntry, MemoryMapEntryKind, Module, SkippedFramebuffer,
+};
+
+/// Passed in EAX by the loader
+pub const BOOTLOADER_MAGIC: u32 = 0x36D7_6289;
+
+const TAG_END: u32 = 0;
+const TAG_COMMAND_LINE: u32 = 1;
+const TAG_BOOTLOADER_NAME: u32 = 2;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+
+const MEMORY_TYPE_AVAILABLE: u32 = 1;
+const MEMORY_TYPE_ACPI_RECLAIMABLE: u32 = 3;
+const FRAMEBUFFER_TYPE_RGB: u8 = 1;
+
+/// The trampoline maps at least this much
+const IDENTITY_MAP_MIN_SIZE: usize = 4 * 1024 * 1024 * 1024;
+
+const MEMORY_MAP_BUFFER_SIZE: usize = MAX_MEMORY_REGION_COUNT;
+static MEMORY_MAP_BUFFER: InitOnce<ArrayVec<MemoryMapEntry, MEMORY_MAP_BUFFER_SIZE>> = InitOnce::new(ArrayVec::new_const());
+const MODULE_BUFFER_SIZE: usize = 64;
+static MODULE_BUFFER: InitOnce<ArrayVec<Module, MODULE_BUFFER_SIZE>> = InitOnce::new(ArrayVec::new_const());
+/// The usable framebuffer or the reason it was skipped
+static FRAMEBUFFER_INFO_BUFFER: InitOnce<(ArrayVec<FramebufferInfo, 1>, ArrayVec<SkippedFramebuffer, 1>)> =
+    InitOnce::new((ArrayVec::new_const(), ArrayVec::new_const()));
+
+/// Long mode entry point, called by the trampoline with the loader's EAX / EBX values and the kernel
+/// load address
+#[export_name = "multiboot2_start"]
+extern "C" fn multiboot2_start(magic: u32, info: u32, kernel_physical_base: u64, kernel_virtual_base: u64) -> ! {
+    assert_eq!(magic, BOOTLOADER_MAGIC, "Not booted by a multiboot2 loader");
+    // SAFETY: the loader placed the information structure in the identity mapped low memory
+    let info = unsafe {
+        let total_size = (info as usize as *const u32).read();
+        core::slice::from_raw_parts(info as usize as *const u8, total_size as usize)
+    };
+    let tags = Tags::new(info).expect("Invalid multiboot2 information");
+
+    let memory_map = load_memory_map(tags.clone());
+    let (framebuffers, skipped_framebuffers) = load_framebuffer_info(tags.clone());
+    let boot_data = BootData {
+        terminal_writer: BootTerminalWriter::select(None),
+        bootloader_info: BootloaderInfo {
+            protocol: BootloaderProtocol::Multiboot2,
+            name: tags.clone().find(|x| x.typ == TAG_BOOTLOADER_NAME).and_then(|x| c_str(x.data)),
+            version: None,
+        },
+        memory_map,
+        identity_map_base: PhysicalAddress::new(0),
+        identity_map_end: memory_map.end().max(PhysicalAddress::new(IDENTITY_MAP_MIN_SIZE)),
+        framebuffers,
+        boot_time: rtc_boot_time(),
+        kernel_address: (kernel_physical_base.into(), kernel_virtual_base.into()),
+        command_line: tags.clone().find(|x| x.typ == TAG_COMMAND_LINE).and_then(|x| c_str(x.data)),
+        smbios_entry_point: None,
+        modules: load_modules(tags),
+        skipped_framebuffers,
+    };
+
+    super::main(boot_data);
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Tag<'a> {
+    typ: u32,
+    /// Tag contents after the header
+    data: &'a [u8],
+}
+
+/// Iterator over the tags of a boot information structure, stops at the end tag
+#[derive(Clone, Debug)]
+struct Tags<'a> {
+    info: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Tags<'a> {
+    /// Returns `None` if the structure is shorter than its header
+    fn new(info: &'a [u8]) -> Option<Self> {
+        let total_size = read_u32(info, 0)? as usize;
+        Some(Self { info: info.get(..total_size)?, offset: 8 })
+    }
+}
+
+impl<'a> Iterator for Tags<'a> {
+    type Item = Tag<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (typ, start, size) = next_tag(self.info, self.offset)?;
+        self.offset = (start + size).next_multiple_of(8);
+        Some(Tag { typ, data: &self.info[start + 8..start + size] })
+    }
+}
+
+/// (type, offset, size) of the tag at `offset`, `None` for the end tag or a truncated tag
+const fn next_tag(info: &[u8], offset: usize) -> Option<(u32, usize, usize)> {
+    let (Some(typ), Some(size)) = (read_u32(info, offset), read_u32(info, offset + 4)) else {
+        return None;
+    };
+    let size = size as usize;
+    if typ == TAG_END || size < 8 || offset + size > info.len() {
+        return None;
+    }
+    Some((typ, offset, size))
+}
+
+const fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    if offset + 4 > bytes.len() {
+        return None;
+    }
+    Some(u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(read_u32(bytes, offset)? as u64 | (read_u32(bytes, offset + 4)? as u64) << 32)
+}
+
+fn c_str(bytes: &[u8]) -> Option<&str> {
+    CStr::from_bytes_until_nul(bytes).ok()?.to_str().ok()
+}
+
+fn load_memory_map(mut tags: Tags<'static>) -> MemoryMap {
+    let tag = tags.find(|x| x.typ == TAG_MEMORY_MAP).expect("Memory map unavailable");
+    let entries = memory_map_entries(tag.data).expect("Invalid memory map");
+    let entry_count = entries.len();
+
+    let entries = MEMORY_MAP_BUFFER.initialize(|buffer| {
+        let dropped_usable_size = fill_memory_map(buffer, entries);
+        if buffer.len() < entry_count {
+            warn!(
+                "Memory map too large ({entry_count} / max. {MEMORY_MAP_BUFFER_SIZE}), {} KiB of usable memory dropped",
+                dropped_usable_size / 1024
+            );
+        }
+    });
+
+    let memory_map = MemoryMap {
+        entries: entries.as_slice(),
+    };
+    if let Err(error) = memory_map.validate() {
+        panic!("Invalid memory map: {error}");
+    }
+    memory_map
+}
+
+/// Bytes of every memory map entry read by [memory_map_entry], entries may be larger
+const MEMORY_MAP_ENTRY_SIZE: usize = 20;
+
+/// Entries of a memory map tag, `None` if the tag header is truncated or invalid
+fn memory_map_entries(data: &[u8]) -> Option<impl ExactSizeIterator<Item = MemoryMapEntry> + '_> {
+    // u32 entry_size, u32 entry_version, entries: u64 base, u64 length, u32 type, u32 reserved
+    let entry_size = read_u32(data, 0)? as usize;
+    if entry_size < MEMORY_MAP_ENTRY_SIZE {
+        return None;
+    }
+    let entries = data.get(8..)?.chunks_exact(entry_size).map(|entry| {
+        memory_map_entry(entry.first_chunk().expect("entry_size checked above"))
+    });
+    Some(entries)
+}
+
+fn memory_map_entry(entry: &[u8; MEMORY_MAP_ENTRY_SIZE]) -> MemoryMapEntry {
+    let field = |range: Range<usize>| entry[range].iter().rev().fold(0, |value, &byte| value << 8 | byte as u64);
+    let (base, len, typ) = (field(0..8), field(8..16), field(16..20) as u32);
+    let kind = match typ {
+        MEMORY_TYPE_AVAILABLE => MemoryMapEntryKind::Usable,
+        MEMORY_TYPE_ACPI_RECLAIMABLE => MemoryMapEntryKind::Reclaimable,
+        _ => MemoryMapEntryKind::Reserved,
+    };
+    MemoryMapEntry::new((base as usize).into(), len as usize, kind)
+}
+
+fn load_framebuffer_info(mut tags: Tags<'static>) -> (FramebufferList, &'static [SkippedFramebuffer]) {
+    let (entries, skipped) = FRAMEBUFFER_INFO_BUFFER.initialize(|(buffer, skipped)| {
+        let Some(tag) = tags.find(|x| x.typ == TAG_FRAMEBUFFER) else {
+            return;
+        };
+        match framebuffer_info(tag.data) {
+            Ok(entry) => buffer.push(entry),
+            Err(reason) => skipped.push(SkippedFramebuffer { index: 0, reason }),
+        }
+    });
+
+    (FramebufferList { entries: entries.as_slice() }, skipped.as_slice())
+}
+
+/// Only direct RGB framebuffers are supported, EGA text mode and indexed color framebuffers are rejected
+fn framebuffer_info(data: &[u8]) -> Result<FramebufferInfo, FramebufferSkipReason> {
+    // u64 address, u32 pitch, u32 width, u32 height, u8 bpp, u8 type, u16 reserved, color info
+    let header = (read_u64(data, 0), read_u32(data, 8), read_u32(data, 12), read_u32(data, 16), data.get(20), data.get(21));
+    let (Some(address), Some(pitch), Some(width), Some(height), Some(&bpp), Some(&typ)) = header else {
+        return Err(FramebufferSkipReason::Truncated);
+    };
+    if typ != FRAMEBUFFER_TYPE_RGB {
+        return Err(FramebufferSkipReason::UnsupportedType(typ));
+    }
+    let color = data.get(24..30).ok_or(FramebufferSkipReason::Truncated)?;
+
+    // Color info: red position, red size, green position, ..., the same layout as limine's
+    let color_mode = CustomColorMode {
+        red_shift: color[0],
+        red_mask: color[1],
+        green_shift: color[2],
+        green_mask: color[3],
+        blue_shift: color[4],
+        blue_mask: color[5],
+    };
+    let entry = FramebufferInfo {
+        // Identity mapped at 0
+        address: VirtualAddress::new(address as usize),
+        bpp,
+        color_mode: if color_mode == CustomColorMode::RGB888 && bpp == 32 { ColorMode::Rgb } else { ColorMode::Custom(color_mode) },
+        width: width as usize,
+        height: height as usize,
+        stride: pitch as usize,
+    };
+    validate_framebuffer_info(&entry).map_err(FramebufferSkipReason::Invalid)?;
+    Ok(entry)
+}
+
+fn load_modules(tags: Tags<'static>) -> &'static [Module] {
+    MODULE_BUFFER.initialize(|buffer| {
+        let modules = tags.filter(|x| x.typ == TAG_MODULE).filter_map(|tag| {
+            let module = ModuleTag::parse(tag.data)?;
+            // SAFETY: modules are loaded into identity mapped memory reserved in the memory map
+            let data = unsafe { core::slice::from_raw_parts(module.start as usize as *const u8, module.len as usize) };
+            Some(Module { name: module.command_line.unwrap_or_default(), command_line: module.command_line, data })
+        });
+        let ignored = fill_modules(buffer, modules);
+        if ignored > 0 {
+            warn!("Too many modules (max. {MODULE_BUFFER_SIZE}), {ignored} ignored");
+        }
+    }).as_slice()
+}
+
+/// Physical location of a module, the loader reports no separate name
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ModuleTag<'a> {
+    start: u32,
+    len: u32,
+    command_line: Option<&'a str>,
+}
+
+impl<'a> ModuleTag<'a> {
+    /// u32 start, u32 end, command line
+    fn parse(data: &'a [u8]) -> Option<Self> {
+        let (start, end) = (read_u32(data, 0)?, read_u32(data, 4)?);
+        Some(Self { start, len: end.checked_sub(start)?, command_line: c_str(data.get(8..)?) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// Tag with its header, padded to 8 bytes
+    fn tag(typ: u32, payload: &[u8]) -> Vec<u8> {
+        let mut tag = Vec::new();
+        tag.extend(typ.to_le_bytes());
+        tag.extend((payload.len() as u32 + 8).to_le_bytes());
+        tag.extend(payload);
+        tag.resize(tag.len().next_multiple_of(8), 0);
+        tag
+    }
+
+    /// Boot information structure with `tags` followed by the end tag
+    fn info(tags: &[Vec<u8>]) -> Vec<u8> {
+        let mut info = std::vec![0; 8];
+        tags.iter().for_each(|x| info.extend(x));
+        info.extend(tag(TAG_END, &[]));
+        let total_size = info.len() as u32;
+        info[..4].copy_from_slice(&total_size.to_le_bytes());
+        info
+    }
+
+    fn memory_map_tag(entries: &[(u64, u64, u32)]) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend(24_u32.to_le_bytes());
+        payload.extend(0_u32.to_le_bytes());
+        for &(base, len, typ) in entries {
+            payload.extend(base.to_le_bytes());
+            payload.extend(len.to_le_bytes());
+            payload.extend(typ.to_le_bytes());
+            payload.extend(0_u32.to_le_bytes());
+        }
+        tag(TAG_MEMORY_MAP, &payload)
+    }
+
+    fn framebuffer_tag(typ: u8, bpp: u8) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend(0xFD00_0000_u64.to_le_bytes());
+        payload.extend(4096_u32.to_le_bytes());
+        payload.extend(1024_u32.to_le_bytes());
+        payload.extend(768_u32.to_le_bytes());
+        payload.extend([bpp, typ, 0, 0]);
+        // RGB888: red at 16, green at 8, blue at 0
+        payload.extend([16, 8, 8, 8, 0, 8]);
+        tag(TAG_FRAMEBUFFER, &payload)
+    }
+
+    fn find(info: &[u8], typ: u32) -> Option<Tag<'_>> {
+        Tags::new(info).unwrap().find(|x| x.typ == typ)
+    }
+
+    /// Header, a command line tag ("abc", padded to 16 bytes) and the end tag
+    const SAMPLE_INFO: [u8; 32] = [
+        32, 0, 0, 0, 0, 0, 0, 0,
+        1, 0, 0, 0, 12, 0, 0, 0, b'a', b'b', b'c', 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 8, 0, 0, 0,
+    ];
+
+    #[test]
+    fn sample_info_tags() {
+        assert_eq!(read_u32(&SAMPLE_INFO, 0), Some(32));
+        assert_eq!(next_tag(&SAMPLE_INFO, 8), Some((TAG_COMMAND_LINE, 8, 12)));
+        // The next tag starts at the 8-byte aligned end of the command line tag
+        assert!(next_tag(&SAMPLE_INFO, (8 + 12_usize).next_multiple_of(8)).is_none());
+        // Truncated tag
+        assert!(next_tag(&SAMPLE_INFO, 28).is_none());
+    }
+
+    #[test]
+    fn memory_map() {
+        let info = info(&[
+            tag(TAG_COMMAND_LINE, b"quiet\0"),
+            memory_map_tag(&[(0, 0x9F000, MEMORY_TYPE_AVAILABLE), (0xE0000, 0x20000, 2), (0x100000, 0x1000, MEMORY_TYPE_ACPI_RECLAIMABLE)]),
+        ]);
+        let entries = memory_map_entries(find(&info, TAG_MEMORY_MAP).unwrap().data).unwrap();
+        assert_eq!(entries.len(), 3);
+        let entries: Vec<_> = entries.collect();
+        assert_eq!(entries, [
+            MemoryMapEntry::new(PhysicalAddress::new(0), 0x9F000, MemoryMapEntryKind::Usable),
+            MemoryMapEntry::new(PhysicalAddress::new(0xE0000), 0x20000, MemoryMapEntryKind::Reserved),
+            MemoryMapEntry::new(PhysicalAddress::new(0x100000), 0x1000, MemoryMapEntryKind::Reclaimable),
+        ]);
+    }
+
+    #[test]
+    fn memory_map_fill_counts_only_dropped_entries() {
+        let mut payload = memory_map_tag(&[
+            (0x100000, 0x4000, MEMORY_TYPE_AVAILABLE), (0, 0x1000, 2), (0x200000, 0x1000, MEMORY_TYPE_AVAILABLE)
+        ]);
+        // A partial trailing entry is not an entry
+        payload.extend([0xFF; 8]);
+        let data = &payload[8..];
+        let entries = memory_map_entries(data).unwrap();
+        let entry_count = entries.len();
+        assert_eq!(entry_count, 3);
+
+        let mut buffer = ArrayVec::<MemoryMapEntry, 2>::new();
+        let dropped = fill_memory_map(&mut buffer, entries);
+        assert_eq!((buffer.len(), dropped), (2, 0));
+        assert_eq!(entry_count - buffer.len(), 1);
+        assert!(buffer.iter().all(|entry| entry.kind == MemoryMapEntryKind::Usable));
+
+        let mut buffer = ArrayVec::<MemoryMapEntry, 4>::new();
+        assert_eq!(fill_memory_map(&mut buffer, memory_map_entries(data).unwrap()), 0);
+        assert_eq!(buffer.len(), entry_count);
+    }
+
+    #[test]
+    fn memory_map_truncated_header() {
+        assert!(memory_map_entries(&24_u32.to_le_bytes()).is_none());
+        // Entries too small for the base, length and type
+        assert!(memory_map_entries(&[16, 0, 0, 0, 0, 0, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn framebuffer() {
+        let info = info(&[framebuffer_tag(FRAMEBUFFER_TYPE_RGB, 32)]);
+        let framebuffer = framebuffer_info(find(&info, TAG_FRAMEBUFFER).unwrap().data).unwrap();
+        assert_eq!(usize::from(framebuffer.address), 0xFD00_0000);
+        assert_eq!((framebuffer.width, framebuffer.height, framebuffer.stride, framebuffer.bpp), (1024, 768, 4096, 32));
+        assert!(matches!(framebuffer.color_mode, ColorMode::Rgb));
+    }
+
+    #[test]
+    fn framebuffer_unsupported() {
+        // EGA text mode
+        let info = info(&[framebuffer_tag(2, 16)]);
+        let result = framebuffer_info(find(&info, TAG_FRAMEBUFFER).unwrap().data);
+        assert!(matches!(result, Err(FramebufferSkipReason::UnsupportedType(2))));
+        // Truncated before the color info
+        let result = framebuffer_info(&framebuffer_tag(FRAMEBUFFER_TYPE_RGB, 32)[8..30]);
+        assert!(matches!(result, Err(FramebufferSkipReason::Truncated)));
+        // Stride smaller than a row
+        let mut tag = framebuffer_tag(FRAMEBUFFER_TYPE_RGB, 32);
+        tag[16..20].copy_from_slice(&1024_u32.to_le_bytes());
+        assert!(matches!(framebuffer_info(&tag[8..]), Err(FramebufferSkipReason::Invalid(_))));
+    }
+
+    #[test]
+    fn modules() {
+        let module = |start: u32, end: u32, command_line: &[u8]| {
+            let mut payload = Vec::new();
+            payload.extend(start.to_le_bytes());
+            payload.extend(end.to_le_bytes());
+            payload.extend(command_line);
+            tag(TAG_MODULE, &payload)
+        };
+        let info = info(&[module(0x200000, 0x201000, b"initramfs\0"), module(0x300000, 0x300000, b"\0"), module(0x2000, 0x1000, b"\0")]);
+        let modules: Vec<_> = Tags::new(&info).unwrap()
+            .filter(|x| x.typ == TAG_MODULE)
+            .map(|x| ModuleTag::parse(x.data))
+            .collect();
+        assert_eq!(modules, [
+            Some(ModuleTag { start: 0x200000, len: 0x1000, command_line: Some("initramfs") }),
+            Some(ModuleTag { start: 0x300000, len: 0, command_line: Some("") }),
+            // Ends before it starts
+            None,
+        ]);
+    }
+}
@@ -0,0 +1,51 @@
+use crate::arch::devices::framebuffer::{Framebuffer, Pixel, Rgb};
+
+use super::logo::{Rect, LOGO_HEIGHT, LOGO_WIDTH};
+
+const TRACK: Rgb = Rgb::from_argb32(0xdddddd);
+const FILL: Rgb = Rgb::from_argb32(0xa31f34);
+const BAR_HEIGHT: usize = 6;
+/// Space between the logo and the bar
+const MARGIN: usize = 24;
+
+/// Boot progress bar drawn below the logo
+pub struct ProgressBar<'fb> {
+    framebuffer: &'fb Framebuffer<'fb>,
+    origin: Pixel,
+    width: usize,
+}
+
+impl<'fb> ProgressBar<'fb> {
+    /// Returns `None` if the framebuffer is too small to fit the bar below the logo
+    pub fn new(framebuffer: &'fb Framebuffer<'fb>) -> Option<Self> {
+        let (width, height) = (framebuffer.info.width, framebuffer.info.height);
+        let bar_width = LOGO_WIDTH.min(width);
+        let y = (height / 2).checked_add(LOGO_HEIGHT / 2 + MARGIN)?;
+        if y + BAR_HEIGHT > height {
+            return None;
+        }
+
+        let bar = Self {
+            framebuffer,
+            origin: Pixel { x: (width - bar_width) / 2, y },
+            width: bar_width,
+        };
+        bar.set_progress(0, 1);
+        Some(bar)
+    }
+
+    /// Redraws the bar `done / total` full
+    pub fn set_progress(&self, done: usize, total: usize) {
+        let filled = filled_width(self.width, done, total);
+        Rect::new(self.framebuffer, self.origin, filled, BAR_HEIGHT).fill(FILL);
+        Rect::new(self.framebuffer, self.origin + (filled, 0), self.width - filled, BAR_HEIGHT).fill(TRACK);
+    }
+}
+
+/// Filled part of a `width` pixels wide bar, rounded down
+fn filled_width(width: usize, done: usize, total: usize) -> usize {
+    if total == 0 {
+        return width;
+    }
+    width * done.min(total) / total
+}
@@ -1,17 +1,17 @@
-use core::mem::MaybeUninit;
-
-use lazy_static::lazy_static;
+use arrayvec::ArrayVec;
 use limine::{
     LimineBootInfoRequest, LimineFramebufferRequest, LimineHhdmRequest, LimineMmapRequest,
     LimineTerminal, LimineTerminalRequest, LimineTerminalResponse, LimineBootTimeRequest, LimineKernelAddressRequest,
+    LimineKernelFileRequest, LimineMemoryMapEntryType, LimineFramebuffer, LimineModuleRequest, LimineSmbiosRequest,
 };
-use spin::Mutex;
+use spin::{Mutex, Once};
+use static_assertions::const_assert;
 
-use crate::{allocator::physical::MAX_MEMORY_REGION_COUNT, common::{sync::UnsafeSync, time::UnixEpochTime}, arch::{PhysicalAddress, VirtualAddress, devices::framebuffer::{ColorMode, CustomColorMode}}};
+use crate::{allocator::physical::MAX_MEMORY_REGION_COUNT, common::{log::warn, sync::InitOnce, time::UnixEpochTime}, arch::{PhysicalAddress, VirtualAddress, devices::framebuffer::{ColorMode, CustomColorMode}}};
 
 use super::{
-    BootData, BootTerminalWriter, BootloaderInfo, FramebufferInfo, FramebufferList, MemoryMap,
-    MemoryMapEntry, MemoryMapEntryKind,
+    fill_memory_map, fill_modules, rtc_boot_time, validate_framebuffer_info, BootData, BootTerminalWriter, BootloaderInfo, FramebufferInfo, FramebufferList,
+    FramebufferSkipReason, MemoryMap, MemoryMapEntry, MemoryMapEntryKind, Module, SkippedFramebuffer,
 };
 
 static BOOTLOADER_INFO_REQUEST: LimineBootInfoRequest = LimineBootInfoRequest::new(0);
@@ -21,34 +21,56 @@ static HHDM: LimineHhdmRequest = LimineHhdmRequest::new(0);
 static FRAMEBUFFER_REQUEST: LimineFramebufferRequest = LimineFramebufferRequest::new(0);
 static BOOT_TIME_REQUEST: LimineBootTimeRequest = LimineBootTimeRequest::new(0);
 static KERNEL_ADDRESS_REQUEST: LimineKernelAddressRequest = LimineKernelAddressRequest::new(0);
+static KERNEL_FILE_REQUEST: LimineKernelFileRequest = LimineKernelFileRequest::new(0);
+static SMBIOS_REQUEST: LimineSmbiosRequest = LimineSmbiosRequest::new(0);
+static MODULE_REQUEST: LimineModuleRequest = LimineModuleRequest::new(0);
 
-// TODO: use InitOnce
+// Filled once during boot, the loaders hand out `&'static` slices of the buffers
 const MEMORY_MAP_BUFFER_SIZE: usize = MAX_MEMORY_REGION_COUNT;
-static mut MEMORY_MAP_BUFFER: [MaybeUninit<MemoryMapEntry>; MEMORY_MAP_BUFFER_SIZE] =
-    [MaybeUninit::uninit(); MEMORY_MAP_BUFFER_SIZE];
+static MEMORY_MAP_BUFFER: InitOnce<ArrayVec<MemoryMapEntry, MEMORY_MAP_BUFFER_SIZE>> = InitOnce::new(ArrayVec::new_const());
+
+const IDENTITY_MAP_MIN_SIZE: usize = 4 * 1024 * 1024 * 1024;
+
+const MODULE_BUFFER_SIZE: usize = 64;
+static MODULE_BUFFER: InitOnce<ArrayVec<Module, MODULE_BUFFER_SIZE>> = InitOnce::new(ArrayVec::new_const());
 
 const FRAMEBUFFER_INFO_BUFFER_SIZE: usize = 1024;
-static mut FRAMEBUFFER_INFO_BUFFER: [MaybeUninit<FramebufferInfo>; FRAMEBUFFER_INFO_BUFFER_SIZE] =
-    [MaybeUninit::uninit(); FRAMEBUFFER_INFO_BUFFER_SIZE];
+/// Usable framebuffers and the skipped ones
+static FRAMEBUFFER_INFO_BUFFER: InitOnce<(
+    ArrayVec<FramebufferInfo, FRAMEBUFFER_INFO_BUFFER_SIZE>,
+    ArrayVec<SkippedFramebuffer, FRAMEBUFFER_INFO_BUFFER_SIZE>
+)> = InitOnce::new((ArrayVec::new_const(), ArrayVec::new_const()));
 
-#[export_name = "_start"]
+#[cfg_attr(not(test), export_name = "_start")]
 extern "C" fn limine_start() -> ! {
-    let terminal_writer = BootTerminalWriter(LimineTerminalWriter::write_str);
+    let terminal_writer = BootTerminalWriter::select(
+        LimineTerminalWriter::is_available().then_some(BootTerminalWriter::bootloader(LimineTerminalWriter::write_str))
+    );
     let bootloader_info = load_bootloader_info();
     let memory_map = load_memory_map();
     let identity_map_base = load_direct_map_base();
-    let framebuffers = load_framebuffer_info();
+    // Limine maps the first 4 GiB and every memory map entry
+    let identity_map_end = memory_map.end().max(PhysicalAddress::new(IDENTITY_MAP_MIN_SIZE));
+    let (framebuffers, skipped_framebuffers) = load_framebuffer_info();
     let boot_time = load_boot_time();
     let kernel_address = load_kernel_address();
+    let command_line = load_command_line();
+    let smbios_entry_point = load_smbios_entry_point();
+    let modules = load_modules();
 
     let boot_data = BootData {
         terminal_writer,
         bootloader_info,
         memory_map,
         identity_map_base,
+        identity_map_end,
         framebuffers,
         boot_time,
         kernel_address,
+        command_line,
+        smbios_entry_point,
+        modules,
+        skipped_framebuffers,
     };
 
     super::main(boot_data);
@@ -73,41 +95,46 @@ fn load_memory_map() -> MemoryMap {
         .get()
         .expect("Memory map unavailable");
 
-    if MEMORY_MAP_BUFFER_SIZE < mmap.entry_count as usize {
-        panic!(
-            "Memory map too large ({} / max. {})",
-            mmap.entry_count, MEMORY_MAP_BUFFER_SIZE
-        );
-    }
-
     let entries = mmap.entries.as_ptr().expect("Invalid memory map");
-
-    #[allow(clippy::needless_range_loop)]
-    for i in 0..mmap.entry_count as usize {
-        unsafe {
-            let entry = entries.add(i).read().get().expect("Invalid memory map");
-
-            use limine::LimineMemoryMapEntryType as LimineMemType;
-            MEMORY_MAP_BUFFER[i] = MaybeUninit::new(MemoryMapEntry::new(
-                (entry.base as usize).into(),
-                entry.len as usize,
-                match entry.typ {
-                    LimineMemType::AcpiNvs
-                    | LimineMemType::AcpiReclaimable
-                    | LimineMemType::BadMemory
-                    | LimineMemType::BootloaderReclaimable
-                    | LimineMemType::Framebuffer
-                    | LimineMemType::Reserved => MemoryMapEntryKind::Reserved,
-
-                    LimineMemType::KernelAndModules => MemoryMapEntryKind::Kernel,
-                    LimineMemType::Usable => MemoryMapEntryKind::Usable,
-                },
-            ));
+    let entries = MEMORY_MAP_BUFFER.initialize(|buffer| {
+        let entries = (0..mmap.entry_count as usize).map(|i| {
+            let entry = unsafe { entries.add(i).read().get().expect("Invalid memory map") };
+            MemoryMapEntry::new((entry.base as usize).into(), entry.len as usize, entry.typ.into())
+        });
+        let dropped_usable_size = fill_memory_map(buffer, entries);
+        if buffer.len() < mmap.entry_count as usize {
+            warn!(
+                "Memory map too large ({} / max. {MEMORY_MAP_BUFFER_SIZE}), {} KiB of usable memory dropped",
+                mmap.entry_count, dropped_usable_size / 1024
+            );
         }
+    });
+
+    let memory_map = MemoryMap {
+        entries: entries.as_slice(),
+    };
+    if let Err(error) = memory_map.validate() {
+        panic!("Invalid memory map: {error}");
     }
 
-    MemoryMap {
-        entries: unsafe { MaybeUninit::slice_assume_init_ref(&MEMORY_MAP_BUFFER[..mmap.entry_count as usize]) },
+    memory_map
+}
+
+/// Exhaustive, new limine memory types have to be classified here
+impl From<LimineMemoryMapEntryType> for MemoryMapEntryKind {
+    fn from(value: LimineMemoryMapEntryType) -> Self {
+        match value {
+            LimineMemoryMapEntryType::AcpiNvs
+            | LimineMemoryMapEntryType::BadMemory
+            | LimineMemoryMapEntryType::Framebuffer
+            | LimineMemoryMapEntryType::Reserved => MemoryMapEntryKind::Reserved,
+
+            LimineMemoryMapEntryType::AcpiReclaimable
+            | LimineMemoryMapEntryType::BootloaderReclaimable => MemoryMapEntryKind::Reclaimable,
+
+            LimineMemoryMapEntryType::KernelAndModules => MemoryMapEntryKind::Kernel,
+            LimineMemoryMapEntryType::Usable => MemoryMapEntryKind::Usable,
+        }
     }
 }
 
@@ -120,9 +147,7 @@ fn load_direct_map_base() -> PhysicalAddress {
     offset.into()
 }
 
-fn load_framebuffer_info() -> FramebufferList {
-    const LIMINE_MEMORY_MODEL_RGB: u8 = 1;
-
+fn load_framebuffer_info() -> (FramebufferList, &'static [SkippedFramebuffer]) {
     let fb = FRAMEBUFFER_REQUEST
         .get_response()
         .get()
@@ -136,46 +161,67 @@ fn load_framebuffer_info() -> FramebufferList {
         );
     }
 
-    #[allow(clippy::needless_range_loop)]
-    for i in 0..fb.framebuffer_count as usize {
-        unsafe {
-            let limine_fb = entries.add(i).read().get().expect("Invalid framebuffer info");
-            let color_mode = if limine_fb.memory_model == LIMINE_MEMORY_MODEL_RGB {
-                ColorMode::Rgb
-            } else {
-                ColorMode::Custom(
-                    CustomColorMode {
-                        red_mask: limine_fb.red_mask_size,
-                        red_shift: limine_fb.red_mask_shift,
-                        green_mask: limine_fb.green_mask_size,
-                        green_shift: limine_fb.green_mask_shift,
-                        blue_mask: limine_fb.blue_mask_size,
-                        blue_shift: limine_fb.blue_mask_shift,
-                    }
-                )
-            };
-
-            let entry = FramebufferInfo {
-                address: limine_fb.address.as_ptr().expect("Invalid framebuffer info").into(),
-                bpp: limine_fb.bpp.try_into().unwrap(),
-                color_mode,
-                width: limine_fb.width as usize,
-                height: limine_fb.height as usize,
-                stride: limine_fb.pitch as usize,
-            };
-            FRAMEBUFFER_INFO_BUFFER[i] = MaybeUninit::new(entry);
+    let (entries, skipped) = FRAMEBUFFER_INFO_BUFFER.initialize(|(buffer, skipped)| {
+        for i in 0..fb.framebuffer_count as usize {
+            let limine_fb = unsafe { entries.add(i).read().get().expect("Invalid framebuffer info") };
+            // Capacity checked above
+            match framebuffer_info(limine_fb) {
+                Ok(entry) => buffer.push(entry),
+                Err(reason) => skipped.push(SkippedFramebuffer { index: i, reason }),
+            }
         }
-    }
+    });
 
-    FramebufferList {
-        entries: unsafe {
-            MaybeUninit::slice_assume_init_ref(&FRAMEBUFFER_INFO_BUFFER[..fb.framebuffer_count as usize])
-        },
-    }
+    (FramebufferList { entries: entries.as_slice() }, skipped.as_slice())
 }
 
+fn framebuffer_info(limine_fb: &LimineFramebuffer) -> Result<FramebufferInfo, FramebufferSkipReason> {
+    const LIMINE_MEMORY_MODEL_RGB: u8 = 1;
+
+    let color_mode = if limine_fb.memory_model == LIMINE_MEMORY_MODEL_RGB {
+        ColorMode::Rgb
+    } else {
+        ColorMode::Custom(
+            CustomColorMode {
+                red_mask: limine_fb.red_mask_size,
+                red_shift: limine_fb.red_mask_shift,
+                green_mask: limine_fb.green_mask_size,
+                green_shift: limine_fb.green_mask_shift,
+                blue_mask: limine_fb.blue_mask_size,
+                blue_shift: limine_fb.blue_mask_shift,
+            }
+        )
+    };
+
+    let bpp = checked_bpp(limine_fb.bpp).ok_or(FramebufferSkipReason::UnsupportedBpp(limine_fb.bpp))?;
+    let entry = FramebufferInfo {
+        address: limine_fb.address.as_ptr().expect("Invalid framebuffer info").into(),
+        bpp,
+        color_mode,
+        width: limine_fb.width as usize,
+        height: limine_fb.height as usize,
+        stride: limine_fb.pitch as usize,
+    };
+    validate_framebuffer_info(&entry).map_err(FramebufferSkipReason::Invalid)?;
+    Ok(entry)
+}
+
+/// Limine reports bpp as a `u16`, `FramebufferInfo` stores a `u8`
+const fn checked_bpp(bpp: u16) -> Option<u8> {
+    if bpp <= u8::MAX as u16 { Some(bpp as u8) } else { None }
+}
+
+const_assert!(matches!(checked_bpp(32), Some(32)));
+const_assert!(matches!(checked_bpp(255), Some(255)));
+const_assert!(checked_bpp(256).is_none());
+const_assert!(checked_bpp(u16::MAX).is_none());
+
 fn load_boot_time() -> UnixEpochTime {
-    let time = BOOT_TIME_REQUEST.get_response().get().expect("Boot time unavailable").boot_time as u64;
+    let Some(response) = BOOT_TIME_REQUEST.get_response().get() else {
+        warn!("Boot time unavailable, reading the RTC");
+        return rtc_boot_time();
+    };
+    let time = response.boot_time as u64;
     UnixEpochTime::new(time.checked_mul(1000).expect("boot time out of range"))
 }
 
@@ -184,28 +230,107 @@ fn load_kernel_address() -> (PhysicalAddress, VirtualAddress) {
     (addresses.physical_base.into(), addresses.virtual_base.into())
 }
 
-// TODO: remove UnsafeSync
-lazy_static! {
-    static ref TERMINAL_RESPONSE: UnsafeSync<Option<&'static LimineTerminalResponse>> =
-        TERMINAL_REQUEST.get_response().get().into();
+/// Prefers the 64-bit entry point
+fn load_smbios_entry_point() -> Option<VirtualAddress> {
+    let smbios = SMBIOS_REQUEST.get_response().get()?;
+    smbios.entry_64.as_ptr()
+        .or_else(|| smbios.entry_32.as_ptr())
+        .map(VirtualAddress::from)
+}
+
+/// Modules are loaded into `KernelAndModules` memory, which is never handed to the frame allocator,
+/// so the returned slices stay valid
+fn load_modules() -> &'static [Module] {
+    let Some(response) = MODULE_REQUEST.get_response().get() else {
+        return &[];
+    };
+    let entries = response.modules.as_ptr().expect("Invalid module list");
+
+    let modules = MODULE_BUFFER.initialize(|buffer| {
+        let files = (0..response.module_count as usize).filter_map(|i| unsafe {
+            let file = entries.add(i).read().get().expect("Invalid module list");
+            let base = file.base.as_ptr()?;
+            Some(Module {
+                name: file.path.to_string().unwrap_or_default(),
+                command_line: file.cmdline.to_string().filter(|x| !x.is_empty()),
+                data: core::slice::from_raw_parts(base, file.length as usize),
+            })
+        });
+        let ignored = fill_modules(buffer, files);
+        if ignored > 0 {
+            warn!("Too many modules, only the first {MODULE_BUFFER_SIZE} are available ({ignored} ignored)");
+        }
+    });
 
-    static ref TERMINAL: Mutex<Option<&'static LimineTerminal>> =
-        unsafe { TERMINAL_RESPONSE.get() }
-            .and_then(|x| x.terminals().and_then(|x| x.first()))
-            .into();
+    modules.as_slice()
 }
 
-/// Warning: Not thread safe
-pub struct LimineTerminalWriter;
+fn load_command_line() -> Option<&'static str> {
+    let kernel_file = KERNEL_FILE_REQUEST.get_response().get()?.kernel_file.get()?;
+    kernel_file.cmdline.to_string()
+}
+
+static TERMINAL_WRITER: Once<Mutex<Option<LimineTerminalWriter>>> = Once::new();
+
+/// Owns the limine terminal, all writes go through the `TERMINAL_WRITER` lock
+pub struct LimineTerminalWriter {
+    response: &'static LimineTerminalResponse,
+    terminal: &'static LimineTerminal,
+}
+
+// SAFETY: only accessed while holding the `TERMINAL_WRITER` lock
+unsafe impl Send for LimineTerminalWriter {}
 
 impl LimineTerminalWriter {
+    fn new() -> Option<Self> {
+        let response = TERMINAL_REQUEST.get_response().get()?;
+        let terminal = response.terminals().and_then(|x| x.first())?;
+        Some(Self { response, terminal })
+    }
+
+    fn global() -> &'static Mutex<Option<Self>> {
+        TERMINAL_WRITER.call_once(|| Mutex::new(Self::new()))
+    }
+
+    fn is_available() -> bool {
+        Self::global().lock().is_some()
+    }
+
+    fn write(&self, str: &str) -> core::fmt::Result {
+        let writer = self.response.write().ok_or(core::fmt::Error)?;
+        writer(self.terminal, str);
+
+        Ok(())
+    }
+
+    /// Fails instead of deadlocking on re-entrant writes (e.g. a panic while printing) \
+    /// The boot terminal is only used before other cores are started
     fn write_str(str: &str) -> core::fmt::Result {
         use core::fmt::Error;
 
-        let writer = unsafe { TERMINAL_RESPONSE.get().ok_or(Error)?.write().ok_or(Error)? };
-        let terminal_lock = TERMINAL.lock();
-        writer(terminal_lock.ok_or(Error)?, str);
+        let writer = Self::global().try_lock().ok_or(Error)?;
+        writer.as_ref().ok_or(Error)?.write(str)
+    }
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_map_entry_kinds() {
+        let table = [
+            (LimineMemoryMapEntryType::Usable, MemoryMapEntryKind::Usable),
+            (LimineMemoryMapEntryType::Reserved, MemoryMapEntryKind::Reserved),
+            (LimineMemoryMapEntryType::AcpiReclaimable, MemoryMapEntryKind::Reclaimable),
+            (LimineMemoryMapEntryType::AcpiNvs, MemoryMapEntryKind::Reserved),
+            (LimineMemoryMapEntryType::BadMemory, MemoryMapEntryKind::Reserved),
+            (LimineMemoryMapEntryType::BootloaderReclaimable, MemoryMapEntryKind::Reclaimable),
+            (LimineMemoryMapEntryType::KernelAndModules, MemoryMapEntryKind::Kernel),
+            (LimineMemoryMapEntryType::Framebuffer, MemoryMapEntryKind::Reserved),
+        ];
+        for (index, (limine_kind, kind)) in table.into_iter().enumerate() {
+            assert_eq!(MemoryMapEntryKind::from(limine_kind), kind, "entry {index}");
+        }
     }
 }
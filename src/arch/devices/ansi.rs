@@ -0,0 +1,221 @@
+// ANSI escape sequence parser for the framebuffer terminal
+// Supported: SGR (`ESC [ ... m`) reset, 16 foreground / background colors, bold as bright,
+// and cursor home (`ESC [ H`), everything else is consumed and ignored
+
+use super::framebuffer::Rgb;
+
+const ESCAPE: char = '\x1b';
+/// Parameters after the limit are dropped
+pub const MAX_PARAMETERS: usize = 4;
+
+/// 16-color palette, indexed by the SGR color number (bright colors at 8..16)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette(pub [Rgb; 16]);
+
+impl Palette {
+    /// VGA text mode colors
+    pub const VGA: Palette = Palette([
+        Rgb::from_argb32(0x000000), Rgb::from_argb32(0xaa0000), Rgb::from_argb32(0x00aa00), Rgb::from_argb32(0xaa5500),
+        Rgb::from_argb32(0x0000aa), Rgb::from_argb32(0xaa00aa), Rgb::from_argb32(0x00aaaa), Rgb::from_argb32(0xaaaaaa),
+        Rgb::from_argb32(0x555555), Rgb::from_argb32(0xff5555), Rgb::from_argb32(0x55ff55), Rgb::from_argb32(0xffff55),
+        Rgb::from_argb32(0x5555ff), Rgb::from_argb32(0xff55ff), Rgb::from_argb32(0x55ffff), Rgb::from_argb32(0xffffff),
+    ]);
+
+    pub const fn color(&self, index: u8) -> Rgb {
+        self.0[index as usize % 16]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::VGA
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Not part of an escape sequence
+    Print(char),
+    /// Select Graphic Rendition, an empty parameter list means reset
+    Sgr(Parameters),
+    CursorHome,
+    /// Consumed as part of a sequence
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Parameters {
+    values: [u16; MAX_PARAMETERS],
+    len: u8,
+}
+
+impl Parameters {
+    const EMPTY: Parameters = Parameters { values: [0; MAX_PARAMETERS], len: 0 };
+
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        self.values[..self.len as usize].iter().copied()
+    }
+
+    pub const fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub const fn get(&self, index: usize) -> Option<u16> {
+        if index < self.len as usize { Some(self.values[index]) } else { None }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Ground,
+    /// After `ESC`
+    Escape,
+    /// After `ESC [`, `current` is the parameter being parsed
+    Csi { parameters: Parameters, current: Option<u16> },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Parser {
+    state: State,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self { state: State::Ground }
+    }
+
+    /// Whether a sequence is in progress
+    pub const fn in_sequence(&self) -> bool {
+        !matches!(self.state, State::Ground)
+    }
+
+    pub const fn feed(&mut self, ch: char) -> Action {
+        match self.state {
+            State::Ground => {
+                if ch == ESCAPE {
+                    self.state = State::Escape;
+                    Action::None
+                } else {
+                    Action::Print(ch)
+                }
+            },
+            State::Escape => {
+                self.state = match ch {
+                    '[' => State::Csi { parameters: Parameters::EMPTY, current: None },
+                    // Restart on a repeated escape
+                    ESCAPE => State::Escape,
+                    // Unsupported two-character sequence
+                    _ => State::Ground,
+                };
+                Action::None
+            },
+            State::Csi { mut parameters, current } => {
+                match ch {
+                    '0'..='9' => {
+                        let digit = ch as u16 - '0' as u16;
+                        let value = match current {
+                            Some(value) => value.saturating_mul(10).saturating_add(digit),
+                            None => digit,
+                        };
+                        self.state = State::Csi { parameters, current: Some(value) };
+                        Action::None
+                    },
+                    ';' => {
+                        // An omitted parameter is 0
+                        let value = match current {
+                            Some(value) => value,
+                            None => 0,
+                        };
+                        push_parameter(&mut parameters, value);
+                        self.state = State::Csi { parameters, current: None };
+                        Action::None
+                    },
+                    // Parameter / intermediate bytes of unsupported sequences
+                    ' '..='/' | ':' | '<'..='?' => Action::None,
+                    '@'..='~' => {
+                        self.state = State::Ground;
+                        if let Some(value) = current {
+                            push_parameter(&mut parameters, value);
+                        }
+                        match ch {
+                            'm' => Action::Sgr(parameters),
+                            'H' if parameters.is_empty() => Action::CursorHome,
+                            _ => Action::None,
+                        }
+                    },
+                    ESCAPE => {
+                        // Incomplete sequence, start a new one
+                        self.state = State::Escape;
+                        Action::None
+                    },
+                    // Not a valid sequence, print the character rather than losing it
+                    _ => {
+                        self.state = State::Ground;
+                        Action::Print(ch)
+                    },
+                }
+            },
+        }
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+const fn push_parameter(parameters: &mut Parameters, value: u16) {
+    if (parameters.len as usize) < MAX_PARAMETERS {
+        parameters.values[parameters.len as usize] = value;
+        parameters.len += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{vec, vec::Vec};
+
+    use super::*;
+
+    /// Feeds `input` and returns the last action
+    fn feed_all(parser: &mut Parser, input: &str) -> Action {
+        input.chars().fold(Action::None, |_, ch| parser.feed(ch))
+    }
+
+    fn sgr(action: Action) -> Option<Vec<u16>> {
+        match action {
+            Action::Sgr(parameters) => Some(parameters.iter().collect()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn color_then_reset() {
+        let mut parser = Parser::new();
+        assert_eq!(sgr(feed_all(&mut parser, "\x1b[31m")), Some(vec![31]));
+        assert_eq!(parser.feed('x'), Action::Print('x'));
+        assert_eq!(sgr(feed_all(&mut parser, "\x1b[0m")), Some(vec![0]));
+        assert_eq!(sgr(feed_all(&mut parser, "\x1b[m")), Some(vec![]));
+        // Empty parameters are zeros
+        assert_eq!(sgr(feed_all(&mut parser, "\x1b[1;;33m")), Some(vec![1, 0, 33]));
+        assert!(!parser.in_sequence());
+    }
+
+    #[test]
+    fn incomplete_and_unknown_sequences() {
+        let mut parser = Parser::new();
+        assert_eq!(feed_all(&mut parser, "\x1b[3"), Action::None);
+        assert!(parser.in_sequence());
+        // Interrupted by a new sequence
+        assert_eq!(feed_all(&mut parser, "\x1b[H"), Action::CursorHome);
+        // Unknown sequences are ignored
+        assert_eq!(feed_all(&mut parser, "\x1b[2J"), Action::None);
+        assert_eq!(feed_all(&mut parser, "\x1b7"), Action::None);
+        assert!(!parser.in_sequence());
+    }
+}
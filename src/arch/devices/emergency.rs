@@ -0,0 +1,67 @@
+use core::fmt::Write;
+
+use spin::Once;
+
+use crate::arch::{devices::framebuffer::{FramebufferInfo, Pixel, RawFramebuffer, Rgb}, serial::{self, SerialPort}};
+
+static FRAMEBUFFER: Once<FramebufferInfo> = Once::new();
+
+const PANIC_COLOR: Rgb = Rgb::from_argb32(0xcc0000);
+const PANIC_BAR_HEIGHT: usize = 8;
+
+/// Registers the framebuffer marked on panic, may only be called once
+pub fn set_framebuffer(info: FramebufferInfo) {
+    FRAMEBUFFER.call_once(|| info);
+}
+
+/// Lock-free output for panic / double fault contexts \
+/// Writes to COM1 one byte at a time and marks the framebuffer (if registered) with a red bar. \
+/// Not reentrancy protected by design: concurrent or nested use may interleave output, but never deadlocks.
+pub struct EmergencyWriter {
+    serial: SerialPort,
+}
+
+impl EmergencyWriter {
+    /// Initializes COM1 unless [serial::com1] already did (without waiting for it)
+    pub fn new() -> Self {
+        // SAFETY: COM1 is a 16550 compatible UART, a missing UART ignores writes
+        let serial = unsafe { SerialPort::new(SerialPort::COM1_BASE) };
+        if !serial::com1_initialized() {
+            // SAFETY: nothing else has configured the port, at worst another processor is doing the same
+            unsafe { serial.initialize() };
+        }
+        Self { serial }
+    }
+
+    /// Fills the top rows of the registered framebuffer
+    pub fn mark_framebuffer(&self) {
+        // `Once::get` never blocks, returns `None` if registration is in progress
+        let Some(&info) = FRAMEBUFFER.get() else {
+            return;
+        };
+        // SAFETY: info was provided by the bootloader
+        let Ok(framebuffer) = (unsafe { RawFramebuffer::new(info) }) else {
+            return;
+        };
+
+        for y in 0..PANIC_BAR_HEIGHT.min(info.height) {
+            for x in 0..info.width {
+                unsafe {
+                    framebuffer.write_pixel_rgb_unchecked(Pixel { x, y }, PANIC_COLOR);
+                }
+            }
+        }
+    }
+}
+
+impl Default for EmergencyWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Write for EmergencyWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        (&self.serial).write_str(s)
+    }
+}
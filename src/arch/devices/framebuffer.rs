@@ -1,11 +1,40 @@
-use core::ops::{Deref, Sub, Add, AddAssign, SubAssign};
+use core::{fmt::Display, ops::{Deref, Range, Sub, Add, AddAssign, SubAssign}};
+
+use spin::Once;
 
 use crate::{common::macros::{token_type, assert_arg}, arch::VirtualAddress};
 
+use super::registry::{self, DeviceKind};
+
+static FRAMEBUFFERS: Once<FramebufferList> = Once::new();
+
 token_type!(FramebuffersToken);
 
-pub fn initialize(_framebuffers: FramebufferList) -> FramebuffersToken {
-    todo!()
+/// This function may only be called once, all subsequent calls will panic or be ignored \
+/// `RawFramebuffer`s hold on to the memory described by `framebuffers`, switching modes (replacing the list)
+/// would leave them dangling, so the list is fixed for the kernel's lifetime
+pub fn initialize(framebuffers: FramebufferList) -> FramebuffersToken {
+    // best effort panic
+    if FRAMEBUFFERS.is_completed() {
+        panic!("Framebuffers already initialized");
+    }
+
+    FRAMEBUFFERS.call_once(|| {
+        unsafe {
+            registry::mark_initialized(DeviceKind::Framebuffer);
+        }
+        framebuffers
+    });
+
+    unsafe {
+        FramebuffersToken::new()
+    }
+}
+
+pub fn framebuffers(#[allow(unused_variables)] token: FramebuffersToken) -> FramebufferList {
+    debug_assert!(FRAMEBUFFERS.is_completed());
+    // SAFETY: initialized, guaranteed by the token
+    unsafe { *FRAMEBUFFERS.get_unchecked() }
 }
 
 // TODO: refactor
@@ -31,9 +60,9 @@ impl<'a> Deref for Framebuffer<'a> {
 }
 
 #[derive(Debug)]
-#[repr(transparent)]
 pub struct RawFramebuffer {
     pub info: FramebufferInfo,
+    bytes_per_pixel: BytesPerPixel,
 }
 
 impl RawFramebuffer {
@@ -42,10 +71,72 @@ impl RawFramebuffer {
     /// Safety:
     /// The framebuffer info and lifetime must be valid
     pub unsafe fn new(info: FramebufferInfo) -> Result<Self, ()> {
-        if info.color_mode == ColorMode::Rgb && info.bpp == 32 {
-            Ok(Self { info })
-        } else {
-            Err(())
+        unsafe {
+            Self::from_boot_info(info).map_err(|_| ())
+        }
+    }
+
+    /// Validates everything that can be checked without touching the framebuffer memory \
+    /// Safety:
+    /// `info.address` must point to `info.stride * info.height` bytes of framebuffer memory
+    /// valid for the lifetime of the returned object (guaranteed for `BootData` framebuffers)
+    pub unsafe fn from_boot_info(info: FramebufferInfo) -> Result<Self, FramebufferError> {
+        if info.address.as_ptr().is_null() {
+            return Err(FramebufferError::NullAddress);
+        }
+        if info.width == 0 || info.height == 0 {
+            return Err(FramebufferError::Empty);
+        }
+        let bytes_per_pixel = BytesPerPixel::from_bpp(info.bpp).ok_or(FramebufferError::UnsupportedBpp(info.bpp))?;
+        if info.stride < bytes_per_pixel.offset(info.width) {
+            return Err(FramebufferError::StrideTooSmall);
+        }
+        if info.stride % bytes_per_pixel.get() != 0 {
+            return Err(FramebufferError::UnalignedStride);
+        }
+        // TODO: non-32bpp write path
+        if info.color_mode != ColorMode::Rgb || bytes_per_pixel != BytesPerPixel::FOUR {
+            return Err(FramebufferError::UnsupportedMode);
+        }
+
+        Ok(Self { info, bytes_per_pixel })
+    }
+
+    pub fn bytes_per_pixel(&self) -> BytesPerPixel {
+        self.bytes_per_pixel
+    }
+
+    /// Byte offset of `pixel` from the framebuffer address
+    fn pixel_offset(&self, pixel: Pixel) -> usize {
+        pixel.y * self.info.stride + self.bytes_per_pixel.offset(pixel.x)
+    }
+
+    /// Creates an ARGB32 framebuffer backed by `buffer` instead of video memory (e.g. an off-screen buffer)
+    pub fn new_in_memory(buffer: &'static mut [u32], width: usize, height: usize) -> Self {
+        assert_arg!(buffer, buffer.len() >= width * height, "Buffer too small");
+
+        Self {
+            info: FramebufferInfo {
+                address: buffer.as_mut_ptr().into(),
+                bpp: 32,
+                color_mode: ColorMode::Rgb,
+                width,
+                height,
+                stride: width * core::mem::size_of::<u32>(),
+            },
+            bytes_per_pixel: BytesPerPixel::FOUR,
+        }
+    }
+
+    /// Returns the framebuffer memory as raw pixel values, including the padding at the end of each row
+    /// Safety:
+    /// The framebuffer must not be written to while the slice is alive
+    pub unsafe fn as_raw_slice(&self) -> &[u32] {
+        unsafe {
+            core::slice::from_raw_parts(
+                self.info.address.as_ptr().cast::<u32>(),
+                self.info.stride * self.info.height / core::mem::size_of::<u32>()
+            )
         }
     }
 
@@ -61,7 +152,7 @@ impl RawFramebuffer {
     pub unsafe fn write_pixel_raw_unchecked(&self, pixel: Pixel, value: u32) {
         unsafe {
             // Assumes 4 byte aligned pixels
-            let offset = pixel.y * self.info.stride + pixel.x * core::mem::size_of::<u32>();
+            let offset = self.pixel_offset(pixel);
             self.info.address.as_mut_ptr()
                 .cast::<u8>().add(offset)
                 .cast::<u32>().write_volatile(value);
@@ -93,12 +184,143 @@ impl RawFramebuffer {
     /// Warning: no double buffering
     pub unsafe fn read_pixel_raw_unchecked(&self, pixel: Pixel) -> u32 {
         unsafe {
-            let offset = pixel.y * self.info.stride + pixel.x * core::mem::size_of::<u32>();
+            let offset = self.pixel_offset(pixel);
             self.info.address.as_mut_ptr()
                 .cast::<u8>().add(offset)
                 .cast::<u32>().read_volatile()
         }
     }
+
+    /// Iterates over all pixels row by row, yielding their raw values \
+    /// Warning: reads every pixel one at a time, not intended for per-frame use
+    pub fn pixels(&self) -> impl Iterator<Item = (Pixel, u32)> + '_ {
+        let (width, height) = (self.info.width, self.info.height);
+        (0..height)
+            .flat_map(move |y| (0..width).map(move |x| Pixel { x, y }))
+            // SAFETY: pixel is within the framebuffer bounds
+            .map(|pixel| (pixel, unsafe { self.read_pixel_raw_unchecked(pixel) }))
+    }
+
+    /// Copies the visible pixels into `destination` as a tightly packed `width * height` ARGB32 buffer,
+    /// skipping the row padding \
+    /// Warning: no double buffering
+    pub fn capture(&self, destination: &mut [u32]) {
+        let (width, height) = (self.info.width, self.info.height);
+        assert_arg!(destination, destination.len() >= width * height, "Buffer too small");
+        if width == 0 {
+            return;
+        }
+
+        for (y, row) in destination.chunks_exact_mut(width).take(height).enumerate() {
+            for (x, value) in row.iter_mut().enumerate() {
+                // SAFETY: pixel is within the framebuffer bounds
+                *value = unsafe { self.read_pixel_raw_unchecked(Pixel { x, y }) };
+            }
+        }
+    }
+
+    /// Stalls until the display enters vertical blank, so that a following [RawFramebuffer::present] doesn't tear \
+    /// Limitation: the generic linear framebuffer has no access to the display controller,
+    /// this is a no-op and tearing is possible - vblank detection has to be provided by a display driver
+    pub fn wait_for_vblank(&self) {}
+
+    /// Copies a tightly packed `width * height` ARGB32 off-screen buffer (e.g. filled by [RawFramebuffer::capture]
+    /// or an in-memory framebuffer) to the framebuffer, waiting for vblank first
+    pub fn present(&self, back_buffer: &[u32]) {
+        let (width, height) = (self.info.width, self.info.height);
+        assert_arg!(back_buffer, back_buffer.len() >= width * height, "Buffer too small");
+        if width == 0 {
+            return;
+        }
+
+        self.wait_for_vblank();
+        let base = self.info.address.as_mut_ptr().cast::<u8>();
+        for (y, row) in back_buffer.chunks_exact(width).take(height).enumerate() {
+            // SAFETY: the row is within the framebuffer, ARGB32 rows are `width * 4` bytes long
+            unsafe {
+                core::ptr::copy_nonoverlapping(row.as_ptr(), base.add(y * self.info.stride).cast::<u32>(), width);
+            }
+        }
+    }
+
+    /// Fills the rectangle at `origin`, clipped to the framebuffer
+    pub fn fill_rect(&self, origin: Pixel, size: (usize, usize), color: Rgb) {
+        let end_x = origin.x.saturating_add(size.0).min(self.info.width);
+        let end_y = origin.y.saturating_add(size.1).min(self.info.height);
+        if origin.x >= end_x {
+            return;
+        }
+
+        let value = color.into_argb32();
+        for y in origin.y..end_y {
+            // SAFETY: the span is within row `y`
+            unsafe {
+                self.fill_scanline_unchecked(Pixel { x: origin.x, y }, end_x - origin.x, value);
+            }
+        }
+    }
+
+    /// Writes `value` to `len` pixels starting at `start` with a single slice fill \
+    /// Safety:
+    /// The span must be within a single row of the framebuffer
+    unsafe fn fill_scanline_unchecked(&self, start: Pixel, len: usize, value: u32) {
+        unsafe {
+            // Assumes 4 byte aligned pixels
+            let row = self.info.address.as_mut_ptr().cast::<u8>().add(self.pixel_offset(start)).cast::<u32>();
+            core::slice::from_raw_parts_mut(row, len).fill(value);
+        }
+    }
+
+    /// Moves the whole framebuffer up by `rows * line_height` pixel rows, filling the revealed band with `background`
+    pub fn scroll_up(&self, rows: usize, line_height: usize, background: Rgb) {
+        self.scroll_region_up(0..self.info.height, rows.saturating_mul(line_height), background);
+    }
+
+    /// Moves pixel rows in `region` up by `shift`, filling the revealed band at the bottom of the region with `background` \
+    /// Rows are copied whole (including the row padding)
+    pub fn scroll_region_up(&self, region: Range<usize>, shift: usize, background: Rgb) {
+        let region = region.start.min(self.info.height)..region.end.min(self.info.height);
+        let shift = shift.min(region.len());
+        if shift == 0 {
+            return;
+        }
+
+        let stride = self.info.stride;
+        let row_size = self.bytes_per_pixel.offset(self.info.width);
+        let base = self.info.address.as_mut_ptr().cast::<u8>();
+        for y in region.start..(region.end - shift) {
+            // SAFETY: both rows are within the framebuffer and distinct (shift > 0)
+            unsafe {
+                core::ptr::copy_nonoverlapping(base.add((y + shift) * stride), base.add(y * stride), row_size);
+            }
+        }
+
+        self.fill_rect(Pixel { x: 0, y: region.end - shift }, (self.info.width, shift), background);
+    }
+
+    /// Draws a `size` block cursor at `position`
+    pub fn draw_cursor(&self, position: Pixel, size: (usize, usize), color: Rgb) {
+        self.fill_rect(position, size, color);
+    }
+
+    /// Restores the background under a cursor drawn with [RawFramebuffer::draw_cursor]
+    pub fn erase_cursor(&self, position: Pixel, size: (usize, usize), background: Rgb) {
+        self.fill_rect(position, size, background);
+    }
+
+    /// Writes the raw value returned by `f` to every pixel, row by row \
+    /// Warning: writes every pixel one at a time, not intended for per-frame use
+    pub fn for_each_pixel(&self, mut f: impl FnMut(Pixel) -> u32) {
+        for y in 0..self.info.height {
+            for x in 0..self.info.width {
+                let pixel = Pixel { x, y };
+                // SAFETY: pixel is within the framebuffer bounds
+                unsafe {
+                    self.write_pixel_raw_unchecked(pixel, f(pixel));
+                }
+            }
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -107,6 +329,21 @@ pub struct Pixel {
     pub y: usize,
 }
 
+impl Pixel {
+    /// Clamps each coordinate at 0 instead of underflowing
+    pub const fn saturating_sub(self, rhs: (usize, usize)) -> Self {
+        Pixel { x: self.x.saturating_sub(rhs.0), y: self.y.saturating_sub(rhs.1) }
+    }
+
+    /// Returns `None` if either coordinate overflows
+    pub const fn checked_add(self, rhs: (usize, usize)) -> Option<Self> {
+        match (self.x.checked_add(rhs.0), self.y.checked_add(rhs.1)) {
+            (Some(x), Some(y)) => Some(Pixel { x, y }),
+            _ => None,
+        }
+    }
+}
+
 impl From<(usize, usize)> for Pixel {
     fn from(value: (usize, usize)) -> Self {
         Pixel { x: value.0, y: value.1 }
@@ -172,6 +409,71 @@ impl Rgb {
         let r = (value >> 16) as u8;
         Self { r, g, b }
     }
+
+    /// Luminance-weighted gray (BT.601 weights, fixed point with a sum of 256)
+    pub const fn grayscale(self) -> Self {
+        let luma = ((self.r as u32 * 77 + self.g as u32 * 150 + self.b as u32 * 29) >> 8) as u8;
+        Self { r: luma, g: luma, b: luma }
+    }
+
+    /// Multiplies every channel by `factor / 255` (0 - black, 255 - unchanged), rounded to the nearest value
+    pub const fn scale_brightness(self, factor: u8) -> Self {
+        const fn scale(channel: u8, factor: u8) -> u8 {
+            ((channel as u32 * factor as u32 + 127) / 255) as u8
+        }
+
+        Self {
+            r: scale(self.r, factor),
+            g: scale(self.g, factor),
+            b: scale(self.b, factor),
+        }
+    }
+}
+
+impl Rgb {
+    /// Packs the color into a `bpp` bits wide pixel value
+    pub fn pack(self, mode: &ColorMode, bpp: u8) -> u32 {
+        let mode = mode.channels(bpp);
+        let value = pack_channel(self.r, mode.red_mask, mode.red_shift)
+            | pack_channel(self.g, mode.green_mask, mode.green_shift)
+            | pack_channel(self.b, mode.blue_mask, mode.blue_shift);
+        value & bpp_mask(bpp)
+    }
+
+    /// Unpacks a `bpp` bits wide pixel value
+    pub fn unpack(value: u32, mode: &ColorMode, bpp: u8) -> Self {
+        let mode = mode.channels(bpp);
+        let value = value & bpp_mask(bpp);
+        Rgb {
+            r: unpack_channel(value, mode.red_mask, mode.red_shift),
+            g: unpack_channel(value, mode.green_mask, mode.green_shift),
+            b: unpack_channel(value, mode.blue_mask, mode.blue_shift),
+        }
+    }
+}
+
+fn bpp_mask(bpp: u8) -> u32 {
+    if bpp >= 32 { u32::MAX } else { (1_u32 << bpp) - 1 }
+}
+
+/// Scales an 8 bit channel to `size` bits
+fn pack_channel(value: u8, size: u8, shift: u8) -> u32 {
+    if size == 0 || shift >= 32 {
+        return 0;
+    }
+    let max = (1_u64 << size.min(32)) - 1;
+    let scaled = (value as u64 * max + 127) / 255;
+    (scaled << shift) as u32
+}
+
+/// Scales a `size` bits wide channel to 8 bits
+fn unpack_channel(value: u32, size: u8, shift: u8) -> u8 {
+    if size == 0 || shift >= 32 {
+        return 0;
+    }
+    let max = (1_u64 << size.min(32)) - 1;
+    let channel = (value as u64 >> shift) & max;
+    ((channel * 255 + max / 2) / max) as u8
 }
 
 impl From<Rgb> for u32 {
@@ -188,6 +490,33 @@ impl From<u32> for Rgb {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FramebufferError {
+    NullAddress,
+    /// Zero width or height
+    Empty,
+    UnsupportedBpp(u8),
+    /// Stride shorter than a row of pixels
+    StrideTooSmall,
+    /// Stride not a multiple of the pixel size
+    UnalignedStride,
+    /// Valid, but no write path exists for the color mode and pixel size
+    UnsupportedMode,
+}
+
+impl Display for FramebufferError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            FramebufferError::NullAddress => f.write_str("Framebuffer address is null"),
+            FramebufferError::Empty => f.write_str("Empty framebuffer"),
+            FramebufferError::UnsupportedBpp(bpp) => write!(f, "Unsupported bits per pixel: {bpp}"),
+            FramebufferError::StrideTooSmall => f.write_str("Framebuffer stride smaller than a row"),
+            FramebufferError::UnalignedStride => f.write_str("Framebuffer stride not a multiple of the pixel size"),
+            FramebufferError::UnsupportedMode => f.write_str("Unsupported framebuffer color mode"),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct FramebufferList {
     pub entries: &'static [FramebufferInfo],
@@ -208,6 +537,40 @@ pub struct FramebufferInfo {
     pub stride: usize,
 }
 
+/// Pixel size in bytes, only whole-byte pixel formats (8, 16, 24 and 32 bpp) are supported
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BytesPerPixel(u8);
+
+impl BytesPerPixel {
+    pub const ONE: BytesPerPixel = BytesPerPixel(1);
+    pub const TWO: BytesPerPixel = BytesPerPixel(2);
+    pub const THREE: BytesPerPixel = BytesPerPixel(3);
+    pub const FOUR: BytesPerPixel = BytesPerPixel(4);
+
+    /// Returns `None` if `bpp` is not 8, 16, 24 or 32
+    pub const fn from_bpp(bpp: u8) -> Option<Self> {
+        match bpp {
+            8 | 16 | 24 | 32 => Some(BytesPerPixel(bpp / 8)),
+            _ => None,
+        }
+    }
+
+    pub const fn get(self) -> usize {
+        self.0 as usize
+    }
+
+    /// Byte offset of pixel `x` within a row
+    pub const fn offset(self, x: usize) -> usize {
+        x * self.0 as usize
+    }
+}
+
+impl From<BytesPerPixel> for usize {
+    fn from(val: BytesPerPixel) -> Self {
+        val.get()
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ColorMode {
     Rgb,
@@ -224,3 +587,260 @@ pub struct CustomColorMode {
     pub blue_mask: u8,
     pub blue_shift: u8,
 }
+
+impl CustomColorMode {
+    pub const RGB888: CustomColorMode = CustomColorMode {
+        red_mask: 8, red_shift: 16, green_mask: 8, green_shift: 8, blue_mask: 8, blue_shift: 0,
+    };
+    pub const RGB565: CustomColorMode = CustomColorMode {
+        red_mask: 5, red_shift: 11, green_mask: 6, green_shift: 5, blue_mask: 5, blue_shift: 0,
+    };
+    pub const RGB332: CustomColorMode = CustomColorMode {
+        red_mask: 3, red_shift: 5, green_mask: 3, green_shift: 2, blue_mask: 2, blue_shift: 0,
+    };
+}
+
+impl ColorMode {
+    /// Channel layout, `ColorMode::Rgb` uses the standard layout for `bpp`
+    pub fn channels(&self, bpp: u8) -> CustomColorMode {
+        match self {
+            ColorMode::Custom(mode) => *mode,
+            ColorMode::Rgb if bpp >= 24 => CustomColorMode::RGB888,
+            ColorMode::Rgb if bpp >= 16 => CustomColorMode::RGB565,
+            ColorMode::Rgb => CustomColorMode::RGB332,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{boxed::Box, vec, vec::Vec};
+
+    use super::*;
+
+    fn in_memory(width: usize, height: usize) -> RawFramebuffer {
+        RawFramebuffer::new_in_memory(Box::leak(vec![0; width * height].into_boxed_slice()), width, height)
+    }
+
+    fn contents(framebuffer: &RawFramebuffer) -> Vec<u32> {
+        let mut buffer = vec![0; framebuffer.info.width * framebuffer.info.height];
+        framebuffer.capture(&mut buffer);
+        buffer
+    }
+
+    #[test]
+    fn bytes_per_pixel_offsets() {
+        for (bpp, bytes) in [(8, 1), (16, 2), (24, 3), (32, 4)] {
+            let bytes_per_pixel = BytesPerPixel::from_bpp(bpp).unwrap();
+            assert_eq!(bytes_per_pixel.get(), bytes);
+            assert_eq!(bytes_per_pixel.offset(0), 0);
+            assert_eq!(bytes_per_pixel.offset(7), 7 * bytes);
+        }
+        for bpp in [0, 1, 4, 12, 15, 31, 40] {
+            assert!(BytesPerPixel::from_bpp(bpp).is_none(), "{bpp} bpp accepted");
+        }
+
+        let info = |bpp, stride| FramebufferInfo {
+            // Never dereferenced by validation
+            address: VirtualAddress::new(0x1000),
+            bpp,
+            color_mode: ColorMode::Rgb,
+            width: 10,
+            height: 10,
+            stride,
+        };
+        let validate = |info| unsafe { RawFramebuffer::from_boot_info(info) }.map(|_| ());
+        assert!(validate(info(32, 40)).is_ok());
+        assert!(matches!(validate(info(12, 40)), Err(FramebufferError::UnsupportedBpp(12))));
+        // 24 bpp rows are 30 bytes, the stride must be a multiple of 3
+        assert!(matches!(validate(info(24, 29)), Err(FramebufferError::StrideTooSmall)));
+        assert!(matches!(validate(info(24, 32)), Err(FramebufferError::UnalignedStride)));
+        assert!(matches!(validate(info(24, 33)), Err(FramebufferError::UnsupportedMode)));
+    }
+
+    #[test]
+    fn pack_applies_custom_masks_and_shifts() {
+        let red = Rgb { r: 0xFF, g: 0, b: 0 };
+        let green = Rgb { r: 0, g: 0xFF, b: 0 };
+        let blue = Rgb { r: 0, g: 0, b: 0xFF };
+
+        let bgr = ColorMode::Custom(CustomColorMode {
+            red_mask: 8, red_shift: 0, green_mask: 8, green_shift: 8, blue_mask: 8, blue_shift: 16,
+        });
+        assert_eq!(red.pack(&bgr, 32), 0x00_00_FF);
+        assert_eq!(green.pack(&bgr, 32), 0x00_FF_00);
+        assert_eq!(blue.pack(&bgr, 32), 0xFF_00_00);
+
+        let rgb565 = ColorMode::Custom(CustomColorMode::RGB565);
+        assert_eq!(red.pack(&rgb565, 16), 0xF800);
+        assert_eq!(green.pack(&rgb565, 16), 0x07E0);
+        assert_eq!(blue.pack(&rgb565, 16), 0x001F);
+        // 16 bpp RGB falls back to the same layout
+        assert_eq!(green.pack(&ColorMode::Rgb, 16), 0x07E0);
+
+        for color in [red, green, blue, Rgb { r: 0xFF, g: 0xFF, b: 0xFF }] {
+            assert_eq!(Rgb::unpack(color.pack(&bgr, 32), &bgr, 32), color);
+            assert_eq!(Rgb::unpack(color.pack(&rgb565, 16), &rgb565, 16), color);
+        }
+        // Bits above bpp are ignored
+        assert_eq!(Rgb::unpack(0xFFFF_0000, &rgb565, 16), Rgb { r: 0, g: 0, b: 0 });
+        // Channels wider than 8 bits are scaled down
+        let deep = ColorMode::Custom(CustomColorMode {
+            red_mask: 10, red_shift: 20, green_mask: 10, green_shift: 10, blue_mask: 10, blue_shift: 0,
+        });
+        assert_eq!(red.pack(&deep, 32), 0x3FF << 20);
+        assert_eq!(Rgb::unpack(0x200 << 10, &deep, 32), Rgb { r: 0, g: 0x80, b: 0 });
+    }
+
+    #[test]
+    fn grayscale_and_brightness() {
+        let gray = |level| Rgb { r: level, g: level, b: level };
+        assert_eq!(Rgb { r: 0xFF, g: 0, b: 0 }.grayscale(), gray(76));
+        assert_eq!(Rgb { r: 0, g: 0xFF, b: 0 }.grayscale(), gray(149));
+        assert_eq!(Rgb { r: 0, g: 0, b: 0xFF }.grayscale(), gray(28));
+        assert_eq!(Rgb::WHITE.grayscale(), Rgb::WHITE);
+        assert_eq!(Rgb::BLACK.grayscale(), Rgb::BLACK);
+        assert_eq!(gray(0x42).grayscale(), gray(0x42));
+
+        let color = Rgb { r: 0xFF, g: 0x80, b: 0x01 };
+        assert_eq!(color.scale_brightness(0), Rgb::BLACK);
+        assert_eq!(color.scale_brightness(255), color);
+        // Rounded to the nearest value
+        assert_eq!(color.scale_brightness(128), Rgb { r: 0x80, g: 0x40, b: 0x01 });
+        assert_eq!(Rgb::WHITE.scale_brightness(1), gray(1));
+    }
+
+    #[test]
+    fn pixel_arithmetic_saturates_and_checks() {
+        let center = Pixel { x: 100, y: 50 };
+        assert_eq!(center.saturating_sub((120, 20)), Pixel { x: 0, y: 30 });
+        assert_eq!(center.saturating_sub((200, 200)), Pixel { x: 0, y: 0 });
+        assert_eq!(center.checked_add((1, 2)), Some(Pixel { x: 101, y: 52 }));
+        assert_eq!(center.checked_add((usize::MAX, 0)), None);
+        assert_eq!(Pixel { x: 0, y: usize::MAX }.checked_add((0, 1)), None);
+    }
+
+    #[test]
+    fn capture_round_trips_a_padded_gradient() {
+        let (width, height, stride_pixels) = (4, 3, 6);
+        let buffer: &'static mut [u32] = Box::leak(vec![u32::MAX; stride_pixels * height].into_boxed_slice());
+        let info = FramebufferInfo {
+            address: buffer.as_mut_ptr().into(),
+            bpp: 32,
+            color_mode: ColorMode::Rgb,
+            width,
+            height,
+            stride: stride_pixels * 4,
+        };
+        // SAFETY: the buffer is leaked and covers `stride * height` bytes
+        let framebuffer = unsafe { RawFramebuffer::from_boot_info(info) }.unwrap();
+
+        let gradient = |pixel: Pixel| Rgb { r: (pixel.x * 0x40) as u8, g: (pixel.y * 0x80) as u8, b: 0x20 }.into_argb32();
+        framebuffer.for_each_pixel(gradient);
+        // Row padding isn't captured, larger buffers keep their tail
+        let mut captured = vec![0; width * height + 1];
+        framebuffer.capture(&mut captured);
+        let expected: Vec<_> = (0..height).flat_map(|y| (0..width).map(move |x| gradient(Pixel { x, y }))).collect();
+        assert_eq!(captured[..width * height], expected);
+        assert_eq!(captured[width * height], 0);
+
+        // Back through `present` onto another framebuffer
+        let copy = in_memory(width, height);
+        copy.present(&captured);
+        assert_eq!(contents(&copy), expected);
+    }
+
+    #[test]
+    #[should_panic]
+    fn capture_rejects_a_small_buffer() {
+        in_memory(4, 3).capture(&mut [0; 11]);
+    }
+
+    #[test]
+    fn second_initialize_panics_and_keeps_the_list() {
+        // The only test touching `FRAMEBUFFERS`
+        let info = in_memory(2, 2).info;
+        let first = FramebufferList { entries: Box::leak(Box::new([info])) };
+        let token = initialize(first);
+        assert!(registry::is_initialized(DeviceKind::Framebuffer));
+
+        let replacement = FramebufferList { entries: Box::leak(Box::new([info, info])) };
+        assert!(std::panic::catch_unwind(|| initialize(replacement)).is_err());
+        // Handles to the first list stay valid
+        assert!(core::ptr::eq(framebuffers(token).entries, first.entries));
+    }
+
+    #[test]
+    fn gradient_fill_respects_stride() {
+        let (width, height, stride_pixels) = (3, 2, 5);
+        let buffer: &'static mut [u32] = Box::leak(vec![u32::MAX; stride_pixels * height].into_boxed_slice());
+        let info = FramebufferInfo {
+            address: buffer.as_mut_ptr().into(),
+            bpp: 32,
+            color_mode: ColorMode::Rgb,
+            width,
+            height,
+            stride: stride_pixels * 4,
+        };
+        // SAFETY: the buffer is leaked and covers `stride * height` bytes
+        let framebuffer = unsafe { RawFramebuffer::from_boot_info(info) }.unwrap();
+
+        let gradient = |pixel: Pixel| (pixel.y * 0x10 + pixel.x) as u32;
+        framebuffer.for_each_pixel(gradient);
+        let pixels: Vec<_> = framebuffer.pixels().collect();
+        assert_eq!(pixels.len(), width * height);
+        assert!(pixels.iter().all(|&(pixel, value)| value == gradient(pixel)));
+        assert_eq!(pixels[3].0, Pixel { x: 0, y: 1 });
+
+        // Row padding is left alone
+        let raw = unsafe { framebuffer.as_raw_slice() };
+        assert_eq!(raw, [0x00, 0x01, 0x02, u32::MAX, u32::MAX, 0x10, 0x11, 0x12, u32::MAX, u32::MAX]);
+    }
+
+    #[test]
+    fn fill_rect_is_clipped() {
+        let framebuffer = in_memory(4, 3);
+        let color = Rgb::from_argb32(0x123456);
+        framebuffer.fill_rect(Pixel { x: 2, y: 1 }, (10, 10), color);
+        framebuffer.fill_rect(Pixel { x: 5, y: 0 }, (1, 1), color);
+
+        let c = color.into_argb32();
+        assert_eq!(contents(&framebuffer), [
+            0, 0, 0, 0,
+            0, 0, c, c,
+            0, 0, c, c,
+        ]);
+    }
+
+    #[test]
+    fn scroll_up_moves_rows_and_clears_the_revealed_band() {
+        let (width, height) = (3, 6);
+        let framebuffer = in_memory(width, height);
+        // Every pixel encodes its row
+        framebuffer.for_each_pixel(|pixel| pixel.y as u32 + 1);
+        let background = Rgb::from_argb32(0xabcdef);
+
+        // 2 text rows of 2 pixel rows
+        framebuffer.scroll_up(1, 2, background);
+        let b = background.into_argb32();
+        let rows: Vec<_> = contents(&framebuffer).chunks_exact(width).map(|row| row.to_vec()).collect();
+        assert_eq!(rows, [[3; 3], [4; 3], [5; 3], [6; 3], [b; 3], [b; 3]]);
+
+        // Shifts past the end clear everything
+        framebuffer.scroll_up(10, 2, background);
+        assert!(contents(&framebuffer).iter().all(|&x| x == b));
+    }
+
+    #[test]
+    fn scroll_region_up_keeps_rows_outside_the_region() {
+        let (width, height) = (2, 5);
+        let framebuffer = in_memory(width, height);
+        framebuffer.for_each_pixel(|pixel| pixel.y as u32 + 1);
+        let background = Rgb::from_argb32(0);
+
+        framebuffer.scroll_region_up(1..4, 1, background);
+        let b = background.into_argb32();
+        let rows: Vec<_> = contents(&framebuffer).chunks_exact(width).map(|row| row.to_vec()).collect();
+        assert_eq!(rows, [[1; 2], [3; 2], [4; 2], [b; 2], [5; 2]]);
+    }
+}
@@ -1 +1,8 @@
+pub mod ansi;
+pub mod emergency;
 pub mod framebuffer;
+pub mod registry;
+pub mod terminal;
+
+#[cfg(target_arch = "x86_64")]
+pub use super::x86_64::devices::{pit, rtc};
@@ -0,0 +1,50 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::common::macros::token_type;
+
+use super::framebuffer::FramebuffersToken;
+
+// Tracks which devices finished initialization, so boot code can query devices without driver specific globals
+// TODO: keyboard, PIT
+
+static INITIALIZED: AtomicU32 = AtomicU32::new(0);
+
+token_type!(SerialToken);
+token_type!(LocalApicToken);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DeviceKind {
+    Serial,
+    Framebuffer,
+    LocalApic,
+}
+
+impl DeviceKind {
+    pub const ALL: [DeviceKind; 3] = [DeviceKind::Serial, DeviceKind::Framebuffer, DeviceKind::LocalApic];
+
+    const fn bit(self) -> u32 {
+        1 << self as u8
+    }
+}
+
+/// Should only be called by the device's driver once the device is usable
+pub unsafe fn mark_initialized(kind: DeviceKind) {
+    INITIALIZED.fetch_or(kind.bit(), Ordering::Release);
+}
+
+pub fn is_initialized(kind: DeviceKind) -> bool {
+    INITIALIZED.load(Ordering::Acquire) & kind.bit() != 0
+}
+
+pub fn serial() -> Option<SerialToken> {
+    is_initialized(DeviceKind::Serial).then(|| unsafe { SerialToken::new() })
+}
+
+pub fn framebuffers() -> Option<FramebuffersToken> {
+    is_initialized(DeviceKind::Framebuffer).then(|| unsafe { FramebuffersToken::new() })
+}
+
+pub fn local_apic() -> Option<LocalApicToken> {
+    is_initialized(DeviceKind::LocalApic).then(|| unsafe { LocalApicToken::new() })
+}
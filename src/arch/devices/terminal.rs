@@ -0,0 +1,336 @@
+use core::fmt::Write;
+
+use super::{ansi::{self, Action, Palette}, framebuffer::{Pixel, RawFramebuffer, Rgb}};
+
+/// Monospace bitmap font, glyphs are at most 8 pixels wide
+pub trait Font {
+    /// Glyph (width, height) in pixels
+    fn glyph_size(&self) -> (usize, usize);
+
+    /// One byte per glyph row, most significant bit is the leftmost pixel
+    fn glyph(&self, ch: u8) -> Option<&[u8]>;
+}
+
+/// Font stored as consecutive glyphs starting at `first_char`
+#[derive(Clone, Copy, Debug)]
+pub struct BitmapFont<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub first_char: u8,
+    pub glyphs: &'a [u8],
+}
+
+impl<'a> Font for BitmapFont<'a> {
+    fn glyph_size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn glyph(&self, ch: u8) -> Option<&[u8]> {
+        let index = ch.checked_sub(self.first_char)? as usize;
+        self.glyphs.get(index * self.height..(index + 1) * self.height)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: u8,
+    pub foreground: Rgb,
+    pub background: Rgb,
+}
+
+impl Cell {
+    pub const fn blank(foreground: Rgb, background: Rgb) -> Self {
+        Self { ch: b' ', foreground, background }
+    }
+}
+
+/// Text terminal with a fixed size cell grid, usable before any allocator is initialized \
+/// Handles ANSI SGR colors and cursor home, see [ansi]
+#[derive(Clone, Debug)]
+pub struct StaticFramebufferTerminal<const COLS: usize, const ROWS: usize> {
+    cells: [[Cell; COLS]; ROWS],
+    column: usize,
+    row: usize,
+    foreground: Rgb,
+    background: Rgb,
+    /// Colors restored by an SGR reset
+    default_colors: (Rgb, Rgb),
+    /// Palette index of the foreground color set by SGR, `None` for the default color
+    foreground_index: Option<u8>,
+    /// SGR 1, normal palette colors are rendered as their bright variants
+    bold: bool,
+    palette: Palette,
+    parser: ansi::Parser,
+    /// Rows changed since the last render
+    dirty: [bool; ROWS],
+    /// Scrolls since the last render
+    scrolled: usize,
+    /// Cursor cell drawn by the last render
+    rendered_cursor: Option<(usize, usize)>,
+}
+
+impl<const COLS: usize, const ROWS: usize> StaticFramebufferTerminal<COLS, ROWS> {
+    pub const fn new(foreground: Rgb, background: Rgb) -> Self {
+        Self::with_palette(foreground, background, Palette::VGA)
+    }
+
+    pub const fn with_palette(foreground: Rgb, background: Rgb, palette: Palette) -> Self {
+        Self {
+            cells: [[Cell::blank(foreground, background); COLS]; ROWS],
+            column: 0,
+            row: 0,
+            foreground,
+            background,
+            default_colors: (foreground, background),
+            foreground_index: None,
+            bold: false,
+            palette,
+            parser: ansi::Parser::new(),
+            dirty: [true; ROWS],
+            scrolled: 0,
+            rendered_cursor: None,
+        }
+    }
+
+    pub fn cells(&self) -> &[[Cell; COLS]; ROWS] {
+        &self.cells
+    }
+
+    /// Cursor (column, row)
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.column, self.row)
+    }
+
+    /// Also sets the colors restored by an SGR reset
+    pub fn set_colors(&mut self, foreground: Rgb, background: Rgb) {
+        self.foreground = foreground;
+        self.background = background;
+        self.default_colors = (foreground, background);
+        self.foreground_index = None;
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.update_foreground();
+    }
+
+    /// Non-ASCII characters are written as `?`
+    pub fn write_char(&mut self, ch: char) {
+        match self.parser.feed(ch) {
+            Action::Print(ch) => self.put_char(ch),
+            Action::Sgr(parameters) => {
+                if parameters.is_empty() {
+                    self.apply_sgr(0);
+                }
+                for parameter in parameters.iter() {
+                    self.apply_sgr(parameter);
+                }
+            },
+            Action::CursorHome => {
+                self.column = 0;
+                self.row = 0;
+            },
+            Action::None => (),
+        }
+    }
+
+    /// Unsupported attributes are ignored
+    fn apply_sgr(&mut self, parameter: u16) {
+        match parameter {
+            0 => {
+                self.background = self.default_colors.1;
+                self.foreground_index = None;
+                self.bold = false;
+            },
+            1 => self.bold = true,
+            22 => self.bold = false,
+            30..=37 => self.foreground_index = Some(parameter as u8 - 30),
+            39 => self.foreground_index = None,
+            40..=47 => self.background = self.palette.color(parameter as u8 - 40),
+            49 => self.background = self.default_colors.1,
+            90..=97 => self.foreground_index = Some(parameter as u8 - 90 + 8),
+            100..=107 => self.background = self.palette.color(parameter as u8 - 100 + 8),
+            _ => (),
+        }
+        self.update_foreground();
+    }
+
+    /// Bold only brightens palette colors 0-7, the default color is kept as is
+    fn update_foreground(&mut self) {
+        self.foreground = match self.foreground_index {
+            Some(index) if self.bold && index < 8 => self.palette.color(index + 8),
+            Some(index) => self.palette.color(index),
+            None => self.default_colors.0,
+        };
+    }
+
+    fn put_char(&mut self, ch: char) {
+        match ch {
+            '\n' => self.new_line(),
+            '\r' => self.column = 0,
+            ch => {
+                if self.column >= COLS {
+                    self.new_line();
+                }
+                let ch = if ch.is_ascii() { ch as u8 } else { b'?' };
+                self.cells[self.row][self.column] = Cell { ch, foreground: self.foreground, background: self.background };
+                self.dirty[self.row] = true;
+                self.column += 1;
+            }
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < ROWS {
+            self.row += 1;
+        } else {
+            self.scroll_up();
+        }
+    }
+
+    /// Moves all rows up by one, clearing the last row
+    pub fn scroll_up(&mut self) {
+        if ROWS == 0 {
+            return;
+        }
+        self.cells.copy_within(1.., 0);
+        self.cells[ROWS - 1] = [Cell::blank(self.foreground, self.background); COLS];
+        self.dirty.copy_within(1.., 0);
+        self.dirty[ROWS - 1] = true;
+        self.scrolled += 1;
+    }
+
+    pub fn clear(&mut self) {
+        self.cells = [[Cell::blank(self.foreground, self.background); COLS]; ROWS];
+        self.column = 0;
+        self.row = 0;
+        self.dirty = [true; ROWS];
+        self.scrolled = 0;
+    }
+
+    /// Draws the whole grid starting at the top left corner, cells outside the framebuffer are skipped
+    pub fn render(&mut self, framebuffer: &RawFramebuffer, font: &impl Font) {
+        self.dirty = [true; ROWS];
+        self.scrolled = 0;
+        self.render_incremental(framebuffer, font);
+    }
+
+    /// Redraws only the rows changed since the last render, scrolls are applied to the framebuffer directly
+    pub fn render_incremental(&mut self, framebuffer: &RawFramebuffer, font: &impl Font) {
+        let (glyph_width, glyph_height) = font.glyph_size();
+
+        // Erased before scrolling, so at its previous position
+        if let Some((column, row)) = self.rendered_cursor.take() {
+            let background = row
+                .checked_sub(self.scrolled)
+                .and_then(|row| self.cells[row].get(column))
+                .map_or(self.background, |x| x.background);
+            framebuffer.erase_cursor(Self::cell_origin(column, row, font), (glyph_width, glyph_height), background);
+            // Redraw the glyph under the cursor
+            if let Some(row) = row.checked_sub(self.scrolled) {
+                self.dirty[row] = true;
+            }
+        }
+
+        if self.scrolled > 0 {
+            // Rows revealed by the scroll are dirty
+            framebuffer.scroll_region_up(0..ROWS * glyph_height, self.scrolled * glyph_height, self.background);
+            self.scrolled = 0;
+        }
+
+        for (row, cells) in self.cells.iter().enumerate() {
+            if !core::mem::take(&mut self.dirty[row]) {
+                continue;
+            }
+            for (column, cell) in cells.iter().enumerate() {
+                let origin = Self::cell_origin(column, row, font);
+                if origin.x + glyph_width > framebuffer.info.width || origin.y + glyph_height > framebuffer.info.height {
+                    continue;
+                }
+                render_cell(framebuffer, font, origin, cell);
+            }
+        }
+    }
+
+    /// Draws a block cursor over the current cell, erased by the next render
+    pub fn render_cursor(&mut self, framebuffer: &RawFramebuffer, font: &impl Font, color: Rgb) {
+        let column = self.column.min(COLS.saturating_sub(1));
+        framebuffer.draw_cursor(Self::cell_origin(column, self.row, font), font.glyph_size(), color);
+        self.rendered_cursor = Some((column, self.row));
+    }
+
+    fn cell_origin(column: usize, row: usize, font: &impl Font) -> Pixel {
+        let (glyph_width, glyph_height) = font.glyph_size();
+        Pixel { x: column * glyph_width, y: row * glyph_height }
+    }
+}
+
+/// `origin` must leave space for a whole glyph
+fn render_cell(framebuffer: &RawFramebuffer, font: &impl Font, origin: Pixel, cell: &Cell) {
+    let (glyph_width, glyph_height) = font.glyph_size();
+    let glyph = font.glyph(cell.ch);
+    for y in 0..glyph_height {
+        let bits = glyph.and_then(|x| x.get(y)).copied().unwrap_or(0);
+        for x in 0..glyph_width {
+            let color = if bits & (0x80 >> x) != 0 { cell.foreground } else { cell.background };
+            unsafe {
+                framebuffer.write_pixel_rgb_unchecked(origin + (x, y), color);
+            }
+        }
+    }
+}
+
+impl<const COLS: usize, const ROWS: usize> Write for StaticFramebufferTerminal<COLS, ROWS> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for ch in s.chars() {
+            self.write_char(ch);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Rgb = Rgb::from_argb32(0xffffff);
+    const BLACK: Rgb = Rgb::from_argb32(0x000000);
+
+    fn text<const COLS: usize, const ROWS: usize>(terminal: &StaticFramebufferTerminal<COLS, ROWS>) -> [String; ROWS] {
+        terminal.cells().map(|row| row.iter().map(|cell| cell.ch as char).collect())
+    }
+
+    #[test]
+    fn scrolling_shifts_the_grid() {
+        let mut terminal = StaticFramebufferTerminal::<4, 2>::new(WHITE, BLACK);
+        _ = terminal.write_str("abcd");
+        assert_eq!(text(&terminal), ["abcd", "    "]);
+        assert_eq!(terminal.cursor(), (4, 0));
+
+        // Wraps to the second row
+        _ = terminal.write_str("ef");
+        assert_eq!(text(&terminal), ["abcd", "ef  "]);
+
+        // The last row is full, scrolls once
+        _ = terminal.write_str("\x1b[44mgh\n");
+        assert_eq!(text(&terminal), ["efgh", "    "]);
+        assert_eq!(terminal.cursor(), (0, 1));
+        // The revealed row uses the current background
+        assert!(terminal.cells()[1].iter().all(|cell| cell.background == Palette::VGA.color(4)));
+
+        _ = terminal.write_str("ij\nkl");
+        assert_eq!(text(&terminal), ["ij  ", "kl  "]);
+        assert_eq!(terminal.scrolled, 2);
+        assert!(terminal.dirty.iter().all(|&dirty| dirty));
+    }
+
+    #[test]
+    fn bold_renders_normal_colors_as_bright() {
+        let mut terminal = StaticFramebufferTerminal::<8, 2>::new(WHITE, BLACK);
+        _ = terminal.write_str("\x1b[31ma\x1b[1mb\x1b[22mc\x1b[1;32md\x1b[91me\x1b[0mf\x1b[1mg");
+        let colors = terminal.cells()[0].map(|cell| cell.foreground);
+        let vga = Palette::VGA;
+        assert_eq!(colors[..7], [vga.color(1), vga.color(9), vga.color(1), vga.color(10), vga.color(9), WHITE, WHITE]);
+    }
+}
@@ -10,8 +10,11 @@ pub use x86_64::*;
 
 use core::{fmt::{Display, Pointer, Debug}, ops::{Add, Sub, AddAssign, SubAssign, Rem, RemAssign}};
 
+use static_assertions::const_assert_eq;
+
 use crate::common::DebugHex;
 
+/// Deliberately not convertible from pointers, which are virtual - see `paging::translate` and `paging::to_virtual`
 #[repr(transparent)]
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct PhysicalAddress(usize);
@@ -32,12 +35,49 @@ impl PhysicalAddress {
         Self(self.0 / rhs * rhs)
     }
 
+    /// Same as [PhysicalAddress::next_multiple_of]
+    #[must_use]
+    pub const fn align_up(self, alignment: usize) -> Self {
+        self.next_multiple_of(alignment)
+    }
+
+    /// Same as [PhysicalAddress::last_multiple_of]
+    #[must_use]
+    pub const fn align_down(self, alignment: usize) -> Self {
+        self.last_multiple_of(alignment)
+    }
+
+    #[must_use]
+    pub const fn page_align_up(self) -> Self {
+        self.align_up(paging::PAGE_SIZE)
+    }
+
+    #[must_use]
+    pub const fn page_align_down(self) -> Self {
+        self.align_down(paging::PAGE_SIZE)
+    }
+
+    #[must_use]
+    pub const fn is_page_aligned(self) -> bool {
+        self.0 % paging::PAGE_SIZE == 0
+    }
+
     #[must_use]
     pub const fn is_aligned<T>(&self) -> bool {
         // TODO: refactor to core::ptr::Alignment when stablized
         (self.0 % core::mem::align_of::<T>()) == 0
     }
 
+    #[must_use]
+    pub const fn as_usize(self) -> usize {
+        self.0
+    }
+
+    #[must_use]
+    pub const fn as_u64(self) -> u64 {
+        self.0 as u64
+    }
+
     #[must_use]
     pub const fn is_aligned_to(&self, alignment: usize) -> bool {
         (self.0 % alignment) == 0
@@ -107,6 +147,16 @@ impl From<u64> for PhysicalAddress {
     }
 }
 
+/// Fails if `value` doesn't fit in `usize`
+#[cfg(not(target_pointer_width = "64"))]
+impl TryFrom<u64> for PhysicalAddress {
+    type Error = core::num::TryFromIntError;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        usize::try_from(value).map(Self)
+    }
+}
+
 impl From<PhysicalAddress> for usize {
     fn from(val: PhysicalAddress) -> Self {
         val.0
@@ -137,6 +187,35 @@ impl Debug for PhysicalAddress {
     }
 }
 
+const_assert_eq!(PhysicalAddress::new(0x1234_5000).as_usize(), 0x1234_5000);
+const_assert_eq!(PhysicalAddress::new(usize::MAX).as_u64(), usize::MAX as u64);
+
+/// Half-open address range `[start, end)`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AddressRange<T> {
+    pub start: T,
+    pub end: T,
+}
+
+impl<T: Copy + Ord + Sub<T, Output = usize>> AddressRange<T> {
+    pub fn new(start: T, end: T) -> Self {
+        debug_assert!(start <= end);
+        Self { start, end }
+    }
+
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    pub fn contains(&self, address: T) -> bool {
+        self.start <= address && address < self.end
+    }
+}
+
 #[repr(transparent)]
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct VirtualAddress(usize);
@@ -157,6 +236,33 @@ impl VirtualAddress {
         Self(self.0 / rhs * rhs)
     }
 
+    /// Same as [VirtualAddress::next_multiple_of]
+    #[must_use]
+    pub const fn align_up(self, alignment: usize) -> Self {
+        self.next_multiple_of(alignment)
+    }
+
+    /// Same as [VirtualAddress::last_multiple_of]
+    #[must_use]
+    pub const fn align_down(self, alignment: usize) -> Self {
+        self.last_multiple_of(alignment)
+    }
+
+    #[must_use]
+    pub const fn page_align_up(self) -> Self {
+        self.align_up(paging::PAGE_SIZE)
+    }
+
+    #[must_use]
+    pub const fn page_align_down(self) -> Self {
+        self.align_down(paging::PAGE_SIZE)
+    }
+
+    #[must_use]
+    pub const fn is_page_aligned(self) -> bool {
+        self.0 % paging::PAGE_SIZE == 0
+    }
+
     #[must_use]
     pub const fn as_ptr(&self) -> *const () {
         self.0 as *const ()
@@ -166,6 +272,16 @@ impl VirtualAddress {
     pub const fn as_mut_ptr(&self) -> *mut () {
         self.0 as *mut ()
     }
+
+    #[must_use]
+    pub const fn as_usize(self) -> usize {
+        self.0
+    }
+
+    #[must_use]
+    pub const fn as_u64(self) -> u64 {
+        self.0 as u64
+    }
 }
 
 impl Add<usize> for VirtualAddress {
@@ -243,6 +359,16 @@ impl From<u64> for VirtualAddress {
     }
 }
 
+/// Fails if `value` doesn't fit in `usize`
+#[cfg(not(target_pointer_width = "64"))]
+impl TryFrom<u64> for VirtualAddress {
+    type Error = core::num::TryFromIntError;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        usize::try_from(value).map(Self)
+    }
+}
+
 impl From<VirtualAddress> for usize {
     fn from(val: VirtualAddress) -> Self {
         val.0
@@ -255,6 +381,18 @@ impl From<VirtualAddress> for u64 {
     }
 }
 
+impl<T> From<VirtualAddress> for *const T {
+    fn from(val: VirtualAddress) -> Self {
+        val.0 as *const T
+    }
+}
+
+impl<T> From<VirtualAddress> for *mut T {
+    fn from(val: VirtualAddress) -> Self {
+        val.0 as *mut T
+    }
+}
+
 impl Pointer for VirtualAddress {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.write_fmt(format_args!("{:#x}", self.0))
@@ -273,3 +411,34 @@ impl Debug for VirtualAddress {
     }
 }
 
+const_assert_eq!(VirtualAddress::new(0x1234_5000).as_usize(), 0x1234_5000);
+const_assert_eq!(VirtualAddress::new(usize::MAX).as_u64(), usize::MAX as u64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unaligned_addresses_align_both_ways() {
+        let address = PhysicalAddress::new(0x1234_5678);
+        assert_eq!(address.align_up(0x1000), PhysicalAddress::new(0x1234_6000));
+        assert_eq!(address.align_down(0x1000), PhysicalAddress::new(0x1234_5000));
+        assert_eq!(address.align_up(0x10), PhysicalAddress::new(0x1234_5680));
+        assert_eq!(address.page_align_up(), address.next_multiple_of(paging::PAGE_SIZE));
+        assert_eq!(address.page_align_down(), address.last_multiple_of(paging::PAGE_SIZE));
+        assert!(!address.is_page_aligned());
+        assert!(address.page_align_up().is_page_aligned());
+
+        let address = VirtualAddress::new(0xFFFF_8000_0000_0FFF);
+        assert_eq!(address.page_align_up(), VirtualAddress::new(0xFFFF_8000_0000_1000));
+        assert_eq!(address.page_align_down(), VirtualAddress::new(0xFFFF_8000_0000_0000));
+        assert_eq!(address.align_up(0x20_0000), VirtualAddress::new(0xFFFF_8000_0020_0000));
+        assert!(!address.is_page_aligned());
+
+        // Aligned addresses are unchanged
+        let aligned = VirtualAddress::new(0x20_0000);
+        assert!(aligned.is_page_aligned());
+        assert_eq!(aligned.page_align_up(), aligned);
+        assert_eq!(aligned.align_down(0x20_0000), aligned);
+    }
+}
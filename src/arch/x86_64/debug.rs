@@ -0,0 +1,159 @@
+// Hardware debugging support: single-stepping (RFLAGS.TF) and breakpoints in DR0-DR3
+// DR7 layout (per slot n):
+// 2n       local enable
+// 2n + 1   global enable (unused, no task switches)
+// 16 + 4n  condition (00 - execute, 01 - write, 11 - read / write)
+// 18 + 4n  length (00 - 1 byte, 01 - 2 bytes, 11 - 4 bytes, 10 - 8 bytes)
+// DR6 bits 0:3 report the breakpoints that were hit, bit 14 a single-step trap, DR6 isn't cleared by the CPU
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{arch::VirtualAddress, common::{bits::BitField, macros::assert_arg}};
+
+use super::intrinsics::{read_dr, write_dr};
+
+pub const BREAKPOINT_SLOTS: u8 = 4;
+
+static SINGLE_STEP: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakpointLength {
+    One,
+    Two,
+    Four,
+    Eight,
+}
+
+impl BreakpointLength {
+    const fn encode(self) -> u64 {
+        match self {
+            BreakpointLength::One => 0b00,
+            BreakpointLength::Two => 0b01,
+            BreakpointLength::Four => 0b11,
+            BreakpointLength::Eight => 0b10,
+        }
+    }
+
+    pub const fn bytes(self) -> usize {
+        match self {
+            BreakpointLength::One => 1,
+            BreakpointLength::Two => 2,
+            BreakpointLength::Four => 4,
+            BreakpointLength::Eight => 8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakpointKind {
+    Execute,
+    Write(BreakpointLength),
+    ReadWrite(BreakpointLength),
+}
+
+impl BreakpointKind {
+    /// (condition, length) fields
+    const fn encode(self) -> (u64, u64) {
+        match self {
+            // Execute breakpoints require a length of 1
+            BreakpointKind::Execute => (0b00, BreakpointLength::One.encode()),
+            BreakpointKind::Write(length) => (0b01, length.encode()),
+            BreakpointKind::ReadWrite(length) => (0b11, length.encode()),
+        }
+    }
+}
+
+/// Returns `dr7` with breakpoint `slot` enabled as `kind`, other slots are preserved
+pub const fn dr7_with_breakpoint(dr7: u64, slot: u8, kind: BreakpointKind) -> u64 {
+    let (condition, length) = kind.encode();
+    let slot = slot as u32;
+    let enable = BitField::bit(2 * slot);
+    let condition_field = BitField::new(16 + 4 * slot, 17 + 4 * slot);
+    let length_field = BitField::new(18 + 4 * slot, 19 + 4 * slot);
+
+    let dr7 = enable.set(dr7, 1);
+    let dr7 = condition_field.set(dr7, condition);
+    length_field.set(dr7, length)
+}
+
+/// Returns `dr7` with breakpoint `slot` disabled
+pub const fn dr7_without_breakpoint(dr7: u64, slot: u8) -> u64 {
+    let slot = slot as u32;
+    let slot_fields = BitField::bit(2 * slot).mask() | BitField::new(16 + 4 * slot, 19 + 4 * slot).mask();
+    dr7 & !slot_fields
+}
+
+// execute breakpoint in slot 0: L0 set, R/W0 and LEN0 clear
+static_assertions::const_assert_eq!(dr7_with_breakpoint(0, 0, BreakpointKind::Execute), 0b1);
+static_assertions::const_assert_eq!(dr7_with_breakpoint(u64::MAX, 0, BreakpointKind::Execute), u64::MAX & !(0b1111 << 16));
+static_assertions::const_assert_eq!(
+    dr7_with_breakpoint(0, 3, BreakpointKind::ReadWrite(BreakpointLength::Eight)),
+    1 << 6 | 0b1011 << 28
+);
+
+/// Programs hardware breakpoint `slot` (0 - 3) on the current CPU \
+/// `address` must be aligned to the breakpoint length \
+/// Safety:
+/// The #DB handler must be installed
+pub unsafe fn set_hw_breakpoint(slot: u8, address: VirtualAddress, kind: BreakpointKind) {
+    assert_arg!(slot, slot < BREAKPOINT_SLOTS);
+    if let BreakpointKind::Write(length) | BreakpointKind::ReadWrite(length) = kind {
+        assert_arg!(address, address % length.bytes() == 0, "Must be aligned to the breakpoint length");
+    }
+
+    let address = usize::from(address) as u64;
+    unsafe {
+        match slot {
+            0 => write_dr!(0, address),
+            1 => write_dr!(1, address),
+            2 => write_dr!(2, address),
+            _ => write_dr!(3, address),
+        };
+        write_dr!(7, dr7_with_breakpoint(read_dr!(7), slot, kind));
+    }
+}
+
+/// Disables hardware breakpoint `slot` (0 - 3) on the current CPU
+pub fn clear_hw_breakpoint(slot: u8) {
+    assert_arg!(slot, slot < BREAKPOINT_SLOTS);
+    unsafe {
+        write_dr!(7, dr7_without_breakpoint(read_dr!(7), slot));
+    }
+}
+
+/// Keeps the trap flag set after every #DB, so single-stepping continues once started with `StackFrame::set_trap_flag`
+pub fn set_single_step(enabled: bool) {
+    SINGLE_STEP.store(enabled, Ordering::SeqCst);
+}
+
+pub fn single_step() -> bool {
+    SINGLE_STEP.load(Ordering::SeqCst)
+}
+
+/// Decoded DR6
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DebugStatus(pub u64);
+
+impl DebugStatus {
+    const SINGLE_STEP: u64 = 1 << 14;
+
+    /// Reads and clears DR6 on the current CPU
+    pub fn take() -> Self {
+        unsafe {
+            let status = read_dr!(6);
+            // Reserved bits keep their default values
+            write_dr!(6, 0xFFFF0FF0);
+            Self(status)
+        }
+    }
+
+    /// Lowest breakpoint slot that was hit
+    pub fn breakpoint(self) -> Option<u8> {
+        let hits = self.0 & 0b1111;
+        (hits != 0).then(|| hits.trailing_zeros() as u8)
+    }
+
+    pub fn single_step(self) -> bool {
+        self.0 & Self::SINGLE_STEP != 0
+    }
+}
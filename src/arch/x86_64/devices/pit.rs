@@ -0,0 +1,58 @@
+// 8254 programmable interval timer, only used as a reference clock to calibrate other counters
+// Channel 2 (the PC speaker channel) is gated by bit 0 of port 0x61 and its output is readable at bit 5,
+// the speaker enable (bit 1) is kept clear. In mode 0 the output goes high once the count reaches zero
+
+use static_assertions::const_assert_eq;
+
+use crate::arch::intrinsics::{port_read_u8, port_write_u8, without_interrupts};
+
+/// Input clock in hertz
+pub const FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL_2_PORT: u16 = 0x42;
+const COMMAND_PORT: u16 = 0x43;
+const GATE_PORT: u16 = 0x61;
+
+/// Channel 2, low then high byte, mode 0 (interrupt on terminal count), binary
+const CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+const GATE_CHANNEL_2: u8 = 1 << 0;
+const SPEAKER_ENABLE: u8 = 1 << 1;
+const CHANNEL_2_OUTPUT: u8 = 1 << 5;
+
+/// 10 ms countdown
+const CALIBRATION_TICKS: u16 = (FREQUENCY / 100) as u16;
+/// Upper bound of output polls (each port read takes about a microsecond) before the PIT is assumed missing
+const MAX_POLLS: usize = 1_000_000;
+
+/// Frequency in hertz of `counter`, measured over a 10 ms countdown of channel 2 \
+/// `counter` must increase monotonically (it may wrap around), interrupts are disabled during the measurement \
+/// Returns `None` if the countdown doesn't finish (no PIT or channel 2 not gated through port 0x61)
+pub fn calibrate(mut counter: impl FnMut() -> u64) -> Option<u64> {
+    without_interrupts(|| unsafe {
+        let gate = port_read_u8(GATE_PORT) & !(GATE_CHANNEL_2 | SPEAKER_ENABLE);
+        // The count is loaded while the gate is low, raising it starts the countdown
+        port_write_u8(GATE_PORT, gate);
+        port_write_u8(COMMAND_PORT, CHANNEL_2_ONE_SHOT);
+        port_write_u8(CHANNEL_2_PORT, CALIBRATION_TICKS as u8);
+        port_write_u8(CHANNEL_2_PORT, (CALIBRATION_TICKS >> 8) as u8);
+
+        let start = counter();
+        port_write_u8(GATE_PORT, gate | GATE_CHANNEL_2);
+        let finished = (0..MAX_POLLS).any(|_| port_read_u8(GATE_PORT) & CHANNEL_2_OUTPUT != 0);
+        let elapsed = counter().wrapping_sub(start);
+        port_write_u8(GATE_PORT, gate);
+
+        finished.then(|| counter_frequency(elapsed, CALIBRATION_TICKS))
+    })
+}
+
+/// Frequency of a counter that advanced by `elapsed` during `pit_ticks` PIT ticks
+const fn counter_frequency(elapsed: u64, pit_ticks: u16) -> u64 {
+    (elapsed as u128 * FREQUENCY as u128 / pit_ticks as u128) as u64
+}
+
+const_assert_eq!(CALIBRATION_TICKS, 11931);
+// A counter running at the PIT frequency
+const_assert_eq!(counter_frequency(CALIBRATION_TICKS as u64, CALIBRATION_TICKS), FREQUENCY);
+// The countdown is slightly shorter than 10 ms
+const_assert_eq!(counter_frequency(30_000_000, CALIBRATION_TICKS), 3_000_206_185);
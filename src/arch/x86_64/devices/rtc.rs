@@ -0,0 +1,140 @@
+// CMOS real-time clock, a wall clock source that doesn't depend on the bootloader
+// Registers are selected through port 0x70 (bit 7 also masks NMIs, kept clear) and accessed through port 0x71
+// Register B selects binary / BCD values (bit 2) and 24 / 12 hour format (bit 1), in the 12 hour format
+// bit 7 of the hour marks PM
+// The century register location comes from the ACPI FADT, until ACPI is parsed years are assumed to be 20xx
+
+use static_assertions::{const_assert, const_assert_eq};
+
+use crate::{arch::intrinsics::{port_read_u8, port_write_u8, without_interrupts}, common::time::{DateTime, UnixEpochTime}};
+
+const INDEX_PORT: u16 = 0x70;
+const DATA_PORT: u16 = 0x71;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0A;
+const STATUS_B: u8 = 0x0B;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const HOUR_PM: u8 = 1 << 7;
+
+const CENTURY: u32 = 2000;
+
+/// Register values as stored by the RTC
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct RawTime {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+}
+
+/// Current wall-clock time, `None` if the RTC holds an invalid date (e.g. a dead CMOS battery)
+pub fn now() -> Option<UnixEpochTime> {
+    read().map(DateTime::unix_time)
+}
+
+/// Current UTC date and time, assuming the RTC is set to UTC \
+/// Returns `None` if any of the registers is out of range
+pub fn read() -> Option<DateTime> {
+    let (raw, status_b) = without_interrupts(|| {
+        // The values can change between the reads, repeat until two consecutive reads agree
+        let mut last = read_raw();
+        loop {
+            let current = read_raw();
+            if current == last {
+                break;
+            }
+            last = current;
+        }
+        (last, unsafe { read_register(STATUS_B) })
+    });
+    decode(raw, status_b)
+}
+
+fn read_raw() -> RawTime {
+    unsafe {
+        // The registers are inconsistent during an update (~2 ms each second)
+        while read_register(STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+            core::hint::spin_loop();
+        }
+
+        RawTime {
+            second: read_register(SECONDS),
+            minute: read_register(MINUTES),
+            hour: read_register(HOURS),
+            day: read_register(DAY),
+            month: read_register(MONTH),
+            year: read_register(YEAR),
+        }
+    }
+}
+
+unsafe fn read_register(register: u8) -> u8 {
+    unsafe {
+        port_write_u8(INDEX_PORT, register);
+        port_read_u8(DATA_PORT)
+    }
+}
+
+const fn decode(raw: RawTime, status_b: u8) -> Option<DateTime> {
+    const fn convert(value: u8, status_b: u8) -> u8 {
+        if status_b & STATUS_B_BINARY != 0 { value } else { bcd_to_binary(value) }
+    }
+
+    let pm = raw.hour & HOUR_PM != 0;
+    let mut hour = convert(raw.hour & !HOUR_PM, status_b);
+    if status_b & STATUS_B_24_HOUR == 0 {
+        // 12 AM is midnight, 12 PM is noon
+        hour = hour % 12 + if pm { 12 } else { 0 };
+    }
+
+    let date_time = DateTime {
+        year: CENTURY + convert(raw.year, status_b) as u32,
+        month: convert(raw.month, status_b),
+        day: convert(raw.day, status_b),
+        hour,
+        minute: convert(raw.minute, status_b),
+        second: convert(raw.second, status_b),
+    };
+    if date_time.is_valid() { Some(date_time) } else { None }
+}
+
+pub const fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+const_assert_eq!(bcd_to_binary(0x00), 0);
+const_assert_eq!(bcd_to_binary(0x09), 9);
+const_assert_eq!(bcd_to_binary(0x59), 59);
+const_assert_eq!(bcd_to_binary(0x99), 99);
+
+// 2024-02-29 13:45:30, BCD, 12 hour format
+const_assert_eq!(
+    decode(RawTime { second: 0x30, minute: 0x45, hour: HOUR_PM | 0x01, day: 0x29, month: 0x02, year: 0x24 }, 0)
+        .unwrap().unix_time().seconds(),
+    1709214330
+);
+// 12 AM, binary, 12 hour format
+const_assert_eq!(decode(RawTime { second: 0, minute: 0, hour: 12, day: 1, month: 1, year: 0 }, STATUS_B_BINARY).unwrap().hour, 0);
+// 2000-01-01 00:00:00, binary, 24 hour format
+const_assert_eq!(
+    decode(RawTime { second: 0, minute: 0, hour: 0, day: 1, month: 1, year: 0 }, STATUS_B_BINARY | STATUS_B_24_HOUR)
+        .unwrap().unix_time().seconds(),
+    946684800
+);
+// Out of range registers: day 0, month 13, 2023-02-29 and a missing RTC (all bits set)
+const_assert!(decode(RawTime { second: 0, minute: 0, hour: 0, day: 0, month: 1, year: 0 }, STATUS_B_BINARY).is_none());
+const_assert!(decode(RawTime { second: 0, minute: 0, hour: 0, day: 1, month: 13, year: 0 }, STATUS_B_BINARY).is_none());
+const_assert!(decode(RawTime { second: 0, minute: 0, hour: 0, day: 0x29, month: 0x02, year: 0x23 }, 0).is_none());
+const_assert!(decode(RawTime { second: 0xFF, minute: 0xFF, hour: 0xFF, day: 0xFF, month: 0xFF, year: 0xFF }, 0xFF).is_none());
+const_assert_eq!(UnixEpochTime::new(1709214330000).date_time().unix_time().millis(), 1709214330000);
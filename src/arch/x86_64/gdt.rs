@@ -0,0 +1,136 @@
+// Global descriptor table and task state segment
+// Long mode ignores segment bases and limits (except FS / GS), the GDT only provides code segments
+// for both privilege levels and the TSS descriptor. The TSS holds the interrupt stack table (IST),
+// gates with a non-zero IST index always switch to a known-good stack
+// Selector layout, follows the IA32_STAR constraints (see `syscalls`):
+// 0x00 - null
+// 0x08 - kernel code, 0x10 - kernel data
+// 0x18 - user base (32-bit user code, unused), 0x20 - user data, 0x28 - user code
+// 0x30 - TSS (16 bytes, two entries)
+
+use static_assertions::const_assert_eq;
+
+use crate::arch::VirtualAddress;
+
+use super::{PrivilegeLevel, SegmentIndex};
+
+pub const KERNEL_CODE: SegmentIndex = SegmentIndex::new(1);
+pub const KERNEL_DATA: SegmentIndex = SegmentIndex::new(2);
+pub const USER_BASE: SegmentIndex = SegmentIndex::new(3);
+pub const USER_DATA: SegmentIndex = SegmentIndex::new(4);
+pub const USER_CODE: SegmentIndex = SegmentIndex::new(5);
+pub const TASK_STATE: SegmentIndex = SegmentIndex::new(6);
+
+const ENTRY_COUNT: usize = 8;
+
+// Present, accessed, limit 0xFFFFF in 4 KiB units, code segments have the L (64-bit) flag set
+const KERNEL_CODE_DESCRIPTOR: u64 = 0x00AF_9B00_0000_FFFF;
+const KERNEL_DATA_DESCRIPTOR: u64 = 0x00CF_9300_0000_FFFF;
+const USER_BASE_DESCRIPTOR: u64 = 0x00CF_FB00_0000_FFFF;
+const USER_DATA_DESCRIPTOR: u64 = 0x00CF_F300_0000_FFFF;
+const USER_CODE_DESCRIPTOR: u64 = 0x00AF_FB00_0000_FFFF;
+
+/// Available 64-bit TSS
+const TSS_TYPE: u64 = 0x9;
+const PRESENT: u64 = 1 << 47;
+
+#[repr(C, align(16))]
+#[derive(Clone, Debug)]
+pub struct Gdt {
+    entries: [u64; ENTRY_COUNT],
+}
+const_assert_eq!(core::mem::size_of::<Gdt>(), ENTRY_COUNT * 8);
+
+impl Gdt {
+    /// Flat kernel / user segments and a descriptor for `tss`
+    pub fn new(tss: &'static TaskStateSegment) -> Self {
+        let (low, high) = tss_descriptor(VirtualAddress::new(tss as *const TaskStateSegment as usize));
+        let mut entries = [0; ENTRY_COUNT];
+        entries[KERNEL_CODE.value() as usize] = KERNEL_CODE_DESCRIPTOR;
+        entries[KERNEL_DATA.value() as usize] = KERNEL_DATA_DESCRIPTOR;
+        entries[USER_BASE.value() as usize] = USER_BASE_DESCRIPTOR;
+        entries[USER_DATA.value() as usize] = USER_DATA_DESCRIPTOR;
+        entries[USER_CODE.value() as usize] = USER_CODE_DESCRIPTOR;
+        entries[TASK_STATE.value() as usize] = low;
+        entries[TASK_STATE.value() as usize + 1] = high;
+        Self { entries }
+    }
+
+    /// Loads the GDT and the task register on the current CPU, reloads CS, DS, ES and SS \
+    /// FS and GS are left alone, loading them would clear their base
+    pub fn load(&'static self) {
+        super::intrinsics::load_gdt(self, core::mem::size_of::<Self>());
+        // SAFETY: the selectors refer to flat kernel segments of the table loaded above
+        unsafe {
+            super::intrinsics::reload_segments(
+                KERNEL_CODE.selector(PrivilegeLevel::KERNEL),
+                KERNEL_DATA.selector(PrivilegeLevel::KERNEL)
+            );
+        }
+        // SAFETY: the TSS descriptor is present and not busy, every processor has its own GDT
+        unsafe { super::intrinsics::load_task_register(TASK_STATE.selector(PrivilegeLevel::KERNEL)) };
+    }
+}
+
+/// Low and high halves of a 16-byte TSS descriptor
+const fn tss_descriptor(address: VirtualAddress) -> (u64, u64) {
+    let base = address.0 as u64;
+    let limit = (core::mem::size_of::<TaskStateSegment>() - 1) as u64;
+    let low = (limit & 0xFFFF)
+        | (base & 0xFF_FFFF) << 16
+        | TSS_TYPE << 40
+        | PRESENT
+        | (limit >> 16 & 0xF) << 48
+        | (base >> 24 & 0xFF) << 56;
+    (low, base >> 32)
+}
+
+const_assert_eq!(tss_descriptor(VirtualAddress::new(0xFFFF_8000_1234_5678)).0, 0x1200_8934_5678_0067);
+const_assert_eq!(tss_descriptor(VirtualAddress::new(0xFFFF_8000_1234_5678)).1, 0xFFFF_8000);
+const_assert_eq!(KERNEL_CODE.selector(PrivilegeLevel::KERNEL), 0x08);
+const_assert_eq!(USER_BASE.selector(PrivilegeLevel::KERNEL) + 16, USER_CODE.selector(PrivilegeLevel::KERNEL));
+
+/// 64-bit task state segment, only the stack pointers are used
+#[repr(C, packed(4))]
+#[derive(Clone, Copy, Debug)]
+pub struct TaskStateSegment {
+    _reserved_0: u32,
+    /// RSP0-2, loaded on privilege level changes
+    pub privilege_stacks: [u64; 3],
+    _reserved_1: u64,
+    /// IST1-7, IST index `n` of an IDT entry selects `interrupt_stacks[n - 1]`
+    pub interrupt_stacks: [u64; 7],
+    _reserved_2: u64,
+    _reserved_3: u16,
+    /// Past the segment limit - no I/O permission bitmap
+    pub io_map_base: u16,
+}
+const_assert_eq!(core::mem::size_of::<TaskStateSegment>(), 104);
+
+impl TaskStateSegment {
+    pub const fn new() -> Self {
+        Self {
+            _reserved_0: 0,
+            privilege_stacks: [0; 3],
+            _reserved_1: 0,
+            interrupt_stacks: [0; 7],
+            _reserved_2: 0,
+            _reserved_3: 0,
+            io_map_base: core::mem::size_of::<Self>() as u16,
+        }
+    }
+
+    /// Sets the stack used by IDT entries with IST index `index` (1-7)
+    pub fn set_interrupt_stack(&mut self, index: u8, top: VirtualAddress) {
+        assert!((1..=7).contains(&index), "IST index must be in [1, 7]");
+        let mut stacks = self.interrupt_stacks;
+        stacks[index as usize - 1] = top.0 as u64;
+        self.interrupt_stacks = stacks;
+    }
+}
+
+impl Default for TaskStateSegment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
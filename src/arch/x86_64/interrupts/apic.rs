@@ -0,0 +1,451 @@
+use spin::Once;
+
+use crate::{
+    arch::{devices::{pit, registry::{self, DeviceKind}}, intrinsics::{cpuid, read_msr, write_msr}, paging::{self, IdentityMapToken}, PhysicalAddress, VirtualAddress},
+    common::{bits::BitField, time::ticks}
+};
+
+use super::{define_interrupt_handler, idt::IdtVector, InterruptController, InterruptHandler, SpuriousInterrupt, StackFrame};
+
+const IA32_APIC_BASE_MSR: u32 = 0x1B;
+const APIC_BASE_ADDRESS_MASK: u64 = 0xFFFFFFFFFF000;
+const APIC_BASE_X2APIC_ENABLE_BIT: u64 = 1 << 10;
+const APIC_BASE_GLOBAL_ENABLE_BIT: u64 = 1 << 11;
+
+/// x2APIC registers are MSRs at `X2APIC_MSR_BASE + (xAPIC offset >> 4)`
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+static LOCAL_APIC: Once<LocalApic> = Once::new();
+
+/// This function may only be called once, all subsequent calls will be ignored \
+/// Switches to x2APIC mode if supported
+pub fn initialize_local_apic(identity_map: IdentityMapToken) -> &'static LocalApic {
+    LOCAL_APIC.call_once(|| unsafe {
+        let apic = LocalApic::new(identity_map);
+        registry::mark_initialized(DeviceKind::LocalApic);
+        apic
+    })
+}
+
+/// Will panic if not initialized
+pub fn local_apic() -> &'static LocalApic {
+    LOCAL_APIC.get().expect("Local APIC uninitialized")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApicMode {
+    /// Memory mapped registers
+    XApic { base: VirtualAddress },
+    /// MSR-based registers, required for APIC IDs above 255
+    X2Apic,
+}
+
+impl ApicMode {
+    /// Extracts the APIC ID from an ID register value
+    pub const fn id_from_register(self, value: u32) -> u32 {
+        match self {
+            ApicMode::XApic { .. } => value >> 24,
+            ApicMode::X2Apic => value,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct ApicId(pub u32);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DeliveryMode {
+    Fixed = 0b000,
+    Nmi = 0b100,
+    Init = 0b101,
+    StartUp = 0b110,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DestinationShorthand {
+    /// Use the destination field
+    None = 0b00,
+    ToSelf = 0b01,
+    AllIncludingSelf = 0b10,
+    AllExcludingSelf = 0b11,
+}
+
+/// Interrupt Command Register contents
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InterruptCommand {
+    pub vector: u8,
+    pub delivery_mode: DeliveryMode,
+    pub shorthand: DestinationShorthand,
+    /// Ignored unless `shorthand` is `DestinationShorthand::None`
+    pub destination: ApicId,
+}
+
+impl InterruptCommand {
+    const VECTOR: BitField = BitField::new(0, 7);
+    const DELIVERY_MODE: BitField = BitField::new(8, 10);
+    const DELIVERY_STATUS: BitField = BitField::bit(12);
+    const LEVEL_ASSERT: BitField = BitField::bit(14);
+    const SHORTHAND: BitField = BitField::new(18, 19);
+    const XAPIC_DESTINATION: BitField = BitField::new(56, 63);
+    const X2APIC_DESTINATION: BitField = BitField::new(32, 63);
+
+    pub const fn fixed(destination: ApicId, vector: u8) -> Self {
+        Self { vector, delivery_mode: DeliveryMode::Fixed, shorthand: DestinationShorthand::None, destination }
+    }
+
+    /// Encodes the command as the 64-bit ICR value \
+    /// The xAPIC destination field is 8-bit wide, higher bits of `destination` are truncated
+    pub const fn encode(self, mode: ApicMode) -> u64 {
+        let mut value = 0;
+        value = Self::VECTOR.set(value, self.vector as u64);
+        value = Self::DELIVERY_MODE.set(value, self.delivery_mode as u64);
+        // must be set for everything except INIT level de-assert, which isn't used on modern CPUs
+        value = Self::LEVEL_ASSERT.set(value, 1);
+        value = Self::SHORTHAND.set(value, self.shorthand as u64);
+        match mode {
+            ApicMode::XApic { .. } => Self::XAPIC_DESTINATION.set(value, self.destination.0 as u64),
+            ApicMode::X2Apic => Self::X2APIC_DESTINATION.set(value, self.destination.0 as u64),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LocalApic {
+    mode: ApicMode,
+}
+
+impl LocalApic {
+    const ID_REGISTER: usize = 0x20;
+    const EOI_REGISTER: usize = 0xB0;
+    const SPURIOUS_VECTOR_REGISTER: usize = 0xF0;
+    const ICR_LOW_REGISTER: usize = 0x300;
+    const ICR_HIGH_REGISTER: usize = 0x310;
+    const LVT_TIMER_REGISTER: usize = 0x320;
+    const TIMER_INITIAL_COUNT_REGISTER: usize = 0x380;
+    const TIMER_CURRENT_COUNT_REGISTER: usize = 0x390;
+    const TIMER_DIVIDE_REGISTER: usize = 0x3E0;
+    /// Divide configuration 0b0011, the timer runs at bus clock / 16
+    const TIMER_DIVIDE_BY_16: u32 = 0b0011;
+    /// LVT timer mode field (bits 17:18), 01 - periodic
+    const LVT_TIMER_PERIODIC: u32 = 1 << 17;
+    const LVT_MASKED: u32 = 1 << 16;
+    /// Spurious vector register bit 8, LVT entries stay masked while it's clear
+    const APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+
+    /// The local APIC registers must be accessible through the identity map
+    unsafe fn new(identity_map: IdentityMapToken) -> Self {
+        let apic_base = unsafe { read_msr(IA32_APIC_BASE_MSR) };
+        if cpuid::x2apic() {
+            // xAPIC -> x2APIC is a valid transition, EN must stay set
+            unsafe {
+                write_msr(
+                    IA32_APIC_BASE_MSR,
+                    apic_base | APIC_BASE_GLOBAL_ENABLE_BIT | APIC_BASE_X2APIC_ENABLE_BIT
+                );
+            }
+            let apic = Self { mode: ApicMode::X2Apic };
+            unsafe { apic.enable() };
+            return apic;
+        }
+
+        let base = apic_base & APIC_BASE_ADDRESS_MASK;
+        let apic = Self {
+            mode: ApicMode::XApic {
+                base: paging::to_virtual(PhysicalAddress::from(base), identity_map),
+            },
+        };
+        unsafe { apic.enable() };
+        apic
+    }
+
+    /// Sets the software enable bit, spurious interrupts use [IdtVector::SPURIOUS] \
+    /// Safety:
+    /// The registers must be accessible
+    unsafe fn enable(&self) {
+        unsafe {
+            self.write_register(
+                Self::SPURIOUS_VECTOR_REGISTER,
+                Self::APIC_SOFTWARE_ENABLE | IdtVector::SPURIOUS.value() as u32
+            );
+        }
+    }
+
+    pub fn mode(&self) -> ApicMode {
+        self.mode
+    }
+
+    /// Local APIC ID, 8-bit in xAPIC mode and 32-bit in x2APIC mode
+    pub fn id(&self) -> u32 {
+        let value = unsafe { self.read_register(Self::ID_REGISTER) };
+        self.mode.id_from_register(value)
+    }
+
+    pub fn send_ipi(&self, destination: ApicId, vector: u8) {
+        unsafe {
+            self.send_command(InterruptCommand::fixed(destination, vector));
+        }
+    }
+
+    pub fn broadcast_ipi_all_but_self(&self, vector: u8) {
+        unsafe {
+            self.send_command(InterruptCommand {
+                vector,
+                delivery_mode: DeliveryMode::Fixed,
+                shorthand: DestinationShorthand::AllExcludingSelf,
+                destination: ApicId(0),
+            });
+        }
+    }
+
+    /// Resets the target processor into the wait-for-SIPI state \
+    /// Safety:
+    /// The target processor must not be running code that expects to continue
+    pub unsafe fn send_init(&self, destination: ApicId) {
+        unsafe {
+            self.send_command(InterruptCommand {
+                vector: 0,
+                delivery_mode: DeliveryMode::Init,
+                shorthand: DestinationShorthand::None,
+                destination,
+            });
+        }
+    }
+
+    /// Starts the target processor in real mode at physical address `start_page * 0x1000` \
+    /// Safety:
+    /// The target must be in the wait-for-SIPI state and the start page must contain valid startup code
+    pub unsafe fn send_startup(&self, destination: ApicId, start_page: u8) {
+        unsafe {
+            self.send_command(InterruptCommand {
+                vector: start_page,
+                delivery_mode: DeliveryMode::StartUp,
+                shorthand: DestinationShorthand::None,
+                destination,
+            });
+        }
+    }
+
+    /// Fires `vector` every `initial_count` timer ticks (bus clock / 16), a count of 0 stops the timer \
+    /// The tick frequency depends on the bus clock, see [LocalApic::start_periodic_timer_at] for a calibrated rate \
+    /// Safety:
+    /// `vector` must have a handler that signals the end of interrupt
+    pub unsafe fn start_periodic_timer(&self, vector: u8, initial_count: u32) {
+        unsafe {
+            self.write_register(Self::TIMER_DIVIDE_REGISTER, Self::TIMER_DIVIDE_BY_16);
+            self.write_register(Self::LVT_TIMER_REGISTER, Self::LVT_TIMER_PERIODIC | vector as u32);
+            // Writing the initial count starts the timer
+            self.write_register(Self::TIMER_INITIAL_COUNT_REGISTER, initial_count);
+        }
+    }
+
+    /// Fires `vector` about `hertz` times per second, `timer_frequency` comes from [LocalApic::calibrate_timer] \
+    /// The exact resulting rate is stored as the tick frequency (see `ticks::set_tick_frequency`) and returned \
+    /// Safety:
+    /// Same as [LocalApic::start_periodic_timer]
+    pub unsafe fn start_periodic_timer_at(&self, vector: u8, timer_frequency: u64, hertz: u64) -> u64 {
+        let initial_count = periodic_timer_count(timer_frequency, hertz);
+        let tick_frequency = timer_frequency / initial_count as u64;
+        ticks::set_tick_frequency(tick_frequency);
+        unsafe { self.start_periodic_timer(vector, initial_count) };
+        tick_frequency
+    }
+
+    /// Timer ticks per second (bus clock / 16), measured against the PIT \
+    /// Stops the timer, returns `None` if the PIT is unavailable
+    pub fn calibrate_timer(&self) -> Option<u64> {
+        unsafe {
+            self.write_register(Self::TIMER_DIVIDE_REGISTER, Self::TIMER_DIVIDE_BY_16);
+            // Masked one-shot, counts down from the initial count
+            self.write_register(Self::LVT_TIMER_REGISTER, Self::LVT_MASKED);
+            self.write_register(Self::TIMER_INITIAL_COUNT_REGISTER, u32::MAX);
+        }
+        let frequency = pit::calibrate(|| {
+            u32::MAX as u64 - unsafe { self.read_register(Self::TIMER_CURRENT_COUNT_REGISTER) } as u64
+        });
+        self.stop_timer();
+        frequency
+    }
+
+    pub fn stop_timer(&self) {
+        unsafe {
+            self.write_register(Self::LVT_TIMER_REGISTER, Self::LVT_MASKED);
+            self.write_register(Self::TIMER_INITIAL_COUNT_REGISTER, 0);
+        }
+    }
+
+    /// Writes the ICR and waits until the IPI is accepted \
+    /// Safety:
+    /// The command's vector must have a handler on the target processors (for fixed delivery)
+    pub unsafe fn send_command(&self, command: InterruptCommand) {
+        let value = command.encode(self.mode);
+        unsafe {
+            match self.mode {
+                ApicMode::XApic { .. } => {
+                    // the write to the low half sends the IPI
+                    self.write_register(Self::ICR_HIGH_REGISTER, (value >> 32) as u32);
+                    self.write_register(Self::ICR_LOW_REGISTER, value as u32);
+                    while InterruptCommand::DELIVERY_STATUS.get(self.read_register(Self::ICR_LOW_REGISTER) as u64) != 0 {
+                        core::hint::spin_loop();
+                    }
+                }
+                // single 64-bit MSR, no delivery status in x2APIC mode
+                ApicMode::X2Apic => write_msr(x2apic_msr(Self::ICR_LOW_REGISTER), value),
+            }
+        }
+    }
+
+    /// `register` must be a valid register offset (as in the xAPIC MMIO layout)
+    pub unsafe fn read_register(&self, register: usize) -> u32 {
+        unsafe {
+            match self.mode {
+                ApicMode::XApic { base } => (base + register).as_ptr().cast::<u32>().read_volatile(),
+                ApicMode::X2Apic => read_msr(x2apic_msr(register)) as u32,
+            }
+        }
+    }
+
+    /// `register` must be a valid, writable register offset (as in the xAPIC MMIO layout)
+    pub unsafe fn write_register(&self, register: usize, value: u32) {
+        unsafe {
+            match self.mode {
+                ApicMode::XApic { base } => (base + register).as_mut_ptr().cast::<u32>().write_volatile(value),
+                ApicMode::X2Apic => write_msr(x2apic_msr(register), value as u64),
+            }
+        }
+    }
+}
+
+/// Initial count of the highest rate not above `hertz` interrupts per second, clamped to the counter range
+const fn periodic_timer_count(timer_frequency: u64, hertz: u64) -> u32 {
+    let count = timer_frequency.div_ceil(hertz);
+    if count == 0 {
+        1
+    } else if count > u32::MAX as u64 {
+        u32::MAX
+    } else {
+        count as u32
+    }
+}
+
+const fn x2apic_msr(register: usize) -> u32 {
+    X2APIC_MSR_BASE + (register >> 4) as u32
+}
+
+impl InterruptController for LocalApic {
+    fn end_of_interrupt(&self) {
+        unsafe {
+            self.write_register(Self::EOI_REGISTER, 0);
+        }
+    }
+}
+
+define_interrupt_handler! {
+    handler SpuriousInterruptHandler(_frame: &mut StackFrame) for SpuriousInterrupt {
+        // Spurious interrupts aren't in service, sending an EOI would acknowledge a real one
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::boxed::Box;
+
+    use super::*;
+
+    /// xAPIC register page backed by host memory
+    struct MockRegisters(*mut u32);
+
+    impl MockRegisters {
+        fn new() -> Self {
+            Self(Box::leak(Box::new([0_u32; 0x400 / 4])).as_mut_ptr())
+        }
+
+        fn apic(&self) -> LocalApic {
+            LocalApic { mode: ApicMode::XApic { base: VirtualAddress::new(self.0 as usize) } }
+        }
+
+        fn read(&self, register: usize) -> u32 {
+            unsafe { self.0.add(register / 4).read_volatile() }
+        }
+
+        fn write(&self, register: usize, value: u32) {
+            unsafe { self.0.add(register / 4).write_volatile(value) }
+        }
+    }
+
+    #[test]
+    fn id_read_depends_on_the_mode() {
+        let registers = MockRegisters::new();
+        let apic = registers.apic();
+        // xAPIC IDs live in bits 24:31, the rest is reserved
+        registers.write(LocalApic::ID_REGISTER, 0x0500_00FF);
+        assert_eq!(apic.id(), 5);
+
+        // x2APIC uses the whole 32-bit register
+        assert_eq!(ApicMode::X2Apic.id_from_register(0x0500_00FF), 0x0500_00FF);
+        assert_eq!(ApicMode::X2Apic.id_from_register(0x1_0000), 0x1_0000);
+        assert_eq!(apic.mode().id_from_register(0x1_0000), 0);
+    }
+
+    #[test]
+    fn fixed_ipi_encoding() {
+        let command = InterruptCommand::fixed(ApicId(3), 0x40);
+        let xapic = ApicMode::XApic { base: VirtualAddress::new(0) };
+        // vector 0x40, fixed delivery, level assert, destination in bits 56:63
+        assert_eq!(command.encode(xapic), 0x0300_0000_0000_4040);
+        assert_eq!(command.encode(ApicMode::X2Apic), 0x0000_0003_0000_4040);
+
+        // Only x2APIC can address IDs above 255
+        let command = InterruptCommand::fixed(ApicId(0x1_0203), 0x40);
+        assert_eq!(command.encode(xapic) >> 32, 0x0300_0000);
+        assert_eq!(command.encode(ApicMode::X2Apic) >> 32, 0x1_0203);
+
+        let startup = InterruptCommand {
+            vector: 0x08,
+            delivery_mode: DeliveryMode::StartUp,
+            shorthand: DestinationShorthand::AllExcludingSelf,
+            destination: ApicId(0),
+        };
+        assert_eq!(startup.encode(ApicMode::X2Apic), 0x000C_4608);
+    }
+
+    #[test]
+    fn xapic_ipi_writes_both_icr_halves() {
+        let registers = MockRegisters::new();
+        registers.apic().send_ipi(ApicId(7), 0x41);
+        assert_eq!(registers.read(LocalApic::ICR_HIGH_REGISTER), 0x0700_0000);
+        assert_eq!(registers.read(LocalApic::ICR_LOW_REGISTER), 0x4041);
+
+        registers.apic().broadcast_ipi_all_but_self(0x42);
+        assert_eq!(registers.read(LocalApic::ICR_LOW_REGISTER), 0x000C_4042);
+    }
+
+    #[test]
+    fn periodic_timer_count_never_exceeds_the_rate() {
+        // 6.25 MHz timer (100 MHz bus clock)
+        assert_eq!(periodic_timer_count(6_250_000, 100), 62_500);
+        // Rounded up, 99 Hz runs at 98.99 Hz
+        assert_eq!(periodic_timer_count(6_250_000, 99), 63_132);
+        assert_eq!(periodic_timer_count(6_250_000, 1), 6_250_000);
+        // Clamped to the 32-bit counter and to a single timer tick per interrupt
+        assert_eq!(periodic_timer_count(u64::MAX, 1), u32::MAX);
+        assert_eq!(periodic_timer_count(1_000, 10_000), 1);
+        assert_eq!(periodic_timer_count(0, 100), 1);
+
+        let registers = MockRegisters::new();
+        unsafe { registers.apic().start_periodic_timer(0x30, 62_500) };
+        assert_eq!(registers.read(LocalApic::TIMER_DIVIDE_REGISTER), LocalApic::TIMER_DIVIDE_BY_16);
+        assert_eq!(registers.read(LocalApic::LVT_TIMER_REGISTER), LocalApic::LVT_TIMER_PERIODIC | 0x30);
+        assert_eq!(registers.read(LocalApic::TIMER_INITIAL_COUNT_REGISTER), 62_500);
+    }
+
+    #[test]
+    fn x2apic_registers_map_to_msrs() {
+        assert_eq!(x2apic_msr(LocalApic::ID_REGISTER), 0x802);
+        assert_eq!(x2apic_msr(LocalApic::EOI_REGISTER), 0x80B);
+        assert_eq!(x2apic_msr(LocalApic::ICR_LOW_REGISTER), 0x830);
+        assert_eq!(x2apic_msr(LocalApic::TIMER_DIVIDE_REGISTER), 0x83E);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{arch::{debug::{self, DebugStatus}, intrinsics::{cpuid, read_cr}, probe, stack, VirtualAddress}, common::log::{debug, trace}};
+
+use super::{
+    define_interrupt_handler, AlignmentCheck, BoundRangeExceeded, Breakpoint, ControlProtectionException, Debug, DeviceNotAvailable,
+    DoubleFault, ErrorCode, GeneralProtection, HypervisorInjectionException, IntegerDivideByZero, InterruptHandler, InvalidOpcode,
+    InvalidTTS, MachineCheck, NonMaskableInterrupt, Overflow, PageFault, SecurityException, SegmentNotPresent,
+    SimdFloatingPointException, StackFrame, StackSegmentFault, VirtualizationException, VmmCommunicationException,
+    X87FloatingPointError
+};
+
+define_interrupt_handler! {
+    handler DoubleFaultHandler(frame: &mut StackFrame, _error_code: ErrorCode) for DoubleFault {
+        // Runs on its own IST stack (see `Processor::default_idt`), the interrupted stack may be unusable
+        // #DF is an abort, the error code is always 0 and the frame can't be resumed
+        if stack::is_guard_address(frame.stack_pointer) {
+            panic!(
+                "Double fault at {} on a kernel stack (stack pointer {}), likely an overflow",
+                frame.instruction_pointer, frame.stack_pointer
+            );
+        }
+
+        panic!("Double fault at {}, stack pointer {}", frame.instruction_pointer, frame.stack_pointer);
+    }
+}
+
+define_interrupt_handler! {
+    handler PageFaultHandler(frame: &mut StackFrame, error_code: ErrorCode) for PageFault {
+        if probe::fixup_fault(frame) {
+            return;
+        }
+
+        // SAFETY: CR2 holds the faulting address
+        let address = VirtualAddress::new(unsafe { read_cr!(2) } as usize);
+        // Bit 0 clear - the page wasn't present
+        if error_code.0 & 1 == 0 && stack::is_guard_address(address) {
+            panic!("Kernel stack overflow (guard page {address} hit at {})", frame.instruction_pointer);
+        }
+        // Bit 3 - a paging structure entry has reserved bits set, usually an address above MAXPHYADDR
+        if error_code.0 & (1 << 3) != 0 {
+            panic!(
+                "Page fault at {}, reserved bits set in an entry mapping {address} (MAXPHYADDR {})",
+                frame.instruction_pointer, cpuid::phys_address_bits()
+            );
+        }
+
+        panic!("Page fault ({:#x}) at {}, address {address}", error_code.0, frame.instruction_pointer);
+    }
+}
+
+define_interrupt_handler! {
+    handler DebugHandler(frame: &mut StackFrame) for Debug {
+        let status = DebugStatus::take();
+        if let Some(slot) = status.breakpoint() {
+            debug!("Hardware breakpoint {slot} hit at {}", frame.instruction_pointer);
+            // Don't hit the same execute breakpoint again
+            frame.set_resume_flag(true);
+        }
+        if status.single_step() {
+            trace!("Single step at {}", frame.instruction_pointer);
+        }
+
+        frame.set_trap_flag(debug::single_step());
+    }
+}
+
+define_interrupt_handler! {
+    handler BreakpointHandler(frame: &mut StackFrame) for Breakpoint {
+        // #BP is a trap, the frame points past `int3`
+        debug!("Breakpoint at {}", frame.instruction_pointer);
+    }
+}
+
+define_interrupt_handler! {
+    handler DivideErrorHandler(frame: &mut StackFrame) for IntegerDivideByZero {
+        #[cfg(feature = "fault-injection")]
+        if super::fault_injection::fixup_fault(frame) {
+            return;
+        }
+
+        panic!("Divide error at {}", frame.instruction_pointer);
+    }
+}
+
+/// Default for exceptions the kernel doesn't handle (yet)
+fn unhandled(name: &str, frame: &StackFrame, error_code: Option<ErrorCode>) -> ! {
+    match error_code {
+        Some(error_code) => panic!("{name} ({:#x}) at {}", error_code.0, frame.instruction_pointer),
+        None => panic!("{name} at {}", frame.instruction_pointer),
+    }
+}
+
+define_interrupt_handler! {
+    handler NonMaskableInterruptHandler(frame: &mut StackFrame) for NonMaskableInterrupt {
+        unhandled("Non-maskable interrupt", frame, None);
+    }
+    handler OverflowHandler(frame: &mut StackFrame) for Overflow {
+        unhandled("Overflow", frame, None);
+    }
+    handler BoundRangeExceededHandler(frame: &mut StackFrame) for BoundRangeExceeded {
+        unhandled("Bound range exceeded", frame, None);
+    }
+    handler InvalidOpcodeHandler(frame: &mut StackFrame) for InvalidOpcode {
+        unhandled("Invalid opcode", frame, None);
+    }
+    handler DeviceNotAvailableHandler(frame: &mut StackFrame) for DeviceNotAvailable {
+        unhandled("Device not available", frame, None);
+    }
+    handler InvalidTssHandler(frame: &mut StackFrame, error_code: ErrorCode) for InvalidTTS {
+        unhandled("Invalid TSS", frame, Some(error_code));
+    }
+    handler SegmentNotPresentHandler(frame: &mut StackFrame, error_code: ErrorCode) for SegmentNotPresent {
+        unhandled("Segment not present", frame, Some(error_code));
+    }
+    handler StackSegmentFaultHandler(frame: &mut StackFrame, error_code: ErrorCode) for StackSegmentFault {
+        unhandled("Stack segment fault", frame, Some(error_code));
+    }
+    handler GeneralProtectionHandler(frame: &mut StackFrame, error_code: ErrorCode) for GeneralProtection {
+        unhandled("General protection fault", frame, Some(error_code));
+    }
+    handler X87FloatingPointErrorHandler(frame: &mut StackFrame) for X87FloatingPointError {
+        unhandled("x87 floating point error", frame, None);
+    }
+    handler AlignmentCheckHandler(frame: &mut StackFrame, error_code: ErrorCode) for AlignmentCheck {
+        unhandled("Alignment check", frame, Some(error_code));
+    }
+    handler MachineCheckHandler(frame: &mut StackFrame) for MachineCheck {
+        unhandled("Machine check", frame, None);
+    }
+    handler SimdFloatingPointHandler(frame: &mut StackFrame) for SimdFloatingPointException {
+        unhandled("SIMD floating point exception", frame, None);
+    }
+    handler VirtualizationHandler(frame: &mut StackFrame) for VirtualizationException {
+        unhandled("Virtualization exception", frame, None);
+    }
+    handler ControlProtectionHandler(frame: &mut StackFrame, error_code: ErrorCode) for ControlProtectionException {
+        unhandled("Control protection exception", frame, Some(error_code));
+    }
+    handler HypervisorInjectionHandler(frame: &mut StackFrame) for HypervisorInjectionException {
+        unhandled("Hypervisor injection exception", frame, None);
+    }
+    handler VmmCommunicationHandler(frame: &mut StackFrame, error_code: ErrorCode) for VmmCommunicationException {
+        unhandled("VMM communication exception", frame, Some(error_code));
+    }
+    handler SecurityHandler(frame: &mut StackFrame, error_code: ErrorCode) for SecurityException {
+        unhandled("Security exception", frame, Some(error_code));
+    }
+}
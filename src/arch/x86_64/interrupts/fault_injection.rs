@@ -0,0 +1,110 @@
+// Fault injection harness for the exception handlers, enabled with the `fault-injection` feature
+// Raises exceptions deterministically and checks that the handler ran (see `stats`) and that execution
+// resumed with the scratch registers intact, which exercises the `define_interrupt_handler!` entry stubs
+//
+// Running under QEMU:
+// cargo run --features fault-injection,test-qemu -- -device isa-debug-exit,iobase=0xf4,iosize=0x04
+// A failed check panics, with `test-qemu` QEMU exits with status 33 on success and 35 on failure (see `qemu`)
+
+use core::{arch::asm, sync::atomic::{AtomicUsize, Ordering}};
+
+use crate::{
+    arch::{intrinsics::{interrupts_enabled, without_interrupts}, paging::{kernel_vm, PagingToken, PAGE_SIZE}, probe},
+    common::log::info
+};
+
+use super::{idt::IdtVector, software_interrupt, stats, StackFrame};
+
+/// Address of the faulting instruction, 0 if no fault is being injected
+static INJECTED_INSTRUCTION: AtomicUsize = AtomicUsize::new(0);
+/// Address execution continues at after the injected fault
+static INJECTED_FIXUP: AtomicUsize = AtomicUsize::new(0);
+
+/// Values loaded into the scratch registers before a fault, checked after the handler returns
+const SENTINEL: u64 = 0x5A5A_0000_DEAD_BEEF;
+
+/// Raises #BP, #DE and #PF once each and checks [without_interrupts], requires the default IDT to be loaded
+pub fn run(token: PagingToken) {
+    check_without_interrupts();
+    expect_fault(IdtVector::BREAKPOINT, software_interrupt::<{ IdtVector::BREAKPOINT.value() }>);
+    expect_fault(IdtVector::INTEGER_DIVIDE_BY_ZERO, divide_by_zero);
+    expect_fault(IdtVector::PAGE_FAULT, || {
+        // Allocated but never mapped
+        let range = kernel_vm(token).allocate(PAGE_SIZE, PAGE_SIZE).expect("No virtual address space for the #PF test");
+        // SAFETY: the page isn't mapped, so reading it has no side effects
+        let value = unsafe { probe::probe_read(range.start) };
+        assert!(value.is_none(), "Read from an unmapped page succeeded");
+        _ = kernel_vm(token).free(range);
+    });
+    info!("Fault injection passed");
+}
+
+/// RFLAGS.IF is clear inside [without_interrupts] and restored afterwards, including nested calls
+fn check_without_interrupts() {
+    // Ends with the flag in its initial state
+    let initial = interrupts_enabled();
+    for enabled in [!initial, initial] {
+        unsafe {
+            if enabled {
+                asm!("sti", options(nostack));
+            } else {
+                asm!("cli", options(nostack));
+            }
+        }
+        without_interrupts(|| {
+            assert!(!interrupts_enabled(), "IF set inside without_interrupts");
+            without_interrupts(|| assert!(!interrupts_enabled(), "IF set inside nested without_interrupts"));
+            assert!(!interrupts_enabled(), "IF restored by a nested without_interrupts");
+        });
+        assert_eq!(interrupts_enabled(), enabled, "IF not restored by without_interrupts");
+    }
+}
+
+fn expect_fault(vector: IdtVector, inject: impl FnOnce()) {
+    let before = stats::count(vector);
+    inject();
+    let count = stats::count(vector) - before;
+    assert_eq!(count, 1, "Vector {} handled {count} times", vector.value());
+}
+
+/// Divides by zero, the #DE handler resumes after the `div` (see [fixup_fault])
+fn divide_by_zero() {
+    let (r8, r9, r10, r11): (u64, u64, u64, u64);
+    unsafe {
+        asm!(
+            "lea {tmp}, [rip + 3f]",
+            "mov [{fixup}], {tmp}",
+            "lea {tmp}, [rip + 2f]",
+            "mov [{instruction}], {tmp}",
+            "xor edx, edx",
+            "mov eax, 1",
+            "xor ecx, ecx",
+            "2:",
+            "div ecx",
+            "3:",
+            "mov qword ptr [{instruction}], 0",
+            tmp = out(reg) _,
+            fixup = in(reg) INJECTED_FIXUP.as_ptr(),
+            instruction = in(reg) INJECTED_INSTRUCTION.as_ptr(),
+            inout("r8") SENTINEL => r8,
+            inout("r9") SENTINEL => r9,
+            inout("r10") SENTINEL => r10,
+            inout("r11") SENTINEL => r11,
+            out("eax") _, out("ecx") _, out("edx") _,
+            options(nostack)
+        );
+    }
+    assert!([r8, r9, r10, r11] == [SENTINEL; 4], "Scratch registers not restored by the #DE handler");
+}
+
+/// Called by exception handlers, skips an injected faulting instruction \
+/// Returns `false` if the fault wasn't injected
+pub fn fixup_fault(frame: &mut StackFrame) -> bool {
+    let instruction = INJECTED_INSTRUCTION.load(Ordering::SeqCst);
+    if instruction == 0 || usize::from(frame.instruction_pointer) != instruction {
+        return false;
+    }
+
+    frame.instruction_pointer = INJECTED_FIXUP.load(Ordering::SeqCst).into();
+    true
+}
@@ -1,12 +1,13 @@
-use core::{fmt::Debug, ops::{Index, IndexMut}};
+use core::{fmt::{Debug, Display}, ops::{Index, IndexMut}};
 
-use static_assertions::const_assert_eq;
+use static_assertions::{const_assert, const_assert_eq};
 
-use crate::{common::macros::debug_assert_arg, arch::PrivilegeLevel};
+use crate::{common::{bits::BitField, macros::{assert_arg, debug_assert_arg}}, arch::PrivilegeLevel};
 
 use super::{InterruptHandler, Interrupt};
 
 #[repr(C)]
+#[derive(Clone)]
 pub struct Idt {
     entries: [IdtEntry; 256]
 }
@@ -19,16 +20,51 @@ impl Idt {
         }
     }
 
+    /// Loads the IDT on the current CPU, interrupts must be disabled (checked in debug builds) \
+    /// Every CPU loads its own IDT, see `Processor::install`
     pub fn load(&'static self) {
+        debug_assert!(!crate::arch::intrinsics::interrupts_enabled(), "IDT loaded with interrupts enabled");
         crate::arch::intrinsics::load_idt(self);
     }
 
+    /// Installs a present kernel interrupt gate using the kernel code segment (see [crate::arch::gdt]) \
+    /// Loaded IDTs are immutable (`&'static`), handlers must be registered before [Idt::load] \
+    /// Panics if the vector is reserved, see [Idt::swap_handler]
     pub fn register_handler<Handler: InterruptHandler>(&mut self) {
+        if let Err(error) = self.swap_handler::<Handler>() {
+            panic!("{error}");
+        }
+    }
+
+    /// Same as [Idt::register_handler], returns the replaced entry \
+    /// Reserved vectors (see [IdtVector::is_reserved]) are rejected
+    pub fn swap_handler<Handler: InterruptHandler>(&mut self) -> Result<IdtEntry, ReservedVectorError> {
         type RawHandler = extern "C" fn() -> !;
         let vector: IdtVector = Handler::Interrupt::VECTOR;
+        if vector.is_reserved() {
+            return Err(ReservedVectorError(vector));
+        }
+
         #[allow(deprecated)]
         let handler: RawHandler = Handler::invoke;
-        self[vector].set_offset(handler as usize);
+        let entry = IdtEntry::new(
+            handler as usize,
+            crate::arch::gdt::KERNEL_CODE.selector(PrivilegeLevel::KERNEL),
+            0,
+            GateType::INTERRUPT,
+            PrivilegeLevel::KERNEL
+        );
+        Ok(core::mem::replace(&mut self[vector], entry))
+    }
+}
+
+/// A handler was registered for a reserved vector
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReservedVectorError(pub IdtVector);
+
+impl Display for ReservedVectorError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_fmt(format_args!("vector {} is reserved", self.0.value()))
     }
 }
 
@@ -38,6 +74,44 @@ impl Default for Idt {
     }
 }
 
+/// Builds a fully populated [Idt] once, the finished table can be cloned for every processor \
+/// (see `Processor::with_idt`)
+#[derive(Clone, Default)]
+pub struct IdtBuilder {
+    idt: Idt,
+}
+
+impl IdtBuilder {
+    pub fn new() -> Self {
+        Self { idt: Idt::new() }
+    }
+
+    /// Registers `Handler` for its vector, see [Idt::register_handler]
+    pub fn handler<Handler: InterruptHandler>(mut self) -> Self {
+        self.idt.register_handler::<Handler>();
+        self
+    }
+
+    /// Switches the present entry for `vector` to IST stack `ist_index` (1-7), see `gdt::TaskStateSegment`
+    pub fn interrupt_stack(mut self, vector: IdtVector, ist_index: u8) -> Self {
+        assert_arg!(ist_index, (1..8).contains(&ist_index));
+        assert!(self.idt[vector].is_present(), "No handler registered for vector {}", vector.value());
+        self.idt[vector].data.set_ist(ist_index);
+        self
+    }
+
+    /// Sets the entry for `vector` directly (e.g. a custom IST index or gate type)
+    pub fn entry(mut self, vector: IdtVector, entry: IdtEntry) -> Self {
+        debug_assert_arg!(vector, !vector.is_reserved() || !entry.is_present(), "Reserved vector");
+        self.idt[vector] = entry;
+        self
+    }
+
+    pub fn build(self) -> Idt {
+        self.idt
+    }
+}
+
 impl Index<IdtVector> for Idt {
     type Output = IdtEntry;
 
@@ -70,7 +144,7 @@ const_assert_eq!(core::mem::size_of::<IdtEntry>(), 16);
 
 impl IdtEntry {
     pub fn new(offset: usize, segment_selector: u16, ist_index: u8, gate_type: GateType, dpl: PrivilegeLevel) -> Self {
-        debug_assert_arg!(ist_index, ist_index < 16, "ist_index must be less than 16");
+        debug_assert_arg!(ist_index, ist_index < 8, "ist_index must be less than 8");
         let offset = offset as u64;
         Self {
             offset_low: offset as u16,
@@ -82,6 +156,15 @@ impl IdtEntry {
         }
     }
 
+    pub fn is_present(self) -> bool {
+        self.data.present()
+    }
+
+    /// Interrupt stack table index, 0 - stay on the current stack
+    pub fn ist(self) -> u8 {
+        self.data.ist()
+    }
+
     pub fn offset(self) -> usize {
         (self.offset_low as u64 | (self.offset_mid as u64) << 16 | (self.offset_high as u64) << 32) as usize
     }
@@ -99,6 +182,11 @@ pub struct IdtEntryData(u16);
 const_assert_eq!(core::mem::size_of::<IdtEntryData>(), 2);
 
 impl IdtEntryData {
+    const IST: BitField = BitField::new(0, 2);
+    const GATE_TYPE: BitField = BitField::new(8, 11);
+    const DPL: BitField = BitField::new(13, 14);
+    const PRESENT: BitField = BitField::bit(15);
+
     pub fn new(ist_index: u8, gate_type: GateType, dpl: PrivilegeLevel) -> Self {
         let mut entry = IdtEntryData(0);
         entry.set_ist(ist_index);
@@ -112,44 +200,44 @@ impl IdtEntryData {
         IdtEntryData(0)
     }
 
+    fn get(self, field: BitField) -> u64 {
+        field.get(self.0 as u64)
+    }
+
+    fn set(&mut self, field: BitField, value: u64) {
+        self.0 = field.set(self.0 as u64, value) as u16;
+    }
+
     pub fn ist(self) -> u8 {
-        (self.0 as u8) & 0b111
+        self.get(Self::IST) as u8
     }
 
     pub fn set_ist(&mut self, value: u8) {
-        let ist = value as u16 & 0b111;
-        let mask = !(0b111_u16);
-        self.0 = (self.0 & mask) | ist;
+        self.set(Self::IST, value as u64);
     }
 
     pub fn gate_type(self) -> GateType {
-        GateType::from((self.0 >> 8) as u8 & 0b1111)
+        GateType::from(self.get(Self::GATE_TYPE) as u8)
     }
 
     pub fn set_gate_type(&mut self, value: GateType) {
-        let value = Into::<u8>::into(value) as u16;
-        let mask = !(0b1111_u16 << 8);
-        self.0 = (self.0 & mask) | (value << 8);
+        self.set(Self::GATE_TYPE, Into::<u8>::into(value) as u64);
     }
 
     pub fn dpl(self) -> PrivilegeLevel {
-        PrivilegeLevel::from((self.0 >> 13) as u8 & 0b11)
+        PrivilegeLevel::from(self.get(Self::DPL) as u8)
     }
 
     pub fn set_dpl(&mut self, value: PrivilegeLevel) {
-        let value: u8 = value.into();
-        let value = value as u16;
-        let mask = !(0b11_u16 << 13);
-        self.0 = (self.0 & mask) | (value << 13);
+        self.set(Self::DPL, Into::<u8>::into(value) as u64);
     }
 
     pub fn present(self) -> bool {
-        self.0 >> 15 != 0
+        self.get(Self::PRESENT) != 0
     }
 
     pub fn set_present(&mut self, value: bool) {
-        let value = value as u16;
-        self.0 = (self.0 & !(1_u16 << 15)) | (value << 15);
+        self.set(Self::PRESENT, value as u64);
     }
 }
 
@@ -203,7 +291,7 @@ impl IdtVector {
     pub const INVALID_OPCODE: IdtVector = IdtVector(6);
     pub const DEVICE_NOT_AVAILABLE: IdtVector = IdtVector(7);
     pub const DOUBLE_FAULT: IdtVector = IdtVector(8);
-    /// Unused
+    /// Legacy, never raised by 486 and later CPUs, reserved (no handler can be registered)
     pub const COPROCESSOR_SEGMENT_OVERRUN: IdtVector = IdtVector(9);
     pub const INVALID_TTS: IdtVector = IdtVector(10);
     pub const SEGMENT_NOT_PRESENT: IdtVector = IdtVector(11);
@@ -227,13 +315,41 @@ impl IdtVector {
     /// AMD specific
     pub const SECURITY_EXCEPTION: IdtVector = IdtVector(30);
 
+    /// First external interrupt vector
+    pub const TIMER: IdtVector = IdtVector(32);
+    /// Inter-processor TLB invalidation request
+    pub const TLB_SHOOTDOWN: IdtVector = IdtVector(0xFD);
+    /// Local APIC spurious interrupt, must not be acknowledged
+    pub const SPURIOUS: IdtVector = IdtVector(0xFF);
+
     /// [0:32) - predefined interrupts \
     /// [32: 255] - software / maskable external interrupts
+    pub const fn value(self) -> u8 {
+        self.0
+    }
+
     pub fn is_predefined(self) -> bool {
         self.0 < 32
     }
+
+    /// Predefined vectors the CPU never raises: 9 (legacy coprocessor segment overrun), 15 and the
+    /// unassigned 22-27 and 31
+    pub const fn is_reserved(self) -> bool {
+        matches!(self.0, 9 | 15 | 22..=27 | 31)
+    }
+
+    /// Predefined vectors in use, i.e. the exceptions that can be raised
+    pub fn all_exceptions() -> impl Iterator<Item = IdtVector> {
+        (0..32).map(IdtVector).filter(|x| !x.is_reserved())
+    }
 }
 
+const_assert!(IdtVector(15).is_reserved());
+const_assert!(IdtVector::COPROCESSOR_SEGMENT_OVERRUN.is_reserved());
+const_assert!(!IdtVector::PAGE_FAULT.is_reserved());
+const_assert!(!IdtVector::X87_FLOATING_POINT_ERROR.is_reserved());
+const_assert!(!IdtVector::TIMER.is_reserved());
+
 impl From<IdtVector> for u8 {
     fn from(val: IdtVector) -> Self {
         val.0
@@ -245,3 +361,70 @@ impl From<u8> for IdtVector {
         IdtVector(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::arch::{gdt, interrupts::{define_interrupt_handler, exceptions::{BreakpointHandler, PageFaultHandler}, CoprocessorSegmentOverrun, StackFrame}};
+
+    use super::*;
+
+    define_interrupt_handler! {
+        handler ReservedHandler(_frame: &mut StackFrame) for CoprocessorSegmentOverrun {}
+    }
+
+    #[test]
+    fn builder_registers_kernel_interrupt_gates() {
+        let idt = IdtBuilder::new()
+            .handler::<BreakpointHandler>()
+            .handler::<PageFaultHandler>()
+            .interrupt_stack(IdtVector::PAGE_FAULT, 2)
+            .build();
+
+        let entry = idt[IdtVector::BREAKPOINT];
+        assert!(entry.is_present());
+        #[allow(deprecated)]
+        let handler = BreakpointHandler::invoke as extern "C" fn() -> ! as usize;
+        assert_eq!(entry.offset(), handler);
+        assert_eq!(entry.segment_selector, gdt::KERNEL_CODE.selector(PrivilegeLevel::KERNEL));
+        assert_eq!(entry.data.gate_type(), GateType::INTERRUPT);
+        assert_eq!(entry.data.dpl(), PrivilegeLevel::KERNEL);
+        assert_eq!(entry.ist(), 0);
+        assert_eq!(idt[IdtVector::PAGE_FAULT].ist(), 2);
+
+        let present = (0..=255).map(IdtVector::from).filter(|&vector| idt[vector].is_present()).collect::<std::vec::Vec<_>>();
+        assert_eq!(present, [IdtVector::BREAKPOINT, IdtVector::PAGE_FAULT]);
+
+        // Every processor gets an identical copy
+        let copy = idt.clone();
+        assert_eq!(copy[IdtVector::PAGE_FAULT].offset(), idt[IdtVector::PAGE_FAULT].offset());
+        assert_eq!(copy[IdtVector::PAGE_FAULT].ist(), 2);
+    }
+
+    #[test]
+    fn builder_sets_entries_directly() {
+        let entry = IdtEntry::new(0xFFFF_FFFF_8000_1234, 0x08, 3, GateType::TRAP, PrivilegeLevel::USERSPACE);
+        let idt = IdtBuilder::new().entry(IdtVector::from(0x80), entry).build();
+
+        let stored = idt[IdtVector::from(0x80)];
+        assert_eq!(stored.offset(), 0xFFFF_FFFF_8000_1234);
+        assert_eq!(stored.ist(), 3);
+        assert_eq!(stored.data.gate_type(), GateType::TRAP);
+        assert_eq!(stored.data.dpl(), PrivilegeLevel::USERSPACE);
+    }
+
+    #[test]
+    fn reserved_vectors_are_rejected() {
+        let mut idt = Idt::new();
+        assert_eq!(
+            idt.swap_handler::<ReservedHandler>().unwrap_err(),
+            ReservedVectorError(IdtVector::COPROCESSOR_SEGMENT_OVERRUN)
+        );
+        assert!(!idt[IdtVector::COPROCESSOR_SEGMENT_OVERRUN].is_present());
+    }
+
+    #[test]
+    #[should_panic]
+    fn interrupt_stack_requires_a_handler() {
+        IdtBuilder::new().interrupt_stack(IdtVector::DOUBLE_FAULT, 1);
+    }
+}
@@ -1,8 +1,16 @@
-use crate::common::mem::Bittable;
+use static_assertions::const_assert_eq;
+
+use crate::{arch::VirtualAddress, common::mem::Bittable};
 
 use self::idt::IdtVector;
 
+pub mod apic;
+pub mod exceptions;
+#[cfg(feature = "fault-injection")]
+pub mod fault_injection;
 pub mod idt;
+pub mod stats;
+pub mod timer;
 
 pub trait Interrupt {
     type Handler;
@@ -20,6 +28,29 @@ macro_rules! define_interrupt {
     };
 }
 
+/// Raises interrupt `VECTOR` with `int`, e.g. to test IDT wiring (see [stats::count]) \
+/// Gate DPLs only restrict `int` from lower privilege levels, kernel code may invoke any vector \
+/// Panics if the current IDT has no present gate for `VECTOR` (the CPU would raise #GP or #NP instead)
+pub fn software_interrupt<const VECTOR: u8>() {
+    let (base, size) = super::intrinsics::loaded_idt();
+    let offset = VECTOR as usize * core::mem::size_of::<idt::IdtEntry>();
+    assert!(offset < size, "Vector {VECTOR} outside the loaded IDT");
+    // SAFETY: the entry is within the loaded IDT
+    let entry = unsafe { base.as_ptr().cast::<u8>().add(offset).cast::<idt::IdtEntry>().read() };
+    assert!(entry.is_present(), "No handler registered for vector {VECTOR}");
+
+    unsafe {
+        core::arch::asm!("int {}", const VECTOR);
+    }
+}
+
+/// Triggers #BP and checks that the handler ran, requires the default IDT to be loaded
+pub fn self_test() {
+    let before = stats::count(IdtVector::BREAKPOINT);
+    software_interrupt::<{ IdtVector::BREAKPOINT.value() }>();
+    assert_eq!(stats::count(IdtVector::BREAKPOINT), before + 1, "Breakpoint handler didn't run");
+}
+
 pub trait InterruptHandler {
     type Interrupt: self::Interrupt;
 
@@ -28,6 +59,72 @@ pub trait InterruptHandler {
     extern "C" fn invoke() -> !;
 }
 
+// Interrupt entry stack layout (growing downwards):
+// [alignment padding to 16 bytes, done by the CPU]
+// StackFrame
+// ErrorCode                                    - only for interrupts with an error code
+// saved scratch registers (SAVED_REGISTERS_SIZE)
+// padding (ERROR_CODE_PADDING)                 - only for interrupts with an error code
+// The stack must be 16-byte aligned at the `call` (System V ABI)
+
+/// Scratch (caller-saved) registers saved by the entry stubs
+#[doc(hidden)]
+pub const SAVED_REGISTER_COUNT: usize = 9;
+#[doc(hidden)]
+pub const SAVED_REGISTERS_SIZE: usize = SAVED_REGISTER_COUNT * 8;
+/// Keeps the stack aligned when the CPU pushes an error code
+#[doc(hidden)]
+pub const ERROR_CODE_PADDING: usize = 8;
+/// Offsets from the stack pointer at the `call`
+#[doc(hidden)]
+pub const ERROR_CODE_OFFSET: usize = ERROR_CODE_PADDING + SAVED_REGISTERS_SIZE;
+#[doc(hidden)]
+pub const ERROR_CODE_FRAME_OFFSET: usize = ERROR_CODE_OFFSET + core::mem::size_of::<ErrorCode>();
+
+// The CPU aligns the stack before pushing the frame, so everything above the return address must add up
+// to a multiple of 16
+const_assert_eq!((core::mem::size_of::<StackFrame>() + SAVED_REGISTERS_SIZE) % 16, 0);
+const_assert_eq!((core::mem::size_of::<StackFrame>() + ERROR_CODE_FRAME_OFFSET) % 16, 0);
+
+#[doc(hidden)]
+macro_rules! _interrupt_save_registers {
+    () => {
+        "
+        push    r11
+        push    r10
+        push    r9
+        push    r8
+        push    rdi
+        push    rsi
+        push    rdx
+        push    rcx
+        push    rax
+        "
+    };
+}
+#[doc(hidden)]
+pub(crate) use _interrupt_save_registers;
+
+/// Must restore exactly the registers saved by `_interrupt_save_registers`
+#[doc(hidden)]
+macro_rules! _interrupt_restore_registers {
+    () => {
+        "
+        pop     rax
+        pop     rcx
+        pop     rdx
+        pop     rsi
+        pop     rdi
+        pop     r8
+        pop     r9
+        pop     r10
+        pop     r11
+        "
+    };
+}
+#[doc(hidden)]
+pub(crate) use _interrupt_restore_registers;
+
 macro_rules! _define_interrupt_handler_asm {
     (($arg:ident : $argtype:ty)) => {
         {
@@ -40,33 +137,15 @@ macro_rules! _define_interrupt_handler_asm {
                 ::core::mem::align_of::<StackFrame>()
             );
 
-            ::core::arch::asm!(
-                "
-                push    r11
-                push    r10
-                push    r9
-                push    r8
-                push    rdi
-                push    rsi
-                push    rdx
-                push    rcx
-                push    rax
-                cld
-                lea     rdi, [rsp + 72]
-                call    {}
-                pop     rax
-                pop     rcx
-                pop     rdx
-                pop     rsi
-                pop     rdi
-                pop     r8
-                pop     r9
-                pop     r10
-                pop     r11
-                iretq
-                ",
-                sym Self::handler,
-                options(noreturn)
+            ::core::arch::naked_asm!(
+                $crate::arch::x86_64::interrupts::_interrupt_save_registers!(),
+                "cld",
+                "lea rdi, [rsp + {frame}]",
+                "call {handler}",
+                $crate::arch::x86_64::interrupts::_interrupt_restore_registers!(),
+                "iretq",
+                frame = const $crate::arch::x86_64::interrupts::SAVED_REGISTERS_SIZE,
+                handler = sym Self::handler,
             )
         }
     };
@@ -87,46 +166,48 @@ macro_rules! _define_interrupt_handler_asm {
             ::static_assertions::const_assert_eq!(::core::mem::align_of::<$argtype2>(), ::core::mem::align_of::<ErrorCode>());
             ::static_assertions::assert_impl_all!($argtype2: $crate::common::mem::Bittable);
 
-            ::core::arch::asm!(
-                "
-                push    rax
-                push    r11
-                push    r10
-                push    r9
-                push    r8
-                push    rdi
-                push    rsi
-                push    rdx
-                push    rcx
-                push    rax
-                push    rax
-                cld
-                mov     rsi, qword ptr [rsp + 88]
-                lea     rdi, [rsp + 96]
-                call    {}
-                add     rsp, 8
-                pop     rax
-                pop     rcx
-                pop     rdx
-                pop     rsi
-                pop     rdi
-                pop     r8
-                pop     r9
-                pop     r10
-                pop     r11
-                add     rsp, 16
-                iretq
-                ",
-                sym Self::handler,
-                options(noreturn)
+            ::core::arch::naked_asm!(
+                $crate::arch::x86_64::interrupts::_interrupt_save_registers!(),
+                "sub rsp, {padding}",
+                "cld",
+                "mov rsi, qword ptr [rsp + {error_code}]",
+                "lea rdi, [rsp + {frame}]",
+                "call {handler}",
+                "add rsp, {padding}",
+                $crate::arch::x86_64::interrupts::_interrupt_restore_registers!(),
+                // error code
+                "add rsp, 8",
+                "iretq",
+                padding = const $crate::arch::x86_64::interrupts::ERROR_CODE_PADDING,
+                error_code = const $crate::arch::x86_64::interrupts::ERROR_CODE_OFFSET,
+                frame = const $crate::arch::x86_64::interrupts::ERROR_CODE_FRAME_OFFSET,
+                handler = sym Self::handler,
             )
         }
     };
 }
 #[doc(hidden)]
-use _define_interrupt_handler_asm;
+pub(crate) use _define_interrupt_handler_asm;
 
+/// `external handler` sends an EOI to the controller returned by the `via` function after the body returns
+/// (including early returns), panics halt the kernel and never send an EOI
 macro_rules! define_interrupt_handler {
+    {external handler $name:ident $args:tt for $interrupt:ident via $controller:path $body:block } => {
+        define_interrupt_handler!{
+            handler $name $args for $interrupt {
+                let _eoi = $crate::arch::interrupts::EoiGuard::new($controller());
+                $body
+            }
+        }
+    };
+    {external handler $name:ident $args:tt for $interrupt:ident via $controller:path $body:block $($tail:tt)*} => {
+        define_interrupt_handler!{
+            external handler $name $args for $interrupt via $controller $body
+        }
+        define_interrupt_handler! {
+            $($tail)*
+        }
+    };
     {handler $name:ident $args:tt for $interrupt:ty $body:block } => {
         pub enum $name {}
 
@@ -134,17 +215,20 @@ macro_rules! define_interrupt_handler {
             // Force `handler` to have the correct signature
             const _HANDLER: <$interrupt as $crate::arch::x86_64::interrupts::Interrupt>::Handler = Self::handler;
 
-            extern "sysv64" fn handler $args -> () $body
+            extern "sysv64" fn handler $args -> () {
+                $crate::arch::interrupts::stats::record(
+                    <$interrupt as $crate::arch::x86_64::interrupts::Interrupt>::VECTOR
+                );
+                $body
+            }
         }
 
         impl InterruptHandler for $name {
             type Interrupt = $interrupt;
 
-            #[naked]
+            #[unsafe(naked)]
             extern "C" fn invoke() -> ! {
-                unsafe {
-                    $crate::arch::x86_64::interrupts::_define_interrupt_handler_asm!($args)
-                }
+                $crate::arch::x86_64::interrupts::_define_interrupt_handler_asm!($args)
             }
         }
     };
@@ -159,11 +243,72 @@ macro_rules! define_interrupt_handler {
 }
 pub(crate) use define_interrupt_handler;
 
-// TODO: store the stack frame
-pub struct StackFrame;
+/// External interrupt controller (PIC, local APIC)
+pub trait InterruptController {
+    /// Acknowledges the interrupt currently being handled
+    fn end_of_interrupt(&self);
+}
+
+/// Sends an EOI to the controller when dropped
+pub struct EoiGuard<'c, C: InterruptController + ?Sized>(&'c C);
+
+impl<'c, C: InterruptController + ?Sized> EoiGuard<'c, C> {
+    pub fn new(controller: &'c C) -> Self {
+        Self(controller)
+    }
+}
+
+impl<'c, C: InterruptController + ?Sized> Drop for EoiGuard<'c, C> {
+    fn drop(&mut self) {
+        self.0.end_of_interrupt();
+    }
+}
+
+/// Interrupt stack frame pushed by the CPU, the handler returns to the state it describes \
+/// Handlers receive the frame in place, `iretq` reloads it from the stack after the handler returns,
+/// so modifications take effect on return. Modifying the frame is safe as far as the handler is concerned,
+/// but returning to an arbitrary instruction pointer, stack or privilege level breaks the interrupted code
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct StackFrame {
+    pub instruction_pointer: VirtualAddress,
+    pub code_segment: u64,
+    pub cpu_flags: u64,
+    pub stack_pointer: VirtualAddress,
+    pub stack_segment: u64,
+}
+const_assert_eq!(core::mem::size_of::<StackFrame>(), 40);
 
 unsafe impl Bittable for StackFrame {}
 
+impl StackFrame {
+    const TRAP_FLAG: u64 = 1 << 8;
+    const RESUME_FLAG: u64 = 1 << 16;
+
+    /// Single-step flag, a #DB exception is raised after the next instruction
+    pub fn trap_flag(&self) -> bool {
+        self.cpu_flags & Self::TRAP_FLAG != 0
+    }
+
+    pub fn set_trap_flag(&mut self, value: bool) {
+        if value {
+            self.cpu_flags |= Self::TRAP_FLAG;
+        } else {
+            self.cpu_flags &= !Self::TRAP_FLAG;
+        }
+    }
+
+    /// Suppresses instruction breakpoints for the first instruction after returning,
+    /// has to be set when returning to an instruction that hit an execute breakpoint
+    pub fn set_resume_flag(&mut self, value: bool) {
+        if value {
+            self.cpu_flags |= Self::RESUME_FLAG;
+        } else {
+            self.cpu_flags &= !Self::RESUME_FLAG;
+        }
+    }
+}
+
 #[repr(transparent)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ErrorCode(pub usize);
@@ -182,8 +327,8 @@ impl From<ErrorCode> for usize {
     }
 }
 
-type InterruptHandlerType = extern "sysv64" fn(&StackFrame);
-type InterruptWithErrorCodeHandlerType = extern "sysv64" fn(&StackFrame, ErrorCode);
+type InterruptHandlerType = extern "sysv64" fn(&mut StackFrame);
+type InterruptWithErrorCodeHandlerType = extern "sysv64" fn(&mut StackFrame, ErrorCode);
 
 define_interrupt!(IntegerDivideByZero = IdtVector::INTEGER_DIVIDE_BY_ZERO, InterruptHandlerType);
 define_interrupt!(Debug = IdtVector::DEBUG, InterruptHandlerType);
@@ -194,6 +339,9 @@ define_interrupt!(BoundRangeExceeded = IdtVector::BOUND_RANGE_EXCEEDED, Interrup
 define_interrupt!(InvalidOpcode = IdtVector::INVALID_OPCODE, InterruptHandlerType);
 define_interrupt!(DeviceNotAvailable = IdtVector::DEVICE_NOT_AVAILABLE, InterruptHandlerType);
 define_interrupt!(DoubleFault = IdtVector::DOUBLE_FAULT, InterruptWithErrorCodeHandlerType);
+// Legacy vector, only raised by external x87 coprocessors (pre-486). Declared for completeness,
+// it must never be wired: it's reserved (see `IdtVector::is_reserved`), so `Idt::swap_handler` rejects it
+// and `IdtVector::all_exceptions` skips it
 define_interrupt!(CoprocessorSegmentOverrun = IdtVector::COPROCESSOR_SEGMENT_OVERRUN, InterruptHandlerType);
 define_interrupt!(InvalidTTS = IdtVector::INVALID_TTS, InterruptWithErrorCodeHandlerType);
 define_interrupt!(SegmentNotPresent = IdtVector::SEGMENT_NOT_PRESENT, InterruptWithErrorCodeHandlerType);
@@ -209,3 +357,8 @@ define_interrupt!(ControlProtectionException = IdtVector::CONTROL_PROTECTION_EXC
 define_interrupt!(HypervisorInjectionException = IdtVector::HYPERVISOR_INJECTION_EXCEPTION, InterruptHandlerType);
 define_interrupt!(VmmCommunicationException = IdtVector::VMM_COMMUNICATION_EXCEPTION, InterruptWithErrorCodeHandlerType);
 define_interrupt!(SecurityException = IdtVector::SECURITY_EXCEPTION, InterruptWithErrorCodeHandlerType);
+
+define_interrupt!(Timer = IdtVector::TIMER, InterruptHandlerType);
+define_interrupt!(SpuriousInterrupt = IdtVector::SPURIOUS, InterruptHandlerType);
+#[cfg(feature = "smp")]
+define_interrupt!(TlbShootdown = IdtVector::TLB_SHOOTDOWN, InterruptHandlerType);
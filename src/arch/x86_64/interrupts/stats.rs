@@ -0,0 +1,76 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::idt::IdtVector;
+
+// Per-vector invocation counters, incremented by every `define_interrupt_handler!` handler
+
+static COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+/// Called in the handler prologue
+#[inline]
+pub fn record(vector: IdtVector) {
+    COUNTS[u8::from(vector) as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn count(vector: IdtVector) -> u64 {
+    COUNTS[u8::from(vector) as usize].load(Ordering::Relaxed)
+}
+
+/// Snapshot of all counters, indexed by vector \
+/// Counters are read one by one, the snapshot isn't atomic as a whole
+pub fn stats() -> [u64; 256] {
+    core::array::from_fn(|vector| COUNTS[vector].load(Ordering::Relaxed))
+}
+
+/// Vectors with a nonzero count
+pub fn nonzero() -> impl Iterator<Item = (IdtVector, u64)> {
+    (0..=u8::MAX)
+        .map(|vector| (IdtVector::from(vector), COUNTS[vector as usize].load(Ordering::Relaxed)))
+        .filter(|&(_, count)| count != 0)
+}
+
+pub fn reset() {
+    for count in &COUNTS {
+        count.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arch::{interrupts::{define_interrupt_handler, CoprocessorSegmentOverrun, InterruptHandler, StackFrame}, VirtualAddress};
+
+    use super::*;
+
+    // Reserved vector, never raised by the other tests
+    define_interrupt_handler! {
+        handler CountedHandler(_frame: &mut StackFrame) for CoprocessorSegmentOverrun {}
+    }
+
+    #[test]
+    fn handler_increments_only_its_vector() {
+        let mut frame = StackFrame {
+            instruction_pointer: VirtualAddress::new(0x1000),
+            code_segment: 0,
+            cpu_flags: 0,
+            stack_pointer: VirtualAddress::new(0),
+            stack_segment: 0,
+        };
+        let vector = IdtVector::COPROCESSOR_SEGMENT_OVERRUN;
+        let before = stats();
+
+        CountedHandler::handler(&mut frame);
+        CountedHandler::handler(&mut frame);
+        let after = stats();
+        assert_eq!(after[vector.value() as usize], before[vector.value() as usize] + 2);
+        assert_eq!(count(vector), after[vector.value() as usize]);
+        assert!(nonzero().any(|(nonzero, count)| nonzero == vector && count >= 2));
+        // The neighbors are reserved too
+        for neighbor in [vector.value() - 1, vector.value() + 1] {
+            assert_eq!(after[neighbor as usize], 0, "vector {neighbor}");
+        }
+
+        reset();
+        assert_eq!(count(vector), 0);
+        assert!(nonzero().all(|(nonzero, _)| nonzero != vector));
+    }
+}
@@ -0,0 +1,81 @@
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::common::{random, time::ticks};
+
+use super::{apic::local_apic, define_interrupt_handler, InterruptHandler, StackFrame, Timer};
+
+/// Called on every timer tick with the interrupted context, a scheduler may replace the frame to switch tasks
+pub type PreemptHook = fn(frame: &mut StackFrame);
+
+/// `PreemptHook` stored as a pointer, null if not registered (read from the interrupt handler, so no locks)
+static PREEMPT_HOOK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Replaces the preemption hook, takes effect on the next tick
+pub fn set_preempt_hook(hook: PreemptHook) {
+    PREEMPT_HOOK.store(hook as *mut (), Ordering::Release);
+}
+
+pub fn clear_preempt_hook() {
+    PREEMPT_HOOK.store(core::ptr::null_mut(), Ordering::Release);
+}
+
+fn on_preempt(frame: &mut StackFrame) {
+    let hook = PREEMPT_HOOK.load(Ordering::Acquire);
+    if !hook.is_null() {
+        // SAFETY: only valid `PreemptHook`s are stored, function pointers and data pointers have the same size
+        let hook = unsafe { core::mem::transmute::<*mut (), PreemptHook>(hook) };
+        hook(frame);
+    }
+}
+
+/// Everything a timer interrupt does except the EOI
+fn tick(frame: &mut StackFrame) {
+    ticks::on_tick();
+    random::collect_interrupt_timing();
+    on_preempt(frame);
+}
+
+define_interrupt_handler! {
+    external handler TimerHandler(frame: &mut StackFrame) for Timer via local_apic {
+        // The EOI is sent when the handler returns, after the hook
+        tick(frame);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arch::VirtualAddress;
+
+    use super::*;
+
+    fn move_instruction_pointer(frame: &mut StackFrame) {
+        frame.instruction_pointer = VirtualAddress::new(0x2000);
+    }
+
+    #[test]
+    fn tick_counts_and_calls_the_hook() {
+        let mut frame = StackFrame {
+            instruction_pointer: VirtualAddress::new(0x1000),
+            code_segment: 0,
+            cpu_flags: 0,
+            stack_pointer: VirtualAddress::new(0),
+            stack_segment: 0,
+        };
+
+        let before = ticks::tick_count();
+        tick(&mut frame);
+        assert_eq!(ticks::tick_count(), before + 1);
+        assert_eq!(frame.instruction_pointer, VirtualAddress::new(0x1000), "no hook registered");
+
+        set_preempt_hook(move_instruction_pointer);
+        tick(&mut frame);
+        assert_eq!(ticks::tick_count(), before + 2);
+        assert_eq!(frame.instruction_pointer, VirtualAddress::new(0x2000));
+
+        clear_preempt_hook();
+        frame.instruction_pointer = VirtualAddress::new(0x1000);
+        tick(&mut frame);
+        assert_eq!(ticks::tick_count(), before + 3);
+        assert_eq!(frame.instruction_pointer, VirtualAddress::new(0x1000));
+    }
+}
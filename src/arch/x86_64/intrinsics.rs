@@ -1,4 +1,9 @@
 use core::{arch::asm, mem::MaybeUninit};
+
+use spin::Once;
+
+use crate::arch::VirtualAddress;
+
 use super::interrupts::idt::Idt;
 
 pub unsafe fn atomic_bit_test_set(value: *mut usize, index: usize) -> bool {
@@ -66,6 +71,9 @@ pub unsafe fn cpuid(eax: MaybeUninit<u32>, ecx: MaybeUninit<u32>) -> CpuidResult
 pub mod cpuid {
     use core::mem::MaybeUninit;
 
+    use spin::Once;
+    use static_assertions::const_assert;
+
     use super::cpuid;
 
     pub fn brand() -> [u8; 12] {
@@ -81,8 +89,146 @@ pub mod cpuid {
 
         out
     }
+
+    /// Highest supported basic CPUID leaf
+    pub fn max_leaf() -> u32 {
+        let res = unsafe {
+            cpuid(MaybeUninit::new(0), MaybeUninit::uninit())
+        };
+
+        res.eax
+    }
+
+    /// x2APIC (MSR-based local APIC access) support
+    pub fn x2apic() -> bool {
+        let res = unsafe {
+            cpuid(MaybeUninit::new(1), MaybeUninit::uninit())
+        };
+
+        res.ecx & (1 << 21) != 0
+    }
+
+    /// Initial local APIC id of the current CPU (CPUID.1:EBX[31:24]), only the low 8 bits of an x2APIC id
+    pub fn initial_apic_id() -> u8 {
+        let res = unsafe {
+            cpuid(MaybeUninit::new(1), MaybeUninit::uninit())
+        };
+
+        (res.ebx >> 24) as u8
+    }
+
+    /// Highest supported extended CPUID leaf
+    pub fn max_extended_leaf() -> u32 {
+        let res = unsafe {
+            cpuid(MaybeUninit::new(0x8000_0000), MaybeUninit::uninit())
+        };
+
+        res.eax
+    }
+
+    /// RDTSCP instruction support
+    pub fn rdtscp() -> bool {
+        if max_extended_leaf() < 0x8000_0001 {
+            return false;
+        }
+
+        let res = unsafe {
+            cpuid(MaybeUninit::new(0x8000_0001), MaybeUninit::uninit())
+        };
+
+        res.edx & (1 << 27) != 0
+    }
+
+    /// Physical address width (MAXPHYADDR), 36 if the CPU doesn't report it
+    pub fn phys_address_bits() -> u8 {
+        address_bits().0
+    }
+
+    /// Linear address width, 48 if the CPU doesn't report it
+    pub fn virt_address_bits() -> u8 {
+        address_bits().1
+    }
+
+    /// (physical, linear) address widths
+    fn address_bits() -> (u8, u8) {
+        static ADDRESS_BITS: Once<(u8, u8)> = Once::new();
+
+        *ADDRESS_BITS.call_once(|| {
+            if max_extended_leaf() < 0x8000_0008 {
+                return decode_address_bits(None);
+            }
+            let res = unsafe {
+                cpuid(MaybeUninit::new(0x8000_0008), MaybeUninit::uninit())
+            };
+            decode_address_bits(Some(res.eax))
+        })
+    }
+
+    /// Decodes EAX of leaf 0x80000008: bits 0:7 physical, 8:15 linear address width
+    const fn decode_address_bits(eax: Option<u32>) -> (u8, u8) {
+        match eax {
+            Some(eax) if eax & 0xFF != 0 => (eax as u8, (eax >> 8) as u8),
+            // The SDM default without the leaf
+            _ => (36, 48),
+        }
+    }
+
+    const_assert!(matches!(decode_address_bits(Some(0x3027)), (39, 48)));
+    const_assert!(matches!(decode_address_bits(Some(0x0000_3930)), (48, 57)));
+    const_assert!(matches!(decode_address_bits(Some(0xFFFF_3034)), (52, 48)));
+    const_assert!(matches!(decode_address_bits(None), (36, 48)));
+
+    /// 5-level paging (LA57) support
+    pub fn la57() -> bool {
+        if max_leaf() < 7 {
+            return false;
+        }
+
+        let res = unsafe {
+            cpuid(MaybeUninit::new(7), MaybeUninit::new(0))
+        };
+
+        res.ecx & (1 << 16) != 0
+    }
 }
 
+/// Memory ordering instructions \
+/// x86 orders ordinary (write-back) loads and stores strongly, the fences matter for weakly ordered accesses:
+/// non-temporal stores, write-combining memory (e.g. the framebuffer) and `rdtsc` \
+/// Page table writes don't need a fence before `invlpg` / a CR3 reload, both are serializing with respect to
+/// earlier stores, but the compiler must not reorder the write past the flush ([compiler_fence])
+pub mod fence {
+    use core::arch::asm;
+
+    pub use core::sync::atomic::{compiler_fence, Ordering};
+
+    /// `mfence` - all earlier loads and stores complete before any later load or store,
+    /// e.g. before signaling a device that reads a buffer written with non-temporal stores
+    pub fn memory() {
+        unsafe {
+            asm!("mfence", options(nostack, preserves_flags));
+        }
+    }
+
+    /// `sfence` - earlier stores (including non-temporal and write-combining ones) become visible before later stores,
+    /// e.g. after drawing to a write-combining framebuffer
+    pub fn store() {
+        unsafe {
+            asm!("sfence", options(nostack, preserves_flags));
+        }
+    }
+
+    /// `lfence` - earlier instructions complete before later ones start (loads included),
+    /// e.g. before `rdtsc` or after a bounds check guarding speculative loads
+    pub fn load() {
+        unsafe {
+            asm!("lfence", options(nostack, preserves_flags));
+        }
+    }
+}
+
+/// Warning: `rdtsc` isn't serializing, the CPU may execute it before earlier instructions complete
+/// (or after later ones start), use [time_stamp_counter_serializing] for fine-grained measurements
 pub fn time_stamp_counter() -> u64 {
     let low: u32;
     let high: u32;
@@ -96,16 +242,260 @@ pub fn time_stamp_counter() -> u64 {
     (high as u64) << 32 | (low as u64)
 }
 
+/// Reads the time stamp counter after all earlier instructions complete \
+/// Returns the counter and the processor id (IA32_TSC_AUX) if `rdtscp` is supported,
+/// falls back to `lfence; rdtsc` otherwise
+pub fn time_stamp_counter_serializing() -> (u64, Option<u32>) {
+    static RDTSCP_SUPPORTED: Once<bool> = Once::new();
+
+    let low: u32;
+    let high: u32;
+    if *RDTSCP_SUPPORTED.call_once(cpuid::rdtscp) {
+        let aux: u32;
+        unsafe {
+            asm!(
+                "rdtscp",
+                out("eax") low, out("edx") high, out("ecx") aux,
+                options(nostack, nomem, preserves_flags)
+            );
+        }
+        ((high as u64) << 32 | (low as u64), Some(aux))
+    } else {
+        unsafe {
+            asm!(
+                "lfence",
+                "rdtsc",
+                out("eax") low, out("edx") high,
+                options(nostack, nomem, preserves_flags)
+            );
+        }
+        ((high as u64) << 32 | (low as u64), None)
+    }
+}
+
+pub unsafe fn read_msr(msr: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        asm!(
+            "rdmsr",
+            in("ecx") msr, out("eax") low, out("edx") high,
+            options(nostack, nomem, preserves_flags)
+        );
+    }
+    (high as u64) << 32 | (low as u64)
+}
+
+pub unsafe fn write_msr(msr: u32, value: u64) {
+    unsafe {
+        asm!(
+            "wrmsr",
+            in("ecx") msr, in("eax") value as u32, in("edx") (value >> 32) as u32,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+pub unsafe fn port_read_u8(port: u16) -> u8 {
+    let value: u8;
+    unsafe {
+        asm!(
+            "in al, dx",
+            in("dx") port, out("al") value,
+            options(nostack, nomem, preserves_flags)
+        );
+    }
+    value
+}
+
+pub unsafe fn port_write_u8(port: u16, value: u8) {
+    unsafe {
+        asm!(
+            "out dx, al",
+            in("dx") port, in("al") value,
+            options(nostack, nomem, preserves_flags)
+        );
+    }
+}
+
+pub unsafe fn port_write_u32(port: u16, value: u32) {
+    unsafe {
+        asm!(
+            "out dx, eax",
+            in("dx") port, in("eax") value,
+            options(nostack, nomem, preserves_flags)
+        );
+    }
+}
+
+/// Operand of `lidt` and `lgdt`
+#[repr(C, packed)]
+struct IdtDescriptor {
+    limit: u16,
+    base: u64,
+}
+
 // TODO: should it be unsafe?
 pub fn load_idt(idt: &'static Idt) {
-    let idt = idt as *const Idt;
+    let descriptor = IdtDescriptor {
+        limit: (core::mem::size_of::<Idt>() - 1) as u16,
+        base: idt as *const Idt as u64,
+    };
+    unsafe {
+        asm!(
+            "lidt [{}]",
+            in(reg) &descriptor,
+            options(readonly, preserves_flags, nostack)
+        );
+    }
+}
+
+/// Loads a descriptor table of `size` bytes at `gdt` with `lgdt`
+pub fn load_gdt<T>(gdt: &'static T, size: usize) {
+    let descriptor = IdtDescriptor {
+        limit: (size - 1) as u16,
+        base: gdt as *const T as u64,
+    };
+    unsafe {
+        asm!(
+            "lgdt [{}]",
+            in(reg) &descriptor,
+            options(readonly, preserves_flags, nostack)
+        );
+    }
+}
+
+/// Reloads CS with a far return and DS, ES and SS with `data`
+/// # Safety
+/// Both selectors must refer to valid kernel segments of the loaded GDT
+pub unsafe fn reload_segments(code: u16, data: u16) {
     unsafe {
         asm!(
-            "lidt {}",
-            in(reg) idt,
+            "push {code}",
+            "lea {tmp}, [rip + 2f]",
+            "push {tmp}",
+            "retfq",
+            "2:",
+            "mov ds, {data:x}",
+            "mov es, {data:x}",
+            "mov ss, {data:x}",
+            code = in(reg) code as u64,
+            data = in(reg) data,
+            tmp = out(reg) _,
+            options(preserves_flags)
+        );
+    }
+}
+
+/// Loads the task register with `ltr`, which marks the TSS descriptor busy
+/// # Safety
+/// `selector` must refer to an available TSS descriptor of the loaded GDT
+pub unsafe fn load_task_register(selector: u16) {
+    unsafe {
+        asm!(
+            "ltr {:x}",
+            in(reg) selector,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// Base and size in bytes of the IDT loaded on the current CPU
+pub fn loaded_idt() -> (VirtualAddress, usize) {
+    let mut descriptor = IdtDescriptor { limit: 0, base: 0 };
+    unsafe {
+        asm!(
+            "sidt [{}]",
+            in(reg) &mut descriptor,
+            options(preserves_flags, nostack)
+        );
+    }
+    (VirtualAddress::new(descriptor.base as usize), descriptor.limit as usize + 1)
+}
+
+/// Current code segment selector
+pub fn code_segment() -> u16 {
+    let selector: u16;
+    unsafe {
+        asm!(
+            "mov {:x}, cs",
+            out(reg) selector,
             options(nomem, preserves_flags, nostack)
         );
     }
+    selector
+}
+
+/// Invalidates the TLB entry for the page containing `address` on the current CPU
+pub fn invalidate_page(address: VirtualAddress) {
+    unsafe {
+        asm!(
+            "invlpg [{}]",
+            in(reg) address.as_ptr(),
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// Flushes all non-global TLB entries on the current CPU by reloading CR3 \
+/// Global pages (kernel mappings with CR4.PGE set) survive, see [flush_tlb_global]
+pub fn flush_tlb_all() {
+    unsafe {
+        write_cr!(3, read_cr!(3));
+    }
+}
+
+/// Flushes all TLB entries including global ones on the current CPU by toggling CR4.PGE
+pub fn flush_tlb_global() {
+    const CR4_PGE_BIT: u64 = 1 << 7;
+    without_interrupts(|| unsafe {
+        let cr4 = read_cr!(4);
+        if cr4 & CR4_PGE_BIT != 0 {
+            write_cr!(4, cr4 & !CR4_PGE_BIT);
+            write_cr!(4, cr4);
+        } else {
+            write_cr!(3, read_cr!(3));
+        }
+    });
+}
+
+/// Checks the interrupt flag (RFLAGS.IF)
+pub fn interrupts_enabled() -> bool {
+    let flags: u64;
+    unsafe {
+        asm!(
+            "pushfq",
+            "pop {}",
+            out(reg) flags,
+            options(nomem, preserves_flags)
+        );
+    }
+    flags & (1 << 9) != 0
+}
+
+/// Runs `f` with maskable interrupts disabled, restoring the previous interrupt flag afterwards
+/// `cli` and `sti` aren't marked `nomem`, so memory accesses in `f` aren't moved outside the critical section
+pub fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    // Host unit tests run in userspace, where `cli` / `sti` fault
+    if cfg!(test) {
+        return f();
+    }
+
+    let enabled = interrupts_enabled();
+    if enabled {
+        unsafe {
+            asm!("cli", options(nostack));
+        }
+    }
+
+    let result = f();
+
+    if enabled {
+        unsafe {
+            asm!("sti", options(nostack));
+        }
+    }
+    result
 }
 
 pub fn halt() -> ! {
@@ -144,3 +534,40 @@ macro_rules! write_cr {
     }}
 }
 pub(super) use write_cr;
+
+macro_rules! read_dr {
+    ($register:literal) => {{
+        let result: u64;
+        ::core::arch::asm!(
+            concat!("mov {}, dr", $register),
+            out(reg) result,
+            options(nomem, preserves_flags, nostack)
+        );
+        result
+    }}
+}
+pub(super) use read_dr;
+
+macro_rules! write_dr {
+    ($register:literal, $value:expr) => {{
+        let value: u64 = $value;
+        ::core::arch::asm!(
+            concat!("mov dr", $register, ", {}"),
+            in(reg) value,
+            options(nomem, preserves_flags, nostack)
+        );
+        value
+    }}
+}
+pub(super) use write_dr;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interrupt_flag_is_set_in_userspace() {
+        // `cli` / `sti` fault in userspace, see `fault_injection` for the `without_interrupts` check
+        assert!(interrupts_enabled());
+    }
+}
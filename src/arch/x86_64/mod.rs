@@ -1,23 +1,98 @@
+pub mod debug;
+pub mod devices;
+pub mod gdt;
 pub mod interrupts;
 pub mod intrinsics;
 pub mod paging;
+pub mod probe;
+pub mod processor;
+#[cfg(feature = "test-qemu")]
+pub mod qemu;
+pub mod serial;
+pub mod stack;
 pub mod syscalls;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct PrivilegeLevel(u8);
 
 impl PrivilegeLevel {
-    pub const KERNEL: PrivilegeLevel = PrivilegeLevel::from(0);
-    pub const USERSPACE: PrivilegeLevel = PrivilegeLevel::from(3);
+    pub const KERNEL: PrivilegeLevel = PrivilegeLevel::new(0);
+    pub const USERSPACE: PrivilegeLevel = PrivilegeLevel::new(3);
+    pub const MAX: u8 = 3;
 
-    pub const fn from(value: u8) -> Self {
+    /// Panics if `value` is greater than 3, see [PrivilegeLevel::try_new]
+    pub const fn new(value: u8) -> Self {
+        assert!(value <= Self::MAX, "Privilege level out of range");
         PrivilegeLevel(value)
     }
+
+    pub const fn try_new(value: u8) -> Result<Self, OutOfRange> {
+        if value <= Self::MAX {
+            Ok(PrivilegeLevel(value))
+        } else {
+            Err(OutOfRange { value: value as u64, max: Self::MAX as u64 })
+        }
+    }
+
+    /// Same as [PrivilegeLevel::new]
+    pub const fn from(value: u8) -> Self {
+        Self::new(value)
+    }
 }
 
+/// Panics if `value` is greater than 3
 impl From<u8> for PrivilegeLevel {
     fn from(value: u8) -> Self {
-        PrivilegeLevel(value)
+        PrivilegeLevel::new(value)
+    }
+}
+
+/// Index of a GDT / LDT descriptor (13-bit selector field)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SegmentIndex(u16);
+
+impl SegmentIndex {
+    pub const MAX: u16 = 8191;
+
+    /// Panics if `value` is greater than 8191, see [SegmentIndex::try_new]
+    pub const fn new(value: u16) -> Self {
+        assert!(value <= Self::MAX, "Segment index out of range");
+        SegmentIndex(value)
+    }
+
+    /// For values decoded at runtime (e.g. a #GP error code or a corrupt descriptor)
+    pub const fn try_new(value: u16) -> Result<Self, OutOfRange> {
+        if value <= Self::MAX {
+            Ok(SegmentIndex(value))
+        } else {
+            Err(OutOfRange { value: value as u64, max: Self::MAX as u64 })
+        }
+    }
+
+    pub const fn value(self) -> u16 {
+        self.0
+    }
+
+    /// GDT selector with the requested privilege level
+    pub const fn selector(self, rpl: PrivilegeLevel) -> u16 {
+        self.0 << 3 | rpl.0 as u16
+    }
+}
+
+static_assertions::const_assert!(SegmentIndex::try_new(8192).is_err());
+static_assertions::const_assert!(SegmentIndex::try_new(8191).is_ok());
+static_assertions::const_assert!(PrivilegeLevel::try_new(4).is_err());
+
+/// A value exceeded the maximum of its field
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutOfRange {
+    pub value: u64,
+    pub max: u64,
+}
+
+impl core::fmt::Display for OutOfRange {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "value {} out of range (max. {})", self.value, self.max)
     }
 }
 
@@ -0,0 +1,137 @@
+// Kernel image page permissions
+//
+// Section bounds come from the linker script (build/x86-64_limine.ld), which must define
+// `__kernel_{text,rodata,data}_{start,end}` with page aligned start symbols,
+// `__kernel_data_end` must be placed after .bss.
+
+use static_assertions::const_assert;
+
+use crate::arch::{intrinsics::{invalidate_page, read_cr, read_msr, write_cr, write_msr}, VirtualAddress};
+
+use super::{current_address_space, level1_entry_mut, IdentityMapToken, PagingToken, WalkError, PAGE_SIZE};
+
+const IA32_EFER_MSR: u32 = 0xC0000080;
+const EFER_NXE_BIT: u64 = 1 << 11;
+const CR0_WP_BIT: u64 = 1 << 16;
+const CR4_PGE_BIT: u64 = 1 << 7;
+
+extern "C" {
+    static __kernel_text_start: u8;
+    static __kernel_text_end: u8;
+    static __kernel_rodata_start: u8;
+    static __kernel_rodata_end: u8;
+    static __kernel_data_start: u8;
+    static __kernel_data_end: u8;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KernelSection {
+    /// .text, read + execute
+    Text,
+    /// .rodata, read only
+    ReadOnlyData,
+    /// .data and .bss, read + write
+    Data,
+}
+
+impl KernelSection {
+    pub const ALL: [KernelSection; 3] = [KernelSection::Text, KernelSection::ReadOnlyData, KernelSection::Data];
+
+    pub const fn writable(self) -> bool {
+        matches!(self, KernelSection::Data)
+    }
+
+    pub const fn no_execute(self) -> bool {
+        !matches!(self, KernelSection::Text)
+    }
+
+    /// [start; end) virtual address range
+    pub fn range(self) -> (VirtualAddress, VirtualAddress) {
+        unsafe {
+            let (start, end) = match self {
+                KernelSection::Text => (&__kernel_text_start, &__kernel_text_end),
+                KernelSection::ReadOnlyData => (&__kernel_rodata_start, &__kernel_rodata_end),
+                KernelSection::Data => (&__kernel_data_start, &__kernel_data_end),
+            };
+            ((start as *const u8).into(), (end as *const u8).into())
+        }
+    }
+}
+
+/// Enables no-execute pages (EFER.NXE) on the current CPU
+pub fn enable_no_execute() {
+    unsafe {
+        let efer = read_msr(IA32_EFER_MSR);
+        write_msr(IA32_EFER_MSR, efer | EFER_NXE_BIT);
+    }
+}
+
+/// Without EFER.NXE the no-execute bit is reserved, setting it makes walks through the entry fault
+pub fn no_execute_enabled() -> bool {
+    unsafe { read_msr(IA32_EFER_MSR) & EFER_NXE_BIT != 0 }
+}
+
+/// Makes read-only pages read-only for ring 0 too (CR0.WP) on the current CPU \
+/// Read-only kernel mappings (see [harden_kernel_mappings]) and copy-on-write depend on it,
+/// without it kernel writes ignore the writable bit
+pub fn enable_write_protect() {
+    unsafe {
+        let cr0 = read_cr!(0);
+        write_cr!(0, cr0 | CR0_WP_BIT);
+    }
+}
+
+pub fn write_protect_enabled() -> bool {
+    unsafe { read_cr!(0) & CR0_WP_BIT != 0 }
+}
+
+/// Enables global pages (CR4.PGE) on the current CPU \
+/// Global TLB entries survive CR3 reloads, kernel-half mappings are the same in every address space
+/// so [map](super::map) marks them global from then on. Flushing them needs `flush_tlb_global`
+pub fn enable_global_pages() {
+    unsafe {
+        let cr4 = read_cr!(4);
+        write_cr!(4, cr4 | CR4_PGE_BIT);
+    }
+}
+
+pub fn global_pages_enabled() -> bool {
+    unsafe { read_cr!(4) & CR4_PGE_BIT != 0 }
+}
+
+/// Whether a mapping of `address` should be global: kernel-half, not user accessible pages
+pub const fn is_global_mapping(address: VirtualAddress, user: bool) -> bool {
+    // Kernel-half addresses are sign extended, bit 63 is set with both paging modes
+    !user && address.0 >> 63 != 0
+}
+
+const_assert!(is_global_mapping(VirtualAddress::new(0xFFFF_FFFF_8000_0000), false));
+const_assert!(!is_global_mapping(VirtualAddress::new(0xFFFF_FFFF_8000_0000), true));
+const_assert!(!is_global_mapping(VirtualAddress::new(0x0000_7FFF_FFFF_F000), true));
+const_assert!(!is_global_mapping(VirtualAddress::new(0x0000_0000_0040_0000), false));
+
+/// Makes .text read + execute, .rodata read only and .data / .bss non-executable,
+/// the pages are marked global if global pages are enabled \
+/// Requires CR0.WP ([enable_write_protect]), without it ring 0 writes to read-only pages don't fault \
+/// Safety:
+/// The kernel must be mapped with 4 KiB pages, no references to its page table entries may exist
+pub unsafe fn harden_kernel_mappings(token: PagingToken) -> Result<(), WalkError> {
+    let identity_map: IdentityMapToken = token.into();
+    assert!(write_protect_enabled(), "CR0.WP must be set before hardening the kernel mappings");
+    let global = global_pages_enabled();
+
+    for section in KernelSection::ALL {
+        let (start, end) = section.range();
+        let mut page = start.last_multiple_of(PAGE_SIZE);
+        while page < end {
+            let entry = unsafe { level1_entry_mut(current_address_space(), page, identity_map)? };
+            entry.set_writable(section.writable());
+            entry.set_no_execute(section.no_execute());
+            entry.set_global(global);
+            invalidate_page(page);
+            page += PAGE_SIZE;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,69 @@
+// Device memory mappings
+// MMIO regions are mapped into address space from the kernel virtual allocator, the frames are never
+// allocated or freed (they aren't RAM managed by the frame allocator)
+
+use core::fmt::Display;
+
+use crate::arch::{PhysicalAddress, VirtualAddress};
+
+use super::{kernel_vm, map_with_cache_mode, unmap, CacheMode, PageFlags, PagingToken, WalkError, PAGE_SIZE};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapError {
+    /// No virtual address space left for the mapping
+    OutOfVirtualSpace,
+    /// The range wraps around the address space
+    InvalidRange,
+    Walk(WalkError),
+}
+
+impl From<WalkError> for MapError {
+    fn from(value: WalkError) -> Self {
+        MapError::Walk(value)
+    }
+}
+
+impl Display for MapError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            MapError::OutOfVirtualSpace => f.write_str("out of virtual address space"),
+            MapError::InvalidRange => f.write_str("range wraps around the address space"),
+            MapError::Walk(error) => f.write_fmt(format_args!("page table walk failed: {error:?}")),
+        }
+    }
+}
+
+/// Maps `[physical, physical + len)` as non-executable, writable device memory and returns
+/// the virtual address corresponding to `physical` \
+/// The range is extended to whole pages, typical cache modes are `Uncached` for registers
+/// and `WriteCombining` for framebuffers
+pub fn map_mmio(physical: PhysicalAddress, len: usize, cache_mode: CacheMode, token: PagingToken) -> Result<VirtualAddress, MapError> {
+    let first_frame = physical.page_align_down();
+    let offset = physical - first_frame;
+    let size = offset.checked_add(len)
+        .and_then(|x| x.checked_next_multiple_of(PAGE_SIZE))
+        .filter(|&size| usize::from(first_frame).checked_add(size).is_some())
+        .ok_or(MapError::InvalidRange)?;
+    let pages = size / PAGE_SIZE;
+
+    let vm = kernel_vm(token);
+    let range = vm.allocate(size, PAGE_SIZE).ok_or(MapError::OutOfVirtualSpace)?;
+    let base = range.start;
+
+    let flags = PageFlags { writable: true, executable: false, user: false };
+    for index in 0..pages {
+        let page = base + index * PAGE_SIZE;
+        // SAFETY: the page is in an unused part of the MMIO area, device memory isn't referenced by Rust objects
+        let mapped = unsafe { map_with_cache_mode(page, first_frame + index * PAGE_SIZE, flags, cache_mode, token) };
+        if let Err(error) = mapped {
+            for page in (0..index).map(|i| base + i * PAGE_SIZE) {
+                // SAFETY: mapped above, not handed out yet
+                _ = unsafe { unmap(page, token) };
+            }
+            _ = vm.free(range);
+            return Err(error.into());
+        }
+    }
+
+    Ok(base + offset)
+}
@@ -1,13 +1,29 @@
 #![allow(dead_code)] // TODO (WIP)
+mod harden;
+mod mmio;
+mod pat;
+mod recursive;
+#[cfg(feature = "smp")]
+pub mod shootdown;
 mod structs;
 
+use core::fmt::Display;
+
 use spin::Once;
+use static_assertions::const_assert_eq;
 use structs::*;
 pub use structs::PAGE_SIZE;
+pub use harden::{
+    enable_global_pages, enable_write_protect, global_pages_enabled, harden_kernel_mappings, is_global_mapping,
+    no_execute_enabled, write_protect_enabled, KernelSection
+};
+pub use mmio::{map_mmio, MapError};
+pub use pat::{initialize_pat, pat_value, CacheMode, PAT_LAYOUT};
+pub use recursive::{recursive_map, setup_recursive, RecursiveMap};
 
 use crate::{
-    allocator::physical::FrameAllocator,
-    arch::{intrinsics::write_cr, PhysicalAddress, VirtualAddress},
+    allocator::{physical::{global_allocator, FrameAllocatorToken}, vm::VmAllocator},
+    arch::{intrinsics::{invalidate_page, write_cr}, AddressRange, PhysicalAddress, VirtualAddress},
     common::macros::{token_from, token_type}
 };
 
@@ -16,41 +32,205 @@ use super::intrinsics::read_cr;
 // u64 on private api
 // usize on public api (same public interface on various architectures)
 
+static PAGING_INITIALIZED: Once<()> = Once::new();
 static IDENTITY_MAP_BASE: Once<PhysicalAddress> = Once::new();
+/// First physical address the identity map isn't guaranteed to cover
+static IDENTITY_MAP_END: Once<PhysicalAddress> = Once::new();
+
+/// Kernel virtual address space handed out by [kernel_vm] (MMIO mappings, heap), 2 TiB below the kernel stacks \
+/// The identity map (HHDM) starts at 0xFFFF_8000_0000_0000 with 4-level paging (0xFF00_0000_0000_0000 with 5-level)
+/// and must end below the window, which leaves 124 TiB of physical memory with 4-level paging, checked on first use
+const KERNEL_VM_WINDOW: AddressRange<VirtualAddress> =
+    AddressRange { start: VirtualAddress::new(0xFFFF_FC00_0000_0000), end: VirtualAddress::new(0xFFFF_FE00_0000_0000) };
+static KERNEL_VM: VmAllocator<256> = VmAllocator::new(KERNEL_VM_WINDOW);
+static KERNEL_VM_CHECKED: Once<()> = Once::new();
 
 const CR3_ADDRESS_MASK: u64 = 0xFFFFFFFFFF000;
+const CR4_LA57_BIT: u64 = 1 << 12;
+
+// Token flow:
+// initialize_identity_map -> IdentityMapToken, enough for to_virtual and reading page tables
+// physical::initialize(IdentityMapToken) -> FrameAllocatorToken
+// initialize(FrameAllocatorToken, IdentityMapToken) -> PagingToken, required by map / unmap / translate,
+// converts into both previous tokens
 
 token_type!(PagingToken);
 
 token_type!(IdentityMapToken);
 
-// TODO
 token_from!(PagingToken, IdentityMapToken);
+token_from!(PagingToken, FrameAllocatorToken);
 
-/// This function may only be called once, all subsequent calls will panic or be ignored
-pub fn initialize_identity_map(identity_map_base: PhysicalAddress) -> IdentityMapToken {
+/// This function may only be called once, all subsequent calls will panic or be ignored \
+/// `end` is the first physical address that doesn't have to be mapped (e.g. the end of the highest usable region),
+/// panics if `[identity_map_base, identity_map_base + end)` isn't a valid canonical range
+pub fn initialize_identity_map(identity_map_base: PhysicalAddress, end: PhysicalAddress) -> IdentityMapToken {
     // best effort panic
     if IDENTITY_MAP_BASE.is_completed() {
         panic!("Identity map already initialized.");
     }
 
+    if let Err(error) = validate_identity_map(identity_map_base, end, PagingMode::current()) {
+        panic!("Invalid identity map (base {identity_map_base}, end {end}): {error}");
+    }
+
     IDENTITY_MAP_BASE.call_once(|| identity_map_base);
+    IDENTITY_MAP_END.call_once(|| end);
 
     unsafe {
         IdentityMapToken::new()
     }
 }
 
-pub fn initialize(frame_allocator: FrameAllocator, identity_map: IdentityMapToken) {
-    let _ = (identity_map, frame_allocator);
-    todo!()
+/// This function may only be called once, all subsequent calls will panic or be ignored \
+/// Enables no-execute pages, write protection in ring 0, global pages and programs the PAT on the current CPU
+pub fn initialize(frame_allocator: FrameAllocatorToken, identity_map: IdentityMapToken) -> PagingToken {
+    // best effort panic
+    if PAGING_INITIALIZED.is_completed() {
+        panic!("Paging already initialized.");
+    }
+
+    PAGING_INITIALIZED.call_once(|| {
+        let _ = (frame_allocator, identity_map);
+        harden::enable_no_execute();
+        // Nothing before this point writes through read-only mappings, limine maps the kernel per ELF segment
+        harden::enable_write_protect();
+        harden::enable_global_pages();
+        // SAFETY: the first 4 PAT slots keep their defaults, existing mappings don't use the others
+        unsafe {
+            initialize_pat();
+        }
+    });
+
+    unsafe {
+        PagingToken::new()
+    }
+}
+
+/// Allocator of the kernel virtual address window, see [KERNEL_VM_WINDOW]
+pub fn kernel_vm(token: PagingToken) -> &'static VmAllocator<256> {
+    KERNEL_VM_CHECKED.call_once(|| {
+        let identity_map: IdentityMapToken = token.into();
+        debug_assert!(IDENTITY_MAP_END.is_completed());
+        // SAFETY: initialized together with the identity map base, which the token guarantees
+        let end = unsafe { *IDENTITY_MAP_END.get_unchecked() };
+        let identity_map_end = usize::from(identity_map_base(identity_map)).saturating_add(end.0);
+        let window = KERNEL_VM_WINDOW;
+        assert!(
+            identity_map_end <= usize::from(window.start) || usize::from(identity_map_base(identity_map)) >= usize::from(window.end),
+            "Identity map overlaps the kernel virtual address window"
+        );
+    });
+    &KERNEL_VM
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdentityMapError {
+    /// `base + end` exceeds the virtual address space
+    AddressOverflow,
+    /// The mapped range isn't canonical or crosses the canonical hole
+    NonCanonical,
 }
 
-/// Returns corresponding virtual address from the identity mapping
+impl Display for IdentityMapError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            IdentityMapError::AddressOverflow => f.write_str("mapped range wraps around the address space"),
+            IdentityMapError::NonCanonical => f.write_str("mapped range is not canonical"),
+        }
+    }
+}
+
+/// Checks that physical addresses `[0, end)` mapped at `base` form a canonical virtual address range under `mode`
+pub fn validate_identity_map(base: PhysicalAddress, end: PhysicalAddress, mode: PagingMode) -> Result<(), IdentityMapError> {
+    let first = base.0;
+    let last = match end.0.checked_sub(1) {
+        Some(last) => first.checked_add(last).ok_or(IdentityMapError::AddressOverflow)?,
+        None => return Ok(()),
+    };
+
+    // Canonical addresses have bits [address_bits - 1, 63] all equal,
+    // both ends being in the same half guarantees the range doesn't cross the hole
+    let sign_bits = |address: usize| (address as isize) >> (mode.virtual_address_bits() - 1);
+    match (sign_bits(first), sign_bits(last)) {
+        (0, 0) | (-1, -1) => Ok(()),
+        _ => Err(IdentityMapError::NonCanonical),
+    }
+}
+
+/// Checks if `address` is covered by the identity map
+pub fn is_identity_mapped(address: PhysicalAddress, #[allow(unused_variables)] token: IdentityMapToken) -> bool {
+    debug_assert!(IDENTITY_MAP_END.is_completed());
+    // SAFETY: initialized together with the identity map base, which the token guarantees
+    address < unsafe { *IDENTITY_MAP_END.get_unchecked() }
+}
+
+/// Returns corresponding virtual address from the identity mapping \
+/// `address` should be covered by the identity map, see [is_identity_mapped]
 pub fn to_virtual(address: PhysicalAddress, token: IdentityMapToken) -> VirtualAddress {
+    debug_assert!(is_identity_mapped(address, token), "Physical address {address} outside the identity map");
     (Into::<usize>::into(identity_map_base(token)) + address.0).into()
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PagingMode {
+    /// 4-level paging, 48-bit virtual addresses
+    Level4,
+    /// 5-level paging (LA57), 57-bit virtual addresses
+    Level5,
+}
+
+impl PagingMode {
+    /// Reads the paging mode currently set up by the bootloader
+    pub fn current() -> Self {
+        let cr4 = unsafe { read_cr!(4) };
+        if cr4 & CR4_LA57_BIT != 0 {
+            PagingMode::Level5
+        } else {
+            PagingMode::Level4
+        }
+    }
+
+    /// Checks if 5-level paging is supported by the CPU
+    pub fn is_level5_supported() -> bool {
+        super::intrinsics::cpuid::la57()
+    }
+
+    /// Number of page table levels, walks start at this level
+    pub const fn levels(self) -> u8 {
+        match self {
+            PagingMode::Level4 => 4,
+            PagingMode::Level5 => 5,
+        }
+    }
+
+    /// Significant virtual address bits, higher bits must be copies of the highest significant bit
+    pub const fn virtual_address_bits(self) -> u32 {
+        match self {
+            PagingMode::Level4 => 48,
+            PagingMode::Level5 => 57,
+        }
+    }
+}
+
+impl VirtualAddress {
+    /// Index into the page table at `level` (1 - page table, 5 - PML5 table)
+    pub const fn page_table_index(self, level: u8) -> usize {
+        debug_assert!(level >= 1 && level <= 5);
+        (self.0 >> (12 + 9 * (level as usize - 1))) & 0x1FF
+    }
+
+    /// Index into the PML5 table, only meaningful with `PagingMode::Level5`
+    pub const fn pml5_index(self) -> usize {
+        self.page_table_index(5)
+    }
+
+    pub const fn pml4_index(self) -> usize {
+        self.page_table_index(4)
+    }
+}
+
+/// With `PagingMode::Level5` CR3 points to the PML5 table instead
 unsafe fn read_pml4_address() -> PhysicalAddress {
     unsafe {
         (read_cr!(3) & CR3_ADDRESS_MASK).into()
@@ -70,8 +250,366 @@ fn identity_map_base(#[allow(unused_variables)] token: IdentityMapToken) -> Phys
     }
 }
 
+/// Permissions of a mapped page, present pages are always readable
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PageFlags {
+    pub writable: bool,
+    pub executable: bool,
+    /// Accessible from userspace
+    pub user: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WalkError {
+    /// An entry on the path isn't present
+    NotMapped,
+    /// The address is mapped by a 2 MiB or 1 GiB page
+    LargePage,
+    /// The page is already mapped
+    AlreadyMapped,
+    /// No frame available for a new page table
+    OutOfMemory,
+}
+
+const LARGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+const HUGE_PAGE_SIZE: usize = 1024 * 1024 * 1024;
+/// Page size bit, only valid in level 2 and 3 entries
+const PAGE_SIZE_BIT: u64 = 1 << 7;
+
+/// Maps the 4 KiB page at `address` to `frame` in the active address space, missing page tables are allocated \
+/// Safety:
+/// Mapping over memory referenced by existing references or aliasing writable kernel memory is UB
+pub unsafe fn map(address: VirtualAddress, frame: PhysicalAddress, flags: PageFlags, token: PagingToken) -> Result<(), WalkError> {
+    unsafe {
+        current_address_space().map(address, frame, flags, token)
+    }
+}
+
+/// Same as [map], with the memory type of the page selected through the PAT \
+/// Safety:
+/// See [map], mapping the same frame with different cache modes is UB
+pub unsafe fn map_with_cache_mode(
+    address: VirtualAddress,
+    frame: PhysicalAddress,
+    flags: PageFlags,
+    cache_mode: CacheMode,
+    token: PagingToken
+) -> Result<(), WalkError> {
+    unsafe {
+        current_address_space().map_with_cache_mode(address, frame, flags, cache_mode, token)
+    }
+}
+
+/// Removes the 4 KiB mapping at `address` from the active address space on the current CPU
+/// and returns the frame it mapped \
+/// Other CPUs have to be notified separately (see `shootdown`), page tables are never freed \
+/// Safety:
+/// No references to the unmapped page may exist
+pub unsafe fn unmap(address: VirtualAddress, token: PagingToken) -> Result<PhysicalAddress, WalkError> {
+    unsafe {
+        current_address_space().unmap(address, token)
+    }
+}
+
+/// Returns the physical address `address` is mapped to in the active address space, including large pages
+pub fn translate(address: VirtualAddress, token: PagingToken) -> Option<PhysicalAddress> {
+    current_address_space().translate(address, token)
+}
+
+/// The address space active on the current CPU
+pub fn current_address_space() -> AddressSpace {
+    // SAFETY: reading CR3 has no side effects
+    let cr3 = unsafe { read_cr!(3) };
+    AddressSpace::from_cr3(cr3, PagingMode::current())
+}
+
+/// Page table hierarchy identified by its root table, may be inactive \
+/// Doesn't own the tables (nothing is freed on drop) and isn't synchronized with changes to them,
+/// for the active space it aliases the tables modified by [map] / [unmap]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AddressSpace {
+    root: PhysicalAddress,
+    mode: PagingMode,
+}
+
+impl AddressSpace {
+    /// `cr3` flag and PCID bits are ignored
+    pub const fn from_cr3(cr3: u64, mode: PagingMode) -> Self {
+        Self { root: PhysicalAddress::new((cr3 & CR3_ADDRESS_MASK) as usize), mode }
+    }
+
+    /// Frame of the PML4 table (PML5 with `PagingMode::Level5`)
+    pub const fn root(&self) -> PhysicalAddress {
+        self.root
+    }
+
+    pub const fn mode(&self) -> PagingMode {
+        self.mode
+    }
+
+    /// Maps the 4 KiB page at `address` to `frame`, missing page tables are allocated \
+    /// Safety:
+    /// See [map]
+    pub unsafe fn map(&self, address: VirtualAddress, frame: PhysicalAddress, flags: PageFlags, token: PagingToken) -> Result<(), WalkError> {
+        unsafe {
+            self.map_with_cache_mode(address, frame, flags, CacheMode::WriteBack, token)
+        }
+    }
+
+    /// Same as [AddressSpace::map], with the memory type of the page selected through the PAT \
+    /// Safety:
+    /// See [map_with_cache_mode]
+    pub unsafe fn map_with_cache_mode(
+        &self,
+        address: VirtualAddress,
+        frame: PhysicalAddress,
+        flags: PageFlags,
+        cache_mode: CacheMode,
+        token: PagingToken
+    ) -> Result<(), WalkError> {
+        debug_assert!(address.is_page_aligned() && frame.is_page_aligned());
+        unsafe {
+            let entry = walk(*self, address, Some((token.into(), flags)), token.into())?;
+            if entry.present() {
+                return Err(WalkError::AlreadyMapped);
+            }
+
+            let mut new_entry = Level1PageTableEntry::from_raw(0);
+            new_entry.set_address(frame);
+            new_entry.set_writable(flags.writable);
+            new_entry.set_no_execute(!flags.executable);
+            new_entry.set_user(flags.user);
+            new_entry.set_cache_mode(cache_mode);
+            new_entry.set_global(global_pages_enabled() && is_global_mapping(address, flags.user));
+            new_entry.set_present(true);
+            *entry = new_entry;
+        }
+        Ok(())
+    }
+
+    /// Removes the 4 KiB mapping at `address` and returns the frame it mapped,
+    /// the page is invalidated in the TLB of the current CPU \
+    /// Safety:
+    /// See [unmap]
+    pub unsafe fn unmap(&self, address: VirtualAddress, token: PagingToken) -> Result<PhysicalAddress, WalkError> {
+        unsafe {
+            let entry = level1_entry_mut(*self, address, token.into())?;
+            let frame = entry.address();
+            *entry = Level1PageTableEntry::from_raw(0);
+            invalidate_page(address);
+            Ok(frame)
+        }
+    }
+
+    /// Returns the physical address `address` is mapped to, including large pages
+    pub fn translate(&self, address: VirtualAddress, token: PagingToken) -> Option<PhysicalAddress> {
+        let identity_map: IdentityMapToken = token.into();
+        let offset = |page_size: usize| usize::from(address) % page_size;
+        unsafe {
+            let mut pml4_address = self.root;
+            if self.mode == PagingMode::Level5 {
+                let entry = table::<5>(pml4_address, identity_map)[address.pml5_index()];
+                pml4_address = entry.present().then(|| entry.address())?;
+            }
+
+            let entry = table::<4>(pml4_address, identity_map)[address.pml4_index()];
+            if !entry.present() {
+                return None;
+            }
+
+            let entry = table::<3>(entry.address(), identity_map)[address.page_table_index(3)];
+            if !entry.present() {
+                return None;
+            } else if entry.page_size() {
+                // Bit 12 is the PAT bit in large page entries
+                return Some(entry.address().align_down(HUGE_PAGE_SIZE) + offset(HUGE_PAGE_SIZE));
+            }
+
+            let entry = table::<2>(entry.address(), identity_map)[address.page_table_index(2)];
+            if !entry.present() {
+                return None;
+            } else if entry.page_size() {
+                return Some(entry.address().align_down(LARGE_PAGE_SIZE) + offset(LARGE_PAGE_SIZE));
+            }
+
+            let entry = table::<1>(entry.address(), identity_map)[address.page_table_index(1)];
+            entry.present().then(|| entry.address() + offset(PAGE_SIZE))
+        }
+    }
+}
+
+// PWT / PCD flags and PCID bits aren't part of the root
+const_assert_eq!(AddressSpace::from_cr3(0x0012_3456_7000 | 0x18, PagingMode::Level4).root().0, 0x0012_3456_7000);
+const_assert_eq!(AddressSpace::from_cr3(0x8000_0000_0001_2FFF, PagingMode::Level5).root().0, 0x0001_2000);
+
+unsafe fn table<const LEVEL: u8>(address: PhysicalAddress, identity_map: IdentityMapToken) -> &'static mut PageTable<LEVEL> {
+    unsafe {
+        &mut *get_kernel_map_virtual_address::<PageTable<LEVEL>>(address, identity_map).cast_mut()
+    }
+}
+
+/// Returns the table referenced by `entry`, if `allocate` is set missing tables are allocated
+/// and existing entries are widened to allow the access described by the flags
+unsafe fn child_table<const PARENT: u8, const CHILD: u8>(
+    entry: &mut PageTableEntry<PARENT>,
+    allocate: Option<(FrameAllocatorToken, PageFlags)>,
+    identity_map: IdentityMapToken
+) -> Result<&'static mut PageTable<CHILD>, WalkError> {
+    debug_assert!(PARENT == CHILD + 1);
+
+    if !entry.present() {
+        let Some((frame_allocator, flags)) = allocate else {
+            return Err(WalkError::NotMapped);
+        };
+        let frame = global_allocator(frame_allocator).allocate(1).ok_or(WalkError::OutOfMemory)?;
+        unsafe {
+            to_virtual(frame, identity_map).as_mut_ptr().cast::<u8>().write_bytes(0, PAGE_SIZE);
+        }
+
+        // Permissions are restricted by the level 1 entry
+        let mut new_entry = PageTableEntry::<PARENT>::from_raw(0).with_address(frame);
+        new_entry.set_writable(true);
+        new_entry.set_user(flags.user);
+        new_entry.set_present(true);
+        *entry = new_entry;
+    } else if (PARENT == 2 || PARENT == 3) && entry.raw() & PAGE_SIZE_BIT != 0 {
+        return Err(WalkError::LargePage);
+    } else if let Some((_, flags)) = allocate {
+        // Access is the intersection of all levels, tables created by the bootloader or for
+        // other mappings may be more restrictive than the new mapping
+        if flags.writable {
+            entry.set_writable(true);
+        }
+        if flags.user {
+            entry.set_user(true);
+        }
+        if flags.executable {
+            entry.set_no_execute(false);
+        }
+    }
+
+    unsafe {
+        Ok(table::<CHILD>(entry.address(), identity_map))
+    }
+}
+
+/// Walks the page tables of `space` to the 4 KiB page table entry of `address`, the entry may be non-present
+unsafe fn walk(
+    space: AddressSpace,
+    address: VirtualAddress,
+    allocate: Option<(FrameAllocatorToken, PageFlags)>,
+    identity_map: IdentityMapToken
+) -> Result<&'static mut Level1PageTableEntry, WalkError> {
+    unsafe {
+        let root = space.root();
+        let pml4 = if space.mode() == PagingMode::Level5 {
+            let pml5 = table::<5>(root, identity_map);
+            child_table::<5, 4>(&mut pml5[address.pml5_index()], allocate, identity_map)?
+        } else {
+            table::<4>(root, identity_map)
+        };
+
+        let pdpt = child_table::<4, 3>(&mut pml4[address.pml4_index()], allocate, identity_map)?;
+        let page_directory = child_table::<3, 2>(&mut pdpt[address.page_table_index(3)], allocate, identity_map)?;
+        let page_table = child_table::<2, 1>(&mut page_directory[address.page_table_index(2)], allocate, identity_map)?;
+        Ok(&mut page_table[address.page_table_index(1)])
+    }
+}
+
+/// Walks the page tables of `space` and returns the 4 KiB page table entry mapping `address` \
+/// Safety:
+/// No other references to the entry may exist while the returned reference is alive
+unsafe fn level1_entry_mut(
+    space: AddressSpace,
+    address: VirtualAddress,
+    identity_map: IdentityMapToken
+) -> Result<&'static mut Level1PageTableEntry, WalkError> {
+    unsafe {
+        let entry = walk(space, address, None, identity_map)?;
+        if !entry.present() {
+            return Err(WalkError::NotMapped);
+        }
+        Ok(entry)
+    }
+}
+
 fn get_kernel_map_virtual_address<T: PageMapLevel>(physical_address: PhysicalAddress, token: IdentityMapToken) -> *const T {
     let identity_map: usize = identity_map_base(token).into();
     let physical_address: usize = physical_address.into();
     (identity_map + physical_address) as *const T
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Address built from per-level indices (PML5 first) and a page offset, sign extended from bit 56
+    fn address(indices: [usize; 5], offset: usize) -> VirtualAddress {
+        let value = indices.iter().fold(0, |value, &index| value << 9 | index) << 12 | offset;
+        VirtualAddress::new((((value << 7) as isize) >> 7) as usize)
+    }
+
+    #[test]
+    fn index_decomposition() {
+        let indices = [0x1AB, 0x123, 0x0FE, 0x001, 0x1FF];
+        let va = address(indices, 0x678);
+        assert_eq!(va.pml5_index(), 0x1AB);
+        assert_eq!(va.pml4_index(), 0x123);
+        for (level, &index) in (1..=5).rev().zip(&indices) {
+            assert_eq!(va.page_table_index(level), index, "level {level}");
+        }
+
+        // 4-level walks ignore bits above 47, the kernel half has them all set
+        let va = VirtualAddress::new(0xFFFF_8000_0000_0000 | 0x1FF << 30 | 0x5 << 21 | 0x123);
+        assert_eq!(va.pml4_index(), 0x100);
+        assert_eq!((va.page_table_index(3), va.page_table_index(2), va.page_table_index(1)), (0x1FF, 0x5, 0));
+        assert_eq!(va.pml5_index(), 0x1FF);
+    }
+
+    #[test]
+    fn identity_map_canonical_in_both_modes() {
+        let end = PhysicalAddress::new(1 << 30);
+        // Kernel half in both modes
+        let base = PhysicalAddress::new(0xFFFF_8000_0000_0000);
+        assert_eq!(validate_identity_map(base, end, PagingMode::Level4), Ok(()));
+        assert_eq!(validate_identity_map(base, end, PagingMode::Level5), Ok(()));
+
+        // Only canonical with 57-bit addresses
+        let base = PhysicalAddress::new(0xFF00_0000_0000_0000);
+        assert_eq!(validate_identity_map(base, end, PagingMode::Level4), Err(IdentityMapError::NonCanonical));
+        assert_eq!(validate_identity_map(base, end, PagingMode::Level5), Ok(()));
+
+        assert_eq!(PagingMode::Level4.levels(), 4);
+        assert_eq!(PagingMode::Level5.levels(), 5);
+    }
+
+    #[test]
+    fn identity_map_rejects_wrapping_and_too_high_ranges() {
+        let base = PhysicalAddress::new(0xFFFF_8000_0000_0000);
+        // The whole kernel half fits, one more byte wraps around
+        assert_eq!(validate_identity_map(base, PhysicalAddress::new(1 << 47), PagingMode::Level4), Ok(()));
+        assert_eq!(
+            validate_identity_map(base, PhysicalAddress::new((1 << 47) + 1), PagingMode::Level4),
+            Err(IdentityMapError::AddressOverflow)
+        );
+
+        // Ends past the top of the address space
+        let base = PhysicalAddress::new(0xFFFF_FFFF_0000_0000);
+        assert_eq!(validate_identity_map(base, PhysicalAddress::new(1 << 32), PagingMode::Level5), Ok(()));
+        assert_eq!(
+            validate_identity_map(base, PhysicalAddress::new((1 << 32) + 0x1000), PagingMode::Level5),
+            Err(IdentityMapError::AddressOverflow)
+        );
+
+        // Lower half base, the range runs into the non-canonical hole
+        let base = PhysicalAddress::new(0x0000_7FFF_0000_0000);
+        assert_eq!(
+            validate_identity_map(base, PhysicalAddress::new(1 << 33), PagingMode::Level4),
+            Err(IdentityMapError::NonCanonical)
+        );
+        assert_eq!(validate_identity_map(base, PhysicalAddress::new(1 << 33), PagingMode::Level5), Ok(()));
+
+        // Nothing to map
+        assert_eq!(validate_identity_map(base, PhysicalAddress::new(0), PagingMode::Level4), Ok(()));
+    }
+}
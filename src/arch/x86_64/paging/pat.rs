@@ -0,0 +1,108 @@
+// Page Attribute Table
+//
+// The memory type of a page is selected by a 3-bit PAT index formed from its entry bits:
+// `PAT << 2 | PCD << 1 | PWT` (PAT is bit 7 in 4 KiB page table entries).
+// The first 4 slots match the power-on defaults, so entries with the PAT bit cleared keep their meaning.
+
+use static_assertions::const_assert;
+
+use crate::arch::intrinsics::write_msr;
+
+use super::structs::Level1PageTableEntry;
+
+const IA32_PAT_MSR: u32 = 0x277;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheMode {
+    WriteBack,
+    WriteThrough,
+    /// Uncached, can be overridden by MTRRs
+    UncachedMinus,
+    Uncached,
+    WriteCombining,
+}
+
+impl CacheMode {
+    /// Memory type encoding used in the IA32_PAT MSR
+    const fn memory_type(self) -> u64 {
+        match self {
+            CacheMode::Uncached => 0x00,
+            CacheMode::WriteCombining => 0x01,
+            CacheMode::WriteThrough => 0x04,
+            CacheMode::WriteBack => 0x06,
+            CacheMode::UncachedMinus => 0x07,
+        }
+    }
+
+    /// PAT index selecting this mode with [PAT_LAYOUT]
+    pub const fn pat_index(self) -> u8 {
+        let mut i = 0;
+        while i < PAT_LAYOUT.len() {
+            if PAT_LAYOUT[i] as u8 == self as u8 {
+                return i as u8;
+            }
+            i += 1;
+        }
+        unreachable!()
+    }
+
+    /// Page table entry (PWT, PCD, PAT) bits selecting this mode
+    pub const fn entry_bits(self) -> (bool, bool, bool) {
+        let index = self.pat_index();
+        (index & 0b001 != 0, index & 0b010 != 0, index & 0b100 != 0)
+    }
+}
+
+// The power-on compatible slots don't need the PAT bit, write-combining uses the reprogrammed slot 4
+const_assert!(matches!(CacheMode::WriteBack.entry_bits(), (false, false, false)));
+const_assert!(matches!(CacheMode::WriteThrough.entry_bits(), (true, false, false)));
+const_assert!(matches!(CacheMode::UncachedMinus.entry_bits(), (false, true, false)));
+const_assert!(matches!(CacheMode::Uncached.entry_bits(), (true, true, false)));
+const_assert!(matches!(CacheMode::WriteCombining.entry_bits(), (false, false, true)));
+
+/// Memory types of PAT slots 0-7
+pub const PAT_LAYOUT: [CacheMode; 8] = [
+    CacheMode::WriteBack,
+    CacheMode::WriteThrough,
+    CacheMode::UncachedMinus,
+    CacheMode::Uncached,
+    CacheMode::WriteCombining,
+    CacheMode::WriteThrough,
+    CacheMode::UncachedMinus,
+    CacheMode::Uncached,
+];
+
+/// IA32_PAT MSR value for [PAT_LAYOUT], one byte per slot
+pub const fn pat_value() -> u64 {
+    let mut value = 0;
+    let mut i = 0;
+    while i < PAT_LAYOUT.len() {
+        value |= PAT_LAYOUT[i].memory_type() << (i * 8);
+        i += 1;
+    }
+    value
+}
+
+/// Programs the PAT with [PAT_LAYOUT] on the current CPU \
+/// Safety:
+/// Existing mappings with the PAT bit set change their memory type, the caches and TLB should be flushed
+pub unsafe fn initialize_pat() {
+    unsafe {
+        write_msr(IA32_PAT_MSR, pat_value());
+    }
+}
+
+impl Level1PageTableEntry {
+    /// Requires [initialize_pat] to be called first
+    pub fn set_cache_mode(&mut self, mode: CacheMode) {
+        let (writethrough, disable_cache, pat) = mode.entry_bits();
+        self.set_writethrough(writethrough);
+        self.set_disable_cache(disable_cache);
+        self.set_pat(pat);
+    }
+
+    pub fn cache_mode(&self) -> CacheMode {
+        let index = (self.pat() as usize) << 2 | (self.disable_cache() as usize) << 1 | self.writethrough() as usize;
+        PAT_LAYOUT[index]
+    }
+}
@@ -0,0 +1,92 @@
+// Recursive page table mapping
+//
+// A PML4 entry (the recursive slot) points back at the PML4 itself, so every page table
+// is reachable at a fixed virtual address without the bootloader identity map.
+// The index of a table is formed by shifting the indices of its parents one level down
+// and filling the freed top-level indices with the recursive slot:
+//
+// PML4:       `slot << 39 | slot << 30 | slot << 21 | slot << 12`
+// PDPT:       `slot << 39 | slot << 30 | slot << 21 | pml4 << 12`
+// Page dir.:  `slot << 39 | slot << 30 | pml4 << 21 | pdpt << 12`
+// Page table: `slot << 39 | pml4 << 30 | pdpt << 21 | pd << 12`
+//
+// The resulting address is sign extended from bit 47. Only 4-level paging is supported.
+
+use spin::Once;
+
+use crate::{arch::VirtualAddress, common::macros::assert_arg};
+
+use super::{get_kernel_map_virtual_address, harden::no_execute_enabled, read_pml4_address, structs::Level4PageTable, IdentityMapToken, PagingMode};
+
+static RECURSIVE_SLOT: Once<usize> = Once::new();
+
+/// Reserves the PML4 entry `slot` as the recursive slot, this function may only be called once \
+/// Safety:
+/// The PML4 entry `slot` must be unused, the 512 GiB virtual address range it covers
+/// must not be used for anything else afterwards
+pub unsafe fn setup_recursive(slot: usize, identity_map: IdentityMapToken) -> RecursiveMap {
+    assert_arg!(slot, slot < Level4PageTable::ENTRY_COUNT);
+    assert!(PagingMode::current() == PagingMode::Level4, "Recursive mapping requires 4-level paging");
+
+    // best effort panic
+    if RECURSIVE_SLOT.is_completed() {
+        panic!("Recursive mapping already set up.");
+    }
+
+    RECURSIVE_SLOT.call_once(|| {
+        unsafe {
+            let pml4_address = read_pml4_address();
+            let pml4 = get_kernel_map_virtual_address::<Level4PageTable>(pml4_address, identity_map).cast_mut();
+            let pml4 = &mut *pml4;
+            let entry = &mut pml4[slot];
+            debug_assert!(!entry.present(), "Recursive slot already in use");
+            entry.set_address(pml4_address);
+            entry.set_writable(true);
+            // Reserved (and faulting on every walk) until EFER.NXE is set
+            entry.set_no_execute(no_execute_enabled());
+            entry.set_present(true);
+        }
+        slot
+    });
+
+    RecursiveMap { slot }
+}
+
+/// Returns the recursive mapping if [setup_recursive] was called
+pub fn recursive_map() -> Option<RecursiveMap> {
+    RECURSIVE_SLOT.get().map(|&slot| RecursiveMap { slot })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecursiveMap {
+    slot: usize,
+}
+
+impl RecursiveMap {
+    pub const fn slot(self) -> usize {
+        self.slot
+    }
+
+    pub const fn pml4(self) -> VirtualAddress {
+        Self::address(self.slot, self.slot, self.slot, self.slot)
+    }
+
+    pub const fn pdpt(self, pml4: usize) -> VirtualAddress {
+        Self::address(self.slot, self.slot, self.slot, pml4)
+    }
+
+    pub const fn page_directory(self, pml4: usize, pdpt: usize) -> VirtualAddress {
+        Self::address(self.slot, self.slot, pml4, pdpt)
+    }
+
+    pub const fn page_table(self, pml4: usize, pdpt: usize, page_directory: usize) -> VirtualAddress {
+        Self::address(self.slot, pml4, pdpt, page_directory)
+    }
+
+    const fn address(l4: usize, l3: usize, l2: usize, l1: usize) -> VirtualAddress {
+        let address = (l4 & 0x1FF) << 39 | (l3 & 0x1FF) << 30 | (l2 & 0x1FF) << 21 | (l1 & 0x1FF) << 12;
+        // Sign extend bit 47
+        let address = if address & (1 << 47) != 0 { address | 0xFFFF_0000_0000_0000 } else { address };
+        VirtualAddress::new(address)
+    }
+}
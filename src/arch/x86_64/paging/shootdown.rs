@@ -0,0 +1,91 @@
+// TLB shootdown
+//
+// Deadlock avoidance ordering:
+// 1. the initiator flushes its own TLB first, this never blocks
+// 2. initiators are serialized by SHOOTDOWN_LOCK, which is acquired with interrupts enabled,
+//    so a CPU spinning on the lock still services the current holder's IPI
+// 3. the request is published, then the IPI is sent
+// 4. the initiator waits for all acknowledgements with interrupts enabled, then releases the lock
+// The IPI handler only invalidates and acknowledges, it never takes locks or sends IPIs,
+// so it can't be part of a wait cycle. `tlb_shootdown` must not be called from interrupt handlers
+// or with interrupts disabled (checked in debug builds).
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Mutex;
+
+use crate::arch::{
+    interrupts::{apic::local_apic, define_interrupt_handler, idt::IdtVector, InterruptHandler, StackFrame, TlbShootdown},
+    intrinsics::{flush_tlb_global, interrupts_enabled, invalidate_page},
+    AddressRange, VirtualAddress
+};
+
+use super::PAGE_SIZE;
+
+/// Ranges larger than this flush the whole TLB instead of invalidating page by page
+const FULL_FLUSH_THRESHOLD: usize = 32 * PAGE_SIZE;
+
+static SHOOTDOWN_LOCK: Mutex<()> = Mutex::new(());
+/// Processors with the shootdown handler installed, including the bootstrap processor
+static ONLINE_PROCESSORS: AtomicUsize = AtomicUsize::new(1);
+
+static PENDING_START: AtomicUsize = AtomicUsize::new(0);
+static PENDING_END: AtomicUsize = AtomicUsize::new(0);
+static PENDING_ACKNOWLEDGEMENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Should be called by each application processor once it can handle `IdtVector::TLB_SHOOTDOWN`
+pub fn processor_online() {
+    ONLINE_PROCESSORS.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Invalidates `range` in the TLBs of all online processors and waits until every processor has done so
+pub fn tlb_shootdown(range: AddressRange<VirtualAddress>) {
+    debug_assert!(interrupts_enabled(), "TLB shootdown with interrupts disabled");
+
+    invalidate_range(range);
+
+    let other_processors = ONLINE_PROCESSORS.load(Ordering::Acquire) - 1;
+    if other_processors == 0 {
+        return;
+    }
+
+    let _guard = SHOOTDOWN_LOCK.lock();
+    PENDING_START.store(range.start.into(), Ordering::Relaxed);
+    PENDING_END.store(range.end.into(), Ordering::Relaxed);
+    PENDING_ACKNOWLEDGEMENTS.store(other_processors, Ordering::Release);
+
+    local_apic().broadcast_ipi_all_but_self(u8::from(IdtVector::TLB_SHOOTDOWN));
+
+    while PENDING_ACKNOWLEDGEMENTS.load(Ordering::Acquire) != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+fn invalidate_range(range: AddressRange<VirtualAddress>) {
+    if range.size() > FULL_FLUSH_THRESHOLD {
+        // The range may contain global kernel pages
+        flush_tlb_global();
+        return;
+    }
+
+    let mut page = range.start.last_multiple_of(PAGE_SIZE);
+    while page < range.end {
+        invalidate_page(page);
+        page += PAGE_SIZE;
+    }
+}
+
+define_interrupt_handler! {
+    external handler TlbShootdownHandler(_frame: &mut StackFrame) for TlbShootdown via local_apic {
+        // Acquire pairs with the Release store of the acknowledgement count
+        if PENDING_ACKNOWLEDGEMENTS.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        let range = AddressRange::new(
+            VirtualAddress::new(PENDING_START.load(Ordering::Relaxed)),
+            VirtualAddress::new(PENDING_END.load(Ordering::Relaxed)),
+        );
+        invalidate_range(range);
+        PENDING_ACKNOWLEDGEMENTS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
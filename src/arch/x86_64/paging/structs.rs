@@ -1,45 +1,64 @@
-use static_assertions::const_assert_eq;
+use core::ops::{Index, IndexMut};
 
-use crate::arch::PhysicalAddress;
+use static_assertions::{const_assert, const_assert_eq};
 
-pub const PAGE_SIZE: usize = 4096;
-
-// #[repr(C, align(4096))]
-// pub struct Level5PageTable {
-//     entries: []
-// }
+use crate::{arch::{intrinsics::cpuid, PhysicalAddress}, common::{bits::BitField, macros::debug_assert_arg}};
 
-// TODO: Refactor struct LevelXPageTable into a macro
+pub const PAGE_SIZE: usize = 4096;
 
-// Page Map Level 4 Table
+/// Page table at `LEVEL` of the hierarchy (1 - page table, 5 - PML5 table)
 #[repr(C, align(4096))]
-pub struct Level4PageTable {
-    entries: [Level4PageTableEntry; 512],
+pub struct PageTable<const LEVEL: u8> {
+    entries: [PageTableEntry<LEVEL>; 512],
 }
-page_table_level_entry!(Level4PageTableEntry);
+
+/// Page Map Level 5 Table, only used with LA57 enabled
+pub type Level5PageTable = PageTable<5>;
+/// Page Map Level 4 Table
+pub type Level4PageTable = PageTable<4>;
+/// Page Directory Pointer Table
+pub type Level3PageTable = PageTable<3>;
+/// Page Directory Table
+pub type Level2PageTable = PageTable<2>;
+pub type Level1PageTable = PageTable<1>;
+
+pub type Level5PageTableEntry = PageTableEntry<5>;
+pub type Level4PageTableEntry = PageTableEntry<4>;
+pub type Level3PageTableEntry = PageTableEntry<3>;
+pub type Level2PageTableEntry = PageTableEntry<2>;
+pub type Level1PageTableEntry = PageTableEntry<1>;
+
+const_assert_eq!(core::mem::size_of::<Level5PageTable>(), PAGE_SIZE);
+const_assert_eq!(core::mem::align_of::<Level5PageTable>(), PAGE_SIZE);
+const_assert_eq!(core::mem::size_of::<Level4PageTable>(), PAGE_SIZE);
 const_assert_eq!(core::mem::align_of::<Level4PageTable>(), PAGE_SIZE);
+const_assert_eq!(core::mem::size_of::<Level3PageTable>(), PAGE_SIZE);
+const_assert_eq!(core::mem::align_of::<Level3PageTable>(), PAGE_SIZE);
+const_assert_eq!(core::mem::size_of::<Level2PageTable>(), PAGE_SIZE);
+const_assert_eq!(core::mem::align_of::<Level2PageTable>(), PAGE_SIZE);
+const_assert_eq!(core::mem::size_of::<Level1PageTable>(), PAGE_SIZE);
+const_assert_eq!(core::mem::align_of::<Level1PageTable>(), PAGE_SIZE);
+const_assert_eq!(core::mem::size_of::<PageTableEntry<1>>(), 8);
 
-// Page Directory Pointer Table
-#[repr(C, align(4096))]
-pub struct Level3PageTable {
-    entries: [Level3PageTableEntry; 512],
+impl<const LEVEL: u8> PageTable<LEVEL> {
+    pub const ENTRY_COUNT: usize = 512;
 }
-page_table_level_entry!(Level3PageTableEntry);
-const_assert_eq!(core::mem::align_of::<Level3PageTable>(), PAGE_SIZE);
 
-// Page Directory Table
-#[repr(C, align(4096))]
-pub struct Level2PageTable {
-    entries: [Level2PageTableEntry; 512],
+/// Panics if `index` is not in range [0:512)
+impl<const LEVEL: u8> Index<usize> for PageTable<LEVEL> {
+    type Output = PageTableEntry<LEVEL>;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.entries[index]
+    }
 }
-page_table_level_entry!(Level2PageTableEntry);
-const_assert_eq!(core::mem::align_of::<Level2PageTable>(), PAGE_SIZE);
 
-#[repr(C, align(4096))]
-pub struct PageTable {
-    entries: [PageTableEntry; 512],
+/// Panics if `index` is not in range [0:512)
+impl<const LEVEL: u8> IndexMut<usize> for PageTable<LEVEL> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.entries[index]
+    }
 }
-const_assert_eq!(core::mem::align_of::<PageTable>(), PAGE_SIZE);
 
 // Page table entry layout (x86_64):
 // 0        present
@@ -49,7 +68,9 @@ const_assert_eq!(core::mem::align_of::<PageTable>(), PAGE_SIZE);
 // 4        disable cache
 // 5        accessed
 // 6        dirty (written)
-// 7        PAT / reserved (0)
+// 7        level 1: PAT
+//          level 2, 3: page size (maps a 2 MiB / 1 GiB page)
+//          level 4, 5: reserved (0)
 // 8        global, CR4 PGE bit must be set
 // 9:11     ignored
 // 12:51    physical address
@@ -57,9 +78,9 @@ const_assert_eq!(core::mem::align_of::<PageTable>(), PAGE_SIZE);
 // 63       no execute / reserved (0)
 #[repr(transparent)]
 #[derive(Clone, Copy)]
-pub struct PageTableEntry(u64);
+pub struct PageTableEntry<const LEVEL: u8>(u64);
 
-impl PageTableEntry {
+impl<const LEVEL: u8> PageTableEntry<LEVEL> {
     page_table_entry_bit!(present, set_present, 0);
 
     page_table_entry_bit!(writable, set_writable, 1);
@@ -74,82 +95,79 @@ impl PageTableEntry {
 
     page_table_entry_bit!(dirty, set_dirty, 6);
 
-    page_table_entry_bit!(pat, set_pat, 7);
-
     page_table_entry_bit!(global, set_global, 8);
 
     page_table_entry_bit!(no_execute, set_no_execute, 63);
 
-    // TODO: tests
-    pub fn address(&self) -> PhysicalAddress {
-        PhysicalAddress::from(((self.0 >> 12) & ((1_u64 << 40) - 1)) << 12)
+    const ADDRESS: BitField = BitField::new(12, 51);
+
+    pub const fn from_raw(value: u64) -> Self {
+        Self(value)
     }
 
+    pub const fn raw(self) -> u64 {
+        self.0
+    }
+
+    /// The address bits are stored in place, so the field doesn't need to be shifted
+    pub const fn address(&self) -> PhysicalAddress {
+        PhysicalAddress::new((self.0 & Self::ADDRESS.mask()) as usize)
+    }
+
+    /// `value` must be `PAGE_SIZE` aligned and below 2^MAXPHYADDR, see [cpuid::phys_address_bits] \
+    /// Only the address field is modified, [PageTableEntry::address] returns `value`
     pub fn set_address(&mut self, value: PhysicalAddress) {
-        let value: u64 = value.0 as u64 >> 12;
-        let mask = ((1 << 40) - 1) << 12;
-        self.0 = (self.0 & !mask) | ((value << 12) & mask);
+        debug_assert_arg!(value, value.is_aligned_to(PAGE_SIZE), "Must be PAGE_SIZE aligned");
+        // Bits above MAXPHYADDR are reserved, setting them makes any access through the entry fault
+        debug_assert_arg!(value, value.0 >> cpuid::phys_address_bits() == 0, "Exceeds the physical address width");
+        *self = self.with_address(value);
+    }
+
+    /// Returns the entry with the address field replaced, bits of `value` outside the field are discarded
+    pub const fn with_address(self, value: PhysicalAddress) -> Self {
+        Self((self.0 & !Self::ADDRESS.mask()) | (value.0 as u64 & Self::ADDRESS.mask()))
     }
 }
 
+// address / with_address round trip, flag bits are preserved
+const_assert!({
+    const FLAGS: u64 = 0x8000_0000_0000_0FFF;
+    const ADDRESS: usize = 0x000F_FFFF_FFFF_F000;
+    let entry = PageTableEntry::<1>::from_raw(FLAGS).with_address(PhysicalAddress::new(ADDRESS));
+    entry.address().0 == ADDRESS && entry.raw() & !PageTableEntry::<1>::ADDRESS.mask() == FLAGS
+});
+const_assert!({
+    let entry = PageTableEntry::<4>::from_raw(u64::MAX).with_address(PhysicalAddress::new(0x1000));
+    entry.address().0 == 0x1000 && entry.raw() | PageTableEntry::<4>::ADDRESS.mask() == u64::MAX
+});
+
+impl PageTableEntry<1> {
+    page_table_entry_bit!(pat, set_pat, 7);
+}
+
+impl PageTableEntry<2> {
+    page_table_entry_bit!(page_size, set_page_size, 7);
+}
+
+impl PageTableEntry<3> {
+    page_table_entry_bit!(page_size, set_page_size, 7);
+}
+
 pub trait PageMapLevel {}
 
-impl PageMapLevel for PageTable {}
-impl PageMapLevel for Level2PageTable {}
-impl PageMapLevel for Level3PageTable {}
-impl PageMapLevel for Level4PageTable {}
+impl<const LEVEL: u8> PageMapLevel for PageTable<LEVEL> {}
 
 macro_rules! page_table_entry_bit {
     ($id:ident, $set_id:ident, $bit:expr) => {
         pub fn $id(&self) -> bool {
-            (self.0 & 1 << $bit) != 0
+            const FIELD: BitField = BitField::bit($bit);
+            FIELD.get(self.0) != 0
         }
 
         pub fn $set_id(&mut self, value: bool) {
-            let mask = 1 << $bit;
-            let value = value as u64;
-            self.0 = (self.0 & !mask) | ((0_u64.wrapping_sub(value)) & mask);
+            const FIELD: BitField = BitField::bit($bit);
+            self.0 = FIELD.set(self.0, value as u64);
         }
     };
 }
 use page_table_entry_bit;
-
-macro_rules! page_table_level_entry {
-    ($name:ident) => {
-        #[repr(transparent)]
-        #[derive(Clone, Copy)]
-        pub struct $name(u64);
-
-        impl $name {
-            page_table_entry_bit!(present, set_present, 0);
-
-            page_table_entry_bit!(writable, set_writable, 1);
-
-            page_table_entry_bit!(user, set_user, 2);
-
-            page_table_entry_bit!(writethrough, set_writethrough, 3);
-
-            page_table_entry_bit!(disable_cache, set_disable_cache, 4);
-
-            page_table_entry_bit!(accessed, set_accessed, 5);
-
-            page_table_entry_bit!(dirty, set_dirty, 6);
-
-            page_table_entry_bit!(page_size, set_page_size, 7);
-
-            page_table_entry_bit!(global, set_global, 8);
-
-            page_table_entry_bit!(no_execute, set_no_execute, 63);
-
-            pub fn address(&self) -> u64 {
-                (self.0 >> 12) & ((1_u64 << 40) - 1)
-            }
-
-            pub fn set_address(&mut self, value: u64) {
-                let mask = ((1 << 40) - 1) << 12;
-                self.0 = (self.0 & !mask) | ((value << 12) & mask);
-            }
-        }
-    };
-}
-use page_table_level_entry;
@@ -0,0 +1,85 @@
+use core::{arch::asm, sync::atomic::{AtomicUsize, Ordering}};
+
+use crate::arch::VirtualAddress;
+
+use super::{interrupts::StackFrame, intrinsics::{cpuid, without_interrupts}};
+
+/// Probe state of every CPU, indexed by the initial APIC id (see [current_probe])
+static PROBES: [ProbeState; PROBE_SLOT_COUNT] = [const { ProbeState::new() }; PROBE_SLOT_COUNT];
+const PROBE_SLOT_COUNT: usize = u8::MAX as usize + 1;
+
+struct ProbeState {
+    /// Address of the probing instruction, 0 if no probe is in progress
+    instruction: AtomicUsize,
+    /// Address execution continues at if the probing instruction faults
+    fixup: AtomicUsize,
+}
+
+impl ProbeState {
+    const fn new() -> Self {
+        Self { instruction: AtomicUsize::new(0), fixup: AtomicUsize::new(0) }
+    }
+}
+
+/// Probe state of the current CPU \
+/// Initial APIC ids are unique on xAPIC systems, with more than 256 x2APIC CPUs some of them share a slot
+fn current_probe() -> &'static ProbeState {
+    &PROBES[cpuid::initial_apic_id() as usize]
+}
+
+/// Reads a `u64` from `address`, returns `None` instead of crashing if the read page faults \
+/// Only the read itself is protected, interrupts are disabled while probing so a probe can't be interrupted
+/// by another one on the same CPU, probes on different CPUs are independent \
+/// This function must not be called from the page fault handler (a fault there is not redirected)
+/// or from an NMI handler \
+/// Safety:
+/// `address` must be 8 byte aligned, reading it must not have side effects (e.g. MMIO registers)
+pub unsafe fn probe_read(address: VirtualAddress) -> Option<u64> {
+    without_interrupts(|| {
+        let probe = current_probe();
+        let value: u64;
+        let faulted: u64;
+        unsafe {
+            asm!(
+                "lea {tmp}, [rip + 3f]",
+                "mov [{fixup}], {tmp}",
+                "lea {tmp}, [rip + 2f]",
+                "mov [{instruction}], {tmp}",
+                "xor {faulted:e}, {faulted:e}",
+                "2:",
+                "mov {value}, [{address}]",
+                "jmp 4f",
+                "3:",
+                "mov {faulted:e}, 1",
+                "4:",
+                "mov qword ptr [{instruction}], 0",
+                tmp = out(reg) _,
+                fixup = in(reg) probe.fixup.as_ptr(),
+                instruction = in(reg) probe.instruction.as_ptr(),
+                address = in(reg) address.as_ptr(),
+                value = out(reg) value,
+                faulted = out(reg) faulted,
+                options(nostack)
+            );
+        }
+
+        if faulted == 0 {
+            Some(value)
+        } else {
+            None
+        }
+    })
+}
+
+/// Called by the page fault handler, redirects a faulting probe of the current CPU to its fixup path \
+/// Returns `false` if the fault wasn't caused by a probe
+pub fn fixup_fault(frame: &mut StackFrame) -> bool {
+    let probe = current_probe();
+    let instruction = probe.instruction.load(Ordering::SeqCst);
+    if instruction == 0 || usize::from(frame.instruction_pointer) != instruction {
+        return false;
+    }
+
+    frame.instruction_pointer = probe.fixup.load(Ordering::SeqCst).into();
+    true
+}
@@ -0,0 +1,127 @@
+use spin::Once;
+
+use super::{gdt::{self, Gdt, TaskStateSegment}, interrupts::{apic::SpuriousInterruptHandler, exceptions::*, idt::{Idt, IdtBuilder, IdtVector}, timer::TimerHandler}, intrinsics::{code_segment, without_interrupts}, stack::InterruptStack, PrivilegeLevel};
+
+/// Built once, every processor gets its own copy
+static DEFAULT_IDT: Once<Idt> = Once::new();
+
+/// IST index of the #DF handler
+pub const DOUBLE_FAULT_STACK: u8 = 1;
+/// IST index of the #PF handler, a page fault on an overflowed kernel stack can't push its frame there
+pub const PAGE_FAULT_STACK: u8 = 2;
+
+// TODO: local APIC id, per-CPU data pointer
+
+/// Known-good stacks for exceptions raised on a broken stack, every processor needs its own set
+#[derive(Default)]
+pub struct InterruptStacks {
+    pub double_fault: InterruptStack,
+    pub page_fault: InterruptStack,
+}
+
+impl InterruptStacks {
+    pub const fn new() -> Self {
+        Self { double_fault: InterruptStack::new(), page_fault: InterruptStack::new() }
+    }
+}
+
+/// Per-processor state
+pub struct Processor {
+    pub idt: Idt,
+    tss: TaskStateSegment,
+    /// Refers to `tss`, built once the processor has a fixed address (see [Processor::install])
+    gdt: Once<Gdt>,
+}
+
+impl Processor {
+    /// Uses a copy of the IDT with all default exception and local APIC handlers installed
+    pub fn new(stacks: &'static InterruptStacks) -> Self {
+        Self::with_idt(Self::default_idt().clone(), stacks)
+    }
+
+    /// `idt` may be a clone of a table finished with [IdtBuilder], its IST indices refer to `stacks`
+    /// (see [DOUBLE_FAULT_STACK])
+    pub fn with_idt(idt: Idt, stacks: &'static InterruptStacks) -> Self {
+        let mut tss = TaskStateSegment::new();
+        tss.set_interrupt_stack(DOUBLE_FAULT_STACK, stacks.double_fault.top());
+        tss.set_interrupt_stack(PAGE_FAULT_STACK, stacks.page_fault.top());
+        Self { idt, tss, gdt: Once::new() }
+    }
+
+    /// Shared template of the default IDT, built on first use \
+    /// Every exception (see [IdtVector::all_exceptions]) has a handler, unexpected ones panic
+    pub fn default_idt() -> &'static Idt {
+        DEFAULT_IDT.call_once(|| {
+            let idt = IdtBuilder::new()
+                .handler::<DivideErrorHandler>()
+                .handler::<DebugHandler>()
+                .handler::<NonMaskableInterruptHandler>()
+                .handler::<BreakpointHandler>()
+                .handler::<OverflowHandler>()
+                .handler::<BoundRangeExceededHandler>()
+                .handler::<InvalidOpcodeHandler>()
+                .handler::<DeviceNotAvailableHandler>()
+                .handler::<DoubleFaultHandler>()
+                .interrupt_stack(IdtVector::DOUBLE_FAULT, DOUBLE_FAULT_STACK)
+                .handler::<InvalidTssHandler>()
+                .handler::<SegmentNotPresentHandler>()
+                .handler::<StackSegmentFaultHandler>()
+                .handler::<GeneralProtectionHandler>()
+                .handler::<PageFaultHandler>()
+                .interrupt_stack(IdtVector::PAGE_FAULT, PAGE_FAULT_STACK)
+                .handler::<X87FloatingPointErrorHandler>()
+                .handler::<AlignmentCheckHandler>()
+                .handler::<MachineCheckHandler>()
+                .handler::<SimdFloatingPointHandler>()
+                .handler::<VirtualizationHandler>()
+                .handler::<ControlProtectionHandler>()
+                .handler::<HypervisorInjectionHandler>()
+                .handler::<VmmCommunicationHandler>()
+                .handler::<SecurityHandler>()
+                .handler::<TimerHandler>()
+                .handler::<SpuriousInterruptHandler>()
+                .build();
+            if let Some(vector) = IdtVector::all_exceptions().find(|&vector| !idt[vector].is_present()) {
+                panic!("No default handler for exception {}", vector.value());
+            }
+            idt
+        })
+    }
+
+    /// Loads the processor's descriptor tables (GDT, TSS and IDT) on the current CPU, once per processor
+    pub fn install(&'static self) {
+        assert!(!self.gdt.is_completed(), "Processor already installed");
+        let gdt = self.gdt.call_once(|| Gdt::new(&self.tss));
+        without_interrupts(|| {
+            gdt.load();
+            debug_assert_eq!(code_segment(), gdt::KERNEL_CODE.selector(PrivilegeLevel::KERNEL));
+            self.idt.load();
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static STACKS: InterruptStacks = InterruptStacks::new();
+
+    #[test]
+    fn new_installs_every_exception_handler() {
+        let processor = Processor::new(&STACKS);
+        for vector in IdtVector::all_exceptions() {
+            assert!(processor.idt[vector].is_present(), "vector {}", vector.value());
+        }
+        assert!(processor.idt[IdtVector::TIMER].is_present());
+        assert!(processor.idt[IdtVector::SPURIOUS].is_present());
+        assert!(!processor.idt[IdtVector::COPROCESSOR_SEGMENT_OVERRUN].is_present());
+
+        assert_eq!(processor.idt[IdtVector::DOUBLE_FAULT].ist(), DOUBLE_FAULT_STACK);
+        assert_eq!(processor.idt[IdtVector::PAGE_FAULT].ist(), PAGE_FAULT_STACK);
+        assert_eq!(processor.idt[IdtVector::GENERAL_PROTECTION].ist(), 0);
+
+        let interrupt_stacks = processor.tss.interrupt_stacks;
+        assert_eq!(interrupt_stacks[DOUBLE_FAULT_STACK as usize - 1], STACKS.double_fault.top().0 as u64);
+        assert_eq!(interrupt_stacks[PAGE_FAULT_STACK as usize - 1], STACKS.page_fault.top().0 as u64);
+    }
+}
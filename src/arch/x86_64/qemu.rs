@@ -0,0 +1,30 @@
+// QEMU integration for automated test runs, enabled with the `test-qemu` feature
+// Requires the isa-debug-exit device: `-device isa-debug-exit,iobase=0xf4,iosize=0x04`
+// Writing `value` to the port makes QEMU exit with status `(value << 1) | 1`, so a kernel exit never
+// looks like a clean QEMU exit (status 0): Success -> 33, Failure -> 35
+
+use super::intrinsics::{halt, port_write_u32};
+
+const ISA_DEBUG_EXIT_PORT: u16 = 0xF4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ExitCode {
+    Success = 0x10,
+    Failure = 0x11,
+}
+
+impl ExitCode {
+    /// QEMU process exit status
+    pub const fn status(self) -> u32 {
+        (self as u32) << 1 | 1
+    }
+}
+
+/// Exits QEMU, halts if the exit device is missing
+pub fn exit(code: ExitCode) -> ! {
+    unsafe {
+        port_write_u32(ISA_DEBUG_EXIT_PORT, code as u32);
+    }
+    halt()
+}
@@ -0,0 +1,101 @@
+use core::fmt::Write;
+
+use spin::Once;
+
+use crate::arch::devices::registry::{self, DeviceKind};
+
+use super::intrinsics::{port_read_u8, port_write_u8};
+
+static COM1: Once<SerialPort> = Once::new();
+
+/// COM1, initialized on first use
+pub fn com1() -> &'static SerialPort {
+    COM1.call_once(|| unsafe {
+        let port = SerialPort::new(SerialPort::COM1_BASE);
+        port.initialize();
+        registry::mark_initialized(DeviceKind::Serial);
+        port
+    })
+}
+
+/// Checks if [com1] finished initializing the port, never blocks
+pub fn com1_initialized() -> bool {
+    COM1.is_completed()
+}
+
+/// Writes to COM1, usable as a `BootTerminalWriter`
+pub fn write_str_com1(str: &str) -> core::fmt::Result {
+    com1().write(str);
+    Ok(())
+}
+
+/// 16550 UART
+#[derive(Debug)]
+pub struct SerialPort {
+    base: u16,
+}
+
+impl SerialPort {
+    pub const COM1_BASE: u16 = 0x3F8;
+
+    const DATA: u16 = 0;
+    const INTERRUPT_ENABLE: u16 = 1;
+    const FIFO_CONTROL: u16 = 2;
+    const LINE_CONTROL: u16 = 3;
+    const MODEM_CONTROL: u16 = 4;
+    const LINE_STATUS: u16 = 5;
+
+    const LINE_STATUS_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+    /// `base` must be the I/O port base of a 16550 compatible UART
+    pub const unsafe fn new(base: u16) -> Self {
+        Self { base }
+    }
+
+    /// Configures the port for 38400 baud, 8N1, polling mode \
+    /// Safety:
+    /// Reconfiguring a port in use may garble output in flight
+    pub unsafe fn initialize(&self) {
+        unsafe {
+            port_write_u8(self.base + Self::INTERRUPT_ENABLE, 0x00);
+            // Divisor latch access
+            port_write_u8(self.base + Self::LINE_CONTROL, 0x80);
+            // Divisor 3 (38400 baud)
+            port_write_u8(self.base + Self::DATA, 0x03);
+            port_write_u8(self.base + Self::INTERRUPT_ENABLE, 0x00);
+            // 8 bits, no parity, 1 stop bit
+            port_write_u8(self.base + Self::LINE_CONTROL, 0x03);
+            // Enable and clear FIFOs, 14 byte threshold
+            port_write_u8(self.base + Self::FIFO_CONTROL, 0xC7);
+            // DTR, RTS, OUT2
+            port_write_u8(self.base + Self::MODEM_CONTROL, 0x0B);
+        }
+    }
+
+    pub fn write_byte(&self, byte: u8) {
+        unsafe {
+            // A missing UART reads as 0xFF, so this can't hang
+            while port_read_u8(self.base + Self::LINE_STATUS) & Self::LINE_STATUS_TRANSMIT_EMPTY == 0 {
+                core::hint::spin_loop();
+            }
+            port_write_u8(self.base + Self::DATA, byte);
+        }
+    }
+
+    /// Translates `\n` into `\r\n`
+    pub fn write(&self, str: &str) {
+        for byte in str.bytes() {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+    }
+}
+
+impl Write for &SerialPort {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write(s);
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+// Kernel stacks are mapped in a dedicated virtual area split into fixed size slots,
+// each stack occupies the top of its slot and everything below it stays unmapped:
+// | guard (unmapped, >= 1 page) | stack pages | <- slot end = stack top
+// Overflowing a stack hits the guard and page faults instead of corrupting memory,
+// any not-present fault in the area is an overflow (see [is_guard_address])
+
+use core::{cell::UnsafeCell, sync::atomic::{AtomicUsize, Ordering}};
+
+use static_assertions::const_assert_eq;
+
+use crate::{allocator::physical::{global_allocator, FRAME_SIZE}, arch::VirtualAddress, common::macros::assert_arg};
+
+use super::paging::{self, PageFlags, PagingToken, WalkError, PAGE_SIZE};
+
+/// Start of the kernel stack area, between the identity map and the kernel image
+const STACK_AREA_BASE: usize = 0xFFFF_FE00_0000_0000;
+const STACK_SLOT_PAGES: usize = 64;
+const STACK_SLOT_SIZE: usize = STACK_SLOT_PAGES * PAGE_SIZE;
+const STACK_SLOT_COUNT: usize = 4096;
+const STACK_AREA_END: usize = STACK_AREA_BASE + STACK_SLOT_COUNT * STACK_SLOT_SIZE;
+
+/// Largest stack, at least one page of every slot is a guard page
+pub const MAX_STACK_PAGES: usize = STACK_SLOT_PAGES - 1;
+
+static NEXT_SLOT: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug)]
+pub struct KernelStack {
+    top: VirtualAddress,
+    pages: usize,
+}
+
+impl KernelStack {
+    /// Maps `pages` frames (at most [MAX_STACK_PAGES]) with an unmapped guard page below them \
+    /// Stack slots are never reused
+    pub fn allocate(pages: usize, token: PagingToken) -> Result<Self, WalkError> {
+        assert_arg!(pages, pages > 0 && pages <= MAX_STACK_PAGES);
+        let slot = NEXT_SLOT.fetch_add(1, Ordering::Relaxed);
+        assert!(slot < STACK_SLOT_COUNT, "Kernel stack area exhausted");
+
+        let (guard, bottom, top) = stack_layout(slot, pages);
+        debug_assert!(guard < bottom);
+        let allocator = global_allocator(token.into());
+        let flags = PageFlags { writable: true, executable: false, user: false };
+        for (index, page) in (0..pages).map(|i| (i, bottom + i * PAGE_SIZE)) {
+            let mapped = allocator.allocate(1).ok_or(WalkError::OutOfMemory).and_then(|frame| {
+                // SAFETY: the page is in an unused stack slot, no references to it exist
+                unsafe { paging::map(page, frame, flags, token) }
+                    .inspect_err(|_| allocator.free(frame, 1))
+            });
+
+            if let Err(error) = mapped {
+                // Release the pages mapped so far
+                for page in (0..index).map(|i| bottom + i * PAGE_SIZE) {
+                    // SAFETY: mapped above, not handed out yet
+                    if let Ok(frame) = unsafe { paging::unmap(page, token) } {
+                        allocator.free(frame, 1);
+                    }
+                }
+                return Err(error);
+            }
+        }
+
+        Ok(Self { top, pages })
+    }
+
+    /// Initial stack pointer, the stack grows down from here
+    pub fn top(&self) -> VirtualAddress {
+        self.top
+    }
+
+    pub fn bottom(&self) -> VirtualAddress {
+        self.top - self.pages * PAGE_SIZE
+    }
+
+    /// Lowest address of the guard page directly below the stack
+    pub fn guard_page(&self) -> VirtualAddress {
+        self.bottom() - PAGE_SIZE
+    }
+}
+
+/// Size of an [InterruptStack]
+pub const INTERRUPT_STACK_SIZE: usize = 4 * PAGE_SIZE;
+
+/// Statically allocated stack for the interrupt stack table (see `gdt::TaskStateSegment`),
+/// usable before paging is initialized \
+/// Has no guard page, only the CPU writes to it - every entry starts at the top, so handlers using it must not nest
+#[repr(C, align(16))]
+pub struct InterruptStack(UnsafeCell<[u8; INTERRUPT_STACK_SIZE]>);
+
+// SAFETY: Rust code never accesses the contents
+unsafe impl Sync for InterruptStack {}
+
+impl InterruptStack {
+    pub const fn new() -> Self {
+        Self(UnsafeCell::new([0; INTERRUPT_STACK_SIZE]))
+    }
+
+    pub fn top(&self) -> VirtualAddress {
+        VirtualAddress::new(self.0.get() as usize + INTERRUPT_STACK_SIZE)
+    }
+}
+
+impl Default for InterruptStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// (guard page, stack bottom, stack top) of a stack of `pages` pages in `slot`
+const fn stack_layout(slot: usize, pages: usize) -> (VirtualAddress, VirtualAddress, VirtualAddress) {
+    let top = STACK_AREA_BASE + (slot + 1) * STACK_SLOT_SIZE;
+    let bottom = top - pages * PAGE_SIZE;
+    (VirtualAddress::new(bottom - PAGE_SIZE), VirtualAddress::new(bottom), VirtualAddress::new(top))
+}
+
+const_assert_eq!(PAGE_SIZE, FRAME_SIZE);
+const_assert_eq!(stack_layout(0, 1).0.0, STACK_AREA_BASE + STACK_SLOT_SIZE - 2 * PAGE_SIZE);
+const_assert_eq!(stack_layout(1, MAX_STACK_PAGES).0.0, STACK_AREA_BASE + STACK_SLOT_SIZE);
+const_assert_eq!(stack_layout(2, 4).2.0, STACK_AREA_BASE + 3 * STACK_SLOT_SIZE);
+
+/// Checks if a page fault at `address` hit a stack guard (only meaningful for not-present faults)
+pub fn is_guard_address(address: VirtualAddress) -> bool {
+    (STACK_AREA_BASE..STACK_AREA_END).contains(&usize::from(address))
+}
@@ -1 +1,98 @@
-// TODO
+// SYSCALL / SYSRET entry
+//
+// IA32_STAR selector layout constraint:
+// STAR[47:32] - kernel CS, SYSCALL loads CS = STAR[47:32], SS = STAR[47:32] + 8
+// STAR[63:48] - user base, SYSRET (64-bit) loads CS = STAR[63:48] + 16, SS = STAR[63:48] + 8
+// so the GDT must contain: kernel code, kernel data (directly after) and
+// [user base], user data, user code (in this order, user base is the 32-bit user code slot).
+
+use core::arch::naked_asm;
+
+use crate::{arch::intrinsics::{read_msr, write_msr}, common::log::debug};
+
+const IA32_EFER_MSR: u32 = 0xC0000080;
+const IA32_STAR_MSR: u32 = 0xC0000081;
+const IA32_LSTAR_MSR: u32 = 0xC0000082;
+const IA32_FMASK_MSR: u32 = 0xC0000084;
+const IA32_KERNEL_GS_BASE_MSR: u32 = 0xC0000102;
+
+const EFER_SCE_BIT: u64 = 1;
+/// Interrupts, direction and trap flags are cleared on entry
+const FMASK: u64 = (1 << 9) | (1 << 10) | (1 << 8);
+
+const ENOSYS: i64 = 38;
+
+/// Per-CPU data used by the entry stub, `IA32_KERNEL_GS_BASE` points to it while in userspace
+// TODO: move into per-CPU data
+#[repr(C)]
+#[derive(Debug)]
+pub struct SyscallCpuData {
+    /// Top of the kernel stack used for syscalls, must be 16 byte aligned
+    pub kernel_stack: u64,
+    /// Scratch slot for the user stack pointer
+    pub user_stack: u64,
+}
+
+/// Enables `syscall` on the current CPU \
+/// Safety:
+/// The GDT must follow the STAR selector layout, `cpu_data` must be valid until userspace is left for good
+pub unsafe fn initialize(kernel_code_selector: u16, user_base_selector: u16, cpu_data: &'static mut SyscallCpuData) {
+    let star = (user_base_selector as u64) << 48 | (kernel_code_selector as u64) << 32;
+    unsafe {
+        write_msr(IA32_STAR_MSR, star);
+        write_msr(IA32_LSTAR_MSR, syscall_entry as *const () as u64);
+        write_msr(IA32_FMASK_MSR, FMASK);
+        write_msr(IA32_KERNEL_GS_BASE_MSR, cpu_data as *mut SyscallCpuData as u64);
+
+        let efer = read_msr(IA32_EFER_MSR);
+        write_msr(IA32_EFER_MSR, efer | EFER_SCE_BIT);
+    }
+}
+
+/// rax - syscall number, rdi, rsi, rdx, r10, r8 - arguments, rcx - user rip, r11 - user rflags
+#[unsafe(naked)]
+extern "C" fn syscall_entry() -> ! {
+    naked_asm!(
+        "
+        swapgs
+        mov     gs:[8], rsp
+        mov     rsp, gs:[0]
+        push    qword ptr gs:[8]
+        push    rcx
+        push    r11
+        push    rdi
+        push    rsi
+        push    rdx
+        push    r10
+        push    r8
+        push    r9
+        mov     r9, r8
+        mov     r8, r10
+        mov     rcx, rdx
+        mov     rdx, rsi
+        mov     rsi, rdi
+        mov     rdi, rax
+        sub     rsp, 8
+        call    {}
+        add     rsp, 8
+        pop     r9
+        pop     r8
+        pop     r10
+        pop     rdx
+        pop     rsi
+        pop     rdi
+        pop     r11
+        pop     rcx
+        pop     rsp
+        swapgs
+        sysretq
+        ",
+        sym dispatch,
+    )
+}
+
+extern "sysv64" fn dispatch(number: u64, arg0: u64, arg1: u64, arg2: u64, arg3: u64, arg4: u64) -> u64 {
+    let _ = (arg0, arg1, arg2, arg3, arg4);
+    debug!("Unimplemented syscall {number}");
+    (-ENOSYS) as u64
+}
@@ -0,0 +1,111 @@
+/// Integer types that can be read from raw bytes
+pub trait FromBytes: Sized {
+    const SIZE: usize;
+
+    /// `bytes.len()` must be equal to `Self::SIZE`
+    fn from_le_slice(bytes: &[u8]) -> Self;
+
+    /// `bytes.len()` must be equal to `Self::SIZE`
+    fn from_be_slice(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_from_bytes {
+    ($($type:ty),*) => {
+        $(
+            impl FromBytes for $type {
+                const SIZE: usize = core::mem::size_of::<$type>();
+
+                fn from_le_slice(bytes: &[u8]) -> Self {
+                    <$type>::from_le_bytes(bytes.try_into().expect("Invalid slice length"))
+                }
+
+                fn from_be_slice(bytes: &[u8]) -> Self {
+                    <$type>::from_be_bytes(bytes.try_into().expect("Invalid slice length"))
+                }
+            }
+        )*
+    };
+}
+
+impl_from_bytes!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+/// Reads a little endian `T` from the start of `bytes`, returns `None` if `bytes` is too short
+pub fn read_unaligned_le<T: FromBytes>(bytes: &[u8]) -> Option<T> {
+    bytes.get(..T::SIZE).map(T::from_le_slice)
+}
+
+/// Reads a big endian `T` from the start of `bytes`, returns `None` if `bytes` is too short
+pub fn read_unaligned_be<T: FromBytes>(bytes: &[u8]) -> Option<T> {
+    bytes.get(..T::SIZE).map(T::from_be_slice)
+}
+
+/// Bit range [start:end] (inclusive) of a `u64`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BitField {
+    start: u32,
+    end: u32,
+}
+
+impl BitField {
+    pub const fn new(start: u32, end: u32) -> Self {
+        assert!(start <= end && end < u64::BITS, "Invalid bit range");
+        Self { start, end }
+    }
+
+    pub const fn bit(index: u32) -> Self {
+        Self::new(index, index)
+    }
+
+    pub const fn width(self) -> u32 {
+        self.end - self.start + 1
+    }
+
+    /// Mask of the field in place
+    pub const fn mask(self) -> u64 {
+        (u64::MAX >> (u64::BITS - self.width())) << self.start
+    }
+
+    /// Extracts the field, shifted to bit 0
+    pub const fn get(self, value: u64) -> u64 {
+        (value & self.mask()) >> self.start
+    }
+
+    /// Returns `value` with the field replaced by `field` (truncated to the field width)
+    pub const fn set(self, value: u64, field: u64) -> u64 {
+        (value & !self.mask()) | ((field << self.start) & self.mask())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bit_field_extracts_and_replaces_a_range() {
+        // Page table entry physical address bits
+        let address = BitField::new(12, 51);
+        assert_eq!(address.width(), 40);
+        assert_eq!(address.mask(), 0x000F_FFFF_FFFF_F000);
+
+        let entry = 0x8000_0001_2345_6063;
+        assert_eq!(address.get(entry), 0x12_3456);
+        assert_eq!(address.set(entry, 0xA_BCDE), 0x8000_0000_ABCD_E063);
+        // Oversized values are truncated to the field
+        assert_eq!(BitField::new(4, 7).set(0, 0x1F), 0xF0);
+
+        assert_eq!(BitField::bit(63).get(entry), 1);
+        assert_eq!(BitField::bit(63).mask(), 1 << 63);
+        assert_eq!(BitField::new(0, 63).mask(), u64::MAX);
+        assert_eq!(BitField::new(0, 63).get(entry), entry);
+    }
+
+    #[test]
+    fn unaligned_reads_respect_endianness() {
+        let bytes = [0x12, 0x34, 0x56, 0x78, 0x9A];
+        assert_eq!(read_unaligned_le::<u32>(&bytes[1..]), Some(0x9A78_5634));
+        assert_eq!(read_unaligned_be::<u32>(&bytes[1..]), Some(0x3456_789A));
+        assert_eq!(read_unaligned_le::<u16>(&bytes), Some(0x3412));
+        assert_eq!(read_unaligned_be::<i8>(&bytes[4..]), Some(-0x66));
+        assert_eq!(read_unaligned_le::<u64>(&bytes), None);
+    }
+}
@@ -9,7 +9,25 @@ pub struct FixedSizeVec<T, const MAX_SIZE: usize> {
 
 impl<T, const MAX_SIZE: usize> FixedSizeVec<T, MAX_SIZE> {
     pub fn new() -> Self {
-        Self { data: MaybeUninit::uninit_array(), len: 0 }
+        Self { data: [const { MaybeUninit::uninit() }; MAX_SIZE], len: 0 }
+    }
+
+    /// Collects up to `MAX_SIZE` elements, on overflow returns the filled vec and the first element that didn't fit \
+    /// Elements past the rejected one are dropped with the iterator, pass `iter.by_ref()` to keep them
+    pub fn try_from_iter(iter: impl IntoIterator<Item = T>) -> Result<Self, (Self, T)> {
+        let mut result = Self::new();
+        for value in iter {
+            if result.len() == MAX_SIZE {
+                return Err((result, value));
+            }
+            // SAFETY: index is within capacity and uninitialized
+            unsafe {
+                let ix = result.len();
+                result.set_unchecked(ix, value);
+                result.set_len(ix + 1);
+            }
+        }
+        Ok(result)
     }
 
     pub fn len(&self) -> usize {
@@ -26,7 +44,7 @@ impl<T, const MAX_SIZE: usize> FixedSizeVec<T, MAX_SIZE> {
 
     pub fn as_slice(&self) -> &[T] {
         unsafe {
-            MaybeUninit::slice_assume_init_ref(&self.data[..self.len])
+            self.data[..self.len].assume_init_ref()
         }
     }
 
@@ -159,7 +177,7 @@ impl<T: Clone, const MAX_SIZE: usize> FixedSizeVec<T, MAX_SIZE> {
 
 impl<T: Copy, const MAX_SIZE: usize> FixedSizeVec<T, MAX_SIZE> {
     pub fn from_slice(slice: &[T]) -> Self {
-        let mut result = Self { data: MaybeUninit::uninit_array(), len: slice.len() };
+        let mut result = Self { data: [const { MaybeUninit::uninit() }; MAX_SIZE], len: slice.len() };
         unsafe {
             core::ptr::copy_nonoverlapping(slice.as_ptr(), result.data.as_mut_ptr().cast::<T>(), slice.len());
         }
@@ -206,7 +224,7 @@ impl<'a, T, const MAX_SIZE: usize> IntoIterator for &'a FixedSizeVec<T, MAX_SIZE
 
     fn into_iter(self) -> Self::IntoIter {
         unsafe {
-            MaybeUninit::slice_assume_init_ref(self.data.get_unchecked(..self.len())).iter()
+            self.data.get_unchecked(..self.len()).assume_init_ref().iter()
         }
     }
 }
@@ -220,3 +238,183 @@ impl<T, const MAX_SIZE: usize> Drop for FixedSizeVec<T, MAX_SIZE> {
         }
     }
 }
+
+/// Open addressing (linear probing) hash map with a fixed capacity of `N` entries \
+/// `no_std` has no default hasher, the hash function is provided on construction
+pub struct FixedHashMap<K, V, const N: usize> {
+    slots: [Slot<K, V>; N],
+    len: usize,
+    hasher: fn(&K) -> u64,
+}
+
+enum Slot<K, V> {
+    Empty,
+    /// Removed entry, lookups have to probe past it
+    Deleted,
+    Occupied(K, V),
+}
+
+impl<K: PartialEq, V, const N: usize> FixedHashMap<K, V, N> {
+    pub fn new(hasher: fn(&K) -> u64) -> Self {
+        Self { slots: core::array::from_fn(|_| Slot::Empty), len: 0, hasher }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Inserts or replaces the value for `key`, returns the replaced value \
+    /// If the map is full and doesn't contain `key` the pair is returned back
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        if let Some(ix) = self.find(&key) {
+            let Slot::Occupied(_, old) = &mut self.slots[ix] else { unreachable!() };
+            return Ok(Some(core::mem::replace(old, value)));
+        }
+
+        let free = self.probe(&key).find(|&ix| !matches!(self.slots[ix], Slot::Occupied(..)));
+        match free {
+            Some(ix) => {
+                self.slots[ix] = Slot::Occupied(key, value);
+                self.len += 1;
+                Ok(None)
+            },
+            None => Err((key, value)),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        match &self.slots[self.find(key)?] {
+            Slot::Occupied(_, value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let ix = self.find(key)?;
+        match &mut self.slots[ix] {
+            Slot::Occupied(_, value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.find(key).is_some()
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let ix = self.find(key)?;
+        match core::mem::replace(&mut self.slots[ix], Slot::Deleted) {
+            Slot::Occupied(_, value) => {
+                self.len -= 1;
+                Some(value)
+            },
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.slots.iter().filter_map(|slot| match slot {
+            Slot::Occupied(key, value) => Some((key, value)),
+            _ => None,
+        })
+    }
+
+    /// Slot indices in probing order for `key`
+    fn probe(&self, key: &K) -> impl Iterator<Item = usize> {
+        let start = if N == 0 { 0 } else { ((self.hasher)(key) % N as u64) as usize };
+        (0..N).map(move |i| (start + i) % N)
+    }
+
+    fn find(&self, key: &K) -> Option<usize> {
+        for ix in self.probe(key) {
+            match &self.slots[ix] {
+                Slot::Empty => return None,
+                Slot::Occupied(existing, _) if existing == key => return Some(ix),
+                _ => (),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{rc::Rc, vec::Vec};
+
+    use super::*;
+
+    #[test]
+    fn try_from_iter_returns_the_rejected_element() {
+        let values: Vec<_> = (0..5).map(Rc::new).collect();
+        let mut iter = values.iter().cloned();
+        let (vec, rejected) = FixedSizeVec::<_, 3>::try_from_iter(iter.by_ref()).unwrap_err();
+        assert_eq!(vec.as_slice().iter().map(|x| **x).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(*rejected, 3);
+        assert_eq!(iter.map(|x| *x).collect::<Vec<_>>(), [4]);
+
+        // Every clone was released, none leaked on the overflow path
+        drop((vec, rejected));
+        assert!(values.iter().all(|x| Rc::strong_count(x) == 1));
+
+        let vec = FixedSizeVec::<_, 3>::try_from_iter([1, 2]).unwrap();
+        assert_eq!(vec.as_slice(), [1, 2]);
+    }
+
+    #[test]
+    fn hash_map_probes_past_collisions_and_removals() {
+        // 1, 5 and 9 all start probing at slot 1
+        let mut map = FixedHashMap::<u32, &str, 4>::new(|&key| key as u64);
+        assert_eq!(map.insert(1, "one"), Ok(None));
+        assert_eq!(map.insert(5, "five"), Ok(None));
+        assert_eq!(map.insert(9, "nine"), Ok(None));
+        assert_eq!(map.insert(5, "FIVE"), Ok(Some("five")));
+        assert_eq!((map.get(&1), map.get(&5), map.get(&9)), (Some(&"one"), Some(&"FIVE"), Some(&"nine")));
+        assert_eq!(map.get(&13), None);
+        assert_eq!(map.len(), 3);
+
+        // The removed entry leaves a tombstone, 9 is still reachable
+        assert_eq!(map.remove(&5), Some("FIVE"));
+        assert_eq!(map.remove(&5), None);
+        assert_eq!(map.get(&9), Some(&"nine"));
+        assert!(!map.contains_key(&5));
+
+        // The tombstone is reused
+        assert_eq!(map.insert(13, "thirteen"), Ok(None));
+        assert_eq!(map.get(&13), Some(&"thirteen"));
+        *map.get_mut(&1).unwrap() = "ONE";
+        let mut entries: Vec<_> = map.iter().map(|(&key, &value)| (key, value)).collect();
+        entries.sort();
+        assert_eq!(entries, [(1, "ONE"), (9, "nine"), (13, "thirteen")]);
+    }
+
+    #[test]
+    fn full_hash_map_rejects_new_keys() {
+        let mut map = FixedHashMap::<u32, u32, 3>::new(|_| 0);
+        for key in 0..3 {
+            assert_eq!(map.insert(key, key * 10), Ok(None));
+        }
+        assert_eq!(map.len(), map.capacity());
+        assert_eq!(map.insert(3, 30), Err((3, 30)));
+        // Lookups of missing keys terminate without an empty slot
+        assert_eq!(map.get(&3), None);
+        // Existing keys can still be replaced
+        assert_eq!(map.insert(2, 21), Ok(Some(20)));
+
+        assert_eq!(map.remove(&0), Some(0));
+        assert_eq!(map.insert(3, 30), Ok(None));
+        assert_eq!(map.get(&3), Some(&30));
+
+        let mut empty = FixedHashMap::<u32, u32, 0>::new(|&key| key as u64);
+        assert!(empty.is_empty());
+        assert_eq!(empty.insert(1, 1), Err((1, 1)));
+        assert_eq!(empty.get(&1), None);
+    }
+}
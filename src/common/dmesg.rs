@@ -0,0 +1,195 @@
+// In-memory ring buffer of recent log records, filled by `log::write` regardless of the live output
+// Writers never block: a record is dropped (and counted) if the buffer is locked, e.g. when a handler
+// interrupts a writer on the same CPU, the lock is held with interrupts disabled otherwise
+
+use core::{fmt::{Arguments, Display, Write}, sync::atomic::{AtomicU64, Ordering}};
+
+use spin::Mutex;
+
+use crate::arch::intrinsics::without_interrupts;
+
+use super::log::Level;
+
+pub const RECORD_COUNT: usize = 256;
+/// Longer messages are truncated
+pub const MESSAGE_SIZE: usize = 120;
+
+static BUFFER: Mutex<RingBuffer> = Mutex::new(RingBuffer::new());
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Record {
+    /// Position in the log since boot
+    pub sequence: u64,
+    pub level: Level,
+    len: u8,
+    /// Part of the message was cut off
+    truncated: bool,
+    message: [u8; MESSAGE_SIZE],
+}
+
+impl Record {
+    const EMPTY: Record = Record { sequence: 0, level: Level::Trace, len: 0, truncated: false, message: [0; MESSAGE_SIZE] };
+
+    pub fn message(&self) -> &str {
+        // Truncation happens on char boundaries
+        core::str::from_utf8(&self.message[..self.len as usize]).unwrap_or_default()
+    }
+
+    /// Whether the message didn't fit
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "[{:>5}] {}", self.level, self.message())?;
+        if self.truncated() {
+            f.write_str("...")?;
+        }
+        Ok(())
+    }
+}
+
+/// Truncating writer into a record's message
+impl Write for Record {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let len = self.len as usize;
+        let mut take = s.len().min(MESSAGE_SIZE - len);
+        while !s.is_char_boundary(take) {
+            take -= 1;
+        }
+        self.message[len..len + take].copy_from_slice(&s.as_bytes()[..take]);
+        self.len += take as u8;
+        self.truncated |= take < s.len();
+        Ok(())
+    }
+}
+
+struct RingBuffer {
+    records: [Record; RECORD_COUNT],
+    /// Sequence number of the next record
+    next: u64,
+}
+
+impl RingBuffer {
+    const fn new() -> Self {
+        Self { records: [Record::EMPTY; RECORD_COUNT], next: 0 }
+    }
+
+    /// Sequence number of the oldest stored record
+    fn first(&self) -> u64 {
+        self.next.saturating_sub(RECORD_COUNT as u64)
+    }
+
+    fn get(&self, sequence: u64) -> Option<Record> {
+        (sequence >= self.first() && sequence < self.next).then(|| self.records[(sequence % RECORD_COUNT as u64) as usize])
+    }
+
+    /// Overwrites the oldest record once full
+    fn push(&mut self, level: Level, args: Arguments) {
+        let record = &mut self.records[(self.next % RECORD_COUNT as u64) as usize];
+        record.sequence = self.next;
+        record.level = level;
+        record.len = 0;
+        record.truncated = false;
+        _ = record.write_fmt(args);
+        self.next += 1;
+    }
+}
+
+/// Stores a log record, called by `log::write` for every enabled record
+pub fn record(level: Level, args: Arguments) {
+    without_interrupts(|| match BUFFER.try_lock() {
+        Some(mut buffer) => buffer.push(level, args),
+        None => {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        },
+    });
+}
+
+/// Records lost because the buffer was locked
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Iterates over the records stored when the iterator was created, oldest first \
+/// Records overwritten in the meantime are skipped
+pub fn records() -> impl Iterator<Item = Record> {
+    let range = without_interrupts(|| {
+        let buffer = BUFFER.lock();
+        buffer.first()..buffer.next
+    });
+    range.filter_map(|sequence| without_interrupts(|| BUFFER.lock().get(sequence)))
+}
+
+/// Prints all stored records to the boot terminal
+pub fn dmesg() {
+    for record in records() {
+        crate::arch::boot::boot_println!("{record}");
+    }
+    if dropped() > 0 {
+        crate::arch::boot::boot_println!("({} records dropped)", dropped());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{string::String, vec::Vec};
+
+    use super::*;
+
+    fn message(len: usize) -> String {
+        "x".repeat(len)
+    }
+
+    #[test]
+    fn messages_are_truncated_past_the_limit() {
+        let mut buffer = RingBuffer::new();
+        let exact = message(MESSAGE_SIZE);
+        buffer.push(Level::Info, format_args!("{exact}"));
+        buffer.push(Level::Info, format_args!("{exact}y"));
+        // 'é' is two bytes, it would end one byte past the limit
+        let split = message(MESSAGE_SIZE - 1);
+        buffer.push(Level::Warn, format_args!("{split}é"));
+        buffer.push(Level::Info, format_args!("{}", ""));
+
+        let record = buffer.get(0).unwrap();
+        assert_eq!(record.message(), exact);
+        assert!(!record.truncated());
+
+        let record = buffer.get(1).unwrap();
+        assert_eq!(record.message(), exact);
+        assert!(record.truncated());
+
+        let record = buffer.get(2).unwrap();
+        assert_eq!(record.message(), split);
+        assert!(record.truncated());
+        assert_eq!(record.level, Level::Warn);
+
+        // Reused slots start out clean
+        let record = buffer.get(3).unwrap();
+        assert_eq!(record.message(), "");
+        assert!(!record.truncated());
+    }
+
+    #[test]
+    fn ring_buffer_wraps_around() {
+        let mut buffer = RingBuffer::new();
+        let total = RECORD_COUNT as u64 + 10;
+        for i in 0..total {
+            buffer.push(Level::Debug, format_args!("record {i}"));
+        }
+
+        assert_eq!(buffer.first(), 10);
+        assert!(buffer.get(9).is_none());
+        assert!(buffer.get(total).is_none());
+        let records: Vec<_> = (buffer.first()..buffer.next).map(|i| buffer.get(i).unwrap()).collect();
+        assert_eq!(records.len(), RECORD_COUNT);
+        for (record, sequence) in records.iter().zip(10..) {
+            assert_eq!(record.sequence, sequence);
+            assert_eq!(record.message(), std::format!("record {sequence}"));
+        }
+    }
+}
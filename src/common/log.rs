@@ -0,0 +1,180 @@
+use core::{fmt::{Arguments, Display}, sync::atomic::{AtomicU8, Ordering}};
+
+// TODO: dispatch to a registered sink (framebuffer terminal, serial) instead of the boot terminal
+// Every record is also kept in the `dmesg` ring buffer, records logged by the boot protocol loaders
+// before the boot terminal exists are only stored there and printed by `print_early_records`
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+    Trace = 5,
+}
+
+impl Level {
+    /// Parses the level name as used by the `loglevel=` command line option
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "error" => Level::Error,
+            "warn" => Level::Warn,
+            "info" => Level::Info,
+            "debug" => Level::Debug,
+            "trace" => Level::Trace,
+            _ => return None,
+        })
+    }
+
+    /// ANSI SGR foreground color of the level tag
+    pub const fn ansi_color(self) -> u8 {
+        match self {
+            Level::Error => 31,
+            Level::Warn => 33,
+            Level::Info => 32,
+            Level::Debug => 36,
+            Level::Trace => 90,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Level::Error,
+            2 => Level::Warn,
+            3 => Level::Info,
+            4 => Level::Debug,
+            _ => Level::Trace,
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Records above `level` are discarded
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn max_level() -> Level {
+    Level::from_u8(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+#[inline]
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Use the `log!` macros instead, they skip disabled records before formatting
+#[doc(hidden)]
+pub fn write(level: Level, args: Arguments) {
+    super::dmesg::record(level, args);
+    if crate::arch::boot::boot_terminal_writer().is_some() {
+        print(level, args);
+    }
+}
+
+/// Prints the records stored before the boot terminal was initialized, called once by `boot::main`
+pub fn print_early_records() {
+    for record in super::dmesg::records() {
+        let ellipsis = if record.truncated() { "..." } else { "" };
+        print(record.level, format_args!("{}{ellipsis}", record.message()));
+    }
+}
+
+fn print(level: Level, message: Arguments) {
+    crate::arch::boot::boot_println!("\x1b[{}m[{level:>5}]\x1b[0m {message}", level.ansi_color());
+}
+
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {{
+        let level = $level;
+        if crate::common::log::enabled(level) {
+            crate::common::log::write(level, format_args!($($arg)*));
+        }
+    }};
+}
+pub(crate) use log;
+
+macro_rules! error {
+    ($($arg:tt)*) => (crate::common::log::log!(crate::common::log::Level::Error, $($arg)*));
+}
+pub(crate) use error;
+
+// `use warn` would be ambiguous with the builtin `warn` attribute
+macro_rules! _warn {
+    ($($arg:tt)*) => (crate::common::log::log!(crate::common::log::Level::Warn, $($arg)*));
+}
+pub(crate) use _warn as warn;
+
+macro_rules! info {
+    ($($arg:tt)*) => (crate::common::log::log!(crate::common::log::Level::Info, $($arg)*));
+}
+pub(crate) use info;
+
+macro_rules! debug {
+    ($($arg:tt)*) => (crate::common::log::log!(crate::common::log::Level::Debug, $($arg)*));
+}
+pub(crate) use debug;
+
+macro_rules! trace {
+    ($($arg:tt)*) => (crate::common::log::log!(crate::common::log::Level::Trace, $($arg)*));
+}
+pub(crate) use trace;
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, fmt};
+
+    use super::*;
+
+    /// Counts how many times it was formatted
+    struct Counted<'a>(&'a Cell<usize>);
+
+    impl fmt::Display for Counted<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            self.0.set(self.0.get() + 1);
+            f.write_str("counted")
+        }
+    }
+
+    #[test]
+    fn disabled_records_are_skipped_before_formatting() {
+        // The only test changing the level, `dmesg` keeps every written record
+        set_max_level(Level::Info);
+        assert_eq!(max_level(), Level::Info);
+        assert!(enabled(Level::Warn) && !enabled(Level::Debug));
+
+        let formatted = Cell::new(0);
+        debug!("filtered debug record {}", Counted(&formatted));
+        assert_eq!(formatted.get(), 0);
+        error!("kept error record {}", Counted(&formatted));
+        assert_eq!(formatted.get(), 1);
+
+        let messages = || crate::common::dmesg::records().map(|record| (record.level, std::string::String::from(record.message())));
+        assert!(!messages().any(|(_, message)| message.starts_with("filtered debug record")));
+        assert!(messages().any(|record| record == (Level::Error, "kept error record counted".into())));
+
+        set_max_level(Level::Trace);
+        debug!("enabled debug record {}", Counted(&formatted));
+        assert_eq!(formatted.get(), 2);
+        assert!(messages().any(|record| record == (Level::Debug, "enabled debug record counted".into())));
+        set_max_level(Level::Info);
+    }
+}
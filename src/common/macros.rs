@@ -77,6 +77,52 @@ macro_rules! debug_assert_arg {
 }
 pub(crate) use debug_assert_arg;
 
+/// Recoverable argument validation error, see [check_arg]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArgError {
+    pub arg: &'static str,
+    pub function: &'static str,
+    pub message: Option<&'static str>,
+}
+
+impl core::fmt::Display for ArgError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_fmt(format_args!(
+            "{} ('{}' at {})",
+            self.message.unwrap_or("Invalid argument value"),
+            self.arg,
+            self.function
+        ))
+    }
+}
+
+/// Like [assert_arg] but returns `Result<(), ArgError>` instead of panicking
+macro_rules! check_arg {
+    ($arg:ident, $expr:expr) => {
+        if $expr {
+            ::core::result::Result::Ok(())
+        } else {
+            ::core::result::Result::Err($crate::common::macros::ArgError {
+                arg: stringify!($arg),
+                function: $crate::common::macros::function_name!(),
+                message: ::core::option::Option::None,
+            })
+        }
+    };
+    ($arg:ident, $expr:expr, $message:expr) => {
+        if $expr {
+            ::core::result::Result::Ok(())
+        } else {
+            ::core::result::Result::Err($crate::common::macros::ArgError {
+                arg: stringify!($arg),
+                function: $crate::common::macros::function_name!(),
+                message: ::core::option::Option::Some($message),
+            })
+        }
+    };
+}
+pub(crate) use check_arg;
+
 /// Prevents creating tokens safely
 #[derive(Clone, Copy)]
 pub struct InnerToken {
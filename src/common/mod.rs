@@ -1,6 +1,9 @@
 use core::fmt::{Debug, LowerHex};
 
+pub mod bits;
 pub mod collections;
+pub mod dmesg;
+pub mod log;
 pub mod macros;
 pub mod mem;
 pub mod random;
@@ -2,10 +2,17 @@ use core::sync::atomic::{AtomicU64, Ordering};
 
 use spin::Once;
 
-use crate::{common::time::UnixEpochTime, arch::intrinsics::time_stamp_counter};
+use crate::{common::{sync::Backoff, time::UnixEpochTime}, arch::intrinsics::time_stamp_counter};
 
 static WEAK_RNG: Once<XorshiftStar> = Once::new();
 
+/// Interrupt timing entropy collected since the last reseed
+static TIMING_POOL: AtomicU64 = AtomicU64::new(0);
+static TIMING_SAMPLES: AtomicU64 = AtomicU64::new(0);
+static LAST_INTERRUPT_TIMESTAMP: AtomicU64 = AtomicU64::new(0);
+/// Samples mixed into the pool before it's used to reseed the weak RNG
+const TIMING_SAMPLES_PER_RESEED: u64 = 64;
+
 /// This function may be only called once, all subsequent calls will panic or be ignored
 pub fn weak_initialize(time: UnixEpochTime) {
     // best effort panic
@@ -21,6 +28,39 @@ pub fn weak_initialize(time: UnixEpochTime) {
     });
 }
 
+/// Initializes the weak RNG with a fixed seed, the generated stream is reproducible \
+/// Not suitable outside of testing and debugging, [collect_interrupt_timing] reseeds still apply \
+/// This function may be only called once (including [weak_initialize]), all subsequent calls will panic or be ignored
+pub fn weak_initialize_with_seed(seed: u64) {
+    // best effort panic
+    if WEAK_RNG.is_completed() {
+        panic!("weak RNG already initialized");
+    }
+
+    WEAK_RNG.call_once(|| XorshiftStar::new(seed));
+}
+
+/// Mixes `extra` into the weak RNG state, ignored if the RNG isn't initialized yet \
+/// The resulting stream is determined by the current state and `extra`
+pub fn weak_reseed(extra: u64) {
+    if let Some(rng) = WEAK_RNG.get() {
+        rng.mix(extra);
+    }
+}
+
+/// Should be called from interrupt handlers, feeds the time stamp counter delta between interrupts
+/// into the weak RNG (weak entropy, the low bits of the delta jitter)
+pub fn collect_interrupt_timing() {
+    let now = time_stamp_counter();
+    let delta = now.wrapping_sub(LAST_INTERRUPT_TIMESTAMP.swap(now, Ordering::Relaxed));
+    let pool = TIMING_POOL.load(Ordering::Relaxed).rotate_left(7) ^ delta;
+    TIMING_POOL.store(pool, Ordering::Relaxed);
+
+    if TIMING_SAMPLES.fetch_add(1, Ordering::Relaxed) % TIMING_SAMPLES_PER_RESEED == TIMING_SAMPLES_PER_RESEED - 1 {
+        weak_reseed(TIMING_POOL.swap(0, Ordering::Relaxed));
+    }
+}
+
 pub fn weak() -> WeakRng {
     WeakRng::new(WEAK_RNG.get().expect("Weak RNG uninitialized"))
 }
@@ -62,16 +102,76 @@ impl XorshiftStar {
         Self(AtomicU64::new(seed))
     }
 
+    /// XORs `extra` into the state, the state stays nonzero
+    pub fn mix(&self, extra: u64) {
+        let _ = self.0.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |state| {
+            Some(match state ^ extra {
+                0 => u64::MAX,
+                value => value,
+            })
+        });
+    }
+
     pub fn next(&self) -> u64 {
-        // TODO: Relax ordering
-        let old = self.0.load(Ordering::SeqCst);
-        let mut value = old;
-        value ^= value >> 12;
-        value ^= value << 25;
-        value ^= value >> 27;
-        match self.0.compare_exchange(old, value, Ordering::SeqCst, Ordering::SeqCst) {
-            Ok(_) => value * Self::M,
-            Err(_) => self.next()
+        let mut backoff = Backoff::new();
+        loop {
+            // TODO: Relax ordering
+            let old = self.0.load(Ordering::SeqCst);
+            let mut value = old;
+            value ^= value >> 12;
+            value ^= value << 25;
+            value ^= value >> 27;
+            match self.0.compare_exchange(old, value, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return value.wrapping_mul(Self::M),
+                Err(_) => backoff.spin(),
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    fn stream(rng: &XorshiftStar) -> Vec<u64> {
+        (0..4).map(|_| rng.next()).collect()
+    }
+
+    #[test]
+    fn fixed_seed_streams_are_identical() {
+        let seed = 0xDEAD_BEEF;
+        let expected = stream(&XorshiftStar::new(seed));
+        assert_eq!(stream(&XorshiftStar::new(seed)), expected);
+        assert_ne!(stream(&XorshiftStar::new(seed + 1)), expected);
+        // Zero is replaced with a valid seed
+        assert_eq!(stream(&XorshiftStar::new(0)), stream(&XorshiftStar::new(u64::MAX)));
+
+        // The only test initializing the global generator
+        weak_initialize_with_seed(seed);
+        let rng = weak();
+        assert_eq!((0..4).map(|_| rng.next()).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn reseeding_changes_the_stream_deterministically() {
+        let seed = 0x1234_5678_9ABC_DEF0;
+        let extra = 0x0F0F_0000_FFFF_0001;
+        let (reseeded, plain) = (XorshiftStar::new(seed), XorshiftStar::new(seed));
+        assert_eq!(reseeded.next(), plain.next());
+
+        let state = reseeded.0.load(Ordering::SeqCst);
+        reseeded.mix(extra);
+        let after = stream(&reseeded);
+        assert_ne!(after, stream(&plain));
+        // Same as a generator seeded with the mixed state
+        assert_eq!(after, stream(&XorshiftStar::new(state ^ extra)));
+
+        // Mixing in the state itself would zero it
+        let rng = XorshiftStar::new(seed);
+        rng.mix(seed);
+        assert_eq!(rng.0.load(Ordering::SeqCst), u64::MAX);
+        assert_ne!(rng.next(), 0);
+    }
+}
@@ -1,4 +1,4 @@
-use core::cell::{SyncUnsafeCell, UnsafeCell};
+use core::cell::SyncUnsafeCell;
 
 use spin::Once;
 
@@ -47,6 +47,24 @@ impl<T> InitOnce<T> {
         })
     }
 
+    /// Replaces the pre-initialization value with the result of `f` if not initialized yet,
+    /// concurrent callers wait for the first initializer and observe its value \
+    /// The pre-initialization value is dropped without being observed,
+    /// use [InitOnce::initialize] if the initializer needs to mutate it in place
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+        self.init_lock.call_once(|| unsafe {
+            // SAFETY:
+            // immutable references may exist only after initialization,
+            // only a single mutable reference may exist at a time, only before initialization.
+            *self.data.get() = f();
+        });
+
+        // SAFETY: initialization completed, no mutable references may exist at this point
+        unsafe {
+            &*self.data.get()
+        }
+    }
+
     /// Will panic if not initialized
     pub fn get(&self) -> &T {
         if !self.init_lock.is_completed() {
@@ -73,6 +91,35 @@ impl<T> InitOnce<T> {
     }
 }
 
+/// Exponential backoff for contended compare-exchange loops, call [Backoff::spin] after each failed attempt
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    /// The spin count stops growing at 2^MAX_STEP
+    const MAX_STEP: u32 = 6;
+
+    pub const fn new() -> Self {
+        Self { step: 0 }
+    }
+
+    /// Busy-waits for 2^step spin loop hints, then increases the step
+    pub fn spin(&mut self) {
+        for _ in 0..(1 << self.step) {
+            core::hint::spin_loop();
+        }
+        if self.step < Self::MAX_STEP {
+            self.step += 1;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.step = 0;
+    }
+}
+
 // pub struct InitOnce<T> {
 //     data: SyncUnsafeCell<MaybeUninit<T>>,
 //     initialized: AtomicBool,
@@ -150,40 +197,40 @@ impl<T> InitOnce<T> {
 //     }
 // }
 
-// TODO: remove
-#[repr(transparent)]
-pub struct UnsafeSync<T>(UnsafeCell<T>);
-
-impl<T> UnsafeSync<T> {
-    pub unsafe fn new(value: T) -> Self {
-        UnsafeSync(UnsafeCell::from(value))
-    }
-
-    pub unsafe fn get(&self) -> &T {
-        unsafe { self.0.get().as_ref().unwrap_unchecked() }
-    }
-
-    pub unsafe fn get_mut(&mut self) -> &mut T {
-        self.0.get_mut()
-    }
-}
-
-impl<T> Default for UnsafeSync<T>
-where
-    T: Default,
-{
-    fn default() -> Self {
-        UnsafeSync(Default::default())
-    }
-}
-
-unsafe impl<T> Sync for UnsafeSync<T> {}
-unsafe impl<T> Send for UnsafeSync<T> {}
+#[cfg(test)]
+mod tests {
+    use std::{sync::{atomic::{AtomicUsize, Ordering}, Barrier}, thread};
+
+    use super::*;
+
+    #[test]
+    fn concurrent_get_or_init_converges_on_one_value() {
+        const THREADS: usize = 8;
+        let cell = InitOnce::new(0);
+        let calls = AtomicUsize::new(0);
+        let barrier = Barrier::new(THREADS);
+
+        let values: Vec<usize> = thread::scope(|scope| {
+            let handles: Vec<_> = (1..=THREADS).map(|id| {
+                let (cell, calls, barrier) = (&cell, &calls, &barrier);
+                scope.spawn(move || {
+                    barrier.wait();
+                    *cell.get_or_init(|| {
+                        calls.fetch_add(1, Ordering::Relaxed);
+                        id
+                    })
+                })
+            }).collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
 
-impl<T> From<T> for UnsafeSync<T> {
-    fn from(value: T) -> Self {
-        unsafe {
-            Self::new(value)
-        }
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        assert_ne!(values[0], 0, "pre-initialization value observed");
+        assert!(values.iter().all(|&value| value == values[0]));
+        assert!(cell.is_completed());
+        assert_eq!(*cell.get(), values[0]);
+        // Later initializers are ignored
+        assert_eq!(*cell.get_or_init(|| usize::MAX), values[0]);
+        assert_eq!(*cell.initialize(|value| *value = usize::MAX), values[0]);
     }
 }
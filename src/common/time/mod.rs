@@ -0,0 +1,119 @@
+use core::fmt::Display;
+
+pub mod stopwatch;
+pub mod ticks;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UnixEpochTime(/* UNIX millis */ u64);
+
+impl UnixEpochTime {
+    pub const UNIX_EPOCH: UnixEpochTime = Self::new(0);
+
+    pub const fn new(milliseconds: u64) -> Self {
+        UnixEpochTime(milliseconds)
+    }
+
+    pub const fn millis(self) -> u64 {
+        self.0
+    }
+
+    pub const fn seconds(self) -> u64 {
+        self.0 / 1000
+    }
+
+    /// UTC calendar date and time
+    pub const fn date_time(self) -> DateTime {
+        // Days to civil date, see: https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let seconds = self.seconds();
+        let days = seconds / 86400;
+        let seconds_of_day = seconds % 86400;
+
+        // Shift the epoch to 0000-03-01
+        let days = days + 719468;
+        let era = days / 146097;
+        let day_of_era = days % 146097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        // March-based month [0:11]
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            year: year as u32,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds_of_day / 3600) as u8,
+            minute: (seconds_of_day / 60 % 60) as u8,
+            second: (seconds_of_day % 60) as u8,
+        }
+    }
+}
+
+/// UTC date and time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u32,
+    /// [1:12]
+    pub month: u8,
+    /// [1:31]
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Checks that every field is in range, e.g. for dates read from hardware \
+    /// [DateTime::unix_time] expects a valid date
+    pub const fn is_valid(&self) -> bool {
+        let leap_year = self.year.is_multiple_of(4) && (!self.year.is_multiple_of(100) || self.year.is_multiple_of(400));
+        let days_in_month = match self.month {
+            2 if leap_year => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        };
+        matches!(self.month, 1..=12)
+            && self.day >= 1 && self.day <= days_in_month
+            && self.hour < 24 && self.minute < 60 && self.second < 60
+    }
+
+    /// Inverse of [UnixEpochTime::date_time], dates before 1970 saturate to the epoch
+    pub const fn unix_time(self) -> UnixEpochTime {
+        // Civil date to days, see: https://howardhinnant.github.io/date_algorithms.html#days_from_civil
+        let year = self.year as u64 - if self.month <= 2 { 1 } else { 0 };
+        let era = year / 400;
+        let year_of_era = year % 400;
+        // March-based month [0:11]
+        let month = if self.month > 2 { self.month - 3 } else { self.month + 9 } as u64;
+        let day_of_year = (153 * month + 2) / 5 + self.day as u64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = (era * 146097 + day_of_era).saturating_sub(719468);
+
+        let seconds = days * 86400 + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64;
+        UnixEpochTime::new(seconds * 1000)
+    }
+}
+
+impl Display for DateTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_fmt(format_args!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        ))
+    }
+}
+
+impl From<u64> for UnixEpochTime {
+    fn from(value: u64) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<UnixEpochTime> for u64 {
+    fn from(val: UnixEpochTime) -> Self {
+        val.0
+    }
+}
@@ -0,0 +1,123 @@
+use core::{fmt::Display, sync::atomic::{AtomicU64, Ordering}, time::Duration};
+
+use crate::arch::intrinsics::time_stamp_counter;
+
+/// Time stamp counter ticks per second, 0 if not calibrated
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// Should be called once the time stamp counter frequency is calibrated (e.g. against the PIT)
+pub fn set_tsc_frequency(hertz: u64) {
+    TSC_FREQUENCY.store(hertz, Ordering::Relaxed);
+}
+
+pub fn tsc_frequency() -> Option<u64> {
+    match TSC_FREQUENCY.load(Ordering::Relaxed) {
+        0 => None,
+        frequency => Some(frequency),
+    }
+}
+
+/// Assumed by [busy_wait] until the counter is calibrated
+const FALLBACK_TSC_FREQUENCY: u64 = 1_000_000_000;
+
+/// Spins for at least `duration`, approximate (assuming a 1 GHz counter) before [set_tsc_frequency] is called
+pub fn busy_wait(duration: Duration) {
+    let frequency = tsc_frequency().unwrap_or(FALLBACK_TSC_FREQUENCY);
+    let cycles = (duration.as_nanos() * frequency as u128 / 1_000_000_000).min(u64::MAX as u128) as u64;
+    let stopwatch = Stopwatch::start();
+    while stopwatch.elapsed().cycles < cycles {
+        core::hint::spin_loop();
+    }
+}
+
+/// Measures time since [Stopwatch::start] using the time stamp counter \
+/// Intended for profiling, `rdtsc` isn't serializing so very short measurements are imprecise
+#[derive(Clone, Copy, Debug)]
+pub struct Stopwatch {
+    start: u64,
+}
+
+impl Stopwatch {
+    pub fn start() -> Self {
+        Self { start: time_stamp_counter() }
+    }
+
+    pub fn elapsed(&self) -> Measurement {
+        self.elapsed_until(time_stamp_counter())
+    }
+
+    /// Time between the start and a counter value of `counter`, the counter may wrap around in between
+    const fn elapsed_until(&self, counter: u64) -> Measurement {
+        Measurement::new(counter.wrapping_sub(self.start))
+    }
+
+    /// Runs `f` and measures how long it took
+    pub fn measure<R>(f: impl FnOnce() -> R) -> (R, Measurement) {
+        let stopwatch = Self::start();
+        let result = f();
+        (result, stopwatch.elapsed())
+    }
+}
+
+/// Elapsed time stamp counter cycles, convertible to a [Duration] once the counter is calibrated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Measurement {
+    pub cycles: u64,
+}
+
+impl Measurement {
+    pub const fn new(cycles: u64) -> Self {
+        Self { cycles }
+    }
+
+    /// Returns `None` if the time stamp counter frequency is unknown
+    pub fn duration(self) -> Option<Duration> {
+        tsc_frequency().map(|frequency| self.duration_at(frequency))
+    }
+
+    /// Duration at a counter frequency of `hertz`
+    pub const fn duration_at(self, hertz: u64) -> Duration {
+        let nanos = self.cycles as u128 * 1_000_000_000 / hertz as u128;
+        Duration::from_nanos(nanos as u64)
+    }
+}
+
+/// Displays the duration if available, raw cycles otherwise
+impl Display for Measurement {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.duration() {
+            Some(duration) => write!(f, "{duration:?}"),
+            None => write!(f, "{} cycles", self.cycles),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn elapsed_math_against_a_mocked_counter() {
+        let stopwatch = Stopwatch { start: 1_000 };
+        assert_eq!(stopwatch.elapsed_until(1_000), Measurement::new(0));
+        assert_eq!(stopwatch.elapsed_until(4_000_001_000), Measurement::new(4_000_000_000));
+        // The counter wrapped around
+        let stopwatch = Stopwatch { start: u64::MAX - 9 };
+        assert_eq!(stopwatch.elapsed_until(5), Measurement::new(15));
+
+        let measurement = Measurement::new(3_000_000_000);
+        assert_eq!(measurement.duration_at(2_000_000_000), Duration::from_millis(1_500));
+        assert_eq!(measurement.duration_at(1_000), Duration::from_secs(3_000_000));
+        assert_eq!(Measurement::new(1).duration_at(3), Duration::from_nanos(333_333_333));
+
+        // The only test touching the frequency
+        assert_eq!(measurement.duration(), None);
+        assert_eq!(measurement.to_string(), "3000000000 cycles");
+        set_tsc_frequency(2_000_000_000);
+        assert_eq!(measurement.duration(), Some(Duration::from_millis(1_500)));
+        assert_eq!(measurement.to_string(), "1.5s");
+        set_tsc_frequency(0);
+    }
+}
@@ -0,0 +1,60 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+/// Ticks per second, 0 if the timer wasn't initialized
+static TICK_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// Ticks since the timer was started
+pub fn tick_count() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Should only be called by the timer interrupt handler
+pub fn on_tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Should be called by the timer driver when the timer is (re)configured
+pub fn set_tick_frequency(hertz: u64) {
+    TICK_FREQUENCY.store(hertz, Ordering::Relaxed);
+}
+
+pub fn tick_frequency() -> Option<u64> {
+    match TICK_FREQUENCY.load(Ordering::Relaxed) {
+        0 => None,
+        frequency => Some(frequency),
+    }
+}
+
+/// Returns `None` if the tick frequency is unknown
+pub fn ticks_to_millis(ticks: u64) -> Option<u64> {
+    let frequency = tick_frequency()?;
+    Some((ticks as u128 * 1000 / frequency as u128) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_to_millis_uses_the_set_frequency() {
+        // The only test touching the frequency
+        assert_eq!(ticks_to_millis(100), None);
+
+        set_tick_frequency(250);
+        assert_eq!(tick_frequency(), Some(250));
+        assert_eq!(ticks_to_millis(0), Some(0));
+        assert_eq!(ticks_to_millis(1), Some(4));
+        assert_eq!(ticks_to_millis(250), Some(1000));
+
+        // Rounds down
+        set_tick_frequency(3);
+        assert_eq!(ticks_to_millis(1), Some(333));
+        // No overflow in the intermediate product
+        set_tick_frequency(1000);
+        assert_eq!(ticks_to_millis(u64::MAX), Some(u64::MAX));
+
+        set_tick_frequency(0);
+        assert_eq!(ticks_to_millis(100), None);
+    }
+}
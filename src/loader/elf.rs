@@ -0,0 +1,463 @@
+use core::{fmt::Display, slice};
+
+use crate::{
+    allocator::physical::global_allocator,
+    arch::{paging::{self, AddressSpace, PageFlags, PagingToken, PAGE_SIZE}, AddressRange, VirtualAddress},
+    common::bits::read_unaligned_le
+};
+
+// ELF64 executable loader, only little endian x86_64 executables are supported
+
+const MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const VERSION_CURRENT: u8 = 1;
+const TYPE_EXECUTABLE: u16 = 2;
+const MACHINE_X86_64: u16 = 0x3E;
+
+const HEADER_SIZE: usize = 64;
+/// Minimum program header size, `e_phentsize` may be larger
+const PROGRAM_HEADER_SIZE: usize = 56;
+/// Loaded segments have to be in the lower half of the 4-level paging address space,
+/// which is also canonical under 5-level paging
+const USER_ADDRESS_END: usize = 1 << 47;
+
+pub const SEGMENT_TYPE_LOAD: u32 = 1;
+
+/// Maps memory for loaded segments, implemented by address spaces
+pub trait SegmentMapper {
+    /// Maps the page aligned `range` with `flags` and returns it as a writable slice,
+    /// the contents don't have to be initialized
+    fn map(&mut self, range: AddressRange<VirtualAddress>, flags: PageFlags, token: PagingToken) -> Result<&mut [u8], ()>;
+}
+
+impl SegmentMapper for AddressSpace {
+    /// Backs `range` with physically contiguous frames and returns them through the identity map,
+    /// the user mapping may be read-only \
+    /// Segments are limited to the largest contiguous allocation of the frame allocator,
+    /// nothing is mapped on failure
+    fn map(&mut self, range: AddressRange<VirtualAddress>, flags: PageFlags, token: PagingToken) -> Result<&mut [u8], ()> {
+        let pages = range.size() / PAGE_SIZE;
+        if pages == 0 {
+            return Ok(&mut []);
+        }
+
+        let allocator = global_allocator(token.into());
+        let frames = allocator.allocate(pages).ok_or(())?;
+        for index in 0..pages {
+            let offset = index * PAGE_SIZE;
+            // SAFETY: the frames were just allocated, the loader only maps user pages
+            if unsafe { AddressSpace::map(self, range.start + offset, frames + offset, flags, token) }.is_err() {
+                for page in (0..index).map(|i| range.start + i * PAGE_SIZE) {
+                    // SAFETY: mapped above, not handed out yet
+                    let _ = unsafe { self.unmap(page, token) };
+                }
+                allocator.free(frames, pages);
+                return Err(());
+            }
+        }
+
+        let memory = paging::to_virtual(frames, token.into());
+        // SAFETY: the frames are owned by the new mapping and only reachable through it and the identity map
+        Ok(unsafe { slice::from_raw_parts_mut(memory.as_mut_ptr().cast(), range.size()) })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ElfError {
+    TooShort,
+    InvalidMagic,
+    /// Not a 64-bit ELF
+    UnsupportedClass,
+    UnsupportedEndianness,
+    UnsupportedVersion,
+    /// Not an executable, position independent executables (`ET_DYN`) would need to be relocated
+    UnsupportedType,
+    UnsupportedMachine,
+    InvalidProgramHeader,
+    /// Segment data outside the file or memory size smaller than file size
+    InvalidSegment { index: usize },
+    /// Alignment isn't a power of two or the address and offset aren't congruent modulo alignment
+    MisalignedSegment { index: usize },
+    /// Segment shares pages with a previous segment or isn't sorted by address
+    OverlappingSegments { index: usize },
+    /// Segment reaches into the canonical hole or the kernel's upper half
+    InvalidAddress { index: usize },
+    /// Entry point outside every executable loaded segment
+    InvalidEntryPoint,
+    MappingFailed { index: usize },
+}
+
+impl Display for ElfError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ElfError::TooShort => f.write_str("File too short"),
+            ElfError::InvalidMagic => f.write_str("Not an ELF file"),
+            ElfError::UnsupportedClass => f.write_str("Not a 64-bit ELF file"),
+            ElfError::UnsupportedEndianness => f.write_str("Not a little endian ELF file"),
+            ElfError::UnsupportedVersion => f.write_str("Unsupported ELF version"),
+            ElfError::UnsupportedType => f.write_str("Not an executable"),
+            ElfError::UnsupportedMachine => f.write_str("Not an x86_64 executable"),
+            ElfError::InvalidProgramHeader => f.write_str("Invalid program header table"),
+            ElfError::InvalidSegment { index } => write!(f, "Invalid segment {index}"),
+            ElfError::MisalignedSegment { index } => write!(f, "Misaligned segment {index}"),
+            ElfError::OverlappingSegments { index } => write!(f, "Segment {index} overlaps a previous segment"),
+            ElfError::InvalidAddress { index } => write!(f, "Segment {index} outside the user address space"),
+            ElfError::InvalidEntryPoint => f.write_str("Entry point outside the executable segments"),
+            ElfError::MappingFailed { index } => write!(f, "Failed to map segment {index}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SegmentFlags {
+    pub readable: bool,
+    pub writable: bool,
+    pub executable: bool,
+}
+
+impl SegmentFlags {
+    const EXECUTE: u32 = 1;
+    const WRITE: u32 = 2;
+    const READ: u32 = 4;
+
+    fn from_raw(value: u32) -> Self {
+        Self {
+            readable: value & Self::READ != 0,
+            writable: value & Self::WRITE != 0,
+            executable: value & Self::EXECUTE != 0,
+        }
+    }
+}
+
+impl From<SegmentFlags> for PageFlags {
+    /// Present pages are always readable \
+    /// Pages are user accessible, [Elf::load] only maps segments in the lower half
+    fn from(value: SegmentFlags) -> Self {
+        PageFlags {
+            writable: value.writable,
+            executable: value.executable,
+            user: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Segment {
+    pub kind: u32,
+    pub flags: SegmentFlags,
+    /// Offset of the segment data in the file
+    pub offset: usize,
+    pub address: VirtualAddress,
+    pub file_size: usize,
+    pub memory_size: usize,
+    pub alignment: usize,
+}
+
+impl Segment {
+    pub fn is_load(&self) -> bool {
+        self.kind == SEGMENT_TYPE_LOAD
+    }
+
+    /// Page aligned range covering the segment in memory
+    fn page_range(&self) -> Option<AddressRange<VirtualAddress>> {
+        let end = usize::from(self.address).checked_add(self.memory_size)?.checked_next_multiple_of(PAGE_SIZE)?;
+        Some(AddressRange::new(self.address.page_align_down(), VirtualAddress::new(end)))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Elf<'a> {
+    data: &'a [u8],
+    entry_point: VirtualAddress,
+    program_header_offset: usize,
+    /// `e_phentsize`, the stride of the program header table
+    program_header_entry_size: usize,
+    program_header_count: usize,
+}
+
+impl<'a> Elf<'a> {
+    /// Validates the file header and the program header table bounds
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < HEADER_SIZE {
+            return Err(ElfError::TooShort);
+        }
+        if data[0..4] != MAGIC {
+            return Err(ElfError::InvalidMagic);
+        }
+        if data[4] != CLASS_64 {
+            return Err(ElfError::UnsupportedClass);
+        }
+        if data[5] != DATA_LITTLE_ENDIAN {
+            return Err(ElfError::UnsupportedEndianness);
+        }
+        if data[6] != VERSION_CURRENT {
+            return Err(ElfError::UnsupportedVersion);
+        }
+
+        // Header length checked above
+        let read_u16 = |offset: usize| read_unaligned_le::<u16>(&data[offset..]).unwrap();
+        let read_u64 = |offset: usize| read_unaligned_le::<u64>(&data[offset..]).unwrap();
+
+        if read_u16(16) != TYPE_EXECUTABLE {
+            return Err(ElfError::UnsupportedType);
+        }
+        if read_u16(18) != MACHINE_X86_64 {
+            return Err(ElfError::UnsupportedMachine);
+        }
+
+        let entry_point = VirtualAddress::new(read_u64(24) as usize);
+        let program_header_offset = read_u64(32) as usize;
+        let program_header_entry_size = read_u16(54) as usize;
+        let program_header_count = read_u16(56) as usize;
+
+        if program_header_count > 0 && program_header_entry_size < PROGRAM_HEADER_SIZE {
+            return Err(ElfError::InvalidProgramHeader);
+        }
+        let table_end = program_header_count
+            .checked_mul(program_header_entry_size)
+            .and_then(|x| x.checked_add(program_header_offset));
+        if table_end.is_none_or(|end| end > data.len()) {
+            return Err(ElfError::InvalidProgramHeader);
+        }
+
+        Ok(Self {
+            data,
+            entry_point,
+            program_header_offset,
+            program_header_entry_size,
+            program_header_count,
+        })
+    }
+
+    pub fn entry_point(&self) -> VirtualAddress {
+        self.entry_point
+    }
+
+    /// All program headers, bounds were checked by [Elf::parse]
+    pub fn segments(&self) -> impl Iterator<Item = Segment> + 'a {
+        let data = self.data;
+        let (offset, entry_size) = (self.program_header_offset, self.program_header_entry_size);
+        (0..self.program_header_count).map(move |index| {
+            let header = &data[offset + index * entry_size..][..PROGRAM_HEADER_SIZE];
+            let read_u32 = |offset: usize| read_unaligned_le::<u32>(&header[offset..]).unwrap();
+            let read_u64 = |offset: usize| read_unaligned_le::<u64>(&header[offset..]).unwrap() as usize;
+            Segment {
+                kind: read_u32(0),
+                flags: SegmentFlags::from_raw(read_u32(4)),
+                offset: read_u64(8),
+                address: VirtualAddress::new(read_u64(16)),
+                file_size: read_u64(32),
+                memory_size: read_u64(40),
+                alignment: read_u64(48),
+            }
+        })
+    }
+
+    /// `PT_LOAD` segments with their program header indices
+    pub fn load_segments(&self) -> impl Iterator<Item = (usize, Segment)> + 'a {
+        self.segments().enumerate().filter(|(_, segment)| segment.is_load())
+    }
+
+    /// Maps and initializes all `PT_LOAD` segments, memory past the file data (`.bss`) is zeroed \
+    /// Returns the entry point, segments mapped before a failure stay mapped
+    pub fn load(&self, mapper: &mut impl SegmentMapper, token: PagingToken) -> Result<VirtualAddress, ElfError> {
+        self.validate_segments()?;
+
+        for (index, segment) in self.load_segments() {
+            // Validated above
+            let range = segment.page_range().unwrap();
+            let memory = mapper.map(range, segment.flags.into(), token).map_err(|_| ElfError::MappingFailed { index })?;
+            if memory.len() < range.size() {
+                return Err(ElfError::MappingFailed { index });
+            }
+
+            let start = segment.address - range.start;
+            let file_end = start + segment.file_size;
+            memory[..start].fill(0);
+            memory[start..file_end].copy_from_slice(&self.data[segment.offset..][..segment.file_size]);
+            memory[file_end..range.size()].fill(0);
+        }
+
+        Ok(self.entry_point)
+    }
+
+    fn validate_segments(&self) -> Result<(), ElfError> {
+        let mut previous_end: Option<VirtualAddress> = None;
+        for (index, segment) in self.load_segments() {
+            let file_end = segment.offset.checked_add(segment.file_size);
+            if file_end.is_none_or(|end| end > self.data.len()) || segment.memory_size < segment.file_size {
+                return Err(ElfError::InvalidSegment { index });
+            }
+
+            let alignment = segment.alignment.max(1);
+            if !alignment.is_power_of_two() || usize::from(segment.address) % alignment != segment.offset % alignment {
+                return Err(ElfError::MisalignedSegment { index });
+            }
+
+            let range = segment.page_range().ok_or(ElfError::InvalidSegment { index })?;
+            if usize::from(range.end) > USER_ADDRESS_END {
+                return Err(ElfError::InvalidAddress { index });
+            }
+            // Segments share page permissions, so page granularity overlaps are rejected too
+            if previous_end.is_some_and(|end| range.start < end) {
+                return Err(ElfError::OverlappingSegments { index });
+            }
+            previous_end = Some(range.end);
+        }
+
+        let entry_point_mapped = self.load_segments().any(|(_, segment)| {
+            segment.flags.executable
+                && segment.address <= self.entry_point
+                && self.entry_point - segment.address < segment.memory_size
+        });
+        if !entry_point_mapped {
+            return Err(ElfError::InvalidEntryPoint);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const ENTRY_POINT: u64 = 0x40_1000;
+
+    /// Executable with a program header table of `entry_size` byte entries, followed by the segment data \
+    /// Segments: (type, flags, offset, address, file size, memory size, alignment)
+    fn elf(entry_size: u16, segments: &[(u32, u32, u64, u64, u64, u64, u64)], data: &[u8]) -> Vec<u8> {
+        let mut elf = Vec::new();
+        elf.extend(MAGIC);
+        elf.extend([CLASS_64, DATA_LITTLE_ENDIAN, VERSION_CURRENT]);
+        elf.resize(16, 0);
+        elf.extend(TYPE_EXECUTABLE.to_le_bytes());
+        elf.extend(MACHINE_X86_64.to_le_bytes());
+        elf.extend(1_u32.to_le_bytes());
+        elf.extend(ENTRY_POINT.to_le_bytes());
+        elf.extend((HEADER_SIZE as u64).to_le_bytes());
+        elf.resize(54, 0);
+        elf.extend(entry_size.to_le_bytes());
+        elf.extend((segments.len() as u16).to_le_bytes());
+        elf.resize(HEADER_SIZE, 0);
+
+        for &(kind, flags, offset, address, file_size, memory_size, alignment) in segments {
+            let start = elf.len();
+            elf.extend(kind.to_le_bytes());
+            elf.extend(flags.to_le_bytes());
+            for value in [offset, address, address, file_size, memory_size, alignment] {
+                elf.extend(value.to_le_bytes());
+            }
+            elf.resize(start + entry_size as usize, 0);
+        }
+        elf.extend(data);
+        elf
+    }
+
+    /// Text and data segments, the data segment has a `.bss` tail
+    fn sample(entry_size: u16) -> Vec<u8> {
+        // Segment data follows the program header table
+        let data_offset = HEADER_SIZE as u64 + 2 * entry_size as u64;
+        let text = (SEGMENT_TYPE_LOAD, 5, data_offset, ENTRY_POINT, 4, 4, 4);
+        let data = (SEGMENT_TYPE_LOAD, 6, data_offset + 4, 0x40_2004, 4, 0x20, 4);
+        elf(entry_size, &[text, data], &[0x90, 0x90, 0xCC, 0xC3, 1, 2, 3, 4])
+    }
+
+    fn token() -> PagingToken {
+        // SAFETY: the test mapper doesn't touch page tables
+        unsafe { PagingToken::new() }
+    }
+
+    struct Mapper(Vec<(AddressRange<VirtualAddress>, PageFlags, Vec<u8>)>);
+
+    impl SegmentMapper for Mapper {
+        fn map(&mut self, range: AddressRange<VirtualAddress>, flags: PageFlags, _: PagingToken) -> Result<&mut [u8], ()> {
+            // Not zeroed, the loader has to initialize the whole range
+            self.0.push((range, flags, std::vec![0xAA; range.size()]));
+            Ok(&mut self.0.last_mut().unwrap().2)
+        }
+    }
+
+    #[test]
+    fn parse_header_and_segments() {
+        // Program headers padded to 64 bytes, parsed with the `e_phentsize` stride
+        for entry_size in [PROGRAM_HEADER_SIZE as u16, 64] {
+            let data = sample(entry_size);
+            let elf = Elf::parse(&data).unwrap();
+            assert_eq!(elf.entry_point(), VirtualAddress::new(ENTRY_POINT as usize));
+
+            let segments: Vec<_> = elf.load_segments().collect();
+            assert_eq!(segments.len(), 2);
+            let (index, data_segment) = segments[1];
+            assert_eq!(index, 1);
+            assert_eq!(data_segment.address, VirtualAddress::new(0x40_2004));
+            assert_eq!((data_segment.file_size, data_segment.memory_size, data_segment.alignment), (4, 0x20, 4));
+            assert_eq!(data_segment.flags, SegmentFlags { readable: true, writable: true, executable: false });
+        }
+    }
+
+    #[test]
+    fn parse_rejects_invalid_headers() {
+        let data = sample(64);
+        assert_eq!(Elf::parse(&data[..HEADER_SIZE - 1]).unwrap_err(), ElfError::TooShort);
+        // Program header table past the end of the file
+        assert_eq!(Elf::parse(&data[..HEADER_SIZE + 100]).unwrap_err(), ElfError::InvalidProgramHeader);
+
+        let mut invalid = data.clone();
+        invalid[0] = 0;
+        assert_eq!(Elf::parse(&invalid).unwrap_err(), ElfError::InvalidMagic);
+
+        let mut shared = data.clone();
+        shared[16] = 3;
+        assert_eq!(Elf::parse(&shared).unwrap_err(), ElfError::UnsupportedType);
+
+        let mut small_entries = data.clone();
+        small_entries[54] = PROGRAM_HEADER_SIZE as u8 - 1;
+        assert_eq!(Elf::parse(&small_entries).unwrap_err(), ElfError::InvalidProgramHeader);
+    }
+
+    #[test]
+    fn load_copies_data_and_zeroes_bss() {
+        let data = sample(64);
+        let mut mapper = Mapper(Vec::new());
+        let entry_point = Elf::parse(&data).unwrap().load(&mut mapper, token()).unwrap();
+        assert_eq!(entry_point, VirtualAddress::new(ENTRY_POINT as usize));
+
+        let (range, flags, memory) = &mapper.0[1];
+        assert_eq!(*range, AddressRange::new(VirtualAddress::new(0x40_2000), VirtualAddress::new(0x40_3000)));
+        assert_eq!(*flags, PageFlags { writable: true, executable: false, user: true });
+        assert!(memory[..4].iter().all(|&x| x == 0));
+        assert_eq!(memory[4..8], [1, 2, 3, 4]);
+        assert!(memory[8..].iter().all(|&x| x == 0));
+    }
+
+    #[test]
+    fn load_rejects_kernel_and_non_canonical_segments() {
+        for address in [0x7FFF_FFFF_F000, 0xFFFF_8000_0000_0000] {
+            let segment = (SEGMENT_TYPE_LOAD, 4, 0, address, 0, 0x2000, 0x1000);
+            let data = elf(64, &[segment, segment], &[]);
+            let result = Elf::parse(&data).unwrap().load(&mut Mapper(Vec::new()), token());
+            assert_eq!(result.unwrap_err(), ElfError::InvalidAddress { index: 0 });
+        }
+    }
+
+    #[test]
+    fn load_rejects_entry_point_outside_executable_segments() {
+        // Entry point in a non-executable segment, past the end of the text segment and in the upper half
+        let cases = [
+            ((SEGMENT_TYPE_LOAD, 4, 0, ENTRY_POINT, 0, 0x10, 0x1000), ENTRY_POINT),
+            ((SEGMENT_TYPE_LOAD, 5, 0, ENTRY_POINT - 0x10, 0, 0x10, 0x10), ENTRY_POINT),
+            ((SEGMENT_TYPE_LOAD, 5, 0, ENTRY_POINT, 0, 0x10, 0x1000), 0xFFFF_8000_0000_0000),
+        ];
+        for (segment, entry_point) in cases {
+            let mut data = elf(64, &[segment], &[]);
+            data[24..32].copy_from_slice(&entry_point.to_le_bytes());
+            let mut mapper = Mapper(Vec::new());
+            let result = Elf::parse(&data).unwrap().load(&mut mapper, token());
+            assert_eq!(result.unwrap_err(), ElfError::InvalidEntryPoint);
+            assert!(mapper.0.is_empty());
+        }
+    }
+}
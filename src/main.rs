@@ -1,23 +1,23 @@
-#![no_std]
-#![no_main]
+// Unit tests are built for the host (`cargo test-host`) with the standard library and test harness
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
+// Nothing calls the kernel entry points in the test binary
+#![cfg_attr(test, allow(dead_code, unused_macros, unused_imports))]
 
 #![deny(unsafe_op_in_unsafe_fn)]
 #![allow(clippy::missing_safety_doc)]
 #![allow(clippy::result_unit_err)]
 
-// Transmute slices if this feature doesn't get stabilized
-#![feature(maybe_uninit_slice)]
-#![feature(maybe_uninit_uninit_array)]
-#![feature(int_roundings)]
-#![feature(is_sorted)]
 #![feature(sync_unsafe_cell)]
-#![feature(naked_functions)]
 
 pub mod allocator;
 pub mod arch;
 pub mod common;
+pub mod loader;
+pub mod smbios;
 
-use core::{panic::PanicInfo, arch::asm};
+#[cfg(not(test))]
+use core::{fmt::Write, panic::PanicInfo};
 
 // Get terminal, setup early logging
 // Get memory map, setup global allocator / kmalloc
@@ -27,12 +27,22 @@ use core::{panic::PanicInfo, arch::asm};
 // Setup IRQs
 // ...
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic_handler(_info: &PanicInfo) -> ! {
-    arch::boot::boot_println!("Panic! {}", _info);
+    let mut writer = arch::devices::emergency::EmergencyWriter::new();
+    _ = writeln!(writer, "Panic! {}", _info);
+    writer.mark_framebuffer();
+    // The boot terminal fails instead of blocking if its lock is held, skip it if it's COM1 as well
+    if arch::boot::boot_terminal_writer().is_some_and(|writer| !writer.is_serial()) {
+        arch::boot::boot_println!("Panic! {}", _info);
+    }
+    #[cfg(feature = "test-qemu")]
+    arch::qemu::exit(arch::qemu::ExitCode::Failure);
+    #[cfg(not(feature = "test-qemu"))]
     loop {
         unsafe {
-            asm!(
+            core::arch::asm!(
                 "cli",
                 "hlt",
             );
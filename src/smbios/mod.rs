@@ -0,0 +1,340 @@
+// System Management BIOS, see: DMTF DSP0134
+
+use core::slice;
+
+use crate::{arch::{paging::{self, IdentityMapToken}, PhysicalAddress, VirtualAddress}, common::bits::read_unaligned_le};
+
+const ANCHOR_32: &[u8] = b"_SM_";
+const ANCHOR_64: &[u8] = b"_SM3_";
+
+const STRUCTURE_HEADER_SIZE: usize = 4;
+const END_OF_TABLE: u8 = 127;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmbiosError {
+    InvalidAnchor,
+    InvalidChecksum,
+    InvalidLength,
+}
+
+/// Parsed SMBIOS entry point with the structure table it points to
+#[derive(Clone, Copy, Debug)]
+pub struct Smbios {
+    pub major_version: u8,
+    pub minor_version: u8,
+    table: &'static [u8],
+}
+
+impl Smbios {
+    /// Parses a 32-bit (`_SM_`) or 64-bit (`_SM3_`) entry point \
+    /// Safety:
+    /// `entry_point` must point to a valid, readable SMBIOS entry point,
+    /// the structure table must be accessible through the identity map
+    pub unsafe fn new(entry_point: VirtualAddress, identity_map: IdentityMapToken) -> Result<Self, SmbiosError> {
+        let ptr = entry_point.as_ptr().cast::<u8>();
+        // Both entry point lengths are stored at most at offset 6
+        let header = unsafe { slice::from_raw_parts(ptr, 7) };
+
+        let (length, major_version, minor_version, table_address, table_length) = if header.starts_with(ANCHOR_64) {
+            let length = header[6] as usize;
+            if length < 0x18 {
+                return Err(SmbiosError::InvalidLength);
+            }
+            let entry = unsafe { slice::from_raw_parts(ptr, length) };
+            let table_length = read_unaligned_le::<u32>(&entry[12..]).unwrap() as usize;
+            let table_address = read_unaligned_le::<u64>(&entry[16..]).unwrap();
+            (length, entry[7], entry[8], table_address, table_length)
+        } else if header.starts_with(ANCHOR_32) {
+            let length = header[5] as usize;
+            if length < 0x1F {
+                return Err(SmbiosError::InvalidLength);
+            }
+            let entry = unsafe { slice::from_raw_parts(ptr, length) };
+            if &entry[16..21] != b"_DMI_" {
+                return Err(SmbiosError::InvalidAnchor);
+            }
+            if !checksum_valid(&entry[16..0x1F]) {
+                return Err(SmbiosError::InvalidChecksum);
+            }
+            let table_length = read_unaligned_le::<u16>(&entry[22..]).unwrap() as usize;
+            let table_address = read_unaligned_le::<u32>(&entry[24..]).unwrap() as u64;
+            (length, entry[6], entry[7], table_address, table_length)
+        } else {
+            return Err(SmbiosError::InvalidAnchor);
+        };
+
+        if !checksum_valid(unsafe { slice::from_raw_parts(ptr, length) }) {
+            return Err(SmbiosError::InvalidChecksum);
+        }
+
+        let table = table_location(PhysicalAddress::from(table_address), identity_map);
+        Ok(Self {
+            major_version,
+            minor_version,
+            table: unsafe { slice::from_raw_parts(table.as_ptr().cast::<u8>(), table_length) },
+        })
+    }
+
+    pub fn structures(&self) -> Structures {
+        Structures { remaining: self.table }
+    }
+
+    /// BIOS information (type 0)
+    pub fn bios_info(&self) -> Option<BiosInfo> {
+        let structure = self.structures().find(|x| x.kind == BiosInfo::KIND)?;
+        Some(BiosInfo {
+            vendor: structure.string_at(0x04),
+            version: structure.string_at(0x05),
+            release_date: structure.string_at(0x08),
+        })
+    }
+
+    /// System information (type 1)
+    pub fn system_info(&self) -> Option<SystemInfo> {
+        let structure = self.structures().find(|x| x.kind == SystemInfo::KIND)?;
+        Some(SystemInfo {
+            manufacturer: structure.string_at(0x04),
+            product_name: structure.string_at(0x05),
+            version: structure.string_at(0x06),
+            serial_number: structure.string_at(0x07),
+        })
+    }
+}
+
+/// Virtual address of the structure table
+#[cfg(not(test))]
+fn table_location(address: PhysicalAddress, identity_map: IdentityMapToken) -> VirtualAddress {
+    paging::to_virtual(address, identity_map)
+}
+
+/// Test tables are backed by host memory, their physical addresses are host pointers
+#[cfg(test)]
+fn table_location(address: PhysicalAddress, _identity_map: IdentityMapToken) -> VirtualAddress {
+    VirtualAddress::new(address.as_usize())
+}
+
+fn checksum_valid(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0_u8, |sum, &x| sum.wrapping_add(x)) == 0
+}
+
+/// Iterator over SMBIOS structures, stops at the end-of-table structure or at the first malformed one
+#[derive(Clone, Debug)]
+pub struct Structures {
+    remaining: &'static [u8],
+}
+
+impl Iterator for Structures {
+    type Item = Structure;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.remaining;
+        if bytes.len() < STRUCTURE_HEADER_SIZE {
+            return None;
+        }
+
+        let kind = bytes[0];
+        let length = bytes[1] as usize;
+        let handle = read_unaligned_le::<u16>(&bytes[2..]).unwrap();
+        if kind == END_OF_TABLE || length < STRUCTURE_HEADER_SIZE || length > bytes.len() {
+            self.remaining = &[];
+            return None;
+        }
+
+        // String set ends with two null bytes (also when empty)
+        let strings_end = bytes[length..]
+            .windows(2)
+            .position(|x| x == [0, 0])
+            .map(|x| length + x + 2);
+        let Some(strings_end) = strings_end else {
+            self.remaining = &[];
+            return None;
+        };
+
+        self.remaining = &bytes[strings_end..];
+        Some(Structure {
+            kind,
+            handle,
+            data: &bytes[..length],
+            strings: &bytes[length..strings_end],
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Structure {
+    pub kind: u8,
+    pub handle: u16,
+    /// Formatted area, including the header
+    pub data: &'static [u8],
+    strings: &'static [u8],
+}
+
+impl Structure {
+    /// Strings are numbered from 1, 0 means no string
+    pub fn string(&self, number: u8) -> Option<&'static str> {
+        if number == 0 {
+            return None;
+        }
+
+        // Strings are never empty, the first empty one is the terminator
+        let string = self.strings.split(|&x| x == 0).take_while(|x| !x.is_empty()).nth(number as usize - 1)?;
+        core::str::from_utf8(string).ok()
+    }
+
+    /// Reads the string number stored at `offset` in the formatted area
+    fn string_at(&self, offset: usize) -> Option<&'static str> {
+        self.data.get(offset).and_then(|&number| self.string(number))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BiosInfo {
+    pub vendor: Option<&'static str>,
+    pub version: Option<&'static str>,
+    pub release_date: Option<&'static str>,
+}
+
+impl BiosInfo {
+    pub const KIND: u8 = 0;
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SystemInfo {
+    pub manufacturer: Option<&'static str>,
+    pub product_name: Option<&'static str>,
+    pub version: Option<&'static str>,
+    pub serial_number: Option<&'static str>,
+}
+
+impl SystemInfo {
+    pub const KIND: u8 = 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{boxed::Box, vec, vec::Vec};
+
+    use super::*;
+
+    /// Sets `entry[index]` so that the bytes in `range` sum to 0
+    fn set_checksum(entry: &mut [u8], index: usize, range: core::ops::Range<usize>) {
+        entry[index] = 0;
+        entry[index] = entry[range].iter().fold(0_u8, |sum, &x| sum.wrapping_add(x)).wrapping_neg();
+    }
+
+    fn entry_point_32(table_address: u32, table_length: u16) -> Vec<u8> {
+        let mut entry = vec![0; 0x1F];
+        entry[..4].copy_from_slice(ANCHOR_32);
+        entry[5] = 0x1F;
+        (entry[6], entry[7]) = (2, 8);
+        entry[16..21].copy_from_slice(b"_DMI_");
+        entry[22..24].copy_from_slice(&table_length.to_le_bytes());
+        entry[24..28].copy_from_slice(&table_address.to_le_bytes());
+        // The intermediate checksum is covered by the entry point checksum
+        set_checksum(&mut entry, 21, 16..0x1F);
+        set_checksum(&mut entry, 4, 0..0x1F);
+        entry
+    }
+
+    fn entry_point_64(table: &[u8]) -> Vec<u8> {
+        let mut entry = vec![0; 0x18];
+        entry[..5].copy_from_slice(ANCHOR_64);
+        entry[6] = 0x18;
+        (entry[7], entry[8]) = (3, 4);
+        entry[12..16].copy_from_slice(&(table.len() as u32).to_le_bytes());
+        entry[16..24].copy_from_slice(&(table.as_ptr() as u64).to_le_bytes());
+        set_checksum(&mut entry, 5, 0..0x18);
+        entry
+    }
+
+    fn parse(entry: &[u8]) -> Result<Smbios, SmbiosError> {
+        unsafe { Smbios::new(VirtualAddress::new(entry.as_ptr() as usize), IdentityMapToken::new()) }
+    }
+
+    /// BIOS information with three strings, system information with an empty string set,
+    /// the end-of-table structure and trailing garbage
+    fn sample_table() -> &'static [u8] {
+        let mut table = vec![0, 0x12, 0, 0, 1, 2, 0, 0, 3];
+        table.resize(0x12, 0);
+        for string in ["Vendor", "v1.0", "01/01/2026", ""] {
+            table.extend(string.as_bytes());
+            table.push(0);
+        }
+        table.extend([1, 8, 1, 0, 1, 0, 0, 0, 0, 0]);
+        table.extend([END_OF_TABLE, 4, 2, 0, 0, 0]);
+        table.extend([0, 4, 3, 0, 0, 0]);
+        table.leak()
+    }
+
+    #[test]
+    fn entry_points() {
+        let entry = entry_point_32(0x1000, 0);
+        let smbios = parse(&entry).unwrap();
+        assert_eq!((smbios.major_version, smbios.minor_version), (2, 8));
+        assert_eq!(smbios.structures().count(), 0);
+
+        let smbios = parse(&entry_point_64(sample_table())).unwrap();
+        assert_eq!((smbios.major_version, smbios.minor_version), (3, 4));
+        let kinds: Vec<_> = smbios.structures().map(|x| (x.kind, x.handle, x.data.len())).collect();
+        assert_eq!(kinds, [(BiosInfo::KIND, 0, 0x12), (SystemInfo::KIND, 1, 8)]);
+
+        let bios = smbios.bios_info().unwrap();
+        assert_eq!((bios.vendor, bios.version, bios.release_date), (Some("Vendor"), Some("v1.0"), Some("01/01/2026")));
+        let system = smbios.system_info().unwrap();
+        assert_eq!((system.manufacturer, system.product_name), (None, None));
+    }
+
+    #[test]
+    fn invalid_entry_points() {
+        let mut entry = entry_point_32(0x1000, 0);
+        entry[0x1E] ^= 1;
+        assert_eq!(parse(&entry).unwrap_err(), SmbiosError::InvalidChecksum);
+        // Intermediate checksum, the entry point checksum is still valid
+        let mut entry = entry_point_32(0x1000, 0);
+        entry[21] ^= 1;
+        set_checksum(&mut entry, 4, 0..0x1F);
+        assert_eq!(parse(&entry).unwrap_err(), SmbiosError::InvalidChecksum);
+        let mut entry = entry_point_32(0x1000, 0);
+        entry[16] = b'X';
+        assert_eq!(parse(&entry).unwrap_err(), SmbiosError::InvalidAnchor);
+        let mut entry = entry_point_32(0x1000, 0);
+        entry[5] = 0x1E;
+        assert_eq!(parse(&entry).unwrap_err(), SmbiosError::InvalidLength);
+
+        let mut entry = entry_point_64(&[]);
+        entry[9] ^= 1;
+        assert_eq!(parse(&entry).unwrap_err(), SmbiosError::InvalidChecksum);
+        entry[..5].copy_from_slice(b"_SM2_");
+        assert_eq!(parse(&entry).unwrap_err(), SmbiosError::InvalidAnchor);
+    }
+
+    #[test]
+    fn structures_stop_at_malformed_data() {
+        let table = sample_table();
+        let structures = |bytes: &'static [u8]| Structures { remaining: bytes }.map(|x| x.kind).collect::<Vec<_>>();
+        assert_eq!(structures(table), [0, 1]);
+        // The system information string set is cut off (the last 13 bytes: its second null, end of table, garbage)
+        assert_eq!(structures(&table[..table.len() - 13]), [0]);
+        // Length past the end of the table
+        assert_eq!(structures(&table[..0x11]), []);
+        assert_eq!(structures(&table[..3]), []);
+        let zero_length: &'static [u8] = Box::leak(Box::new([1, 0, 0, 0, 0, 0]));
+        assert_eq!(structures(zero_length), []);
+    }
+
+    #[test]
+    fn string_numbers() {
+        let mut structures = Structures { remaining: sample_table() };
+        let (bios, system) = (structures.next().unwrap(), structures.next().unwrap());
+        assert_eq!(bios.string(0), None);
+        assert_eq!(bios.string(1), Some("Vendor"));
+        assert_eq!(bios.string(3), Some("01/01/2026"));
+        assert_eq!(bios.string(4), None);
+        assert_eq!(bios.string(u8::MAX), None);
+        // Empty string set (`00 00`)
+        assert_eq!(system.strings, [0, 0]);
+        assert_eq!(system.string(1), None);
+        // Offset past the formatted area
+        assert_eq!(system.string_at(0x10), None);
+    }
+}